@@ -0,0 +1,2 @@
+ALTER TABLE recordings DROP CONSTRAINT recordings_track_number_key;
+ALTER TABLE recordings ADD CONSTRAINT recordings_release_track_number_key UNIQUE (release_id, track_number);
@@ -0,0 +1,7 @@
+DROP INDEX idx_recording_file_hash;
+DROP INDEX idx_recording_file_path;
+
+ALTER TABLE recordings DROP COLUMN available;
+ALTER TABLE recordings DROP COLUMN file_hash;
+ALTER TABLE recordings DROP COLUMN file_modified;
+ALTER TABLE recordings DROP COLUMN file_size;
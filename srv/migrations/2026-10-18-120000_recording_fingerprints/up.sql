@@ -0,0 +1,7 @@
+ALTER TABLE recordings ADD COLUMN file_size BIGINT;
+ALTER TABLE recordings ADD COLUMN file_modified TIMESTAMP WITH TIME ZONE;
+ALTER TABLE recordings ADD COLUMN file_hash VARCHAR(64);
+ALTER TABLE recordings ADD COLUMN available BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE INDEX idx_recording_file_path ON recordings(file_path);
+CREATE INDEX idx_recording_file_hash ON recordings(file_hash);
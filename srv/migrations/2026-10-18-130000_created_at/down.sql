@@ -0,0 +1,11 @@
+DROP INDEX idx_release_created_at;
+DROP INDEX idx_piece_created_at;
+DROP INDEX idx_songwriter_created_at;
+DROP INDEX idx_composer_created_at;
+DROP INDEX idx_performer_created_at;
+
+ALTER TABLE releases DROP COLUMN created_at;
+ALTER TABLE pieces DROP COLUMN created_at;
+ALTER TABLE songwriters DROP COLUMN created_at;
+ALTER TABLE composers DROP COLUMN created_at;
+ALTER TABLE performers DROP COLUMN created_at;
@@ -0,0 +1,11 @@
+ALTER TABLE performers ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE composers ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE songwriters ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE pieces ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE releases ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+CREATE INDEX idx_performer_created_at ON performers(created_at);
+CREATE INDEX idx_composer_created_at ON composers(created_at);
+CREATE INDEX idx_songwriter_created_at ON songwriters(created_at);
+CREATE INDEX idx_piece_created_at ON pieces(created_at);
+CREATE INDEX idx_release_created_at ON releases(created_at);
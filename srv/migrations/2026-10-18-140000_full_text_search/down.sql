@@ -0,0 +1,20 @@
+DROP INDEX idx_recording_piece_name_trgm;
+DROP INDEX idx_release_name_trgm;
+DROP INDEX idx_piece_name_trgm;
+DROP INDEX idx_songwriter_name_trgm;
+DROP INDEX idx_composer_name_trgm;
+DROP INDEX idx_performer_name_trgm;
+
+DROP INDEX idx_recording_document;
+DROP INDEX idx_release_document;
+DROP INDEX idx_piece_document;
+DROP INDEX idx_songwriter_document;
+DROP INDEX idx_composer_document;
+DROP INDEX idx_performer_document;
+
+DROP FUNCTION allegro_document(text, text);
+DROP TEXT SEARCH CONFIGURATION allegro;
+DROP FUNCTION allegro_unaccent(text);
+
+DROP EXTENSION IF EXISTS unaccent;
+DROP EXTENSION IF EXISTS pg_trgm;
@@ -0,0 +1,32 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+-- unaccent is only stable, so it is wrapped to be usable in index expressions
+CREATE FUNCTION allegro_unaccent(text) RETURNS text AS $$
+    SELECT public.unaccent('public.unaccent'::regdictionary, $1)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+-- text search configuration that ignores accents, without stemming names
+CREATE TEXT SEARCH CONFIGURATION allegro (COPY = simple);
+ALTER TEXT SEARCH CONFIGURATION allegro
+    ALTER MAPPING FOR hword, hword_part, word WITH unaccent, simple;
+
+-- searchable document of an entry, with its name weighted above its description
+CREATE FUNCTION allegro_document(name text, description text) RETURNS tsvector AS $$
+    SELECT setweight(to_tsvector('allegro'::regconfig, coalesce(name, '')), 'A')
+        || setweight(to_tsvector('allegro'::regconfig, coalesce(description, '')), 'B')
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE;
+
+CREATE INDEX idx_performer_document ON performers USING GIN (allegro_document(name, description));
+CREATE INDEX idx_composer_document ON composers USING GIN (allegro_document(name, description));
+CREATE INDEX idx_songwriter_document ON songwriters USING GIN (allegro_document(name, description));
+CREATE INDEX idx_piece_document ON pieces USING GIN (allegro_document(name, description));
+CREATE INDEX idx_release_document ON releases USING GIN (allegro_document(name, description));
+CREATE INDEX idx_recording_document ON recordings USING GIN (allegro_document(piece_name, NULL));
+
+CREATE INDEX idx_performer_name_trgm ON performers USING GIN (allegro_unaccent(lower(name)) gin_trgm_ops);
+CREATE INDEX idx_composer_name_trgm ON composers USING GIN (allegro_unaccent(lower(name)) gin_trgm_ops);
+CREATE INDEX idx_songwriter_name_trgm ON songwriters USING GIN (allegro_unaccent(lower(name)) gin_trgm_ops);
+CREATE INDEX idx_piece_name_trgm ON pieces USING GIN (allegro_unaccent(lower(name)) gin_trgm_ops);
+CREATE INDEX idx_release_name_trgm ON releases USING GIN (allegro_unaccent(lower(name)) gin_trgm_ops);
+CREATE INDEX idx_recording_piece_name_trgm ON recordings USING GIN (allegro_unaccent(lower(piece_name)) gin_trgm_ops);
@@ -0,0 +1,13 @@
+CREATE TABLE admin (
+    id SERIAL PRIMARY KEY,
+    username VARCHAR(50) UNIQUE NOT NULL
+);
+
+INSERT INTO admin (username)
+    SELECT users.username FROM users
+    JOIN user_roles ON user_roles.user_id = users.id
+    WHERE user_roles.role = 'admin';
+
+DROP TABLE user_roles;
+DROP TABLE role_permissions;
+DROP TABLE roles;
@@ -0,0 +1,56 @@
+CREATE TABLE roles (
+    name VARCHAR(50) PRIMARY KEY,
+    description TEXT
+);
+
+CREATE TABLE role_permissions (
+    role VARCHAR(50) NOT NULL,
+    permission VARCHAR(50) NOT NULL,
+    PRIMARY KEY (role, permission),
+    FOREIGN KEY (role) REFERENCES roles(name) ON DELETE CASCADE
+);
+
+CREATE TABLE user_roles (
+    user_id INTEGER NOT NULL,
+    role VARCHAR(50) NOT NULL,
+    PRIMARY KEY (user_id, role),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (role) REFERENCES roles(name) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_roles_role ON user_roles(role);
+
+-- built-in roles, the guest role applies to requests without a session and grants nothing
+-- until an administrator gives it permissions
+INSERT INTO roles (name, description) VALUES
+    ('admin', 'Full access, including managing users and roles'),
+    ('editor', 'Edit the catalog, upload media and scan the library'),
+    ('listener', 'Browse, search and stream the catalog'),
+    ('guest', 'Requests made without signing in');
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'catalog.read'),
+    ('admin', 'catalog.search'),
+    ('admin', 'catalog.edit'),
+    ('admin', 'media.stream'),
+    ('admin', 'media.upload'),
+    ('admin', 'library.scan'),
+    ('admin', 'users.manage'),
+    ('editor', 'catalog.read'),
+    ('editor', 'catalog.search'),
+    ('editor', 'catalog.edit'),
+    ('editor', 'media.stream'),
+    ('editor', 'media.upload'),
+    ('editor', 'library.scan'),
+    ('listener', 'catalog.read'),
+    ('listener', 'catalog.search'),
+    ('listener', 'media.stream');
+
+-- existing admins keep their access and every other user becomes a listener
+INSERT INTO user_roles (user_id, role)
+    SELECT users.id, 'admin' FROM users JOIN admin ON admin.username = users.username;
+INSERT INTO user_roles (user_id, role)
+    SELECT users.id, 'listener' FROM users
+    WHERE NOT EXISTS (SELECT 1 FROM admin WHERE admin.username = users.username);
+
+DROP TABLE admin;
@@ -0,0 +1,12 @@
+ALTER TABLE users ALTER COLUMN created_at DROP NOT NULL;
+ALTER TABLE users ADD COLUMN session VARCHAR(255);
+
+-- restore the most recent session of each user
+UPDATE users SET session = latest.token, created_at = latest.created_at
+    FROM (
+        SELECT DISTINCT ON (user_id) user_id, token, created_at
+        FROM sessions ORDER BY user_id, created_at DESC
+    ) AS latest
+    WHERE users.id = latest.user_id;
+
+DROP TABLE sessions;
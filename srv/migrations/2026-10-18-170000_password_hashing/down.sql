@@ -0,0 +1,8 @@
+-- hashed tokens cannot be recovered, so every session is ended
+DELETE FROM sessions;
+ALTER TABLE sessions ALTER COLUMN token_hash TYPE VARCHAR(255);
+ALTER TABLE sessions RENAME COLUMN token_hash TO token;
+
+-- Argon2id hashes cannot be converted back, so those users must have their password reset
+UPDATE users SET salt = '' WHERE salt IS NULL;
+ALTER TABLE users ALTER COLUMN salt SET NOT NULL;
@@ -0,0 +1,7 @@
+-- Argon2id hashes carry their own salt, so only legacy PBKDF2 hashes still use this column
+ALTER TABLE users ALTER COLUMN salt DROP NOT NULL;
+
+-- sessions only keep a SHA-256 hash of their token
+ALTER TABLE sessions RENAME COLUMN token TO token_hash;
+UPDATE sessions SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
+ALTER TABLE sessions ALTER COLUMN token_hash TYPE CHAR(64);
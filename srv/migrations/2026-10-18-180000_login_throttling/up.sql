@@ -0,0 +1,24 @@
+-- audit trail of failed logins, kept whether or not the username exists
+CREATE TABLE login_failures (
+    id SERIAL PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    ip_address VARCHAR(64),
+    user_agent TEXT,
+    reason VARCHAR(32) NOT NULL,
+    attempted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_login_failures_username ON login_failures(username);
+CREATE INDEX idx_login_failures_attempted_at ON login_failures(attempted_at);
+
+-- recent failures per username and per address, when the next attempt is allowed, and how many
+-- attempts were refused while waiting, which are counted here rather than audited one by one
+CREATE TABLE login_throttles (
+    scope VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    blocked_until TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    refused INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (scope, subject)
+);
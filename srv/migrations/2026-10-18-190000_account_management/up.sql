@@ -0,0 +1,2 @@
+-- disabled users keep their account and roles but cannot sign in
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1 @@
+DROP TABLE subsonic_passwords;
@@ -0,0 +1,8 @@
+-- Subsonic token authentication needs the password itself, so clients get a separate password
+-- that is stored encrypted rather than hashed
+CREATE TABLE subsonic_passwords (
+    user_id INTEGER PRIMARY KEY,
+    encrypted_password TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+DELETE FROM role_permissions WHERE permission = 'playlists.edit';
+
+DROP TABLE playlist_shares;
+DROP TABLE playlist_entries;
+DROP TABLE playlists;
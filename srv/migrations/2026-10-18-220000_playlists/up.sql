@@ -0,0 +1,44 @@
+-- playlists belong to one user, who may make them public or share them with other users
+CREATE TABLE playlists (
+    id SERIAL PRIMARY KEY,
+    owner_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    public BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_playlists_owner_id ON playlists(owner_id);
+
+-- a recording may appear in a playlist more than once, so entries have ids of their own, and
+-- positions are checked at the end of each statement so that entries can be renumbered at once
+CREATE TABLE playlist_entries (
+    id SERIAL PRIMARY KEY,
+    playlist_id INTEGER NOT NULL,
+    recording_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    added_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (playlist_id, position) DEFERRABLE,
+    FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
+    FOREIGN KEY (recording_id) REFERENCES recordings(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_playlist_entries_recording_id ON playlist_entries(recording_id);
+
+CREATE TABLE playlist_shares (
+    playlist_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    PRIMARY KEY (playlist_id, user_id),
+    FOREIGN KEY (playlist_id) REFERENCES playlists(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_playlist_shares_user_id ON playlist_shares(user_id);
+
+-- everyone who signs in may keep playlists, guests may at most view public ones
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'playlists.edit'),
+    ('editor', 'playlists.edit'),
+    ('listener', 'playlists.edit');
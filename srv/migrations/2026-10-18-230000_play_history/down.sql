@@ -0,0 +1,2 @@
+DROP TABLE play_events;
+DROP TABLE plays;
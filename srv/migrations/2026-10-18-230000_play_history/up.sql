@@ -0,0 +1,27 @@
+-- each time a user plays a recording, kept up to date as their client reports playback
+CREATE TABLE plays (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    recording_id INTEGER NOT NULL,
+    state VARCHAR(16) NOT NULL DEFAULT 'playing',
+    listened_seconds INTEGER NOT NULL DEFAULT 0,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (recording_id) REFERENCES recordings(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_plays_user_id_started_at ON plays(user_id, started_at);
+CREATE INDEX idx_plays_recording_id ON plays(recording_id);
+
+-- every playback event reported for a play
+CREATE TABLE play_events (
+    id SERIAL PRIMARY KEY,
+    play_id INTEGER NOT NULL,
+    event VARCHAR(16) NOT NULL,
+    position_seconds INTEGER NOT NULL,
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (play_id) REFERENCES plays(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_play_events_play_id ON play_events(play_id);
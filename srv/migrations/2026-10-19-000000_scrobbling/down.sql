@@ -0,0 +1,2 @@
+DROP TABLE scrobble_outbox;
+DROP TABLE scrobble_targets;
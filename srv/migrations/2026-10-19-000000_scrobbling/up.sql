@@ -0,0 +1,28 @@
+-- where users forward their plays, with the token they gave the ListenBrainz-compatible server
+-- stored encrypted since it has to be sent on
+CREATE TABLE scrobble_targets (
+    user_id INTEGER PRIMARY KEY,
+    submit_url VARCHAR(2048) NOT NULL,
+    encrypted_token TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    last_submitted_at TIMESTAMP WITH TIME ZONE,
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- counted plays waiting to be submitted, kept until the target accepts them
+CREATE TABLE scrobble_outbox (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    play_id INTEGER NOT NULL UNIQUE,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES scrobble_targets(user_id) ON DELETE CASCADE,
+    FOREIGN KEY (play_id) REFERENCES plays(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_scrobble_outbox_user_id_next_attempt_at ON scrobble_outbox(user_id, next_attempt_at);
@@ -0,0 +1,3 @@
+DELETE FROM role_permissions WHERE permission = 'library.edit';
+
+DROP TABLE library_entries;
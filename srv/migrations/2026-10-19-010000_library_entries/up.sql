@@ -0,0 +1,38 @@
+-- what each user has starred or rated, with exactly one catalog entry per row so that deleting
+-- the entry takes its stars and ratings with it
+CREATE TABLE library_entries (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    recording_id INTEGER,
+    release_id INTEGER,
+    piece_id INTEGER,
+    performer_id INTEGER,
+    composer_id INTEGER,
+    songwriter_id INTEGER,
+    favorited_at TIMESTAMP WITH TIME ZONE,
+    rating SMALLINT CHECK (rating BETWEEN 1 AND 5),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (recording_id) REFERENCES recordings(id) ON DELETE CASCADE,
+    FOREIGN KEY (release_id) REFERENCES releases(id) ON DELETE CASCADE,
+    FOREIGN KEY (piece_id) REFERENCES pieces(id) ON DELETE CASCADE,
+    FOREIGN KEY (performer_id) REFERENCES performers(id) ON DELETE CASCADE,
+    FOREIGN KEY (composer_id) REFERENCES composers(id) ON DELETE CASCADE,
+    FOREIGN KEY (songwriter_id) REFERENCES songwriters(id) ON DELETE CASCADE,
+    UNIQUE (user_id, recording_id),
+    UNIQUE (user_id, release_id),
+    UNIQUE (user_id, piece_id),
+    UNIQUE (user_id, performer_id),
+    UNIQUE (user_id, composer_id),
+    UNIQUE (user_id, songwriter_id),
+    CHECK (num_nonnulls(recording_id, release_id, piece_id, performer_id, composer_id, songwriter_id) = 1)
+);
+
+CREATE INDEX idx_library_entries_user_id_favorited_at ON library_entries(user_id, favorited_at);
+
+-- everyone who signs in may star and rate, guests may at most read
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'library.edit'),
+    ('editor', 'library.edit'),
+    ('listener', 'library.edit');
@@ -1,5 +1,8 @@
+use crate::api::auth::Authorized;
+use crate::error::{ApiError, UnknownIds};
 use crate::insert;
-use crate::models::{Admin, User};
+use crate::media::{MediaKind, MediaName};
+use crate::permission::requires;
 use crate::Response;
 
 use actix_web::web::{Data, Json};
@@ -15,7 +18,6 @@ pub struct AddArtistRequest {
     pub description: Option<String>,
     pub has_image: bool,
     pub artist_type: String,
-    pub token: String,
 }
 
 /// A request to add a release
@@ -25,7 +27,6 @@ pub struct AddReleaseRequest {
     pub performer_ids: Vec<i32>,
     pub description: Option<String>,
     pub has_image: bool,
-    pub token: String,
 }
 
 /// A request to add a piece
@@ -36,7 +37,6 @@ pub struct AddPieceRequest {
     pub composer_ids: Vec<i32>,
     pub songwriter_ids: Option<Vec<i32>>,
     pub description: Option<String>,
-    pub token: String,
 }
 
 /// A request to add a recording
@@ -46,233 +46,249 @@ pub struct AddRecordingRequest {
     pub release_id: i32,
     pub performer_ids: Vec<i32>,
     pub track_number: i32,
-    pub token: String,
 }
 
-/// Given a token, return whether the user is an admin
-fn check_admin(
-    token: String,
+/// Return the requested ids that were not found, without duplicates
+pub(crate) fn missing_ids(requested: &[i32], found: &[i32]) -> Vec<i32> {
+    let mut missing: Vec<i32> = requested
+        .iter()
+        .filter(|id| !found.contains(id))
+        .copied()
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    missing
+}
+
+/// Find which of the given performer ids do not exist
+pub(crate) fn db_unknownperformers(
+    performer_ids: &[i32],
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> bool {
-    use crate::schema::admin;
-    use crate::schema::users;
-
-    // check if the token is valid
-    let user_res = users::dsl::users
-        .filter(users::dsl::session.eq(Some(token)))
-        .first::<User>(conn);
-    let user: User = match user_res {
-        Ok(_) => user_res.unwrap(),
-        Err(_) => {
-            return false;
-        }
-    };
+) -> QueryResult<Vec<i32>> {
+    use crate::schema::performers;
 
-    // check if the user is an admin
-    let admin_user_res = admin::dsl::admin
-        .filter(admin::dsl::username.eq(&user.username))
-        .first::<Admin>(conn);
-    match admin_user_res {
-        Ok(_) => (),
-        Err(_) => {
-            return false;
-        }
-    };
+    let found: Vec<i32> = performers::dsl::performers
+        .filter(performers::dsl::id.eq_any(performer_ids))
+        .select(performers::dsl::id)
+        .load::<i32>(conn)?;
+    Ok(missing_ids(performer_ids, &found))
+}
+
+/// Find which of the given composer ids do not exist
+pub(crate) fn db_unknowncomposers(
+    composer_ids: &[i32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<Vec<i32>> {
+    use crate::schema::composers;
+
+    let found: Vec<i32> = composers::dsl::composers
+        .filter(composers::dsl::id.eq_any(composer_ids))
+        .select(composers::dsl::id)
+        .load::<i32>(conn)?;
+    Ok(missing_ids(composer_ids, &found))
+}
+
+/// Find which of the given songwriter ids do not exist
+pub(crate) fn db_unknownsongwriters(
+    songwriter_ids: &[i32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<Vec<i32>> {
+    use crate::schema::songwriters;
 
-    return true;
+    let found: Vec<i32> = songwriters::dsl::songwriters
+        .filter(songwriters::dsl::id.eq_any(songwriter_ids))
+        .select(songwriters::dsl::id)
+        .load::<i32>(conn)?;
+    Ok(missing_ids(songwriter_ids, &found))
 }
 
-/// Add a recording to the database, returning the file path of the new recording
+/// Add a recording to the database, returning the name its audio file should be uploaded as
 fn db_addrecording<T>(
     addrecording_req: AddRecordingRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
-    use crate::schema::{pieces, recording_performers, recordings};
-
-    // check for admin privileges
-    let is_admin: bool = check_admin(addrecording_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: "User is not an admin".to_string(),
+) -> Result<String, ApiError> {
+    use crate::schema::{pieces, recording_performers, recordings, releases};
+
+    conn.transaction::<String, ApiError, _>(|conn| {
+        // validate every referenced id before inserting anything
+        let mut unknown_ids = UnknownIds::default();
+        let piece_name: Option<String> = pieces::dsl::pieces
+            .find(addrecording_req.piece_id)
+            .select(pieces::dsl::name)
+            .first::<String>(conn)
+            .optional()?;
+        if piece_name.is_none() {
+            unknown_ids.piece_ids.push(addrecording_req.piece_id);
+        }
+        let release_found: Option<i32> = releases::dsl::releases
+            .find(addrecording_req.release_id)
+            .select(releases::dsl::id)
+            .first::<i32>(conn)
+            .optional()?;
+        if release_found.is_none() {
+            unknown_ids.release_ids.push(addrecording_req.release_id);
+        }
+        unknown_ids.performer_ids = db_unknownperformers(&addrecording_req.performer_ids, conn)?;
+        let piece_name: String = match piece_name {
+            Some(piece_name) if unknown_ids.is_empty() => piece_name,
+            _ => return Err(ApiError::UnknownIds(Box::new(unknown_ids))),
         };
-    }
 
-    // get the piece name from the id
-    let piece_name_res = pieces::dsl::pieces
-        .filter(pieces::dsl::id.eq(addrecording_req.piece_id))
-        .select(pieces::dsl::name)
-        .first::<String>(conn);
-    let piece_name: String = match piece_name_res {
-        Ok(_) => piece_name_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: "Piece id incorrect or piece not found".to_string(),
-            };
-        }
-    };
+        // insert the new recording into the database
+        let new_recording = insert::NewRecording {
+            piece_name,
+            piece_id: addrecording_req.piece_id,
+            release_id: addrecording_req.release_id,
+            track_number: addrecording_req.track_number,
+            file_path: None, // set once the audio file has been uploaded
+            file_size: None,
+            file_modified: None,
+            file_hash: None,
+        };
 
-    // insert the new recording into the database
-    let new_recording = insert::NewRecording {
-        piece_name,
-        piece_id: addrecording_req.piece_id,
-        release_id: addrecording_req.release_id,
-        track_number: addrecording_req.track_number,
-        file_path: None, // initially set to None, will update after getting ID
-    };
+        // insert recording and get its ID
+        let recording_id: i32 = diesel::insert_into(recordings::dsl::recordings)
+            .values(&new_recording)
+            .returning(recordings::dsl::id)
+            .get_result(conn)?;
 
-    // insert recording and get its ID
-    let recording_id: i32 = diesel::insert_into(recordings::dsl::recordings)
-        .values(&new_recording)
-        .returning(recordings::dsl::id)
-        .get_result(conn)
-        .unwrap();
-
-    // generate the file path using the recording ID
-    let new_file_path = format!("recording-{}", recording_id);
-
-    // update the recording with the generated file path
-    diesel::update(recordings::dsl::recordings.find(recording_id))
-        .set(recordings::dsl::file_path.eq(&new_file_path))
-        .execute(conn)
-        .unwrap();
-
-    // insert performer relationships
-    for performer_id in addrecording_req.performer_ids {
-        let _ = diesel::insert_into(recording_performers::table)
-            .values((
-                recording_performers::recording_id.eq(recording_id),
-                recording_performers::performer_id.eq(performer_id),
-            ))
-            .execute(conn);
-    }
+        // insert performer relationships
+        for performer_id in addrecording_req.performer_ids {
+            diesel::insert_into(recording_performers::table)
+                .values((
+                    recording_performers::recording_id.eq(recording_id),
+                    recording_performers::performer_id.eq(performer_id),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
 
-    Response {
-        success: true,
-        message: new_file_path,
-    }
+        // generate the file name using the recording ID
+        Ok(MediaName {
+            kind: MediaKind::Recording,
+            id: recording_id,
+        }
+        .to_string())
+    })
 }
 
 /// Add a piece to the database
 fn db_addpiece<T>(
     addpiece_req: AddPieceRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
+) -> Result<String, ApiError> {
     use crate::schema::{piece_composers, piece_songwriters, pieces};
 
-    // check for admin privileges
-    let is_admin: bool = check_admin(addpiece_req.token, conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: "User is not an admin".to_string(),
+    let songwriter_ids: Vec<i32> = addpiece_req.songwriter_ids.unwrap_or_default();
+    conn.transaction::<String, ApiError, _>(|conn| {
+        // validate every referenced id before inserting anything
+        let unknown_ids = UnknownIds {
+            composer_ids: db_unknowncomposers(&addpiece_req.composer_ids, conn)?,
+            songwriter_ids: db_unknownsongwriters(&songwriter_ids, conn)?,
+            ..Default::default()
         };
-    }
+        if !unknown_ids.is_empty() {
+            return Err(ApiError::UnknownIds(Box::new(unknown_ids)));
+        }
 
-    // insert the new piece into the database
-    let new_piece = insert::NewPiece {
-        name: addpiece_req.name,
-        movements: addpiece_req.movements,
-        description: addpiece_req.description,
-    };
+        // insert the new piece into the database
+        let new_piece = insert::NewPiece {
+            name: addpiece_req.name,
+            movements: addpiece_req.movements,
+            description: addpiece_req.description,
+        };
 
-    // insert piece and get its ID
-    let piece_id: i32 = diesel::insert_into(pieces::dsl::pieces)
-        .values(&new_piece)
-        .returning(pieces::dsl::id)
-        .get_result(conn)
-        .unwrap();
-
-    // insert composer relationships
-    for composer_id in addpiece_req.composer_ids {
-        let _ = diesel::insert_into(piece_composers::table)
-            .values((
-                piece_composers::piece_id.eq(piece_id),
-                piece_composers::composer_id.eq(composer_id),
-            ))
-            .execute(conn);
-    }
+        // insert piece and get its ID
+        let piece_id: i32 = diesel::insert_into(pieces::dsl::pieces)
+            .values(&new_piece)
+            .returning(pieces::dsl::id)
+            .get_result(conn)?;
+
+        // insert composer relationships
+        for composer_id in addpiece_req.composer_ids {
+            diesel::insert_into(piece_composers::table)
+                .values((
+                    piece_composers::piece_id.eq(piece_id),
+                    piece_composers::composer_id.eq(composer_id),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
 
-    // insert songwriter relationships if they exist
-    if let Some(songwriter_ids) = addpiece_req.songwriter_ids {
+        // insert songwriter relationships if they exist
         for songwriter_id in songwriter_ids {
-            let _ = diesel::insert_into(piece_songwriters::table)
+            diesel::insert_into(piece_songwriters::table)
                 .values((
                     piece_songwriters::piece_id.eq(piece_id),
                     piece_songwriters::songwriter_id.eq(songwriter_id),
                 ))
-                .execute(conn);
+                .on_conflict_do_nothing()
+                .execute(conn)?;
         }
-    }
 
-    Response {
-        success: true,
-        message: String::new(),
-    }
+        Ok(String::new())
+    })
 }
 
 /// Add a release to the database
 fn db_addrelease<T>(
     addrelease_req: AddReleaseRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
+) -> Result<String, ApiError> {
     use crate::schema::{release_performers, releases};
 
-    // check for admin privileges
-    let is_admin: bool = check_admin(addrelease_req.token, conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: "User is not an admin".to_string(),
+    conn.transaction::<String, ApiError, _>(|conn| {
+        // validate every referenced id before inserting anything
+        let unknown_ids = UnknownIds {
+            performer_ids: db_unknownperformers(&addrelease_req.performer_ids, conn)?,
+            ..Default::default()
         };
-    }
+        if !unknown_ids.is_empty() {
+            return Err(ApiError::UnknownIds(Box::new(unknown_ids)));
+        }
 
-    // insert the new release into the database
-    let new_release = insert::NewRelease {
-        name: addrelease_req.name,
-        description: addrelease_req.description,
-        image_path: None,
-    };
+        // insert the new release into the database
+        let new_release = insert::NewRelease {
+            name: addrelease_req.name,
+            description: addrelease_req.description,
+            image_path: None,
+        };
 
-    // insert release and get its ID
-    let release_id: i32 = diesel::insert_into(releases::dsl::releases)
-        .values(&new_release)
-        .returning(releases::dsl::id)
-        .get_result(conn)
-        .unwrap();
-
-    // insert performer relationships
-    for performer_id in addrelease_req.performer_ids {
-        let _ = diesel::insert_into(release_performers::table)
-            .values((
-                release_performers::release_id.eq(release_id),
-                release_performers::performer_id.eq(performer_id),
-            ))
-            .execute(conn);
-    }
+        // insert release and get its ID
+        let release_id: i32 = diesel::insert_into(releases::dsl::releases)
+            .values(&new_release)
+            .returning(releases::dsl::id)
+            .get_result(conn)?;
 
-    // handle image path
-    let mut new_image_path = String::new();
-    if addrelease_req.has_image {
-        new_image_path = format!("release-{}", release_id);
-        diesel::update(releases::dsl::releases.find(release_id))
-            .set(releases::dsl::image_path.eq(new_image_path.clone()))
-            .execute(conn)
-            .unwrap();
-    }
+        // insert performer relationships
+        for performer_id in addrelease_req.performer_ids {
+            diesel::insert_into(release_performers::table)
+                .values((
+                    release_performers::release_id.eq(release_id),
+                    release_performers::performer_id.eq(performer_id),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
 
-    Response {
-        success: true,
-        message: new_image_path,
-    }
+        // image name is release-[release id], and is set once the image has been uploaded
+        let mut new_image_path = String::new();
+        if addrelease_req.has_image {
+            new_image_path = MediaName {
+                kind: MediaKind::Release,
+                id: release_id,
+            }
+            .to_string();
+        }
+        Ok(new_image_path)
+    })
 }
 
-/// Add a performer to the database, and return the image path of the performer if it exists
+/// Add a performer to the database, and return the name its image should be uploaded as
 fn db_addperformer(
     addartist_req: AddArtistRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
+) -> Result<String, ApiError> {
     use crate::schema::performers;
 
     // insert the new performer into the database
@@ -284,28 +300,25 @@ fn db_addperformer(
     let performer_id: i32 = diesel::insert_into(performers::dsl::performers)
         .values(&new_performer)
         .returning(performers::dsl::id)
-        .get_result(conn)
-        .unwrap();
+        .get_result(conn)?;
 
-    // actual image path is performer-[performer id]
+    // image name is performer-[performer id], and is set once the image has been uploaded
     let mut new_image_path = String::new();
     if addartist_req.has_image {
-        new_image_path = format!("performer-{}", performer_id);
-        diesel::update(performers::dsl::performers.find(performer_id))
-            .set(performers::dsl::image_path.eq(new_image_path.clone()))
-            .execute(conn)
-            .unwrap();
-    }
-    Response {
-        success: true,
-        message: new_image_path,
+        new_image_path = MediaName {
+            kind: MediaKind::Performer,
+            id: performer_id,
+        }
+        .to_string();
     }
+    Ok(new_image_path)
 }
-/// Add a composer to the database, and return the image path of the composer if it exists
+
+/// Add a composer to the database, and return the name its image should be uploaded as
 fn db_addcomposer(
     addartist_req: AddArtistRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
+) -> Result<String, ApiError> {
     use crate::schema::composers;
 
     // insert the new composer into the database
@@ -317,29 +330,25 @@ fn db_addcomposer(
     let composer_id: i32 = diesel::insert_into(composers::dsl::composers)
         .values(&new_composer)
         .returning(composers::dsl::id)
-        .get_result::<i32>(conn)
-        .unwrap();
+        .get_result::<i32>(conn)?;
 
-    // actual image path is composer-[composer id]
+    // image name is composer-[composer id], and is set once the image has been uploaded
     let mut new_image_path = String::new();
     if addartist_req.has_image {
-        new_image_path = format!("composer-{}", composer_id);
-        diesel::update(composers::dsl::composers.find(composer_id))
-            .set(composers::dsl::image_path.eq(new_image_path.clone()))
-            .execute(conn)
-            .unwrap();
-    }
-    Response {
-        success: true,
-        message: new_image_path,
+        new_image_path = MediaName {
+            kind: MediaKind::Composer,
+            id: composer_id,
+        }
+        .to_string();
     }
+    Ok(new_image_path)
 }
 
-/// Add a songwriter to the database, and return the image path of the songwriter if it exists
+/// Add a songwriter to the database, and return the name its image should be uploaded as
 fn db_addsongwriter(
     addartist_req: AddArtistRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
+) -> Result<String, ApiError> {
     use crate::schema::songwriters;
 
     // insert the new songwriter into the database
@@ -351,157 +360,324 @@ fn db_addsongwriter(
     let songwriter_id: i32 = diesel::insert_into(songwriters::dsl::songwriters)
         .values(&new_songwriter)
         .returning(songwriters::dsl::id)
-        .get_result::<i32>(conn)
-        .unwrap();
+        .get_result::<i32>(conn)?;
 
-    // actual image path is songwriter-[songwriter id]
+    // image name is songwriter-[songwriter id], and is set once the image has been uploaded
     let mut new_image_path = String::new();
     if addartist_req.has_image {
-        new_image_path = format!("songwriter-{}", songwriter_id);
-        diesel::update(songwriters::dsl::songwriters.find(songwriter_id))
-            .set(songwriters::dsl::image_path.eq(new_image_path.clone()))
-            .execute(conn)
-            .unwrap();
-    }
-    Response {
-        success: true,
-        message: new_image_path,
+        new_image_path = MediaName {
+            kind: MediaKind::Songwriter,
+            id: songwriter_id,
+        }
+        .to_string();
     }
+    Ok(new_image_path)
 }
 
 /// Add an artist to the database, and return the image path of the artist if it exists
 fn db_addartist<T>(
     addartist_req: AddArtistRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
-    // check for admin privileges
-    let is_admin: bool = check_admin(addartist_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: "User is not an admin".to_string(),
-        };
-    }
-
+) -> Result<String, ApiError> {
     // determine the type of artist to add
-    if addartist_req.artist_type == "performer" {
-        return db_addperformer(addartist_req, conn);
-    } else if addartist_req.artist_type == "composer" {
-        return db_addcomposer(addartist_req, conn);
-    } else if addartist_req.artist_type == "songwriter" {
-        return db_addsongwriter(addartist_req, conn);
-    } else {
-        return Response {
-            success: false,
-            message: "Invalid artist type".to_string(),
-        };
-    }
+    let add_fn = match addartist_req.artist_type.as_str() {
+        "performer" => db_addperformer,
+        "composer" => db_addcomposer,
+        "songwriter" => db_addsongwriter,
+        _ => return Err(ApiError::Validation("Invalid artist type".to_string())),
+    };
+    conn.transaction::<String, ApiError, _>(|conn| add_fn(addartist_req, conn))
 }
 
 /// Add a piece to the database
 #[post("/music/add/piece")]
 pub async fn addpiece(
+    _caller: Authorized<requires::CatalogEdit>,
     addpiece_req: Json<AddPieceRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
-    // get the addpiece response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let addpiece_response =
-        web::block(move || db_addpiece::<String>(addpiece_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match addpiece_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+) -> Result<HttpResponse, ApiError> {
+    // add the entry to the database
+    let mut conn = pool.get()?;
+    let media_name =
+        web::block(move || db_addpiece::<String>(addpiece_req.into_inner(), &mut conn)).await??;
+
+    // return the name the entry's media should be uploaded as
+    Ok(HttpResponse::Created().json(Response::success(media_name)))
 }
 
 /// Add an releases to the database
 #[post("/music/add/release")]
 pub async fn addrelease(
+    _caller: Authorized<requires::CatalogEdit>,
     addrelease_req: Json<AddReleaseRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
-    // get the addrelease response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let addrelease_response =
-        web::block(move || db_addrelease::<String>(addrelease_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match addrelease_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+) -> Result<HttpResponse, ApiError> {
+    // add the entry to the database
+    let mut conn = pool.get()?;
+    let media_name =
+        web::block(move || db_addrelease::<String>(addrelease_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the name the entry's media should be uploaded as
+    Ok(HttpResponse::Created().json(Response::success(media_name)))
 }
 
 /// Add a recording to the database
 #[post("/music/add/recording")]
 pub async fn addrecording(
+    _caller: Authorized<requires::CatalogEdit>,
     addrecording_req: Json<AddRecordingRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
-    // get the addrecording response from database
-    println!("reached 1");
-    let mut conn = pool.get().expect("Connection pool error");
-    let addrecording_response =
+) -> Result<HttpResponse, ApiError> {
+    // add the entry to the database
+    let mut conn = pool.get()?;
+    let media_name =
         web::block(move || db_addrecording::<String>(addrecording_req.into_inner(), &mut conn))
-            .await;
-
-    println!("reached 2");
+            .await??;
 
-    // return the appropriate response and handle errors
-    match addrecording_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    // return the name the entry's media should be uploaded as
+    Ok(HttpResponse::Created().json(Response::success(media_name)))
 }
 
 /// Add an artist to the database
 #[post("/music/add/artist")]
 pub async fn addartist(
+    _caller: Authorized<requires::CatalogEdit>,
     addartist_req: Json<AddArtistRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
-    // get the addartist response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let addartist_response =
-        web::block(move || db_addartist::<String>(addartist_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match addartist_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
+) -> Result<HttpResponse, ApiError> {
+    // add the entry to the database
+    let mut conn = pool.get()?;
+    let media_name =
+        web::block(move || db_addartist::<String>(addartist_req.into_inner(), &mut conn)).await??;
+
+    // return the name the entry's media should be uploaded as
+    Ok(HttpResponse::Created().json(Response::success(media_name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorBody;
+    use crate::testing::connect;
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+
+    /// Add a performer credited on nothing
+    fn performer(name: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::performers;
+
+        diesel::insert_into(performers::table)
+            .values(performers::name.eq(name))
+            .returning(performers::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Add a composer credited on nothing
+    fn composer(name: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::composers;
+
+        diesel::insert_into(composers::table)
+            .values(composers::name.eq(name))
+            .returning(composers::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// The number of pieces, releases and recordings in the catalog
+    fn counts(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> (i64, i64, i64) {
+        use crate::schema::{pieces, recordings, releases};
+
+        (
+            pieces::table.count().get_result(conn).unwrap(),
+            releases::table.count().get_result(conn).unwrap(),
+            recordings::table.count().get_result(conn).unwrap(),
+        )
+    }
+
+    /// The ids listed by an error, if it is an unknown ids error
+    fn unknown_ids(err: ApiError) -> UnknownIds {
+        match err {
+            ApiError::UnknownIds(unknown_ids) => *unknown_ids,
+            err => panic!("expected unknown ids, got {:?}", err),
         }
     }
+
+    #[test]
+    fn lists_each_missing_id_once_in_order() {
+        assert_eq!(missing_ids(&[9, 3, 5, 9, 1, 3], &[5, 1]), vec![3, 9]);
+        assert_eq!(missing_ids(&[4, 2], &[2, 4, 6]), Vec::<i32>::new());
+        assert_eq!(missing_ids(&[], &[1]), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn adds_entries_with_their_credits() {
+        use crate::schema::{piece_composers, recording_performers, release_performers};
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let performer_id: i32 = performer("Martha Argerich", &mut conn);
+        let composer_id: i32 = composer("Frédéric Chopin", &mut conn);
+
+        let piece = AddPieceRequest {
+            name: "Piano Concerto No. 1".to_string(),
+            movements: Some(3),
+            composer_ids: vec![composer_id, composer_id],
+            songwriter_ids: None,
+            description: None,
+        };
+        assert_eq!(db_addpiece::<String>(piece, &mut conn).unwrap(), "");
+        let piece_id: i32 = piece_composers::table
+            .filter(piece_composers::composer_id.eq(composer_id))
+            .select(piece_composers::piece_id)
+            .first(&mut conn)
+            .unwrap();
+
+        let release = AddReleaseRequest {
+            name: "Chopin: Piano Concertos".to_string(),
+            performer_ids: vec![performer_id],
+            description: None,
+            has_image: true,
+        };
+        let image_name: String = db_addrelease::<String>(release, &mut conn).unwrap();
+        let release_id: i32 = release_performers::table
+            .filter(release_performers::performer_id.eq(performer_id))
+            .select(release_performers::release_id)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(image_name, format!("release-{}", release_id));
+
+        let recording = AddRecordingRequest {
+            piece_id,
+            release_id,
+            performer_ids: vec![performer_id, performer_id],
+            track_number: 1,
+        };
+        let file_name: String = db_addrecording::<String>(recording, &mut conn).unwrap();
+        let recording_id: i32 = file_name
+            .strip_prefix("recording-")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let performers: Vec<i32> = recording_performers::table
+            .filter(recording_performers::recording_id.eq(recording_id))
+            .select(recording_performers::performer_id)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(performers, vec![performer_id]);
+    }
+
+    #[test]
+    fn adds_nothing_when_ids_are_unknown() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let performer_id: i32 = performer("Martha Argerich", &mut conn);
+        let composer_id: i32 = composer("Frédéric Chopin", &mut conn);
+        let before: (i64, i64, i64) = counts(&mut conn);
+
+        let piece = AddPieceRequest {
+            name: "Ballade No. 1".to_string(),
+            movements: None,
+            composer_ids: vec![composer_id, -2, -1, -2],
+            songwriter_ids: Some(vec![-3]),
+            description: None,
+        };
+        let unknown: UnknownIds = unknown_ids(db_addpiece::<String>(piece, &mut conn).unwrap_err());
+        assert_eq!(unknown.composer_ids, vec![-2, -1]);
+        assert_eq!(unknown.songwriter_ids, vec![-3]);
+
+        let release = AddReleaseRequest {
+            name: "Chopin: Ballades".to_string(),
+            performer_ids: vec![performer_id, -4],
+            description: None,
+            has_image: false,
+        };
+        let unknown: UnknownIds =
+            unknown_ids(db_addrelease::<String>(release, &mut conn).unwrap_err());
+        assert_eq!(unknown.performer_ids, vec![-4]);
+
+        let recording = AddRecordingRequest {
+            piece_id: -5,
+            release_id: -6,
+            performer_ids: vec![performer_id, -7],
+            track_number: 1,
+        };
+        let unknown: UnknownIds =
+            unknown_ids(db_addrecording::<String>(recording, &mut conn).unwrap_err());
+        assert_eq!(unknown.piece_ids, vec![-5]);
+        assert_eq!(unknown.release_ids, vec![-6]);
+        assert_eq!(unknown.performer_ids, vec![-7]);
+
+        assert_eq!(counts(&mut conn), before);
+    }
+
+    #[test]
+    fn rolls_back_a_recording_that_conflicts() {
+        use crate::schema::{pieces, recording_performers, releases};
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let performer_id: i32 = performer("Martha Argerich", &mut conn);
+        let piece_id: i32 = diesel::insert_into(pieces::table)
+            .values(pieces::name.eq("Scherzo No. 2"))
+            .returning(pieces::id)
+            .get_result(&mut conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("Chopin Recital"))
+            .returning(releases::id)
+            .get_result(&mut conn)
+            .unwrap();
+        let recording = AddRecordingRequest {
+            piece_id,
+            release_id,
+            performer_ids: vec![performer_id],
+            track_number: 1,
+        };
+        db_addrecording::<String>(recording.clone(), &mut conn).unwrap();
+        let before: (i64, i64, i64) = counts(&mut conn);
+
+        // the track is taken, so nothing of the second recording is kept
+        let err: ApiError = db_addrecording::<String>(recording.clone(), &mut conn).unwrap_err();
+        assert!(matches!(err, ApiError::Conflict(_)));
+        assert_eq!(counts(&mut conn), before);
+        let credits: i64 = recording_performers::table
+            .filter(recording_performers::performer_id.eq(performer_id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(credits, 1);
+
+        // the connection is still usable once the failed insert is rolled back
+        let recording = AddRecordingRequest {
+            track_number: 2,
+            ..recording
+        };
+        db_addrecording::<String>(recording, &mut conn).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn responds_with_the_unknown_ids() {
+        let err = ApiError::UnknownIds(Box::new(UnknownIds {
+            performer_ids: vec![4, 7],
+            ..Default::default()
+        }));
+        let res: HttpResponse = err.error_response();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            value["unknown_ids"],
+            serde_json::json!({ "performer_ids": [4, 7] })
+        );
+        let body: ErrorBody = serde_json::from_value(value).unwrap();
+        assert!(!body.success);
+        assert_eq!(body.error, "unknown_ids");
+    }
 }
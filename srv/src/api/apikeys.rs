@@ -0,0 +1,461 @@
+use crate::api::auth::{require_permission, Authorized, Caller};
+use crate::error::ApiError;
+use crate::insert;
+use crate::models::{DbApiKey, User};
+use crate::password;
+use crate::permission::{requires, ApiKeyScope, Permission};
+use crate::Response;
+
+use actix_web::web::{Data, Json, Query};
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Serialize};
+
+/// Start of every API key, telling them apart from session tokens
+pub const API_KEY_PREFIX: &str = "ak_";
+
+// characters of a key kept in the clear so its owner can recognise it
+const SHOWN_PREFIX_LEN: usize = 11;
+
+// longest name an API key can be given
+const MAX_NAME_LEN: usize = 100;
+
+// minimum number of seconds between updates of when a key was last used
+const LAST_USED_INTERVAL: i64 = 60;
+
+/// A request to create an API key for the current user
+#[derive(Deserialize, Serialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scope: ApiKeyScope,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// A request for the API keys of the current user, or of another user for an administrator
+#[derive(Deserialize, Serialize)]
+pub struct ApiKeysRequest {
+    pub user_id: Option<i32>,
+}
+
+/// A request to revoke an API key
+#[derive(Deserialize, Serialize)]
+pub struct RevokeApiKeyRequest {
+    pub id: i32,
+}
+
+/// An API key as shown to its owner, without the key itself
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub scope: Option<ApiKeyScope>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl From<DbApiKey> for ApiKey {
+    fn from(api_key: DbApiKey) -> Self {
+        ApiKey {
+            id: api_key.id,
+            user_id: api_key.user_id,
+            name: api_key.name,
+            prefix: api_key.prefix,
+            scope: ApiKeyScope::parse(&api_key.scope),
+            created_at: api_key.created_at,
+            last_used_at: api_key.last_used_at,
+            expires_at: api_key.expires_at,
+        }
+    }
+}
+
+/// A newly created API key, the only time the key itself is shown
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+/// Get the unexpired API key matching a key along with its user, noting when it was used
+pub(crate) fn require_apikey(
+    key: &str,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(DbApiKey, User), ApiError> {
+    use crate::schema::{api_keys, users};
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    let (mut api_key, user): (DbApiKey, User) = api_keys::dsl::api_keys
+        .inner_join(users::dsl::users)
+        .filter(api_keys::dsl::key_hash.eq(password::hash_token(key)))
+        .filter(
+            api_keys::dsl::expires_at
+                .is_null()
+                .or(api_keys::dsl::expires_at.gt(now)),
+        )
+        .filter(users::dsl::disabled_at.is_null())
+        .first::<(DbApiKey, User)>(conn)
+        .optional()?
+        .ok_or_else(ApiError::unauthorized)?;
+
+    // scripts make many requests in a row, so only write the time of use once per interval
+    let stale: bool = api_key
+        .last_used_at
+        .is_none_or(|last_used_at| now - last_used_at >= Duration::seconds(LAST_USED_INTERVAL));
+    if stale {
+        api_key.last_used_at = Some(now);
+        diesel::update(api_keys::dsl::api_keys.filter(api_keys::dsl::id.eq(api_key.id)))
+            .set(api_keys::dsl::last_used_at.eq(now))
+            .execute(conn)?;
+    }
+    Ok((api_key, user))
+}
+
+/// Create an API key for the caller, returning the key alongside what is stored about it
+fn db_createapikey(
+    caller: Caller,
+    create_req: CreateApiKeyRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<CreatedApiKey, ApiError> {
+    use crate::schema::api_keys;
+
+    // keys can only be made from a session, so one key cannot mint another
+    let (_, user) = caller.require_session()?;
+    let name: String = create_req.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(ApiError::Validation(format!(
+            "Name must be between 1 and {} characters",
+            MAX_NAME_LEN
+        )));
+    }
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    if create_req
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now)
+    {
+        return Err(ApiError::Validation(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+
+    let key: String = format!("{}{}", API_KEY_PREFIX, password::generate_token()?);
+    let new_api_key = insert::NewApiKey {
+        user_id: user.id,
+        name,
+        key_hash: password::hash_token(&key),
+        prefix: key.chars().take(SHOWN_PREFIX_LEN).collect(),
+        scope: create_req.scope.as_str().to_string(),
+        expires_at: create_req.expires_at,
+    };
+    let api_key: DbApiKey = diesel::insert_into(api_keys::dsl::api_keys)
+        .values(&new_api_key)
+        .get_result::<DbApiKey>(conn)?;
+    Ok(CreatedApiKey {
+        key,
+        api_key: api_key.into(),
+    })
+}
+
+/// Get the API keys of the caller, or of any user for an administrator, newest first
+fn db_listapikeys(
+    caller: Caller,
+    keys_req: ApiKeysRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<ApiKey>, ApiError> {
+    use crate::schema::api_keys;
+
+    let (_, user) = caller.require_session()?;
+    let user_id: i32 = match keys_req.user_id {
+        Some(user_id) if user_id != user.id => {
+            require_permission(&caller, Permission::UsersManage, conn)?;
+            user_id
+        }
+        _ => user.id,
+    };
+
+    let api_keys: Vec<DbApiKey> = api_keys::dsl::api_keys
+        .filter(api_keys::dsl::user_id.eq(user_id))
+        .order((api_keys::dsl::created_at.desc(), api_keys::dsl::id.desc()))
+        .load::<DbApiKey>(conn)?;
+    Ok(api_keys.into_iter().map(ApiKey::from).collect())
+}
+
+/// Revoke an API key of the caller, or of any user for an administrator
+fn db_revokeapikey(
+    caller: Caller,
+    revoke_req: RevokeApiKeyRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::api_keys;
+
+    // keys of other users are not found unless the caller may manage users
+    let (_, user) = caller.require_session()?;
+    let manager: bool = require_permission(&caller, Permission::UsersManage, conn).is_ok();
+    let keys = api_keys::dsl::api_keys.filter(api_keys::dsl::id.eq(revoke_req.id));
+    let revoked: usize = match manager {
+        true => diesel::delete(keys).execute(conn)?,
+        false => diesel::delete(keys.filter(api_keys::dsl::user_id.eq(user.id))).execute(conn)?,
+    };
+    match revoked {
+        0 => Err(ApiError::not_found("API key")),
+        _ => Ok(()),
+    }
+}
+
+/// Create an API key for the current user
+#[post("/auth/apikeys")]
+pub async fn createapikey(
+    caller: Authorized<requires::Anyone>,
+    create_req: Json<CreateApiKeyRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // store the key in the database
+    let mut conn = pool.get()?;
+    let api_key = web::block(move || {
+        db_createapikey(caller.into_inner(), create_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the key, which cannot be shown again
+    Ok(HttpResponse::Created().json(Response::success(api_key)))
+}
+
+/// List the API keys of the current user, or of another user for an administrator
+#[get("/auth/apikeys")]
+pub async fn listapikeys(
+    caller: Authorized<requires::Anyone>,
+    keys_req: Query<ApiKeysRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the keys from the database
+    let mut conn = pool.get()?;
+    let api_keys =
+        web::block(move || db_listapikeys(caller.into_inner(), keys_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the keys on success
+    Ok(HttpResponse::Ok().json(Response::success(api_keys)))
+}
+
+/// Revoke an API key
+#[delete("/auth/apikeys")]
+pub async fn revokeapikey(
+    caller: Authorized<requires::Anyone>,
+    revoke_req: Json<RevokeApiKeyRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the key from the database
+    let mut conn = pool.get()?;
+    web::block(move || db_revokeapikey(caller.into_inner(), revoke_req.into_inner(), &mut conn))
+        .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::Credential;
+    use crate::api::sessions::{self, Client};
+    use crate::testing::connect;
+
+    /// Add a user with the given roles, returning their id
+    fn user(
+        username: &str,
+        roles: &[&str],
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> i32 {
+        use crate::schema::{user_roles, users};
+
+        let user_id: i32 = diesel::insert_into(users::table)
+            .values((
+                users::username.eq(username),
+                users::password_hash.eq("unused"),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap();
+        for role in roles {
+            diesel::insert_into(user_roles::table)
+                .values((user_roles::user_id.eq(user_id), user_roles::role.eq(*role)))
+                .execute(conn)
+                .unwrap();
+        }
+        user_id
+    }
+
+    /// Add an API key for a user, returning its id
+    fn api_key(
+        user_id: i32,
+        key: &str,
+        scope: ApiKeyScope,
+        expires_at: Option<NaiveDateTime>,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> i32 {
+        use crate::schema::api_keys;
+
+        diesel::insert_into(api_keys::table)
+            .values((
+                api_keys::user_id.eq(user_id),
+                api_keys::name.eq(key),
+                api_keys::key_hash.eq(password::hash_token(key)),
+                api_keys::prefix.eq(&key[..SHOWN_PREFIX_LEN]),
+                api_keys::scope.eq(scope.as_str()),
+                api_keys::expires_at.eq(expires_at),
+            ))
+            .returning(api_keys::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Sign in as a user with a new session
+    fn session_caller(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::users;
+
+        let client = Client {
+            user_agent: None,
+            ip_address: None,
+        };
+        let (_, session) = sessions::db_createsession(user_id, client, 3600, conn).unwrap();
+        let user: User = users::table.find(user_id).first(conn).unwrap();
+        Caller::authenticated(Credential::Session(session), user)
+    }
+
+    /// Authenticate with an API key
+    fn key_caller(
+        key: &str,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        let (api_key, user) = require_apikey(key, conn).unwrap();
+        Caller::authenticated(Credential::ApiKey(api_key), user)
+    }
+
+    #[test]
+    fn limits_keys_to_their_scope() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("apikeys-test", &["admin"], &mut conn);
+        api_key(
+            user_id,
+            "ak_apikeys-read",
+            ApiKeyScope::Read,
+            None,
+            &mut conn,
+        );
+        api_key(
+            user_id,
+            "ak_apikeys-upload",
+            ApiKeyScope::Upload,
+            None,
+            &mut conn,
+        );
+
+        let reader: Caller = key_caller("ak_apikeys-read", &mut conn);
+        assert!(require_permission(&reader, Permission::CatalogRead, &mut conn).is_ok());
+        assert!(matches!(
+            require_permission(&reader, Permission::CatalogEdit, &mut conn),
+            Err(ApiError::Forbidden(_))
+        ));
+
+        let uploader: Caller = key_caller("ak_apikeys-upload", &mut conn);
+        for permission in [Permission::MediaUpload, Permission::CatalogEdit] {
+            assert!(require_permission(&uploader, permission, &mut conn).is_ok());
+        }
+        for permission in [
+            Permission::LibraryScan,
+            Permission::PlaylistsEdit,
+            Permission::UsersManage,
+        ] {
+            assert!(matches!(
+                require_permission(&uploader, permission, &mut conn),
+                Err(ApiError::Forbidden(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn refuses_expired_keys_and_keys_of_disabled_users() {
+        use crate::schema::users;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+        let user_id: i32 = user("apikeys-test", &["listener"], &mut conn);
+        api_key(
+            user_id,
+            "ak_apikeys-expired",
+            ApiKeyScope::Read,
+            Some(now - Duration::minutes(1)),
+            &mut conn,
+        );
+        api_key(
+            user_id,
+            "ak_apikeys-current",
+            ApiKeyScope::Read,
+            Some(now + Duration::minutes(1)),
+            &mut conn,
+        );
+
+        assert!(matches!(
+            require_apikey("ak_apikeys-expired", &mut conn),
+            Err(ApiError::Unauthorized(_))
+        ));
+        assert!(require_apikey("ak_apikeys-current", &mut conn).is_ok());
+
+        diesel::update(users::table.find(user_id))
+            .set(users::disabled_at.eq(now))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(matches!(
+            require_apikey("ak_apikeys-current", &mut conn),
+            Err(ApiError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn hides_keys_of_other_users_from_revocation() {
+        use crate::schema::api_keys;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let owner_id: i32 = user("apikeys-owner", &["listener"], &mut conn);
+        let other_id: i32 = user("apikeys-other", &["listener"], &mut conn);
+        let id: i32 = api_key(
+            owner_id,
+            "ak_apikeys-owned",
+            ApiKeyScope::Read,
+            None,
+            &mut conn,
+        );
+
+        let other: Caller = session_caller(other_id, &mut conn);
+        assert!(matches!(
+            db_revokeapikey(other, RevokeApiKeyRequest { id }, &mut conn),
+            Err(ApiError::NotFound(_))
+        ));
+        assert!(api_keys::table
+            .find(id)
+            .first::<DbApiKey>(&mut conn)
+            .is_ok());
+
+        let owner: Caller = session_caller(owner_id, &mut conn);
+        db_revokeapikey(owner, RevokeApiKeyRequest { id }, &mut conn).unwrap();
+        assert!(api_keys::table
+            .find(id)
+            .first::<DbApiKey>(&mut conn)
+            .optional()
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,28 +1,43 @@
+use crate::api::apikeys::{require_apikey, API_KEY_PREFIX};
+use crate::api::lockouts::{db_loginfailed, db_loginsucceeded, db_reservelogin, Throttling};
+use crate::api::roles::{db_countmanagers, db_lockmanagers, db_requireroles};
+use crate::api::sessions::{db_createsession, Client};
+use crate::config::Config;
+use crate::error::ApiError;
 use crate::insert;
-use crate::models::{Admin, User};
+use crate::models::{DbApiKey, DbSession, User};
+use crate::password::{self, Verification};
+use crate::permission::{
+    requires, ApiKeyScope, Permission, Requirement, ADMIN_ROLE, DEFAULT_ROLE, GUEST_ROLE,
+};
 use crate::Response;
 
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::Payload;
+use actix_web::http::header;
 use actix_web::web::{Data, Json};
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::{get, post, web, FromRequest, HttpRequest, HttpResponse};
 use chrono::{Duration, NaiveDateTime};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
-use diesel::result::Error;
-use ring::{digest, pbkdf2};
+use futures_util::future::LocalBoxFuture;
 use serde::{Deserialize, Serialize};
-use std::num::NonZeroU32;
-use uuid::Uuid;
+use std::marker::PhantomData;
+use std::ops::Deref;
 
-// constants for the PBKDF2 algorithm
-static PBKDF2_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
-const PBKDF2_ITER: u32 = 100_000;
-const HASH_LEN: usize = digest::SHA256_OUTPUT_LEN;
+// name of the cookie a session token may be sent in instead of the Authorization header
+pub const SESSION_COOKIE: &str = "allegro_session";
+
+// minimum number of seconds between renewals of a session
+const SESSION_RENEW_INTERVAL: i64 = 60;
 
 /// Return whether access is granted and a session token to an authorization request.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthResponse {
     pub access: bool,
     pub token: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
 }
 
 /// An authorization request from a user.
@@ -32,257 +47,792 @@ pub struct AuthRequest {
     pub password: String,
 }
 
-/// A request to create a new user from an administrator.
+/// A request to create a new user from an administrator, optionally choosing their roles. The
+/// first admin is created with the setup token instead.
 #[derive(Deserialize, Serialize)]
 pub struct AddUserRequest {
     pub username: String,
     pub password: String,
-    pub token: String,
+    pub roles: Option<Vec<String>>,
+    pub setup_token: Option<String>,
 }
 
-/// Represents session information returned from the server
+/// Whether the first admin still has to be created, and whether that needs the setup token.
 #[derive(Debug, Deserialize, Serialize)]
-pub struct Session {
-    pub token: String,
-    pub created_at: NaiveDateTime,
+pub struct SetupStatus {
+    pub available: bool,
+    pub token_required: bool,
+}
+
+/// What a caller authenticated with
+pub enum Credential {
+    Session(DbSession),
+    ApiKey(DbApiKey),
+    // a password sent along with the request itself, as Subsonic clients do
+    Password,
+}
+
+/// The user making a request, authenticated by a bearer token, an API key or a session cookie.
+/// Requests carrying none of them are made by a guest.
+pub struct Caller {
+    auth: Option<(Credential, User)>,
+}
+
+impl Caller {
+    /// A caller authenticated outside of the usual headers
+    pub(crate) fn authenticated(credential: Credential, user: User) -> Self {
+        Caller {
+            auth: Some((credential, user)),
+        }
+    }
+
+    /// The signed in user, if any
+    pub fn user(&self) -> Option<&User> {
+        self.auth.as_ref().map(|(_, user)| user)
+    }
+
+    /// The API key the caller authenticated with, if any
+    pub fn api_key(&self) -> Option<&DbApiKey> {
+        match &self.auth {
+            Some((Credential::ApiKey(api_key), _)) => Some(api_key),
+            _ => None,
+        }
+    }
+
+    /// The user of a caller that must be signed in, by session or API key
+    pub fn require_user(&self) -> Result<&User, ApiError> {
+        self.user().ok_or_else(ApiError::unauthorized)
+    }
+
+    /// The session and user of a caller that must be signed in with a session. Other
+    /// credentials are refused, so a leaked API key cannot be used to take over the account.
+    pub fn require_session(&self) -> Result<(&DbSession, &User), ApiError> {
+        match &self.auth {
+            Some((Credential::Session(session), user)) => Ok((session, user)),
+            Some(_) => Err(ApiError::Forbidden(
+                "This action needs a signed in session".to_string(),
+            )),
+            None => Err(ApiError::unauthorized()),
+        }
+    }
+}
+
+/// A caller allowed everything a route requires, checked before its handler runs. Handlers can
+/// only get their caller this way, so every route has to state what it requires.
+pub struct Authorized<R: Requirement> {
+    caller: Caller,
+    requirement: PhantomData<fn() -> R>,
+}
+
+impl<R: Requirement> Authorized<R> {
+    /// The caller, to hand over to the database
+    pub fn into_inner(self) -> Caller {
+        self.caller
+    }
+}
+
+impl<R: Requirement> Deref for Authorized<R> {
+    type Target = Caller;
+
+    fn deref(&self) -> &Caller {
+        &self.caller
+    }
+}
+
+impl<R: Requirement> FromRequest for Authorized<R> {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token: Result<Option<String>, ApiError> = request_token(req);
+        let pool: Option<Data<Pool<ConnectionManager<PgConnection>>>> = req.app_data().cloned();
+        Box::pin(async move {
+            let mut conn = pool.ok_or(ApiError::Internal)?.get()?;
+            let caller: Caller = web::block(move || {
+                // a request without credentials is a guest, but bad credentials are always refused
+                let caller: Caller = match token? {
+                    Some(token) if token.starts_with(API_KEY_PREFIX) => {
+                        let (api_key, user) = require_apikey(&token, &mut conn)?;
+                        Caller::authenticated(Credential::ApiKey(api_key), user)
+                    }
+                    Some(token) => {
+                        let (session, user) = require_session(&token, &mut conn)?;
+                        Caller::authenticated(Credential::Session(session), user)
+                    }
+                    None => Caller { auth: None },
+                };
+                for permission in R::permissions() {
+                    require_permission(&caller, permission, &mut conn)?;
+                }
+                Ok::<Caller, ApiError>(caller)
+            })
+            .await??;
+            Ok(Authorized {
+                caller,
+                requirement: PhantomData,
+            })
+        })
+    }
+}
+
+/// Read the session token of a request from its Authorization header, falling back to the
+/// session cookie
+fn request_token(req: &HttpRequest) -> Result<Option<String>, ApiError> {
+    if let Some(value) = req.headers().get(header::AUTHORIZATION) {
+        let token: Option<&str> = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+            .filter(|token| !token.is_empty());
+        return match token {
+            Some(token) => Ok(Some(token.to_string())),
+            None => Err(ApiError::Unauthorized(
+                "Authorization header must be a bearer token".to_string(),
+            )),
+        };
+    }
+    Ok(req
+        .cookie(SESSION_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| !token.is_empty()))
+}
+
+/// Build the session cookie holding a token, or clearing it when the token is empty
+pub(crate) fn session_cookie(req: &HttpRequest, token: &str, max_age: i64) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, token.to_string())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(req.connection_info().scheme() == "https")
+        .max_age(CookieDuration::seconds(max_age))
+        .finish()
+}
+
+/// Get the unexpired session a token belongs to along with its user, extending the session
+/// each time it is used
+pub(crate) fn require_session(
+    token: &str,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(DbSession, User), ApiError> {
+    use crate::schema::{sessions, users};
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    let (mut session, user): (DbSession, User) = sessions::dsl::sessions
+        .inner_join(users::dsl::users)
+        .filter(sessions::dsl::token_hash.eq(password::hash_token(token)))
+        .filter(sessions::dsl::expires_at.gt(now))
+        .filter(users::dsl::disabled_at.is_null())
+        .first::<(DbSession, User)>(conn)
+        .optional()?
+        .ok_or_else(ApiError::unauthorized)?;
+
+    // slide the expiry forward by the lifetime the session was given, at most once per interval
+    // so that a burst of requests does not write on every one of them
+    if now - session.last_used_at >= Duration::seconds(SESSION_RENEW_INTERVAL) {
+        let lifetime: Duration = session.expires_at - session.last_used_at;
+        session.last_used_at = now;
+        session.expires_at = now + lifetime;
+        diesel::update(sessions::dsl::sessions.filter(sessions::dsl::id.eq(session.id)))
+            .set((
+                sessions::dsl::last_used_at.eq(session.last_used_at),
+                sessions::dsl::expires_at.eq(session.expires_at),
+            ))
+            .execute(conn)?;
+    }
+    Ok((session, user))
+}
+
+/// Get the names of the roles granted to a user
+pub(crate) fn db_userroles(
+    user_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<String>, ApiError> {
+    use crate::schema::user_roles;
+
+    let roles: Vec<String> = user_roles::dsl::user_roles
+        .filter(user_roles::dsl::user_id.eq(user_id))
+        .select(user_roles::dsl::role)
+        .order(user_roles::dsl::role.asc())
+        .load::<String>(conn)?;
+    Ok(roles)
+}
+
+/// Check whether any of the given roles grants a permission
+pub(crate) fn db_rolesgrant(
+    roles: &[String],
+    permission: Permission,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<bool, ApiError> {
+    use crate::schema::role_permissions;
+
+    let granted: bool = diesel::select(diesel::dsl::exists(
+        role_permissions::dsl::role_permissions
+            .filter(role_permissions::dsl::role.eq_any(roles))
+            .filter(role_permissions::dsl::permission.eq(permission.as_str())),
+    ))
+    .get_result(conn)?;
+    Ok(granted)
+}
+
+/// Check that the caller may perform an action. Guests are allowed whatever the guest role
+/// permits.
+pub(crate) fn require_permission(
+    caller: &Caller,
+    permission: Permission,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    // API keys only get the part of their owner's access their scope covers
+    if let Some(api_key) = caller.api_key() {
+        let scope: Option<ApiKeyScope> = ApiKeyScope::parse(&api_key.scope);
+        if !scope.is_some_and(|scope| scope.allows(permission)) {
+            return Err(ApiError::Forbidden(format!(
+                "API key scope does not include the {} permission",
+                permission
+            )));
+        }
+    }
+
+    // requests without a session only get the permissions of the guest role
+    let user: &User = match caller.user() {
+        Some(user) => user,
+        None if db_rolesgrant(&[GUEST_ROLE.to_string()], permission, conn)? => return Ok(()),
+        None => return Err(ApiError::unauthorized()),
+    };
+
+    // signed in users keep everything a guest may do on top of their own roles
+    let mut roles: Vec<String> = db_userroles(user.id, conn)?;
+    roles.push(GUEST_ROLE.to_string());
+    if !db_rolesgrant(&roles, permission, conn)? {
+        return Err(ApiError::forbidden(permission));
+    }
+    Ok(())
 }
 
 /// Return the number of users in the database
-fn db_countuser<T>(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> Response<i64> {
+fn db_countuser<T>(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<i64, ApiError> {
     use crate::schema::users;
 
-    let num_users: i64 = users::dsl::users.count().get_result(conn).unwrap();
-    Response {
-        success: true,
-        message: num_users,
+    let num_users: i64 = users::dsl::users.count().get_result(conn)?;
+    Ok(num_users)
+}
+
+/// Check the setup token given for creating the first admin, if one is required
+fn require_setup_token(given: Option<&str>, expected: Option<&str>) -> Result<(), ApiError> {
+    let expected: &str = match expected {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+
+    // compare digests so the time taken does not depend on how much of the token matched
+    match given.map(password::hash_token) == Some(password::hash_token(expected)) {
+        true => Ok(()),
+        false => Err(ApiError::Forbidden(
+            "A valid setup token is required to create the first admin".to_string(),
+        )),
     }
 }
 
+/// Check whether the first admin still has to be created, and whether that needs a setup token
+fn db_setupstatus(
+    setup_token: Option<String>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<SetupStatus, ApiError> {
+    let available: bool = db_countmanagers(conn)? == 0;
+    Ok(SetupStatus {
+        available,
+        token_required: available && setup_token.is_some(),
+    })
+}
+
 /// Add the user to the database, checking if admin privileges are required
 fn db_adduser<T>(
+    caller: Caller,
     adduser_req: AddUserRequest,
+    setup_token: Option<String>,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<String> {
-    use crate::schema::admin;
+) -> Result<String, ApiError> {
+    use crate::schema::user_roles;
     use crate::schema::users;
 
-    // if this is the first user, no admin privileges are required
-    let num_users: i64 = users::dsl::users.count().get_result(conn).unwrap();
-    let mut is_admin = true; // Make variable mutable with 'mut'
-    if num_users == 0 {
-        // Compare with reference
-        is_admin = false;
-    }
+    // hash the password with Argon2id, which stores its own salt and parameters
+    let password_hash: String = password::hash(&adduser_req.password)?;
 
-    // if admin privileges are required, proceed with auth checks
-    if is_admin {
-        // check if the token is valid
-        let token = adduser_req.token;
-        let user_res = users::dsl::users
-            .filter(users::dsl::session.eq(Some(token)))
-            .first::<User>(conn);
-        let user: User = match user_res {
-            Ok(_) => user_res.unwrap(),
-            Err(_) => {
-                return Response {
-                    success: false,
-                    message: "Invalid token".to_string(),
-                }
-            }
-        };
+    conn.transaction(|conn| {
+        // only one request at a time may decide whether it creates the first admin
+        db_lockmanagers(conn)?;
 
-        // check if the user is an admin
-        let admin_user_res = admin::dsl::admin
-            .filter(admin::dsl::username.eq(&user.username))
-            .first::<Admin>(conn);
-        match admin_user_res {
-            Ok(_) => (),
-            Err(_) => {
-                return Response {
-                    success: false,
-                    message: "User is not an admin".to_string(),
-                }
-            }
+        // until someone can manage users, the new user becomes an admin if they hold the setup
+        // token, and once someone can, only they may add users
+        let roles: Vec<String> = if db_countmanagers(conn)? == 0 {
+            require_setup_token(adduser_req.setup_token.as_deref(), setup_token.as_deref())?;
+            vec![ADMIN_ROLE.to_string()]
+        } else {
+            // a new user can be given any role and then signed in as, so an API key is not enough
+            caller.require_session()?;
+            require_permission(&caller, Permission::UsersManage, conn)?;
+            let roles: Vec<String> = adduser_req
+                .roles
+                .unwrap_or_else(|| vec![DEFAULT_ROLE.to_string()]);
+            db_requireroles(roles, conn)?
         };
-    }
 
-    // generate a salt and hash the password with PBKDF2
-    let salt: String = Uuid::new_v4().to_string();
-    let mut password_hash: [u8; HASH_LEN] = [0u8; HASH_LEN];
-    pbkdf2::derive(
-        PBKDF2_ALG,
-        NonZeroU32::new(PBKDF2_ITER).unwrap(),
-        &salt.as_bytes(),
-        adduser_req.password.as_bytes(),
-        &mut password_hash,
-    );
-
-    // insert new user into admin if admin privileges are not required
-    if !is_admin {
-        let new_admin = insert::NewAdmin {
-            username: adduser_req.username.clone(),
+        // insert the new user into the database
+        let new_user = insert::NewUser {
+            username: adduser_req.username,
+            password_hash,
         };
-        let _ = diesel::insert_into(admin::dsl::admin)
-            .values(&new_admin)
-            .execute(conn);
-    }
+        let user_id: i32 = diesel::insert_into(users::dsl::users)
+            .values(&new_user)
+            .returning(users::dsl::id)
+            .get_result(conn)?;
 
-    // insert the new user into the database
-    let new_user = insert::NewUser {
-        username: adduser_req.username,
-        password_hash: hex::encode(password_hash),
-        salt,
-        session: None,
-        created_at: None,
-    };
-    let _ = diesel::insert_into(users::dsl::users)
-        .values(&new_user)
-        .execute(conn);
-    Response {
-        success: true,
-        message: "User sucessfully added".to_string(),
-    }
+        // grant the user their roles
+        let new_roles: Vec<insert::NewUserRole> = roles
+            .into_iter()
+            .map(|role| insert::NewUserRole { user_id, role })
+            .collect();
+        diesel::insert_into(user_roles::dsl::user_roles)
+            .values(&new_roles)
+            .execute(conn)?;
+        Ok("User sucessfully added".to_string())
+    })
 }
 
 /// Given a user's authentication request, check if the user exists and if the password is correct.
 fn db_login(
     auth_req: AuthRequest,
+    client: Client,
+    lifetime: i64,
+    throttling: Throttling,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Result<AuthResponse, Error> {
+) -> Result<AuthResponse, ApiError> {
     use crate::schema::users;
 
+    // count the attempt up front, refusing it outright while the username or address is throttled
+    db_reservelogin(&auth_req.username, &client, throttling, conn)?;
+
     // filter for the user
-    let user_res = users::dsl::users
+    let user: Option<User> = users::dsl::users
         .filter(users::dsl::username.eq(&auth_req.username))
-        .first::<User>(conn);
-
-    // check if user exists
-    let user: User = match user_res {
-        Ok(_) => user_res.unwrap(),
-        Err(_) => {
-            return Ok(AuthResponse {
-                access: false,
-                token: None,
-            })
+        .first::<User>(conn)
+        .optional()?;
+
+    // verify the password against whichever kind of hash is stored
+    let verification: Verification = match &user {
+        Some(user) => password::verify(
+            &auth_req.password,
+            &user.password_hash,
+            user.salt.as_deref(),
+        ),
+        None => password::verify_unknown(&auth_req.password),
+    };
+    let user: User = match user {
+        Some(user) if verification != Verification::Invalid => user,
+        _ => {
+            db_loginfailed(&auth_req.username, &client, conn)?;
+            return Err(invalid_credentials());
         }
     };
+    db_loginsucceeded(&user.username, &client, throttling, conn)?;
 
-    // verify the password hash with PBKDF2
-    let verify_res = pbkdf2::verify(
-        PBKDF2_ALG,
-        NonZeroU32::new(PBKDF2_ITER).unwrap(),
-        &user.salt.as_bytes(),
-        auth_req.password.as_bytes(),
-        &hex::decode(user.password_hash).unwrap(),
-    );
-
-    // if the password is correct, create a session token and set time created
-    match verify_res {
-        Ok(_) => {
-            // check if token already exists and has not expired (1 week)
-            if let Some(token) = user.session {
-                if let Some(created_at) = user.created_at {
-                    let now = chrono::Utc::now().naive_utc();
-                    if now - created_at < Duration::days(7) {
-                        return Ok(AuthResponse {
-                            access: true,
-                            token: Some(token),
-                        });
-                    }
-                }
-            }
-
-            // otherwise, create a new token
-            let token = Uuid::new_v4().to_string();
-            let timestamp: NaiveDateTime = chrono::Utc::now().naive_utc();
-            let _ = diesel::update(users::dsl::users)
-                .filter(users::dsl::username.eq(&auth_req.username))
-                .set((
-                    users::dsl::session.eq(Some(token.clone())),
-                    users::dsl::created_at.eq(Some(timestamp)),
-                ))
-                .execute(conn);
-            Ok(AuthResponse {
-                access: true,
-                token: Some(token),
-            })
-        }
-        Err(_) => Ok(AuthResponse {
-            access: false,
-            token: None,
-        }),
+    // only tell whoever knows the password that the account is disabled
+    if user.disabled_at.is_some() {
+        return Err(ApiError::Forbidden("Account is disabled".to_string()));
     }
+
+    // upgrade legacy or outdated hashes now that the plain password is known
+    if verification == Verification::ValidOutdated {
+        let password_hash: String = password::hash(&auth_req.password)?;
+        diesel::update(users::dsl::users.filter(users::dsl::id.eq(user.id)))
+            .set((
+                users::dsl::password_hash.eq(password_hash),
+                users::dsl::salt.eq(None::<String>),
+            ))
+            .execute(conn)?;
+    }
+
+    // give this device a session of its own, leaving the user's other sessions alone
+    let (token, session): (String, DbSession) = db_createsession(user.id, client, lifetime, conn)?;
+    Ok(AuthResponse {
+        access: true,
+        token: Some(token),
+        expires_at: Some(session.expires_at),
+    })
+}
+
+/// Error returned for an unknown username or wrong password, without saying which
+fn invalid_credentials() -> ApiError {
+    ApiError::Unauthorized("Invalid username or password".to_string())
 }
 
 /// Get the number of users registered
 #[get("/auth/countuser")]
-pub async fn countuser(pool: Data<Pool<ConnectionManager<PgConnection>>>) -> HttpResponse {
-    // get the countuser response from the database
-    let mut conn = pool.get().expect("Connection pool error");
-    let countuser_response = web::block(move || db_countuser::<i64>(&mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match countuser_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Ok()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+pub async fn countuser(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the number of users from the database
+    let mut conn = pool.get()?;
+    let num_users = web::block(move || db_countuser::<i64>(&mut conn)).await??;
+
+    // return the number of users
+    Ok(HttpResponse::Ok().json(Response::success(num_users)))
+}
+
+/// Report whether the first admin still has to be created
+#[get("/auth/setup")]
+pub async fn setupstatus(
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // check the managers in the database
+    let mut conn = pool.get()?;
+    let setup_token: Option<String> = config.setup_token.clone();
+    let status = web::block(move || db_setupstatus(setup_token, &mut conn)).await??;
+
+    // return the setup status
+    Ok(HttpResponse::Ok().json(Response::success(status)))
 }
 
 /// Add a user to the system, and determine if admin privileges are required
 #[post("/auth/adduser")]
 pub async fn adduser(
+    caller: Authorized<requires::Anyone>,
     adduser_req: Json<AddUserRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
-    // get the adduser response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let adduser_response =
-        web::block(move || db_adduser::<String>(adduser_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match adduser_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // add the user to the database
+    let mut conn = pool.get()?;
+    let setup_token: Option<String> = config.setup_token.clone();
+    let message = web::block(move || {
+        db_adduser::<String>(
+            caller.into_inner(),
+            adduser_req.into_inner(),
+            setup_token,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    // return the created user message
+    Ok(HttpResponse::Created().json(Response::success(message)))
 }
 
-/// Determine if user is authorized, and if so return a session token.
+/// Determine if user is authorized, and if so return a new session token for this device.
 #[post("/auth/login")]
 pub async fn login(
+    req: HttpRequest,
     auth_req: Json<AuthRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
     // get the authorization response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let auth_response = web::block(move || db_login(auth_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match auth_response {
-        Ok(Ok(response)) => {
-            // handle case where login succeeds
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
+    let mut conn = pool.get()?;
+    let client: Client = Client::from_request(&req);
+    let lifetime: i64 = config.session_lifetime;
+    let throttling: Throttling = Throttling::from_config(&config);
+    let auth_response: AuthResponse = web::block(move || {
+        db_login(
+            auth_req.into_inner(),
+            client,
+            lifetime,
+            throttling,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    // return the session token, also setting it as a cookie for browsers
+    let token: &str = auth_response.token.as_deref().unwrap_or_default();
+    Ok(HttpResponse::Ok()
+        .cookie(session_cookie(&req, token, lifetime))
+        .json(&auth_response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+    use argon2::password_hash::rand_core::OsRng;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    use argon2::{Algorithm, Argon2, Params, Version};
+    use ring::pbkdf2;
+    use std::num::NonZeroU32;
+
+    /// Add a user whose password is stored as given
+    fn stored_user(
+        password_hash: &str,
+        salt: Option<&str>,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> i32 {
+        use crate::schema::users;
+
+        diesel::insert_into(users::table)
+            .values((
+                users::username.eq("auth-test"),
+                users::password_hash.eq(password_hash),
+                users::salt.eq(salt),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// The stored hash and legacy salt of a user
+    fn stored(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (String, Option<String>) {
+        use crate::schema::users;
+
+        users::table
+            .find(user_id)
+            .select((users::password_hash, users::salt))
+            .first(conn)
+            .unwrap()
+    }
+
+    /// Sign in as the test user
+    fn login(
+        password: &str,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<AuthResponse, ApiError> {
+        let auth_req = AuthRequest {
+            username: "auth-test".to_string(),
+            password: password.to_string(),
+        };
+        let client = Client {
+            user_agent: None,
+            ip_address: Some("203.0.113.9".to_string()),
+        };
+        let throttling = Throttling {
+            threshold: 10,
+            lockout: 60,
+        };
+        db_login(auth_req, client, 3600, throttling, conn)
+    }
+
+    /// Hash a password the way it was stored before Argon2id, returning the hash and salt
+    fn legacy_hash(password: &str) -> (String, String) {
+        let salt: &str = "9f86d081884c7d65";
+        let mut hash = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(100_000).unwrap(),
+            salt.as_bytes(),
+            password.as_bytes(),
+            &mut hash,
+        );
+        (hex::encode(hash), salt.to_string())
+    }
+
+    const SETUP_TOKEN: &str = "setup-test-token";
+
+    /// Sign in as a user with a session
+    fn session_caller(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::users;
+
+        let client = Client {
+            user_agent: None,
+            ip_address: None,
+        };
+        let (_, session) = db_createsession(user_id, client, 3600, conn).unwrap();
+        let user: User = users::table.find(user_id).first(conn).unwrap();
+        Caller::authenticated(Credential::Session(session), user)
+    }
+
+    /// Add a user as a caller, on a server whose setup token is `SETUP_TOKEN`
+    fn add_user(
+        caller: Caller,
+        username: &str,
+        setup_token: Option<&str>,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<String, ApiError> {
+        let adduser_req = AddUserRequest {
+            username: username.to_string(),
+            password: "correct horse battery staple".to_string(),
+            roles: None,
+            setup_token: setup_token.map(str::to_string),
+        };
+        db_adduser::<()>(caller, adduser_req, Some(SETUP_TOKEN.to_string()), conn)
+    }
+
+    /// The id and roles of a user, if they were added
+    fn added(
+        username: &str,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Option<(i32, Vec<String>)> {
+        use crate::schema::users;
+
+        let user_id: i32 = users::table
+            .filter(users::username.eq(username))
+            .select(users::id)
+            .first(conn)
+            .optional()
+            .unwrap()?;
+        Some((user_id, db_userroles(user_id, conn).unwrap()))
+    }
+
+    #[test]
+    fn upgrades_legacy_hashes_on_login() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (hash, salt) = legacy_hash("correct horse");
+        let user_id: i32 = stored_user(&hash, Some(&salt), &mut conn);
+
+        assert!(login("correct horse", &mut conn).unwrap().access);
+        let (upgraded, salt) = stored(user_id, &mut conn);
+        assert!(upgraded.starts_with("$argon2id$"));
+        assert_eq!(salt, None);
+        assert_eq!(
+            password::verify("correct horse", &upgraded, None),
+            Verification::Valid
+        );
+
+        // the upgraded hash keeps working
+        assert!(login("correct horse", &mut conn).unwrap().access);
+        assert_eq!(stored(user_id, &mut conn).0, upgraded);
+    }
+
+    #[test]
+    fn leaves_hashes_alone_on_wrong_passwords() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (hash, salt) = legacy_hash("correct horse");
+        let user_id: i32 = stored_user(&hash, Some(&salt), &mut conn);
+
+        let result = login("battery staple", &mut conn);
+        assert!(matches!(result, Err(ApiError::Unauthorized(_))));
+        assert_eq!(stored(user_id, &mut conn), (hash, Some(salt)));
+    }
+
+    #[test]
+    fn rehashes_outdated_parameters_on_login() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let weak = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(8 * 1024, 1, 1, None).unwrap(),
+        );
+        let outdated: String = weak
+            .hash_password(b"correct horse", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+        let user_id: i32 = stored_user(&outdated, None, &mut conn);
+        assert_eq!(
+            password::verify("correct horse", &outdated, None),
+            Verification::ValidOutdated
+        );
+
+        assert!(login("correct horse", &mut conn).unwrap().access);
+        let (rehashed, _) = stored(user_id, &mut conn);
+        assert_ne!(rehashed, outdated);
+        assert_eq!(
+            password::verify("correct horse", &rehashed, None),
+            Verification::Valid
+        );
+    }
+
+    #[test]
+    fn refuses_guests_everything_by_default() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let guest = Caller { auth: None };
+        for permission in Permission::ALL {
+            let result = require_permission(&guest, permission, &mut conn);
+            assert!(
+                matches!(result, Err(ApiError::Unauthorized(_))),
+                "guests were allowed {}",
+                permission
+            );
         }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
+    }
+
+    #[test]
+    fn makes_the_first_user_an_admin_with_the_setup_token() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        for setup_token in [None, Some("wrong")] {
+            let guest = Caller { auth: None };
+            let result = add_user(guest, "first-admin", setup_token, &mut conn);
+            assert!(matches!(result, Err(ApiError::Forbidden(_))));
         }
+        assert_eq!(added("first-admin", &mut conn), None);
+
+        let guest = Caller { auth: None };
+        add_user(guest, "first-admin", Some(SETUP_TOKEN), &mut conn).unwrap();
+        let (_, roles) = added("first-admin", &mut conn).unwrap();
+        assert_eq!(roles, vec![ADMIN_ROLE.to_string()]);
+    }
+
+    #[test]
+    fn stops_honouring_the_setup_token_once_someone_manages_users() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let guest = Caller { auth: None };
+        add_user(guest, "first-admin", Some(SETUP_TOKEN), &mut conn).unwrap();
+        let (admin_id, _) = added("first-admin", &mut conn).unwrap();
+
+        // the token neither lets a guest in nor makes anyone else an admin
+        let guest = Caller { auth: None };
+        let result = add_user(guest, "second-admin", Some(SETUP_TOKEN), &mut conn);
+        assert!(matches!(result, Err(ApiError::Unauthorized(_))));
+        let admin: Caller = session_caller(admin_id, &mut conn);
+        add_user(admin, "listener", Some(SETUP_TOKEN), &mut conn).unwrap();
+        let (listener_id, roles) = added("listener", &mut conn).unwrap();
+        assert_eq!(roles, vec![DEFAULT_ROLE.to_string()]);
+
+        // and users who cannot manage users cannot add any, token or not
+        let listener: Caller = session_caller(listener_id, &mut conn);
+        let result = add_user(listener, "third-admin", Some(SETUP_TOKEN), &mut conn);
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+        assert_eq!(added("second-admin", &mut conn), None);
+        assert_eq!(added("third-admin", &mut conn), None);
+    }
+
+    #[test]
+    fn waits_for_a_concurrent_first_admin() {
+        let Some(mut first) = connect() else {
+            return;
+        };
+        let Some(mut second) = connect() else {
+            return;
+        };
+
+        // the first admin holds the lock until its transaction ends, so a second one cannot
+        // decide it is the first in the meantime
+        let guest = Caller { auth: None };
+        add_user(guest, "first-admin", Some(SETUP_TOKEN), &mut first).unwrap();
+        diesel::sql_query("SET LOCAL lock_timeout = '100ms'")
+            .execute(&mut second)
+            .unwrap();
+        let guest = Caller { auth: None };
+        let result = add_user(guest, "second-admin", Some(SETUP_TOKEN), &mut second);
+        assert!(matches!(result, Err(ApiError::Internal)));
+        assert_eq!(added("second-admin", &mut second), None);
+
+        // once the first is rolled back, the second is the first admin after all
+        drop(first);
+        diesel::sql_query("SET LOCAL lock_timeout = '10s'")
+            .execute(&mut second)
+            .unwrap();
+        let guest = Caller { auth: None };
+        add_user(guest, "second-admin", Some(SETUP_TOKEN), &mut second).unwrap();
+        let (_, roles) = added("second-admin", &mut second).unwrap();
+        assert_eq!(roles, vec![ADMIN_ROLE.to_string()]);
     }
 }
@@ -0,0 +1,1130 @@
+use crate::api::addmusic::{db_unknowncomposers, db_unknownperformers, db_unknownsongwriters};
+use crate::api::auth::Authorized;
+use crate::error::{ApiError, UnknownIds};
+use crate::permission::requires;
+use crate::update;
+use crate::Response;
+
+use actix_web::web::{Data, Json};
+use actix_web::{delete, patch, web, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Deserialize a field that may be missing, null, or set, so that a missing field leaves the
+/// value unchanged while null clears it
+pub(crate) fn double_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// A request to update an artist, with type either performer, composer, or songwriter
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateArtistRequest {
+    pub id: i32,
+    pub artist_type: String,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub description: Option<Option<String>>,
+}
+
+/// A request to update a release, replacing its performers if they are given
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateReleaseRequest {
+    pub id: i32,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub description: Option<Option<String>>,
+    pub performer_ids: Option<Vec<i32>>,
+}
+
+/// A request to update a piece, replacing its composers and songwriters if they are given
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdatePieceRequest {
+    pub id: i32,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub movements: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub description: Option<Option<String>>,
+    pub composer_ids: Option<Vec<i32>>,
+    pub songwriter_ids: Option<Vec<i32>>,
+}
+
+/// A request to update a recording, replacing its performers if they are given
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateRecordingRequest {
+    pub id: i32,
+    pub piece_name: Option<String>,
+    pub piece_id: Option<i32>,
+    pub release_id: Option<i32>,
+    pub track_number: Option<i32>,
+    pub performer_ids: Option<Vec<i32>>,
+}
+
+/// A request to renumber every recording of a release in the given order, starting from one
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReorderTracksRequest {
+    pub release_id: i32,
+    pub recording_ids: Vec<i32>,
+}
+
+/// A request to delete an artist, with type either performer, composer, or songwriter
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteArtistRequest {
+    pub id: i32,
+    pub artist_type: String,
+}
+
+/// A request to delete a release, along with its recordings if cascade is set
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteReleaseRequest {
+    pub id: i32,
+    pub cascade: Option<bool>,
+}
+
+/// A request to delete a piece or recording
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteRequest {
+    pub id: i32,
+}
+
+/// Update a performer in the database
+fn db_updateperformer(
+    update_req: UpdateArtistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<()> {
+    use crate::schema::performers;
+
+    let changes = update::UpdatePerformer {
+        name: update_req.name,
+        description: update_req.description,
+    };
+    conn.transaction(|conn| {
+        // make sure the performer exists before changing anything
+        performers::dsl::performers
+            .find(update_req.id)
+            .select(performers::dsl::id)
+            .first::<i32>(conn)?;
+        if !changes.is_empty() {
+            diesel::update(performers::dsl::performers.find(update_req.id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Update a composer in the database
+fn db_updatecomposer(
+    update_req: UpdateArtistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<()> {
+    use crate::schema::composers;
+
+    let changes = update::UpdateComposer {
+        name: update_req.name,
+        description: update_req.description,
+    };
+    conn.transaction(|conn| {
+        // make sure the composer exists before changing anything
+        composers::dsl::composers
+            .find(update_req.id)
+            .select(composers::dsl::id)
+            .first::<i32>(conn)?;
+        if !changes.is_empty() {
+            diesel::update(composers::dsl::composers.find(update_req.id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Update a songwriter in the database
+fn db_updatesongwriter(
+    update_req: UpdateArtistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<()> {
+    use crate::schema::songwriters;
+
+    let changes = update::UpdateSongwriter {
+        name: update_req.name,
+        description: update_req.description,
+    };
+    conn.transaction(|conn| {
+        // make sure the songwriter exists before changing anything
+        songwriters::dsl::songwriters
+            .find(update_req.id)
+            .select(songwriters::dsl::id)
+            .first::<i32>(conn)?;
+        if !changes.is_empty() {
+            diesel::update(songwriters::dsl::songwriters.find(update_req.id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Update an artist in the database
+fn db_updateartist(
+    update_req: UpdateArtistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    // determine the type of artist to update
+    match update_req.artist_type.as_str() {
+        "performer" => Ok(db_updateperformer(update_req, conn)?),
+        "composer" => Ok(db_updatecomposer(update_req, conn)?),
+        "songwriter" => Ok(db_updatesongwriter(update_req, conn)?),
+        _ => Err(ApiError::Validation("Invalid artist type".to_string())),
+    }
+}
+
+/// Update a release in the database, replacing its performers if they are given
+fn db_updaterelease(
+    update_req: UpdateReleaseRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{release_performers, releases};
+
+    let release_id: i32 = update_req.id;
+    let changes = update::UpdateRelease {
+        name: update_req.name,
+        description: update_req.description,
+    };
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // make sure the release exists before changing anything
+        releases::dsl::releases
+            .find(release_id)
+            .select(releases::dsl::id)
+            .first::<i32>(conn)?;
+
+        // validate every referenced id before changing anything
+        if let Some(performer_ids) = &update_req.performer_ids {
+            let unknown_ids = UnknownIds {
+                performer_ids: db_unknownperformers(performer_ids, conn)?,
+                ..Default::default()
+            };
+            if !unknown_ids.is_empty() {
+                return Err(ApiError::UnknownIds(Box::new(unknown_ids)));
+            }
+        }
+
+        if !changes.is_empty() {
+            diesel::update(releases::dsl::releases.find(release_id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+
+        // replace the performer relationships
+        if let Some(performer_ids) = update_req.performer_ids {
+            diesel::delete(
+                release_performers::dsl::release_performers
+                    .filter(release_performers::dsl::release_id.eq(release_id)),
+            )
+            .execute(conn)?;
+            for performer_id in performer_ids {
+                diesel::insert_into(release_performers::table)
+                    .values((
+                        release_performers::release_id.eq(release_id),
+                        release_performers::performer_id.eq(performer_id),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Update a piece in the database, replacing its composers and songwriters if they are given
+fn db_updatepiece(
+    update_req: UpdatePieceRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{piece_composers, piece_songwriters, pieces};
+
+    let piece_id: i32 = update_req.id;
+    let changes = update::UpdatePiece {
+        name: update_req.name,
+        movements: update_req.movements,
+        description: update_req.description,
+    };
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // make sure the piece exists before changing anything
+        pieces::dsl::pieces
+            .find(piece_id)
+            .select(pieces::dsl::id)
+            .first::<i32>(conn)?;
+
+        // validate every referenced id before changing anything
+        let mut unknown_ids = UnknownIds::default();
+        if let Some(composer_ids) = &update_req.composer_ids {
+            unknown_ids.composer_ids = db_unknowncomposers(composer_ids, conn)?;
+        }
+        if let Some(songwriter_ids) = &update_req.songwriter_ids {
+            unknown_ids.songwriter_ids = db_unknownsongwriters(songwriter_ids, conn)?;
+        }
+        if !unknown_ids.is_empty() {
+            return Err(ApiError::UnknownIds(Box::new(unknown_ids)));
+        }
+
+        if !changes.is_empty() {
+            diesel::update(pieces::dsl::pieces.find(piece_id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+
+        // replace the composer relationships
+        if let Some(composer_ids) = update_req.composer_ids {
+            diesel::delete(
+                piece_composers::dsl::piece_composers
+                    .filter(piece_composers::dsl::piece_id.eq(piece_id)),
+            )
+            .execute(conn)?;
+            for composer_id in composer_ids {
+                diesel::insert_into(piece_composers::table)
+                    .values((
+                        piece_composers::piece_id.eq(piece_id),
+                        piece_composers::composer_id.eq(composer_id),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+        }
+
+        // replace the songwriter relationships
+        if let Some(songwriter_ids) = update_req.songwriter_ids {
+            diesel::delete(
+                piece_songwriters::dsl::piece_songwriters
+                    .filter(piece_songwriters::dsl::piece_id.eq(piece_id)),
+            )
+            .execute(conn)?;
+            for songwriter_id in songwriter_ids {
+                diesel::insert_into(piece_songwriters::table)
+                    .values((
+                        piece_songwriters::piece_id.eq(piece_id),
+                        piece_songwriters::songwriter_id.eq(songwriter_id),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Update a recording in the database, replacing its performers if they are given
+fn db_updaterecording(
+    update_req: UpdateRecordingRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{pieces, recording_performers, recordings, releases};
+
+    let recording_id: i32 = update_req.id;
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // make sure the recording exists before changing anything
+        recordings::dsl::recordings
+            .find(recording_id)
+            .select(recordings::dsl::id)
+            .first::<i32>(conn)?;
+
+        // validate every referenced id before changing anything
+        let mut unknown_ids = UnknownIds::default();
+        let mut new_piece_name: Option<String> = None;
+        if let Some(piece_id) = update_req.piece_id {
+            new_piece_name = pieces::dsl::pieces
+                .find(piece_id)
+                .select(pieces::dsl::name)
+                .first::<String>(conn)
+                .optional()?;
+            if new_piece_name.is_none() {
+                unknown_ids.piece_ids.push(piece_id);
+            }
+        }
+        if let Some(release_id) = update_req.release_id {
+            let release_found: Option<i32> = releases::dsl::releases
+                .find(release_id)
+                .select(releases::dsl::id)
+                .first::<i32>(conn)
+                .optional()?;
+            if release_found.is_none() {
+                unknown_ids.release_ids.push(release_id);
+            }
+        }
+        if let Some(performer_ids) = &update_req.performer_ids {
+            unknown_ids.performer_ids = db_unknownperformers(performer_ids, conn)?;
+        }
+        if !unknown_ids.is_empty() {
+            return Err(ApiError::UnknownIds(Box::new(unknown_ids)));
+        }
+
+        // moving the recording to another piece takes that piece's name unless one is given
+        let changes = update::UpdateRecording {
+            piece_name: update_req.piece_name.or(new_piece_name),
+            piece_id: update_req.piece_id,
+            release_id: update_req.release_id,
+            track_number: update_req.track_number,
+        };
+        if !changes.is_empty() {
+            diesel::update(recordings::dsl::recordings.find(recording_id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+
+        // replace the performer relationships
+        if let Some(performer_ids) = update_req.performer_ids {
+            diesel::delete(
+                recording_performers::dsl::recording_performers
+                    .filter(recording_performers::dsl::recording_id.eq(recording_id)),
+            )
+            .execute(conn)?;
+            for performer_id in performer_ids {
+                diesel::insert_into(recording_performers::table)
+                    .values((
+                        recording_performers::recording_id.eq(recording_id),
+                        recording_performers::performer_id.eq(performer_id),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Renumber the recordings of a release in the given order
+fn db_reordertracks(
+    reorder_req: ReorderTracksRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{recordings, releases};
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // lock the release and its recordings, so none are added or moved before the reorder
+        releases::dsl::releases
+            .find(reorder_req.release_id)
+            .select(releases::dsl::id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::not_found("Release"))?;
+        let mut recording_ids: Vec<i32> = recordings::dsl::recordings
+            .filter(recordings::dsl::release_id.eq(reorder_req.release_id))
+            .select(recordings::dsl::id)
+            .for_update()
+            .load::<i32>(conn)?;
+
+        // every recording of the release must be listed exactly once
+        let mut requested_ids: Vec<i32> = reorder_req.recording_ids.clone();
+        recording_ids.sort_unstable();
+        requested_ids.sort_unstable();
+        if recording_ids.is_empty() || recording_ids != requested_ids {
+            return Err(ApiError::Validation(
+                "Recording ids must list every recording of the release once".to_string(),
+            ));
+        }
+
+        // move every track out of the way first, so no two recordings share a number midway
+        for (index, recording_id) in reorder_req.recording_ids.iter().enumerate() {
+            diesel::update(recordings::dsl::recordings.find(recording_id))
+                .set(recordings::dsl::track_number.eq(-(index as i32) - 1))
+                .execute(conn)?;
+        }
+        diesel::update(
+            recordings::dsl::recordings
+                .filter(recordings::dsl::release_id.eq(reorder_req.release_id)),
+        )
+        .set(recordings::dsl::track_number.eq(recordings::dsl::track_number * -1))
+        .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Delete a performer from the database, refusing while they are credited anywhere
+fn db_deleteperformer(
+    performer_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{performers, recording_performers, release_performers};
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // lock the performer, so nothing can credit them until they are gone
+        performers::dsl::performers
+            .find(performer_id)
+            .select(performers::dsl::id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::not_found("Performer"))?;
+
+        // a performer can only be deleted once nothing credits them
+        let releases: i64 = release_performers::dsl::release_performers
+            .filter(release_performers::dsl::performer_id.eq(performer_id))
+            .count()
+            .get_result(conn)?;
+        let recordings: i64 = recording_performers::dsl::recording_performers
+            .filter(recording_performers::dsl::performer_id.eq(performer_id))
+            .count()
+            .get_result(conn)?;
+        if releases > 0 || recordings > 0 {
+            return Err(ApiError::Conflict(format!(
+                "Performer is credited on {} releases and {} recordings",
+                releases, recordings
+            )));
+        }
+
+        diesel::delete(performers::dsl::performers.find(performer_id)).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Delete a composer from the database, refusing while they are credited on any piece
+fn db_deletecomposer(
+    composer_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{composers, piece_composers};
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // lock the composer, so no piece can credit them until they are gone
+        composers::dsl::composers
+            .find(composer_id)
+            .select(composers::dsl::id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::not_found("Composer"))?;
+
+        // a composer can only be deleted once no piece credits them
+        let pieces: i64 = piece_composers::dsl::piece_composers
+            .filter(piece_composers::dsl::composer_id.eq(composer_id))
+            .count()
+            .get_result(conn)?;
+        if pieces > 0 {
+            return Err(ApiError::Conflict(format!(
+                "Composer is credited on {} pieces",
+                pieces
+            )));
+        }
+
+        diesel::delete(composers::dsl::composers.find(composer_id)).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Delete a songwriter from the database, refusing while they are credited on any piece
+fn db_deletesongwriter(
+    songwriter_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{piece_songwriters, songwriters};
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // lock the songwriter, so no piece can credit them until they are gone
+        songwriters::dsl::songwriters
+            .find(songwriter_id)
+            .select(songwriters::dsl::id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::not_found("Songwriter"))?;
+
+        // a songwriter can only be deleted once no piece credits them
+        let pieces: i64 = piece_songwriters::dsl::piece_songwriters
+            .filter(piece_songwriters::dsl::songwriter_id.eq(songwriter_id))
+            .count()
+            .get_result(conn)?;
+        if pieces > 0 {
+            return Err(ApiError::Conflict(format!(
+                "Songwriter is credited on {} pieces",
+                pieces
+            )));
+        }
+
+        diesel::delete(songwriters::dsl::songwriters.find(songwriter_id)).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Delete an artist from the database
+fn db_deleteartist(
+    delete_req: DeleteArtistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    // determine the type of artist to delete
+    match delete_req.artist_type.as_str() {
+        "performer" => db_deleteperformer(delete_req.id, conn),
+        "composer" => db_deletecomposer(delete_req.id, conn),
+        "songwriter" => db_deletesongwriter(delete_req.id, conn),
+        _ => Err(ApiError::Validation("Invalid artist type".to_string())),
+    }
+}
+
+/// Delete a piece and its credits from the database, refusing while it has recordings
+fn db_deletepiece(
+    delete_req: DeleteRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{piece_composers, piece_songwriters, pieces, recordings};
+
+    let piece_id: i32 = delete_req.id;
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // lock the piece, so no recording can be added to it until it is gone
+        pieces::dsl::pieces
+            .find(piece_id)
+            .select(pieces::dsl::id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::not_found("Piece"))?;
+
+        // recordings belong to other releases too, so they are never deleted along with the piece
+        let recordings: i64 = recordings::dsl::recordings
+            .filter(recordings::dsl::piece_id.eq(piece_id))
+            .count()
+            .get_result(conn)?;
+        if recordings > 0 {
+            return Err(ApiError::Conflict(format!(
+                "Piece has {} recordings",
+                recordings
+            )));
+        }
+
+        diesel::delete(
+            piece_composers::dsl::piece_composers
+                .filter(piece_composers::dsl::piece_id.eq(piece_id)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            piece_songwriters::dsl::piece_songwriters
+                .filter(piece_songwriters::dsl::piece_id.eq(piece_id)),
+        )
+        .execute(conn)?;
+        diesel::delete(pieces::dsl::pieces.find(piece_id)).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Delete recordings by id along with their performer credits
+fn db_deleterecordings(
+    recording_ids: Vec<i32>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<usize> {
+    use crate::schema::{recording_performers, recordings};
+
+    diesel::delete(
+        recording_performers::dsl::recording_performers
+            .filter(recording_performers::dsl::recording_id.eq_any(&recording_ids)),
+    )
+    .execute(conn)?;
+    diesel::delete(recordings::dsl::recordings.filter(recordings::dsl::id.eq_any(&recording_ids)))
+        .execute(conn)
+}
+
+/// Delete a release and its credits from the database, refusing while it has recordings unless
+/// they are deleted along with it
+fn db_deleterelease(
+    delete_req: DeleteReleaseRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{recordings, release_performers, releases};
+
+    let release_id: i32 = delete_req.id;
+    conn.transaction::<(), ApiError, _>(|conn| {
+        // lock the release, so no recording can be added to it until it is gone
+        releases::dsl::releases
+            .find(release_id)
+            .select(releases::dsl::id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::not_found("Release"))?;
+
+        // recordings cannot exist without their release
+        let recording_ids: Vec<i32> = recordings::dsl::recordings
+            .filter(recordings::dsl::release_id.eq(release_id))
+            .select(recordings::dsl::id)
+            .load::<i32>(conn)?;
+        if !recording_ids.is_empty() && !delete_req.cascade.unwrap_or(false) {
+            return Err(ApiError::Conflict(format!(
+                "Release has {} recordings",
+                recording_ids.len()
+            )));
+        }
+
+        // files on disk are left in place, since they may belong to the scanned library
+        db_deleterecordings(recording_ids, conn)?;
+        diesel::delete(
+            release_performers::dsl::release_performers
+                .filter(release_performers::dsl::release_id.eq(release_id)),
+        )
+        .execute(conn)?;
+        diesel::delete(releases::dsl::releases.find(release_id)).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Delete a recording and its performer credits from the database
+fn db_deleterecording(
+    delete_req: DeleteRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    conn.transaction::<(), ApiError, _>(|conn| {
+        match db_deleterecordings(vec![delete_req.id], conn)? {
+            0 => Err(ApiError::not_found("Recording")),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Update an artist in the database
+#[patch("/music/update/artist")]
+pub async fn updateartist(
+    _caller: Authorized<requires::CatalogEdit>,
+    update_req: Json<UpdateArtistRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the update in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_updateartist(update_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Update a release in the database
+#[patch("/music/update/release")]
+pub async fn updaterelease(
+    _caller: Authorized<requires::CatalogEdit>,
+    update_req: Json<UpdateReleaseRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the update in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_updaterelease(update_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Update a piece in the database
+#[patch("/music/update/piece")]
+pub async fn updatepiece(
+    _caller: Authorized<requires::CatalogEdit>,
+    update_req: Json<UpdatePieceRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the update in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_updatepiece(update_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Update a recording in the database
+#[patch("/music/update/recording")]
+pub async fn updaterecording(
+    _caller: Authorized<requires::CatalogEdit>,
+    update_req: Json<UpdateRecordingRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the update in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_updaterecording(update_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Renumber the tracks of a release
+#[patch("/music/update/tracks")]
+pub async fn reordertracks(
+    _caller: Authorized<requires::CatalogEdit>,
+    reorder_req: Json<ReorderTracksRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the new order in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_reordertracks(reorder_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Delete an artist from the database
+#[delete("/music/delete/artist")]
+pub async fn deleteartist(
+    _caller: Authorized<requires::CatalogEdit>,
+    delete_req: Json<DeleteArtistRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the deletion in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deleteartist(delete_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Delete a piece from the database
+#[delete("/music/delete/piece")]
+pub async fn deletepiece(
+    _caller: Authorized<requires::CatalogEdit>,
+    delete_req: Json<DeleteRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the deletion in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deletepiece(delete_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Delete a release from the database
+#[delete("/music/delete/release")]
+pub async fn deleterelease(
+    _caller: Authorized<requires::CatalogEdit>,
+    delete_req: Json<DeleteReleaseRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the deletion in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deleterelease(delete_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Delete a recording from the database
+#[delete("/music/delete/recording")]
+pub async fn deleterecording(
+    _caller: Authorized<requires::CatalogEdit>,
+    delete_req: Json<DeleteRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the deletion in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deleterecording(delete_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+    use serde_json::json;
+
+    /// Catalog entries the tests change
+    struct Catalog {
+        performer_id: i32,
+        piece_id: i32,
+        release_id: i32,
+        recording_ids: Vec<i32>,
+    }
+
+    /// Add a described release by a performer, holding three recordings of a piece
+    fn catalog(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> Catalog {
+        use crate::schema::{pieces, recordings, release_performers, releases};
+
+        let performer_id: i32 = performer("Glenn Gould", conn);
+        let piece_id: i32 = diesel::insert_into(pieces::table)
+            .values(pieces::name.eq("Goldberg Variations"))
+            .returning(pieces::id)
+            .get_result(conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values((
+                releases::name.eq("Goldberg Variations (1981)"),
+                releases::description.eq("Recorded in New York"),
+            ))
+            .returning(releases::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(release_performers::table)
+            .values((
+                release_performers::release_id.eq(release_id),
+                release_performers::performer_id.eq(performer_id),
+            ))
+            .execute(conn)
+            .unwrap();
+        let recording_ids: Vec<i32> = (1..=3)
+            .map(|track_number| {
+                diesel::insert_into(recordings::table)
+                    .values((
+                        recordings::piece_name.eq(format!("Variation {}", track_number)),
+                        recordings::piece_id.eq(piece_id),
+                        recordings::release_id.eq(release_id),
+                        recordings::track_number.eq(track_number),
+                    ))
+                    .returning(recordings::id)
+                    .get_result(conn)
+                    .unwrap()
+            })
+            .collect();
+        Catalog {
+            performer_id,
+            piece_id,
+            release_id,
+            recording_ids,
+        }
+    }
+
+    /// Add a performer credited on nothing
+    fn performer(name: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::performers;
+
+        diesel::insert_into(performers::table)
+            .values(performers::name.eq(name))
+            .returning(performers::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// The name, description and performers of a release
+    fn release(
+        release_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (String, Option<String>, Vec<i32>) {
+        use crate::schema::{release_performers, releases};
+
+        let (name, description) = releases::table
+            .find(release_id)
+            .select((releases::name, releases::description))
+            .first::<(String, Option<String>)>(conn)
+            .unwrap();
+        let performer_ids: Vec<i32> = release_performers::table
+            .filter(release_performers::release_id.eq(release_id))
+            .select(release_performers::performer_id)
+            .load::<i32>(conn)
+            .unwrap();
+        (name, description, performer_ids)
+    }
+
+    /// The recordings of a release in track order
+    fn tracks(
+        release_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Vec<i32> {
+        use crate::schema::recordings;
+
+        recordings::table
+            .filter(recordings::release_id.eq(release_id))
+            .order(recordings::track_number.asc())
+            .select(recordings::id)
+            .load::<i32>(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn updates_only_the_given_fields() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let catalog: Catalog = catalog(&mut conn);
+
+        // a missing field is left alone
+        let update_req: UpdateReleaseRequest =
+            serde_json::from_value(json!({ "id": catalog.release_id, "name": "Goldbergs" }))
+                .unwrap();
+        db_updaterelease(update_req, &mut conn).unwrap();
+        assert_eq!(
+            release(catalog.release_id, &mut conn),
+            (
+                "Goldbergs".to_string(),
+                Some("Recorded in New York".to_string()),
+                vec![catalog.performer_id]
+            )
+        );
+
+        // null clears a field and given credits replace the old ones
+        let other_id: i32 = performer("Murray Perahia", &mut conn);
+        let update_req: UpdateReleaseRequest = serde_json::from_value(json!({
+            "id": catalog.release_id,
+            "description": null,
+            "performer_ids": [other_id],
+        }))
+        .unwrap();
+        db_updaterelease(update_req, &mut conn).unwrap();
+        assert_eq!(
+            release(catalog.release_id, &mut conn),
+            ("Goldbergs".to_string(), None, vec![other_id])
+        );
+    }
+
+    #[test]
+    fn lists_unknown_ids_on_update() {
+        use crate::schema::recordings;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let catalog: Catalog = catalog(&mut conn);
+
+        let update_req: UpdateRecordingRequest = serde_json::from_value(json!({
+            "id": catalog.recording_ids[0],
+            "piece_name": "Aria",
+            "piece_id": -1,
+            "release_id": -2,
+            "performer_ids": [catalog.performer_id, -3],
+        }))
+        .unwrap();
+        let Err(ApiError::UnknownIds(unknown_ids)) = db_updaterecording(update_req, &mut conn)
+        else {
+            panic!("unknown ids were not listed");
+        };
+        assert_eq!(unknown_ids.piece_ids, vec![-1]);
+        assert_eq!(unknown_ids.release_ids, vec![-2]);
+        assert_eq!(unknown_ids.performer_ids, vec![-3]);
+
+        // nothing is changed when any id is unknown
+        let piece_name: String = recordings::table
+            .find(catalog.recording_ids[0])
+            .select(recordings::piece_name)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(piece_name, "Variation 1");
+
+        let update_req: UpdatePieceRequest = serde_json::from_value(json!({
+            "id": catalog.piece_id,
+            "composer_ids": [-4],
+            "songwriter_ids": [-5],
+        }))
+        .unwrap();
+        let Err(ApiError::UnknownIds(unknown_ids)) = db_updatepiece(update_req, &mut conn) else {
+            panic!("unknown ids were not listed");
+        };
+        assert_eq!(unknown_ids.composer_ids, vec![-4]);
+        assert_eq!(unknown_ids.songwriter_ids, vec![-5]);
+    }
+
+    #[test]
+    fn reorders_every_track_of_a_release() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let catalog: Catalog = catalog(&mut conn);
+        let ids: &[i32] = &catalog.recording_ids;
+
+        let reorder_req = ReorderTracksRequest {
+            release_id: catalog.release_id,
+            recording_ids: vec![ids[2], ids[0], ids[1]],
+        };
+        db_reordertracks(reorder_req, &mut conn).unwrap();
+        assert_eq!(
+            tracks(catalog.release_id, &mut conn),
+            vec![ids[2], ids[0], ids[1]]
+        );
+
+        // leaving a recording out changes nothing
+        let reorder_req = ReorderTracksRequest {
+            release_id: catalog.release_id,
+            recording_ids: vec![ids[0], ids[1]],
+        };
+        let result = db_reordertracks(reorder_req, &mut conn);
+        assert!(matches!(result, Err(ApiError::Validation(_))));
+        assert_eq!(
+            tracks(catalog.release_id, &mut conn),
+            vec![ids[2], ids[0], ids[1]]
+        );
+    }
+
+    #[test]
+    fn refuses_to_reorder_a_missing_release() {
+        use crate::schema::releases;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("Goldberg Variations (1955)"))
+            .returning(releases::id)
+            .get_result(&mut conn)
+            .unwrap();
+        diesel::delete(releases::table.find(release_id))
+            .execute(&mut conn)
+            .unwrap();
+
+        let reorder_req = ReorderTracksRequest {
+            release_id,
+            recording_ids: Vec::new(),
+        };
+        let result = db_reordertracks(reorder_req, &mut conn);
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn deletes_releases_with_their_recordings_only_when_asked() {
+        use crate::schema::{recordings, releases};
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let catalog: Catalog = catalog(&mut conn);
+
+        let delete_req = DeleteReleaseRequest {
+            id: catalog.release_id,
+            cascade: None,
+        };
+        let result = db_deleterelease(delete_req, &mut conn);
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+        assert_eq!(tracks(catalog.release_id, &mut conn).len(), 3);
+
+        let delete_req = DeleteReleaseRequest {
+            id: catalog.release_id,
+            cascade: Some(true),
+        };
+        db_deleterelease(delete_req, &mut conn).unwrap();
+        let releases: i64 = releases::table
+            .find(catalog.release_id)
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(releases, 0);
+        let recordings: i64 = recordings::table
+            .filter(recordings::id.eq_any(&catalog.recording_ids))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(recordings, 0);
+
+        let delete_req = DeleteReleaseRequest {
+            id: catalog.release_id,
+            cascade: Some(true),
+        };
+        let result = db_deleterelease(delete_req, &mut conn);
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn refuses_to_delete_credited_entries() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let catalog: Catalog = catalog(&mut conn);
+
+        let result = db_deletepiece(
+            DeleteRequest {
+                id: catalog.piece_id,
+            },
+            &mut conn,
+        );
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+        let result = db_deleteperformer(catalog.performer_id, &mut conn);
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+
+        // once nothing credits them they can go
+        let other_id: i32 = performer("Murray Perahia", &mut conn);
+        db_deleteperformer(other_id, &mut conn).unwrap();
+        let result = db_deleteperformer(other_id, &mut conn);
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+    }
+}
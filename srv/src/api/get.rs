@@ -1,11 +1,17 @@
+use crate::api::auth::{Authorized, Caller};
+use crate::api::library::{db_applystates, EntityType, LibraryView};
+use crate::error::ApiError;
 use crate::models::{Composer, DbPiece, DbRecording, DbRelease, Performer, Songwriter};
+use crate::permission::requires;
 use crate::{IdRequest, Response};
 
-use actix_web::web::{Data, Json};
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::web::{Data, Query};
+use actix_web::{get, web, HttpResponse};
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Represents a recording with all its associated data
 #[derive(Debug, Deserialize, Serialize)]
@@ -17,6 +23,11 @@ pub struct Recording {
     pub performer_ids: Vec<i32>,
     pub track_number: i32,
     pub file_path: Option<String>,
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favorite: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<i16>,
 }
 
 impl Recording {
@@ -30,10 +41,26 @@ impl Recording {
             performer_ids: Vec::new(),
             track_number: -1,
             file_path: None,
+            available: false,
+            favorite: None,
+            rating: None,
         }
     }
 }
 
+impl LibraryView for Recording {
+    const ENTITY_TYPE: EntityType = EntityType::Recording;
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_state(&mut self, favorite: bool, rating: Option<i16>) {
+        self.favorite = Some(favorite);
+        self.rating = rating;
+    }
+}
+
 /// Represents a release with all its associated data
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Release {
@@ -43,6 +70,10 @@ pub struct Release {
     pub image_path: Option<String>,
     pub recording_ids: Option<Vec<i32>>,
     pub performer_ids: Vec<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favorite: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<i16>,
 }
 
 impl Release {
@@ -55,10 +86,25 @@ impl Release {
             image_path: None,
             recording_ids: None,
             performer_ids: Vec::new(),
+            favorite: None,
+            rating: None,
         }
     }
 }
 
+impl LibraryView for Release {
+    const ENTITY_TYPE: EntityType = EntityType::Release;
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_state(&mut self, favorite: bool, rating: Option<i16>) {
+        self.favorite = Some(favorite);
+        self.rating = rating;
+    }
+}
+
 /// Represents a piece with all its associated data
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Piece {
@@ -68,6 +114,10 @@ pub struct Piece {
     pub description: Option<String>,
     pub composer_ids: Vec<i32>,
     pub songwriter_ids: Option<Vec<i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favorite: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<i16>,
 }
 
 impl Piece {
@@ -80,786 +130,1126 @@ impl Piece {
             description: None,
             composer_ids: Vec::new(),
             songwriter_ids: None,
+            favorite: None,
+            rating: None,
+        }
+    }
+}
+
+impl LibraryView for Piece {
+    const ENTITY_TYPE: EntityType = EntityType::Piece;
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn set_state(&mut self, favorite: bool, rating: Option<i16>) {
+        self.favorite = Some(favorite);
+        self.rating = rating;
+    }
+}
+
+// number of entries in a page when no limit is given
+const DEFAULT_LIMIT: i64 = 50;
+
+// largest page that can be requested at once
+const MAX_LIMIT: i64 = 500;
+
+/// Column a list of entries is sorted by
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Id,
+    Name,
+    Added,
+}
+
+/// Direction a list of entries is sorted in
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// A request for one page of a list of entries, optionally filtered by related artists
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ListRequest {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub sort: Option<SortKey>,
+    pub order: Option<SortOrder>,
+    pub composer_id: Option<i32>,
+    pub songwriter_id: Option<i32>,
+    pub performer_id: Option<i32>,
+}
+
+impl ListRequest {
+    /// Validate the requested page, returning its limit and offset
+    pub fn page(&self) -> Result<(i64, i64), ApiError> {
+        let limit: i64 = self.limit.unwrap_or(DEFAULT_LIMIT);
+        let offset: i64 = self.offset.unwrap_or(0);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(ApiError::Validation(format!(
+                "Limit must be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+        if offset < 0 {
+            return Err(ApiError::Validation(
+                "Offset cannot be negative".to_string(),
+            ));
+        }
+        Ok((limit, offset))
+    }
+
+    /// Refuse filters by related artists other than the ones a list can be filtered by, rather
+    /// than listing every entry as if they were not given
+    pub fn check_filters(&self, filters: &[&str]) -> Result<(), ApiError> {
+        let given: [(&str, Option<i32>); 3] = [
+            ("composer_id", self.composer_id),
+            ("songwriter_id", self.songwriter_id),
+            ("performer_id", self.performer_id),
+        ];
+        for (name, id) in given {
+            if id.is_some() && !filters.contains(&name) {
+                return Err(ApiError::Validation(format!(
+                    "This list cannot be filtered by {}",
+                    name
+                )));
+            }
         }
+        Ok(())
+    }
+
+    /// The sort column and direction, defaulting to ascending ids
+    pub fn sorting(&self) -> (SortKey, SortOrder) {
+        (
+            self.sort.unwrap_or_default(),
+            self.order.unwrap_or_default(),
+        )
     }
 }
 
+/// One page of a list of entries, along with the total number of matching entries
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    pub next_offset: Option<i64>,
+}
+
+impl<T> Page<T> {
+    /// Create a page, pointing at the next one if there are entries left
+    pub fn new(items: Vec<T>, total: i64, limit: i64, offset: i64) -> Self {
+        let next_offset: Option<i64> = offset
+            .checked_add(limit)
+            .filter(|next_offset| *next_offset < total);
+        Page {
+            items,
+            total,
+            limit,
+            offset,
+            next_offset,
+        }
+    }
+}
+
+/// Group the (owner id, related id) rows of a join table by owner
+fn group_ids(rows: Vec<(i32, i32)>) -> HashMap<i32, Vec<i32>> {
+    let mut groups: HashMap<i32, Vec<i32>> = HashMap::new();
+    for (owner_id, related_id) in rows {
+        groups.entry(owner_id).or_default().push(related_id);
+    }
+    groups
+}
+
+/// Assemble full pieces from their rows, loading the credits of every piece at once
+pub(crate) fn db_assemblepieces(
+    db_pieces: Vec<DbPiece>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Piece>, ApiError> {
+    use crate::schema::{piece_composers, piece_songwriters};
+
+    // get the composer and songwriter IDs of all pieces in one query each
+    let piece_ids: Vec<i32> = db_pieces.iter().map(|db_piece| db_piece.id).collect();
+    let mut composer_ids: HashMap<i32, Vec<i32>> = group_ids(
+        piece_composers::dsl::piece_composers
+            .filter(piece_composers::dsl::piece_id.eq_any(&piece_ids))
+            .select((
+                piece_composers::dsl::piece_id,
+                piece_composers::dsl::composer_id,
+            ))
+            .load::<(i32, i32)>(conn)?,
+    );
+    let mut songwriter_ids: HashMap<i32, Vec<i32>> = group_ids(
+        piece_songwriters::dsl::piece_songwriters
+            .filter(piece_songwriters::dsl::piece_id.eq_any(&piece_ids))
+            .select((
+                piece_songwriters::dsl::piece_id,
+                piece_songwriters::dsl::songwriter_id,
+            ))
+            .load::<(i32, i32)>(conn)?,
+    );
+
+    // construct the full piece objects
+    let pieces: Vec<Piece> = db_pieces
+        .into_iter()
+        .map(|db_piece| Piece {
+            id: db_piece.id,
+            name: db_piece.name,
+            movements: db_piece.movements,
+            description: db_piece.description,
+            composer_ids: composer_ids.remove(&db_piece.id).unwrap_or_default(),
+            songwriter_ids: songwriter_ids.remove(&db_piece.id),
+            favorite: None,
+            rating: None,
+        })
+        .collect();
+
+    Ok(pieces)
+}
+
+/// Assemble full releases from their rows, loading the performers and recordings of every
+/// release at once
+pub(crate) fn db_assemblereleases(
+    db_releases: Vec<DbRelease>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Release>, ApiError> {
+    use crate::schema::{recordings, release_performers};
+
+    // get the performer and recording IDs of all releases in one query each
+    let release_ids: Vec<i32> = db_releases.iter().map(|db_release| db_release.id).collect();
+    let mut performer_ids: HashMap<i32, Vec<i32>> = group_ids(
+        release_performers::dsl::release_performers
+            .filter(release_performers::dsl::release_id.eq_any(&release_ids))
+            .select((
+                release_performers::dsl::release_id,
+                release_performers::dsl::performer_id,
+            ))
+            .load::<(i32, i32)>(conn)?,
+    );
+    let mut recording_ids: HashMap<i32, Vec<i32>> = group_ids(
+        recordings::dsl::recordings
+            .filter(recordings::dsl::release_id.eq_any(&release_ids))
+            .order(recordings::dsl::track_number.asc())
+            .select((recordings::dsl::release_id, recordings::dsl::id))
+            .load::<(i32, i32)>(conn)?,
+    );
+
+    // construct the full release objects
+    let releases: Vec<Release> = db_releases
+        .into_iter()
+        .map(|db_release| Release {
+            id: db_release.id,
+            name: db_release.name,
+            description: db_release.description,
+            image_path: db_release.image_path,
+            recording_ids: recording_ids.remove(&db_release.id),
+            performer_ids: performer_ids.remove(&db_release.id).unwrap_or_default(),
+            favorite: None,
+            rating: None,
+        })
+        .collect();
+
+    Ok(releases)
+}
+
+/// Assemble full recordings from their rows, loading the performers of every recording at once
+pub(crate) fn db_assemblerecordings(
+    db_recordings: Vec<DbRecording>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Recording>, ApiError> {
+    use crate::schema::recording_performers;
+
+    // get the performer IDs of all recordings in one query
+    let recording_ids: Vec<i32> = db_recordings
+        .iter()
+        .map(|db_recording| db_recording.id)
+        .collect();
+    let mut performer_ids: HashMap<i32, Vec<i32>> = group_ids(
+        recording_performers::dsl::recording_performers
+            .filter(recording_performers::dsl::recording_id.eq_any(&recording_ids))
+            .select((
+                recording_performers::dsl::recording_id,
+                recording_performers::dsl::performer_id,
+            ))
+            .load::<(i32, i32)>(conn)?,
+    );
+
+    // construct the full recording objects
+    let recordings: Vec<Recording> = db_recordings
+        .into_iter()
+        .map(|db_recording| Recording {
+            id: db_recording.id,
+            piece_name: db_recording.piece_name,
+            piece_id: db_recording.piece_id,
+            release_id: db_recording.release_id,
+            performer_ids: performer_ids.remove(&db_recording.id).unwrap_or_default(),
+            track_number: db_recording.track_number,
+            file_path: db_recording.file_path,
+            available: db_recording.available,
+            favorite: None,
+            rating: None,
+        })
+        .collect();
+
+    Ok(recordings)
+}
+
 /// Get specific performer by id from the performers index
 fn db_getperformer<T>(
     performer_req: IdRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Performer> {
+) -> Result<Performer, ApiError> {
     use crate::schema::performers;
 
     // get the performer from the database
-    let performer_res = performers::dsl::performers
+    let performer: Performer = performers::dsl::performers
         .filter(performers::dsl::id.eq(performer_req.id))
-        .first::<Performer>(conn);
-    let performer: Performer = match performer_res {
-        Ok(_) => performer_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Performer::new(),
-            };
-        }
-    };
+        .first::<Performer>(conn)?;
 
-    Response {
-        success: true,
-        message: performer,
-    }
+    Ok(performer)
 }
 
 /// Get specific composer by id from the composers index
 fn db_getcomposer<T>(
     composer_req: IdRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Composer> {
+) -> Result<Composer, ApiError> {
     use crate::schema::composers;
 
     // get the composer from the database
-    let composer_res = composers::dsl::composers
+    let composer: Composer = composers::dsl::composers
         .filter(composers::dsl::id.eq(composer_req.id))
-        .first::<Composer>(conn);
-    let composer: Composer = match composer_res {
-        Ok(_) => composer_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Composer::new(),
-            };
-        }
-    };
+        .first::<Composer>(conn)?;
 
-    Response {
-        success: true,
-        message: composer,
-    }
+    Ok(composer)
 }
 
 /// Get specific songwriter by id from the songwriters index
 fn db_getsongwriter<T>(
     songwriter_req: IdRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Songwriter> {
+) -> Result<Songwriter, ApiError> {
     use crate::schema::songwriters;
 
     // get the songwriter from the database
-    let songwriter_res = songwriters::dsl::songwriters
+    let songwriter: Songwriter = songwriters::dsl::songwriters
         .filter(songwriters::dsl::id.eq(songwriter_req.id))
-        .first::<Songwriter>(conn);
-    let songwriter: Songwriter = match songwriter_res {
-        Ok(_) => songwriter_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Songwriter::new(),
-            };
-        }
-    };
+        .first::<Songwriter>(conn)?;
 
-    Response {
-        success: true,
-        message: songwriter,
-    }
+    Ok(songwriter)
 }
 
 /// Get specific release by id from the releases index
 fn db_getrelease<T>(
+    caller: Caller,
     release_req: IdRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Release> {
-    use crate::schema::{recordings, release_performers, releases};
+) -> Result<Release, ApiError> {
+    use crate::schema::releases;
 
     // get the basic release data
-    let db_release_res = releases::dsl::releases
+    let db_release: DbRelease = releases::dsl::releases
         .filter(releases::dsl::id.eq(release_req.id))
-        .first::<DbRelease>(conn);
-
-    let db_release: DbRelease = match db_release_res {
-        Ok(_) => db_release_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Release::new(),
-            };
-        }
-    };
+        .first::<DbRelease>(conn)?;
 
-    // get all performer IDs for this release
-    let performer_ids: Vec<i32> = release_performers::dsl::release_performers
-        .filter(release_performers::dsl::release_id.eq(release_req.id))
-        .select(release_performers::dsl::performer_id)
-        .load::<i32>(conn)
-        .unwrap_or_default();
+    // construct the full release object with the caller's star and rating
+    let mut releases: Vec<Release> = db_assemblereleases(vec![db_release], conn)?;
+    db_applystates(&caller, &mut releases, conn)?;
+    let release: Release = releases
+        .pop()
+        .ok_or_else(|| ApiError::not_found("Release"))?;
 
-    // get IDs of all recordings on this release, if they exist
-    let recording_ids: Vec<i32> = recordings::dsl::recordings
-        .filter(recordings::dsl::release_id.eq(release_req.id))
-        .select(recordings::dsl::id)
-        .load::<i32>(conn)
-        .unwrap_or_default();
-
-    // construct the full release object
-    let release = Release {
-        id: db_release.id,
-        name: db_release.name,
-        description: db_release.description,
-        image_path: db_release.image_path,
-        recording_ids: if recording_ids.is_empty() {
-            None
-        } else {
-            Some(recording_ids)
-        },
-        performer_ids,
-    };
-
-    Response {
-        success: true,
-        message: release,
-    }
+    Ok(release)
 }
 
 /// Gets recordings by release id from the recordings index
 fn db_getrecordings<T>(
+    caller: Caller,
     release_req: IdRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Recording>> {
-    use crate::schema::{recording_performers, recordings};
+) -> Result<Vec<Recording>, ApiError> {
+    use crate::schema::recordings;
 
-    // get all recording IDs for this release
-    let recording_ids: Vec<i32> = recordings::dsl::recordings
+    // get all recordings for this release in track order
+    let db_recordings: Vec<DbRecording> = recordings::dsl::recordings
         .filter(recordings::dsl::release_id.eq(release_req.id))
-        .select(recordings::dsl::id)
-        .load::<i32>(conn)
-        .unwrap_or_default();
-
-    // get all recordings for this release
-    let recordings_res = recordings::dsl::recordings
-        .filter(recordings::dsl::id.eq_any(&recording_ids))
-        .load::<DbRecording>(conn);
-    let db_recordings: Vec<DbRecording> = match recordings_res {
-        Ok(_) => recordings_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
-    };
-
-    // for each recording, get all performer IDs
-    let mut recordings: Vec<Recording> = Vec::new();
-    for db_recording in db_recordings {
-        // get all performer IDs for this recording
-        let performer_ids: Vec<i32> = recording_performers::dsl::recording_performers
-            .filter(recording_performers::dsl::recording_id.eq(db_recording.id))
-            .select(recording_performers::dsl::performer_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // construct the full recording object
-        let recording = Recording {
-            id: db_recording.id,
-            piece_name: db_recording.piece_name,
-            piece_id: db_recording.piece_id,
-            release_id: db_recording.release_id,
-            performer_ids,
-            track_number: db_recording.track_number,
-            file_path: db_recording.file_path,
-        };
-
-        // push the recording to the recordings vector
-        recordings.push(recording);
-    }
+        .order(recordings::dsl::track_number.asc())
+        .load::<DbRecording>(conn)?;
 
-    Response {
-        success: true,
-        message: recordings,
-    }
+    let mut recordings: Vec<Recording> = db_assemblerecordings(db_recordings, conn)?;
+    db_applystates(&caller, &mut recordings, conn)?;
+    Ok(recordings)
 }
 
 /// Get specific recording by id from the recordings index
 fn db_getrecording<T>(
+    caller: Caller,
     recording_req: IdRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Recording> {
-    use crate::schema::{recording_performers, recordings};
+) -> Result<Recording, ApiError> {
+    use crate::schema::recordings;
 
     // get the basic recording data
-    let recording_res = recordings::dsl::recordings
+    let db_recording: DbRecording = recordings::dsl::recordings
         .filter(recordings::dsl::id.eq(recording_req.id))
-        .first::<DbRecording>(conn);
-
-    let db_recording: DbRecording = match recording_res {
-        Ok(_) => recording_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Recording::new(),
-            };
-        }
-    };
+        .first::<DbRecording>(conn)?;
 
-    // get all performer IDs for this recording
-    let performer_ids: Vec<i32> = recording_performers::dsl::recording_performers
-        .filter(recording_performers::dsl::recording_id.eq(recording_req.id))
-        .select(recording_performers::dsl::performer_id)
-        .load::<i32>(conn)
-        .unwrap_or_default();
-
-    // construct the full recording object
-    let recording = Recording {
-        id: db_recording.id,
-        piece_name: db_recording.piece_name,
-        piece_id: db_recording.piece_id,
-        release_id: db_recording.release_id,
-        performer_ids,
-        track_number: db_recording.track_number,
-        file_path: db_recording.file_path,
-    };
+    // construct the full recording object with the caller's star and rating
+    let mut recordings: Vec<Recording> = db_assemblerecordings(vec![db_recording], conn)?;
+    db_applystates(&caller, &mut recordings, conn)?;
+    let recording: Recording = recordings
+        .pop()
+        .ok_or_else(|| ApiError::not_found("Recording"))?;
 
-    Response {
-        success: true,
-        message: recording,
-    }
+    Ok(recording)
 }
 
 /// Get specific piece by id from the pieces index
 fn db_getpiece<T>(
+    caller: Caller,
     piece_req: IdRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Piece> {
-    use crate::schema::{piece_composers, piece_songwriters, pieces};
+) -> Result<Piece, ApiError> {
+    use crate::schema::pieces;
 
     // Get the basic piece data
-    let db_piece_res = pieces::dsl::pieces
+    let db_piece: DbPiece = pieces::dsl::pieces
         .filter(pieces::dsl::id.eq(piece_req.id))
-        .first::<DbPiece>(conn);
-
-    let db_piece: DbPiece = match db_piece_res {
-        Ok(_) => db_piece_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Piece::new(),
-            };
-        }
-    };
+        .first::<DbPiece>(conn)?;
 
-    // get all composer IDs for this piece
-    let composer_ids: Vec<i32> = piece_composers::dsl::piece_composers
-        .filter(piece_composers::dsl::piece_id.eq(piece_req.id))
-        .select(piece_composers::dsl::composer_id)
-        .load::<i32>(conn)
-        .unwrap_or_default();
-
-    // get all songwriter IDs for this piece
-    let songwriter_ids: Vec<i32> = piece_songwriters::dsl::piece_songwriters
-        .filter(piece_songwriters::dsl::piece_id.eq(piece_req.id))
-        .select(piece_songwriters::dsl::songwriter_id)
-        .load::<i32>(conn)
-        .unwrap_or_default();
-
-    // construct the full piece object
-    let piece = Piece {
-        id: db_piece.id,
-        name: db_piece.name,
-        movements: db_piece.movements,
-        description: db_piece.description,
-        composer_ids,
-        songwriter_ids: if songwriter_ids.is_empty() {
-            None
-        } else {
-            Some(songwriter_ids)
-        },
-    };
+    // construct the full piece object with the caller's star and rating
+    let mut pieces: Vec<Piece> = db_assemblepieces(vec![db_piece], conn)?;
+    db_applystates(&caller, &mut pieces, conn)?;
+    let piece: Piece = pieces.pop().ok_or_else(|| ApiError::not_found("Piece"))?;
 
-    Response {
-        success: true,
-        message: piece,
+    Ok(piece)
+}
+
+/// Select the pieces matching the filters of a list request
+fn pieces_query(list_req: &ListRequest) -> crate::schema::pieces::BoxedQuery<'static, Pg> {
+    use crate::schema::{piece_composers, piece_songwriters, pieces};
+
+    let mut query = pieces::dsl::pieces.into_boxed();
+    if let Some(composer_id) = list_req.composer_id {
+        query = query.filter(
+            pieces::dsl::id.eq_any(
+                piece_composers::dsl::piece_composers
+                    .filter(piece_composers::dsl::composer_id.eq(composer_id))
+                    .select(piece_composers::dsl::piece_id),
+            ),
+        );
+    }
+    if let Some(songwriter_id) = list_req.songwriter_id {
+        query = query.filter(
+            pieces::dsl::id.eq_any(
+                piece_songwriters::dsl::piece_songwriters
+                    .filter(piece_songwriters::dsl::songwriter_id.eq(songwriter_id))
+                    .select(piece_songwriters::dsl::piece_id),
+            ),
+        );
     }
+    query
 }
 
-/// Get all pieces in the pieces index
+/// Get a page of pieces in the pieces index
 fn db_getpieces<T>(
+    caller: Caller,
+    list_req: ListRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Piece>> {
-    use crate::schema::{piece_composers, piece_songwriters, pieces};
-
-    // get all basic piece data
-    let db_pieces_res = pieces::dsl::pieces.load::<DbPiece>(conn);
-    let db_pieces: Vec<DbPiece> = match db_pieces_res {
-        Ok(_) => db_pieces_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
-    };
+) -> Result<Page<Piece>, ApiError> {
+    use crate::schema::pieces;
+
+    list_req.check_filters(&["composer_id", "songwriter_id"])?;
+    let (limit, offset) = list_req.page()?;
+
+    // count every matching piece, then load the requested page of them
+    let total: i64 = pieces_query(&list_req).count().get_result(conn)?;
+    let query =
+        match list_req.sorting() {
+            (SortKey::Id, SortOrder::Asc) => pieces_query(&list_req).order(pieces::dsl::id.asc()),
+            (SortKey::Id, SortOrder::Desc) => pieces_query(&list_req).order(pieces::dsl::id.desc()),
+            (SortKey::Name, SortOrder::Asc) => {
+                pieces_query(&list_req).order((pieces::dsl::name.asc(), pieces::dsl::id.asc()))
+            }
+            (SortKey::Name, SortOrder::Desc) => {
+                pieces_query(&list_req).order((pieces::dsl::name.desc(), pieces::dsl::id.desc()))
+            }
+            (SortKey::Added, SortOrder::Asc) => pieces_query(&list_req)
+                .order((pieces::dsl::created_at.asc(), pieces::dsl::id.asc())),
+            (SortKey::Added, SortOrder::Desc) => pieces_query(&list_req)
+                .order((pieces::dsl::created_at.desc(), pieces::dsl::id.desc())),
+        };
+    let db_pieces: Vec<DbPiece> = query.limit(limit).offset(offset).load::<DbPiece>(conn)?;
 
-    // convert each DB piece into a full Piece with related data
-    let mut full_pieces: Vec<Piece> = Vec::new();
-    for db_piece in db_pieces {
-        // get composer IDs for this piece
-        let composer_ids: Vec<i32> = piece_composers::dsl::piece_composers
-            .filter(piece_composers::dsl::piece_id.eq(db_piece.id))
-            .select(piece_composers::dsl::composer_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // get songwriter IDs for this piece
-        let songwriter_ids: Vec<i32> = piece_songwriters::dsl::piece_songwriters
-            .filter(piece_songwriters::dsl::piece_id.eq(db_piece.id))
-            .select(piece_songwriters::dsl::songwriter_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        full_pieces.push(Piece {
-            id: db_piece.id,
-            name: db_piece.name,
-            movements: db_piece.movements,
-            description: db_piece.description,
-            composer_ids,
-            songwriter_ids: if songwriter_ids.is_empty() {
-                None
-            } else {
-                Some(songwriter_ids)
-            },
-        });
-    }
+    let mut full_pieces: Vec<Piece> = db_assemblepieces(db_pieces, conn)?;
+    db_applystates(&caller, &mut full_pieces, conn)?;
+    Ok(Page::new(full_pieces, total, limit, offset))
+}
 
-    Response {
-        success: true,
-        message: full_pieces,
+/// Select the releases matching the filters of a list request
+fn releases_query(list_req: &ListRequest) -> crate::schema::releases::BoxedQuery<'static, Pg> {
+    use crate::schema::{release_performers, releases};
+
+    let mut query = releases::dsl::releases.into_boxed();
+    if let Some(performer_id) = list_req.performer_id {
+        query = query.filter(
+            releases::dsl::id.eq_any(
+                release_performers::dsl::release_performers
+                    .filter(release_performers::dsl::performer_id.eq(performer_id))
+                    .select(release_performers::dsl::release_id),
+            ),
+        );
     }
+    query
 }
 
-/// Get all releases in the releases index
+/// Get a page of releases in the releases index
 fn db_getreleases<T>(
+    caller: Caller,
+    list_req: ListRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Release>> {
-    use crate::schema::{recordings, release_performers, releases};
-
-    // get all basic release data
-    let db_releases_res = releases::dsl::releases.load::<DbRelease>(conn);
-    let db_releases: Vec<DbRelease> = match db_releases_res {
-        Ok(_) => db_releases_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
+) -> Result<Page<Release>, ApiError> {
+    use crate::schema::releases;
+
+    list_req.check_filters(&["performer_id"])?;
+    let (limit, offset) = list_req.page()?;
+
+    // count every matching release, then load the requested page of them
+    let total: i64 = releases_query(&list_req).count().get_result(conn)?;
+    let query = match list_req.sorting() {
+        (SortKey::Id, SortOrder::Asc) => releases_query(&list_req).order(releases::dsl::id.asc()),
+        (SortKey::Id, SortOrder::Desc) => releases_query(&list_req).order(releases::dsl::id.desc()),
+        (SortKey::Name, SortOrder::Asc) => {
+            releases_query(&list_req).order((releases::dsl::name.asc(), releases::dsl::id.asc()))
+        }
+        (SortKey::Name, SortOrder::Desc) => {
+            releases_query(&list_req).order((releases::dsl::name.desc(), releases::dsl::id.desc()))
         }
+        (SortKey::Added, SortOrder::Asc) => releases_query(&list_req)
+            .order((releases::dsl::created_at.asc(), releases::dsl::id.asc())),
+        (SortKey::Added, SortOrder::Desc) => releases_query(&list_req)
+            .order((releases::dsl::created_at.desc(), releases::dsl::id.desc())),
     };
+    let db_releases: Vec<DbRelease> = query.limit(limit).offset(offset).load::<DbRelease>(conn)?;
 
-    // convert each DB release into a full Release with related data
-    let mut full_releases: Vec<Release> = Vec::new();
-    for db_release in db_releases {
-        // get performer IDs for this release
-        let performer_ids: Vec<i32> = release_performers::dsl::release_performers
-            .filter(release_performers::dsl::release_id.eq(db_release.id))
-            .select(release_performers::dsl::performer_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // get recording IDs for this release
-        let recording_ids: Vec<i32> = recordings::dsl::recordings
-            .filter(recordings::dsl::release_id.eq(db_release.id))
-            .select(recordings::dsl::id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        full_releases.push(Release {
-            id: db_release.id,
-            name: db_release.name,
-            description: db_release.description,
-            image_path: db_release.image_path,
-            recording_ids: if recording_ids.is_empty() {
-                None
-            } else {
-                Some(recording_ids)
-            },
-            performer_ids,
-        });
-    }
-
-    Response {
-        success: true,
-        message: full_releases,
-    }
+    let mut full_releases: Vec<Release> = db_assemblereleases(db_releases, conn)?;
+    db_applystates(&caller, &mut full_releases, conn)?;
+    Ok(Page::new(full_releases, total, limit, offset))
 }
 
-/// Get all performers in the performers index
+/// Get a page of performers in the performers index
 fn db_getperformers<T>(
+    list_req: ListRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Performer>> {
+) -> Result<Page<Performer>, ApiError> {
     use crate::schema::performers;
 
-    // get all performers from the database
-    let artist_res = performers::dsl::performers.load::<Performer>(conn);
-    let artist: Vec<Performer> = match artist_res {
-        Ok(_) => artist_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
+    list_req.check_filters(&[])?;
+    let (limit, offset) = list_req.page()?;
+
+    // count every performer, then load the requested page of them
+    let total: i64 = performers::dsl::performers.count().get_result(conn)?;
+    let query = match list_req.sorting() {
+        (SortKey::Id, SortOrder::Asc) => performers::dsl::performers
+            .into_boxed()
+            .order(performers::dsl::id.asc()),
+        (SortKey::Id, SortOrder::Desc) => performers::dsl::performers
+            .into_boxed()
+            .order(performers::dsl::id.desc()),
+        (SortKey::Name, SortOrder::Asc) => performers::dsl::performers
+            .into_boxed()
+            .order((performers::dsl::name.asc(), performers::dsl::id.asc())),
+        (SortKey::Name, SortOrder::Desc) => performers::dsl::performers
+            .into_boxed()
+            .order((performers::dsl::name.desc(), performers::dsl::id.desc())),
+        (SortKey::Added, SortOrder::Asc) => performers::dsl::performers
+            .into_boxed()
+            .order((performers::dsl::created_at.asc(), performers::dsl::id.asc())),
+        (SortKey::Added, SortOrder::Desc) => performers::dsl::performers.into_boxed().order((
+            performers::dsl::created_at.desc(),
+            performers::dsl::id.desc(),
+        )),
     };
+    let artists: Vec<Performer> = query.limit(limit).offset(offset).load::<Performer>(conn)?;
 
-    Response {
-        success: true,
-        message: artist,
-    }
+    Ok(Page::new(artists, total, limit, offset))
 }
 
-/// Get all composers in the composers index
+/// Get a page of composers in the composers index
 fn db_getcomposers<T>(
+    list_req: ListRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Composer>> {
+) -> Result<Page<Composer>, ApiError> {
     use crate::schema::composers;
 
-    // get all composers from the database
-    let artist_res = composers::dsl::composers.load::<Composer>(conn);
-    let artist: Vec<Composer> = match artist_res {
-        Ok(_) => artist_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
+    list_req.check_filters(&[])?;
+    let (limit, offset) = list_req.page()?;
+
+    // count every composer, then load the requested page of them
+    let total: i64 = composers::dsl::composers.count().get_result(conn)?;
+    let query = match list_req.sorting() {
+        (SortKey::Id, SortOrder::Asc) => composers::dsl::composers
+            .into_boxed()
+            .order(composers::dsl::id.asc()),
+        (SortKey::Id, SortOrder::Desc) => composers::dsl::composers
+            .into_boxed()
+            .order(composers::dsl::id.desc()),
+        (SortKey::Name, SortOrder::Asc) => composers::dsl::composers
+            .into_boxed()
+            .order((composers::dsl::name.asc(), composers::dsl::id.asc())),
+        (SortKey::Name, SortOrder::Desc) => composers::dsl::composers
+            .into_boxed()
+            .order((composers::dsl::name.desc(), composers::dsl::id.desc())),
+        (SortKey::Added, SortOrder::Asc) => composers::dsl::composers
+            .into_boxed()
+            .order((composers::dsl::created_at.asc(), composers::dsl::id.asc())),
+        (SortKey::Added, SortOrder::Desc) => composers::dsl::composers
+            .into_boxed()
+            .order((composers::dsl::created_at.desc(), composers::dsl::id.desc())),
     };
+    let artists: Vec<Composer> = query.limit(limit).offset(offset).load::<Composer>(conn)?;
 
-    Response {
-        success: true,
-        message: artist,
-    }
+    Ok(Page::new(artists, total, limit, offset))
 }
 
-/// Get all songwriters in the performers index
+/// Get a page of songwriters in the songwriters index
 fn db_getsongwriters<T>(
+    list_req: ListRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Songwriter>> {
+) -> Result<Page<Songwriter>, ApiError> {
     use crate::schema::songwriters;
 
-    // get all songwriters from the database
-    let artist_res = songwriters::dsl::songwriters.load::<Songwriter>(conn);
-    let artist: Vec<Songwriter> = match artist_res {
-        Ok(_) => artist_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
+    list_req.check_filters(&[])?;
+    let (limit, offset) = list_req.page()?;
+
+    // count every songwriter, then load the requested page of them
+    let total: i64 = songwriters::dsl::songwriters.count().get_result(conn)?;
+    let query = match list_req.sorting() {
+        (SortKey::Id, SortOrder::Asc) => songwriters::dsl::songwriters
+            .into_boxed()
+            .order(songwriters::dsl::id.asc()),
+        (SortKey::Id, SortOrder::Desc) => songwriters::dsl::songwriters
+            .into_boxed()
+            .order(songwriters::dsl::id.desc()),
+        (SortKey::Name, SortOrder::Asc) => songwriters::dsl::songwriters
+            .into_boxed()
+            .order((songwriters::dsl::name.asc(), songwriters::dsl::id.asc())),
+        (SortKey::Name, SortOrder::Desc) => songwriters::dsl::songwriters
+            .into_boxed()
+            .order((songwriters::dsl::name.desc(), songwriters::dsl::id.desc())),
+        (SortKey::Added, SortOrder::Asc) => songwriters::dsl::songwriters.into_boxed().order((
+            songwriters::dsl::created_at.asc(),
+            songwriters::dsl::id.asc(),
+        )),
+        (SortKey::Added, SortOrder::Desc) => songwriters::dsl::songwriters.into_boxed().order((
+            songwriters::dsl::created_at.desc(),
+            songwriters::dsl::id.desc(),
+        )),
     };
+    let artists: Vec<Songwriter> = query.limit(limit).offset(offset).load::<Songwriter>(conn)?;
 
-    Response {
-        success: true,
-        message: artist,
-    }
+    Ok(Page::new(artists, total, limit, offset))
 }
 
-/// Get all pieces
+/// Get a page of pieces
 #[get("/music/get/pieces")]
-pub async fn getpieces(pool: Data<Pool<ConnectionManager<PgConnection>>>) -> HttpResponse {
+pub async fn getpieces(
+    caller: Authorized<requires::CatalogRead>,
+    list_req: Query<ListRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
     // get the getpieces response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let getpieces_response = web::block(move || db_getpieces::<Vec<DbPiece>>(&mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getpieces_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    let mut conn = pool.get()?;
+    let getpieces_response = web::block(move || {
+        db_getpieces::<Vec<DbPiece>>(caller.into_inner(), list_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getpieces_response)))
 }
 
 /// Get specific piece
-#[post("/music/get/piece")]
+#[get("/music/get/piece")]
 pub async fn getpiece(
-    piece_req: Json<IdRequest>,
+    caller: Authorized<requires::CatalogRead>,
+    piece_req: Query<IdRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the getpiece response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let getpiece_response =
-        web::block(move || db_getpiece::<DbPiece>(piece_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getpiece_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    let mut conn = pool.get()?;
+    let getpiece_response = web::block(move || {
+        db_getpiece::<DbPiece>(caller.into_inner(), piece_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getpiece_response)))
 }
 
-/// Get all releases
+/// Get a page of releases
 #[get("/music/get/releases")]
-pub async fn getreleases(pool: Data<Pool<ConnectionManager<PgConnection>>>) -> HttpResponse {
+pub async fn getreleases(
+    caller: Authorized<requires::CatalogRead>,
+    list_req: Query<ListRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
     // get the getreleases response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let getreleases_response =
-        web::block(move || db_getreleases::<Vec<DbRelease>>(&mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getreleases_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    let mut conn = pool.get()?;
+    let getreleases_response = web::block(move || {
+        db_getreleases::<Vec<DbRelease>>(caller.into_inner(), list_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getreleases_response)))
 }
 
 /// Get specific release
-#[post("/music/get/release")]
+#[get("/music/get/release")]
 pub async fn getrelease(
-    release_req: Json<IdRequest>,
+    caller: Authorized<requires::CatalogRead>,
+    release_req: Query<IdRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the getrelease response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let getrelease_response =
-        web::block(move || db_getrelease::<DbRelease>(release_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getrelease_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    let mut conn = pool.get()?;
+    let getrelease_response = web::block(move || {
+        db_getrelease::<DbRelease>(caller.into_inner(), release_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getrelease_response)))
 }
 
-/// Get all performers
+/// Get a page of performers
 #[get("/music/get/performers")]
-pub async fn getperformers(pool: Data<Pool<ConnectionManager<PgConnection>>>) -> HttpResponse {
+pub async fn getperformers(
+    _caller: Authorized<requires::CatalogRead>,
+    list_req: Query<ListRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
     // get the getperformers response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let getperformers_response =
-        web::block(move || db_getperformers::<Vec<Performer>>(&mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getperformers_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+        web::block(move || db_getperformers::<Vec<Performer>>(list_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getperformers_response)))
 }
 
 /// Get specific performer
-#[post("/music/get/performer")]
+#[get("/music/get/performer")]
 pub async fn getperformer(
-    performer_req: Json<IdRequest>,
+    _caller: Authorized<requires::CatalogRead>,
+    performer_req: Query<IdRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the getperformer response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let getperformer_response =
         web::block(move || db_getperformer::<Performer>(performer_req.into_inner(), &mut conn))
-            .await;
-
-    // return the appropriate response and handle errors
-    match getperformer_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getperformer_response)))
 }
 
-/// Get all composers
+/// Get a page of composers
 #[get("/music/get/composers")]
-pub async fn getcomposers(pool: Data<Pool<ConnectionManager<PgConnection>>>) -> HttpResponse {
+pub async fn getcomposers(
+    _caller: Authorized<requires::CatalogRead>,
+    list_req: Query<ListRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
     // get the getcomposers response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let getcomposers_response =
-        web::block(move || db_getcomposers::<Vec<Performer>>(&mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getcomposers_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+        web::block(move || db_getcomposers::<Vec<Performer>>(list_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getcomposers_response)))
 }
 
 /// Get specific composer
-#[post("/music/get/composer")]
+#[get("/music/get/composer")]
 pub async fn getcomposer(
-    composer_req: Json<IdRequest>,
+    _caller: Authorized<requires::CatalogRead>,
+    composer_req: Query<IdRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the getcomposer response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let getcomposer_response =
-        web::block(move || db_getcomposer::<Performer>(composer_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getcomposer_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+        web::block(move || db_getcomposer::<Performer>(composer_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getcomposer_response)))
 }
 
-/// Get all songwriters
+/// Get a page of songwriters
 #[get("/music/get/songwriters")]
-pub async fn getsongwriters(pool: Data<Pool<ConnectionManager<PgConnection>>>) -> HttpResponse {
+pub async fn getsongwriters(
+    _caller: Authorized<requires::CatalogRead>,
+    list_req: Query<ListRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
     // get the getsongwriters response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let getsongwriters_response =
-        web::block(move || db_getsongwriters::<Vec<Songwriter>>(&mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match getsongwriters_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+        web::block(move || db_getsongwriters::<Vec<Songwriter>>(list_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getsongwriters_response)))
 }
 
 /// Get specific songwriter
-#[post("/music/get/songwriter")]
+#[get("/music/get/songwriter")]
 pub async fn getsongwriter(
-    songwriter_req: Json<IdRequest>,
+    _caller: Authorized<requires::CatalogRead>,
+    songwriter_req: Query<IdRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the getsongwriter response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let getsongwriter_response =
         web::block(move || db_getsongwriter::<Songwriter>(songwriter_req.into_inner(), &mut conn))
-            .await;
-
-    // return the appropriate response and handle errors
-    match getsongwriter_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getsongwriter_response)))
 }
 
 /// Get recordings by release id
-#[post("/music/get/recordings")]
+#[get("/music/get/recordings")]
 pub async fn getrecordings(
-    release_req: Json<IdRequest>,
+    caller: Authorized<requires::CatalogRead>,
+    release_req: Query<IdRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the getrecordings response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let getrecordings_response =
-        web::block(move || db_getrecordings::<Vec<Recording>>(release_req.into_inner(), &mut conn))
-            .await;
-
-    // return the appropriate response and handle errors
-    match getrecordings_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    let mut conn = pool.get()?;
+    let getrecordings_response = web::block(move || {
+        db_getrecordings::<Vec<Recording>>(caller.into_inner(), release_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getrecordings_response)))
 }
 
 /// Get specific recording
-#[post("/music/get/recording")]
+#[get("/music/get/recording")]
 pub async fn getrecording(
-    recording_req: Json<IdRequest>,
+    caller: Authorized<requires::CatalogRead>,
+    recording_req: Query<IdRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the getrecording response from database
-    let mut conn = pool.get().expect("Connection pool error");
-    let getrecording_response =
-        web::block(move || db_getrecording::<Recording>(recording_req.into_inner(), &mut conn))
-            .await;
-
-    // return the appropriate response and handle errors
-    match getrecording_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
+    let mut conn = pool.get()?;
+    let getrecording_response = web::block(move || {
+        db_getrecording::<Recording>(caller.into_inner(), recording_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(getrecording_response)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::Credential;
+    use crate::models::User;
+    use crate::permission::ADMIN_ROLE;
+    use crate::testing::connect;
+    use diesel::connection::InstrumentationEvent;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Catalog entries the listings are filtered down to, so other rows do not get in the way
+    struct Catalog {
+        user_id: i32,
+        composer_id: i32,
+        performer_id: i32,
+        small_release_id: i32,
+        large_release_id: i32,
+    }
+
+    /// Add a composer with six pieces, a performer on two releases and recordings of two and
+    /// of six of the pieces, all fully credited
+    fn catalog(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> Catalog {
+        use crate::schema::{
+            composers, library_entries, performers, piece_composers, piece_songwriters, pieces,
+            recording_performers, recordings, release_performers, releases, songwriters,
+            user_roles, users,
+        };
+
+        let user: User = diesel::insert_into(users::table)
+            .values((
+                users::username.eq("query-count-test"),
+                users::password_hash.eq("unused"),
+            ))
+            .get_result::<User>(conn)
+            .unwrap();
+        diesel::insert_into(user_roles::table)
+            .values((
+                user_roles::user_id.eq(user.id),
+                user_roles::role.eq(ADMIN_ROLE),
+            ))
+            .execute(conn)
+            .unwrap();
+        let composer_id: i32 = diesel::insert_into(composers::table)
+            .values(composers::name.eq("Johannes Brahms"))
+            .returning(composers::id)
+            .get_result(conn)
+            .unwrap();
+        let songwriter_id: i32 = diesel::insert_into(songwriters::table)
+            .values(songwriters::name.eq("Klaus Groth"))
+            .returning(songwriters::id)
+            .get_result(conn)
+            .unwrap();
+        let performer_id: i32 = diesel::insert_into(performers::table)
+            .values(performers::name.eq("Wiener Philharmoniker"))
+            .returning(performers::id)
+            .get_result(conn)
+            .unwrap();
+
+        let mut piece_ids: Vec<i32> = Vec::new();
+        for number in 1..=6 {
+            let piece_id: i32 = diesel::insert_into(pieces::table)
+                .values(pieces::name.eq(format!("Lied No. {}", number)))
+                .returning(pieces::id)
+                .get_result(conn)
+                .unwrap();
+            diesel::insert_into(piece_composers::table)
+                .values((
+                    piece_composers::piece_id.eq(piece_id),
+                    piece_composers::composer_id.eq(composer_id),
+                ))
+                .execute(conn)
+                .unwrap();
+            diesel::insert_into(piece_songwriters::table)
+                .values((
+                    piece_songwriters::piece_id.eq(piece_id),
+                    piece_songwriters::songwriter_id.eq(songwriter_id),
+                ))
+                .execute(conn)
+                .unwrap();
+            diesel::insert_into(library_entries::table)
+                .values((
+                    library_entries::user_id.eq(user.id),
+                    library_entries::piece_id.eq(piece_id),
+                    library_entries::rating.eq(Some(5_i16)),
+                ))
+                .execute(conn)
+                .unwrap();
+            piece_ids.push(piece_id);
         }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
+
+        let mut release_ids: Vec<i32> = Vec::new();
+        for tracks in [2, 6] {
+            let release_id: i32 = diesel::insert_into(releases::table)
+                .values(releases::name.eq(format!("{} Lieder", tracks)))
+                .returning(releases::id)
+                .get_result(conn)
+                .unwrap();
+            diesel::insert_into(release_performers::table)
+                .values((
+                    release_performers::release_id.eq(release_id),
+                    release_performers::performer_id.eq(performer_id),
+                ))
+                .execute(conn)
+                .unwrap();
+            for (track, piece_id) in piece_ids.iter().take(tracks).enumerate() {
+                let recording_id: i32 = diesel::insert_into(recordings::table)
+                    .values((
+                        recordings::piece_name.eq(format!("Lied No. {}", track + 1)),
+                        recordings::piece_id.eq(piece_id),
+                        recordings::release_id.eq(release_id),
+                        recordings::track_number.eq(track as i32 + 1),
+                    ))
+                    .returning(recordings::id)
+                    .get_result(conn)
+                    .unwrap();
+                diesel::insert_into(recording_performers::table)
+                    .values((
+                        recording_performers::recording_id.eq(recording_id),
+                        recording_performers::performer_id.eq(performer_id),
+                    ))
+                    .execute(conn)
+                    .unwrap();
+            }
+            release_ids.push(release_id);
         }
+
+        Catalog {
+            user_id: user.id,
+            composer_id,
+            performer_id,
+            small_release_id: release_ids[0],
+            large_release_id: release_ids[1],
+        }
+    }
+
+    /// Count the queries made on a connection from now on
+    fn count_queries(
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Arc<AtomicUsize> {
+        let queries: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let counter: Arc<AtomicUsize> = Arc::clone(&queries);
+        let instrumentation = move |event: InstrumentationEvent<'_>| {
+            if matches!(event, InstrumentationEvent::StartQuery { .. }) {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+        conn.set_instrumentation(instrumentation);
+        queries
+    }
+
+    /// Sign in as the owner of the catalog
+    fn caller(
+        catalog: &Catalog,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::users;
+
+        let user: User = users::table.find(catalog.user_id).first(conn).unwrap();
+        Caller::authenticated(Credential::Password, user)
+    }
+
+    /// List a page of the catalog's pieces, giving how many were listed and the queries it took
+    fn list_pieces(
+        catalog: &Catalog,
+        limit: i64,
+        queries: &AtomicUsize,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (usize, usize) {
+        let caller: Caller = caller(catalog, conn);
+        let list_req = ListRequest {
+            limit: Some(limit),
+            composer_id: Some(catalog.composer_id),
+            ..Default::default()
+        };
+        queries.store(0, Ordering::SeqCst);
+        let page: Page<Piece> = db_getpieces::<()>(caller, list_req, conn).unwrap();
+        (page.items.len(), queries.load(Ordering::SeqCst))
+    }
+
+    /// List a page of the catalog's releases, giving how many were listed and the queries it
+    /// took
+    fn list_releases(
+        catalog: &Catalog,
+        limit: i64,
+        queries: &AtomicUsize,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (usize, usize) {
+        let caller: Caller = caller(catalog, conn);
+        let list_req = ListRequest {
+            limit: Some(limit),
+            performer_id: Some(catalog.performer_id),
+            ..Default::default()
+        };
+        queries.store(0, Ordering::SeqCst);
+        let page: Page<Release> = db_getreleases::<()>(caller, list_req, conn).unwrap();
+        (page.items.len(), queries.load(Ordering::SeqCst))
+    }
+
+    /// List the recordings of a release, giving how many were listed and the queries it took
+    fn list_recordings(
+        catalog: &Catalog,
+        release_id: i32,
+        queries: &AtomicUsize,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (usize, usize) {
+        let caller: Caller = caller(catalog, conn);
+        queries.store(0, Ordering::SeqCst);
+        let recordings: Vec<Recording> =
+            db_getrecordings::<()>(caller, IdRequest { id: release_id }, conn).unwrap();
+        (recordings.len(), queries.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn lists_pages_in_a_fixed_number_of_queries() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let catalog: Catalog = catalog(&mut conn);
+        let queries: Arc<AtomicUsize> = count_queries(&mut conn);
+
+        let (listed, small) = list_pieces(&catalog, 2, &queries, &mut conn);
+        assert_eq!(listed, 2);
+        assert!(small > 0, "no queries were counted");
+        let (listed, large) = list_pieces(&catalog, 6, &queries, &mut conn);
+        assert_eq!(listed, 6);
+        assert_eq!(small, large);
+
+        let (listed, small) = list_releases(&catalog, 1, &queries, &mut conn);
+        assert_eq!(listed, 1);
+        let (listed, large) = list_releases(&catalog, 2, &queries, &mut conn);
+        assert_eq!(listed, 2);
+        assert_eq!(small, large);
+
+        let (listed, small) =
+            list_recordings(&catalog, catalog.small_release_id, &queries, &mut conn);
+        assert_eq!(listed, 2);
+        let (listed, large) =
+            list_recordings(&catalog, catalog.large_release_id, &queries, &mut conn);
+        assert_eq!(listed, 6);
+        assert_eq!(small, large);
+    }
+
+    #[test]
+    fn stops_paging_at_the_largest_offset() {
+        let page: Page<()> = Page::new(Vec::new(), 120, 50, 50);
+        assert_eq!(page.next_offset, Some(100));
+        let page: Page<()> = Page::new(Vec::new(), 120, 50, 100);
+        assert_eq!(page.next_offset, None);
+        let page: Page<()> = Page::new(Vec::new(), i64::MAX, MAX_LIMIT, i64::MAX - 1);
+        assert_eq!(page.next_offset, None);
+    }
+
+    #[test]
+    fn refuses_filters_a_list_cannot_apply() {
+        let list_req = ListRequest {
+            performer_id: Some(1),
+            ..Default::default()
+        };
+        assert!(list_req.check_filters(&["performer_id"]).is_ok());
+        assert!(matches!(
+            list_req.check_filters(&["composer_id", "songwriter_id"]),
+            Err(ApiError::Validation(_))
+        ));
+        assert!(ListRequest::default().check_filters(&[]).is_ok());
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let catalog: Catalog = catalog(&mut conn);
+        let list_req = ListRequest {
+            composer_id: Some(catalog.composer_id),
+            ..Default::default()
+        };
+        let caller: Caller = caller(&catalog, &mut conn);
+        assert!(matches!(
+            db_getreleases::<()>(caller, list_req.clone(), &mut conn),
+            Err(ApiError::Validation(_))
+        ));
+        assert!(matches!(
+            db_getperformers::<()>(list_req, &mut conn),
+            Err(ApiError::Validation(_))
+        ));
     }
 }
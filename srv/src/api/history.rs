@@ -0,0 +1,921 @@
+use crate::api::auth::{require_permission, Authorized, Caller};
+use crate::api::get::{ListRequest, Page};
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::insert;
+use crate::media;
+use crate::models::DbPlay;
+use crate::permission::{requires, Permission};
+use crate::Response;
+use crate::{scanner, scrobbler};
+
+use actix_web::web::{Data, Json, Query};
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{Duration, NaiveDateTime, SubsecRound};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::sql_types::{BigInt, Integer, Nullable, Text, Timestamptz};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Seconds of listening after which a play counts even if it was not finished, as scrobblers
+/// count them
+pub const COUNTED_SECONDS: i32 = 240;
+
+// how far ahead of the server clock a client may date an event
+const CLOCK_SKEW: i64 = 60;
+
+// condition on the plays table for plays that count, shared by every report
+const COUNTED_CONDITION: &str = "(plays.state = 'finished' OR plays.listened_seconds >= $4)";
+
+/// Something that happened while a recording was playing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackEvent {
+    Started,
+    Progressed,
+    Finished,
+    Skipped,
+}
+
+impl PlaybackEvent {
+    /// Name of the event as stored in the play_events table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlaybackEvent::Started => "started",
+            PlaybackEvent::Progressed => "progressed",
+            PlaybackEvent::Finished => "finished",
+            PlaybackEvent::Skipped => "skipped",
+        }
+    }
+
+    /// State of a play once this event has happened
+    pub fn state(&self) -> &'static str {
+        match self {
+            PlaybackEvent::Started | PlaybackEvent::Progressed => "playing",
+            PlaybackEvent::Finished => "finished",
+            PlaybackEvent::Skipped => "skipped",
+        }
+    }
+}
+
+/// What plays are grouped by when counting them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CountGroup {
+    #[default]
+    Recording,
+    Piece,
+    Composer,
+}
+
+impl CountGroup {
+    /// Joins from the plays table to the grouped table, and its id and name columns
+    fn columns(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            CountGroup::Recording => (
+                "JOIN recordings ON recordings.id = plays.recording_id",
+                "recordings.id",
+                "recordings.piece_name",
+            ),
+            CountGroup::Piece => (
+                "JOIN recordings ON recordings.id = plays.recording_id
+                JOIN pieces ON pieces.id = recordings.piece_id",
+                "pieces.id",
+                "pieces.name",
+            ),
+            CountGroup::Composer => (
+                "JOIN recordings ON recordings.id = plays.recording_id
+                JOIN piece_composers ON piece_composers.piece_id = recordings.piece_id
+                JOIN composers ON composers.id = piece_composers.composer_id",
+                "composers.id",
+                "composers.name",
+            ),
+        }
+    }
+}
+
+/// Length of the periods listening totals are broken down into
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    /// Field given to date_trunc to find the start of a period
+    fn as_str(&self) -> &'static str {
+        match self {
+            Interval::Day => "day",
+            Interval::Week => "week",
+            Interval::Month => "month",
+        }
+    }
+}
+
+/// A playback event reported by a client. Started events begin a new play of a recording and
+/// later events continue it by id; clients that only report finished plays may give the
+/// recording instead.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlaybackEventRequest {
+    pub event: PlaybackEvent,
+    pub play_id: Option<i32>,
+    pub recording_id: Option<i32>,
+    pub position: Option<i32>,
+    pub occurred_at: Option<NaiveDateTime>,
+}
+
+/// A request for the plays of the caller, or of another user for an administrator, over an
+/// optional range of time
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HistoryRequest {
+    pub user_id: Option<i32>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub group: Option<CountGroup>,
+    pub interval: Option<Interval>,
+}
+
+/// A play of a recording, and how far it got
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Play {
+    pub id: i32,
+    pub user_id: i32,
+    pub recording_id: i32,
+    pub piece_name: String,
+    pub release_id: i32,
+    pub state: String,
+    pub listened_seconds: i32,
+    pub counted: bool,
+    pub started_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl Play {
+    /// Describe a play of a recording
+    pub fn new(play: DbPlay, piece_name: String, release_id: i32) -> Self {
+        Play {
+            counted: counted(&play),
+            id: play.id,
+            user_id: play.user_id,
+            recording_id: play.recording_id,
+            piece_name,
+            release_id,
+            state: play.state,
+            listened_seconds: play.listened_seconds,
+            started_at: play.started_at,
+            updated_at: play.updated_at,
+        }
+    }
+}
+
+/// How often a recording, piece or composer was played
+#[derive(Debug, Deserialize, Serialize, QueryableByName)]
+pub struct PlayCount {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = BigInt)]
+    pub plays: i64,
+    #[diesel(sql_type = BigInt)]
+    pub listened_seconds: i64,
+}
+
+/// How much was listened to in a period, or over the whole range when it has no start
+#[derive(Debug, Deserialize, Serialize, QueryableByName)]
+pub struct ListeningTotal {
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<NaiveDateTime>,
+    #[diesel(sql_type = BigInt)]
+    pub plays: i64,
+    #[diesel(sql_type = BigInt)]
+    pub listened_seconds: i64,
+}
+
+/// Listening totals over a range of time, broken down by period when asked
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ListeningTotals {
+    #[serde(flatten)]
+    pub total: ListeningTotal,
+    pub periods: Vec<ListeningTotal>,
+}
+
+/// A number of rows counted by a raw query
+#[derive(QueryableByName)]
+struct Total {
+    #[diesel(sql_type = BigInt)]
+    total: i64,
+}
+
+/// Whether a play counts, having been finished or listened to for long enough
+fn counted(play: &DbPlay) -> bool {
+    play.state == PlaybackEvent::Finished.state() || play.listened_seconds >= COUNTED_SECONDS
+}
+
+/// The user whose history a request is about: the caller, or anyone for an administrator
+fn history_user(
+    caller: &Caller,
+    user_id: Option<i32>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<i32, ApiError> {
+    let own_id: i32 = caller.require_user()?.id;
+    match user_id {
+        Some(user_id) if user_id != own_id => {
+            require_permission(caller, Permission::UsersManage, conn)?;
+            Ok(user_id)
+        }
+        _ => Ok(own_id),
+    }
+}
+
+/// Length of a recording in seconds, if its file can be found and states it
+fn recording_seconds(media_root: &Path, file_path: Option<&str>) -> Option<i32> {
+    let path: PathBuf = media::resolve(media_root, file_path?)?;
+    scanner::read_seconds(&path)
+}
+
+/// The furthest position a play can have reached when an event happened: no further than the
+/// time since it started, nor the length of its recording when that is known
+fn reachable_position(
+    position: i32,
+    started_at: NaiveDateTime,
+    occurred_at: NaiveDateTime,
+    length: Option<i32>,
+) -> i32 {
+    let elapsed: i64 = (occurred_at - started_at).num_seconds();
+    let position: i32 = position.min(i32::try_from(elapsed).unwrap_or(i32::MAX));
+    match length {
+        Some(length) => position.min(length),
+        None => position,
+    }
+}
+
+/// Record a playback event, starting a new play or continuing one of the caller's
+fn db_reportevent(
+    caller: Caller,
+    event_req: PlaybackEventRequest,
+    media_root: &Path,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Play, ApiError> {
+    use crate::schema::{play_events, plays, recordings};
+
+    let user_id: i32 = caller.require_user()?.id;
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+
+    // the database keeps timestamps to the microsecond
+    let occurred_at: NaiveDateTime = event_req.occurred_at.unwrap_or(now).trunc_subsecs(6);
+    if occurred_at > now + Duration::seconds(CLOCK_SKEW) {
+        return Err(ApiError::Validation(
+            "Events cannot happen in the future".to_string(),
+        ));
+    }
+    if event_req.position.is_some_and(|position| position < 0) {
+        return Err(ApiError::Validation(
+            "Position cannot be negative".to_string(),
+        ));
+    }
+
+    conn.transaction::<Play, ApiError, _>(|conn| {
+        let existing: Option<DbPlay> = match (event_req.event, event_req.play_id) {
+            (PlaybackEvent::Started, Some(_)) => {
+                return Err(ApiError::Validation(
+                    "Started events begin a new play and take no play id".to_string(),
+                ))
+            }
+            (_, Some(play_id)) => {
+                let play: DbPlay = plays::dsl::plays
+                    .filter(plays::dsl::id.eq(play_id))
+                    .filter(plays::dsl::user_id.eq(user_id))
+                    .for_update()
+                    .first::<DbPlay>(conn)
+                    .optional()?
+                    .ok_or_else(|| ApiError::not_found("Play"))?;
+                if play.state != PlaybackEvent::Started.state() {
+                    return Err(ApiError::Conflict("Play has already ended".to_string()));
+                }
+                if event_req
+                    .recording_id
+                    .is_some_and(|recording_id| recording_id != play.recording_id)
+                {
+                    return Err(ApiError::Validation(
+                        "Recording does not match the play".to_string(),
+                    ));
+                }
+                Some(play)
+            }
+            (_, None) => None,
+        };
+
+        let recording_id: i32 = match &existing {
+            Some(play) => play.recording_id,
+            None => event_req.recording_id.ok_or_else(|| {
+                ApiError::Validation("A play id or recording id is required".to_string())
+            })?,
+        };
+        let (piece_name, release_id, file_path) = recordings::dsl::recordings
+            .find(recording_id)
+            .select((
+                recordings::dsl::piece_name,
+                recordings::dsl::release_id,
+                recordings::dsl::file_path,
+            ))
+            .first::<(String, i32, Option<String>)>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::not_found("Recording"))?;
+        let length: Option<i32> = recording_seconds(media_root, file_path.as_deref());
+
+        let play: DbPlay = match existing {
+            Some(play) => play,
+            None => {
+                // a play first reported after it started began as long ago as was listened,
+                // which cannot be more than the whole recording
+                let listened: i32 = match event_req.event {
+                    PlaybackEvent::Started => 0,
+                    _ => event_req.position.unwrap_or(0).min(length.unwrap_or(0)),
+                };
+                let new_play = insert::NewPlay {
+                    user_id,
+                    recording_id,
+                    started_at: occurred_at - Duration::seconds(i64::from(listened)),
+                    updated_at: occurred_at,
+                };
+                diesel::insert_into(plays::dsl::plays)
+                    .values(&new_play)
+                    .get_result::<DbPlay>(conn)?
+            }
+        };
+        if occurred_at < play.started_at {
+            return Err(ApiError::Validation(
+                "Events cannot happen before their play started".to_string(),
+            ));
+        }
+
+        // the furthest position reached is taken as the time listened, ignoring seeks back
+        let position: i32 = reachable_position(
+            event_req.position.unwrap_or(play.listened_seconds),
+            play.started_at,
+            occurred_at,
+            length,
+        );
+        let new_event = insert::NewPlayEvent {
+            play_id: play.id,
+            event: event_req.event.as_str().to_string(),
+            position_seconds: position,
+            occurred_at,
+        };
+        diesel::insert_into(play_events::dsl::play_events)
+            .values(&new_event)
+            .execute(conn)?;
+        let was_counted: bool = counted(&play);
+        let play: DbPlay = diesel::update(plays::dsl::plays.find(play.id))
+            .set((
+                plays::dsl::state.eq(event_req.event.state()),
+                plays::dsl::listened_seconds.eq(play.listened_seconds.max(position)),
+                plays::dsl::updated_at.eq(play.updated_at.max(occurred_at)),
+            ))
+            .get_result::<DbPlay>(conn)?;
+
+        // plays are forwarded once, as soon as they count
+        if !was_counted && counted(&play) {
+            scrobbler::enqueue(user_id, play.id, conn)?;
+        }
+
+        Ok(Play::new(play, piece_name, release_id))
+    })
+}
+
+/// Get one page of the plays of a user over a range of time, most recent first
+fn db_listplays(
+    caller: Caller,
+    history_req: HistoryRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Page<Play>, ApiError> {
+    use crate::schema::{plays, recordings};
+
+    let user_id: i32 = history_user(&caller, history_req.user_id, conn)?;
+    let (limit, offset) = ListRequest {
+        limit: history_req.limit,
+        offset: history_req.offset,
+        ..Default::default()
+    }
+    .page()?;
+
+    let filtered = || {
+        let mut query = plays::dsl::plays
+            .inner_join(recordings::dsl::recordings)
+            .filter(plays::dsl::user_id.eq(user_id))
+            .into_boxed();
+        if let Some(from) = history_req.from {
+            query = query.filter(plays::dsl::started_at.ge(from));
+        }
+        if let Some(to) = history_req.to {
+            query = query.filter(plays::dsl::started_at.lt(to));
+        }
+        query
+    };
+    let total: i64 = filtered().count().get_result(conn)?;
+    let rows: Vec<(DbPlay, (String, i32))> = filtered()
+        .order((plays::dsl::started_at.desc(), plays::dsl::id.desc()))
+        .limit(limit)
+        .offset(offset)
+        .select((
+            DbPlay::as_select(),
+            (recordings::dsl::piece_name, recordings::dsl::release_id),
+        ))
+        .load::<(DbPlay, (String, i32))>(conn)?;
+
+    let items: Vec<Play> = rows
+        .into_iter()
+        .map(|(play, (piece_name, release_id))| Play::new(play, piece_name, release_id))
+        .collect();
+    Ok(Page::new(items, total, limit, offset))
+}
+
+/// Count the plays of a user by recording, piece or composer, most played first. Every play
+/// adds to the time listened, but only finished plays or long enough ones add to the count.
+fn db_countplays(
+    caller: Caller,
+    history_req: HistoryRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Page<PlayCount>, ApiError> {
+    let user_id: i32 = history_user(&caller, history_req.user_id, conn)?;
+    let (limit, offset) = ListRequest {
+        limit: history_req.limit,
+        offset: history_req.offset,
+        ..Default::default()
+    }
+    .page()?;
+    let (joins, id, name) = history_req.group.unwrap_or_default().columns();
+
+    let filter: String = format!(
+        "FROM plays {joins}
+        WHERE plays.user_id = $1
+            AND ($2::timestamptz IS NULL OR plays.started_at >= $2)
+            AND ($3::timestamptz IS NULL OR plays.started_at < $3)",
+        joins = joins,
+    );
+    let total: Total = diesel::sql_query(format!(
+        "SELECT COUNT(DISTINCT {id}) AS total {filter}",
+        id = id,
+        filter = filter,
+    ))
+    .bind::<Integer, _>(user_id)
+    .bind::<Nullable<Timestamptz>, _>(history_req.from)
+    .bind::<Nullable<Timestamptz>, _>(history_req.to)
+    .get_result::<Total>(conn)?;
+    let counts: Vec<PlayCount> = diesel::sql_query(format!(
+        "SELECT {id} AS id, {name} AS name,
+            COUNT(*) FILTER (WHERE {counted}) AS plays,
+            COALESCE(SUM(plays.listened_seconds), 0)::bigint AS listened_seconds
+        {filter}
+        GROUP BY {id}, {name}
+        ORDER BY 3 DESC, 4 DESC, 1
+        LIMIT $5 OFFSET $6",
+        id = id,
+        name = name,
+        counted = COUNTED_CONDITION,
+        filter = filter,
+    ))
+    .bind::<Integer, _>(user_id)
+    .bind::<Nullable<Timestamptz>, _>(history_req.from)
+    .bind::<Nullable<Timestamptz>, _>(history_req.to)
+    .bind::<Integer, _>(COUNTED_SECONDS)
+    .bind::<BigInt, _>(limit)
+    .bind::<BigInt, _>(offset)
+    .load::<PlayCount>(conn)?;
+
+    Ok(Page::new(counts, total.total, limit, offset))
+}
+
+/// Total the plays and time listened of a user per period, in a single period with no start
+/// when there is no interval
+fn db_listeningtotals(
+    user_id: i32,
+    history_req: &HistoryRequest,
+    interval: Option<Interval>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<ListeningTotal>, ApiError> {
+    let totals: Vec<ListeningTotal> = diesel::sql_query(format!(
+        "SELECT date_trunc($5, plays.started_at) AS start,
+            COUNT(*) FILTER (WHERE {counted}) AS plays,
+            COALESCE(SUM(plays.listened_seconds), 0)::bigint AS listened_seconds
+        FROM plays
+        WHERE plays.user_id = $1
+            AND ($2::timestamptz IS NULL OR plays.started_at >= $2)
+            AND ($3::timestamptz IS NULL OR plays.started_at < $3)
+        GROUP BY 1
+        ORDER BY 1",
+        counted = COUNTED_CONDITION,
+    ))
+    .bind::<Integer, _>(user_id)
+    .bind::<Nullable<Timestamptz>, _>(history_req.from)
+    .bind::<Nullable<Timestamptz>, _>(history_req.to)
+    .bind::<Integer, _>(COUNTED_SECONDS)
+    .bind::<Nullable<Text>, _>(interval.map(|interval| interval.as_str()))
+    .load::<ListeningTotal>(conn)?;
+    Ok(totals)
+}
+
+/// Total the plays and time listened of a user over a range of time, and per day, week or
+/// month if asked
+fn db_totalplays(
+    caller: Caller,
+    history_req: HistoryRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<ListeningTotals, ApiError> {
+    let user_id: i32 = history_user(&caller, history_req.user_id, conn)?;
+
+    let total: ListeningTotal = db_listeningtotals(user_id, &history_req, None, conn)?
+        .pop()
+        .unwrap_or(ListeningTotal {
+            start: None,
+            plays: 0,
+            listened_seconds: 0,
+        });
+    let periods: Vec<ListeningTotal> = match history_req.interval {
+        Some(interval) => db_listeningtotals(user_id, &history_req, Some(interval), conn)?,
+        None => Vec::new(),
+    };
+    Ok(ListeningTotals { total, periods })
+}
+
+/// Report that playback of a recording started, progressed, finished or was skipped
+#[post("/history/events")]
+pub async fn reportevent(
+    caller: Authorized<requires::MediaStream>,
+    event_req: Json<PlaybackEventRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // record the event in the database
+    let mut conn = pool.get()?;
+    let play = web::block(move || {
+        db_reportevent(
+            caller.into_inner(),
+            event_req.into_inner(),
+            &config.media_root,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    // return the play the event belongs to
+    Ok(HttpResponse::Ok().json(Response::success(play)))
+}
+
+/// List recent plays of the current user
+#[get("/history/plays")]
+pub async fn listplays(
+    caller: Authorized<requires::CatalogRead>,
+    history_req: Query<HistoryRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the plays from the database
+    let mut conn = pool.get()?;
+    let plays =
+        web::block(move || db_listplays(caller.into_inner(), history_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the plays on success
+    Ok(HttpResponse::Ok().json(Response::success(plays)))
+}
+
+/// Count the plays of the current user by recording, piece or composer
+#[get("/history/counts")]
+pub async fn countplays(
+    caller: Authorized<requires::CatalogRead>,
+    history_req: Query<HistoryRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // count the plays in the database
+    let mut conn = pool.get()?;
+    let counts =
+        web::block(move || db_countplays(caller.into_inner(), history_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the counts on success
+    Ok(HttpResponse::Ok().json(Response::success(counts)))
+}
+
+/// Total the listening of the current user over a range of time
+#[get("/history/totals")]
+pub async fn totalplays(
+    caller: Authorized<requires::CatalogRead>,
+    history_req: Query<HistoryRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // total the plays in the database
+    let mut conn = pool.get()?;
+    let totals =
+        web::block(move || db_totalplays(caller.into_inner(), history_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the totals on success
+    Ok(HttpResponse::Ok().json(Response::success(totals)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::Credential;
+    use crate::models::User;
+    use crate::testing::connect;
+
+    /// Add a user who forwards their plays and a recording without a file, giving the user and
+    /// the id of the recording
+    fn listener(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> (User, i32) {
+        use crate::schema::{pieces, recordings, releases, scrobble_targets, users};
+
+        let user: User = diesel::insert_into(users::table)
+            .values((
+                users::username.eq("history-test"),
+                users::password_hash.eq("unused"),
+            ))
+            .get_result::<User>(conn)
+            .unwrap();
+        diesel::insert_into(scrobble_targets::table)
+            .values((
+                scrobble_targets::user_id.eq(user.id),
+                scrobble_targets::submit_url.eq("https://api.listenbrainz.org/1/submit-listens"),
+                scrobble_targets::encrypted_token.eq("unused"),
+            ))
+            .execute(conn)
+            .unwrap();
+        let piece_id: i32 = diesel::insert_into(pieces::table)
+            .values(pieces::name.eq("Symphony No. 7"))
+            .returning(pieces::id)
+            .get_result(conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("Beethoven: Symphonies"))
+            .returning(releases::id)
+            .get_result(conn)
+            .unwrap();
+        let recording_id: i32 = diesel::insert_into(recordings::table)
+            .values((
+                recordings::piece_name.eq("Symphony No. 7: II. Allegretto"),
+                recordings::piece_id.eq(piece_id),
+                recordings::release_id.eq(release_id),
+                recordings::track_number.eq(1),
+            ))
+            .returning(recordings::id)
+            .get_result(conn)
+            .unwrap();
+        (user, recording_id)
+    }
+
+    /// Report an event on behalf of a user, the given number of seconds ago
+    fn report(
+        user: &User,
+        event: PlaybackEvent,
+        play_id: Option<i32>,
+        recording_id: Option<i32>,
+        position: Option<i32>,
+        seconds_ago: i64,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Play, ApiError> {
+        use crate::schema::users;
+
+        let user: User = users::table.find(user.id).first(conn).unwrap();
+        let event_req = PlaybackEventRequest {
+            event,
+            play_id,
+            recording_id,
+            position,
+            occurred_at: Some(chrono::Utc::now().naive_utc() - Duration::seconds(seconds_ago)),
+        };
+        let caller: Caller = Caller::authenticated(Credential::Password, user);
+        db_reportevent(caller, event_req, Path::new("media"), conn)
+    }
+
+    /// Whether a play is waiting to be forwarded
+    fn queued(play_id: i32, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> bool {
+        use crate::schema::scrobble_outbox;
+
+        scrobble_outbox::table
+            .filter(scrobble_outbox::play_id.eq(play_id))
+            .count()
+            .get_result::<i64>(conn)
+            .unwrap()
+            > 0
+    }
+
+    #[test]
+    fn limits_positions_to_the_time_since_the_start() {
+        let started_at = chrono::Utc::now().naive_utc();
+        let occurred_at: NaiveDateTime = started_at + Duration::seconds(30);
+        assert_eq!(reachable_position(20, started_at, occurred_at, None), 20);
+        assert_eq!(
+            reachable_position(2_000_000_000, started_at, occurred_at, None),
+            30
+        );
+        assert_eq!(
+            reachable_position(2_000_000_000, started_at, occurred_at, Some(25)),
+            25
+        );
+        assert_eq!(reachable_position(20, started_at, started_at, None), 0);
+    }
+
+    #[test]
+    fn counts_plays_once_listened_long_enough() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (user, recording_id) = listener(&mut conn);
+
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Started,
+            None,
+            Some(recording_id),
+            None,
+            600,
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(play.state, "playing");
+        assert!(!play.counted);
+
+        // just short of the threshold the play does not count yet
+        let position: i32 = COUNTED_SECONDS - 1;
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Progressed,
+            Some(play.id),
+            None,
+            Some(position),
+            600 - i64::from(position),
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(play.listened_seconds, position);
+        assert!(!play.counted);
+        assert!(!queued(play.id, &mut conn));
+
+        // reaching it counts the play and queues it to be forwarded
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Progressed,
+            Some(play.id),
+            None,
+            Some(COUNTED_SECONDS),
+            600 - i64::from(COUNTED_SECONDS),
+            &mut conn,
+        )
+        .unwrap();
+        assert!(play.counted);
+        assert!(queued(play.id, &mut conn));
+
+        // seeking back keeps the furthest position reached
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Skipped,
+            Some(play.id),
+            None,
+            Some(10),
+            0,
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(play.state, "skipped");
+        assert_eq!(play.listened_seconds, COUNTED_SECONDS);
+        assert!(play.counted);
+    }
+
+    #[test]
+    fn limits_listening_to_the_time_played() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (user, recording_id) = listener(&mut conn);
+
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Started,
+            None,
+            Some(recording_id),
+            None,
+            10,
+            &mut conn,
+        )
+        .unwrap();
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Skipped,
+            Some(play.id),
+            None,
+            Some(2_000_000_000),
+            0,
+            &mut conn,
+        )
+        .unwrap();
+        assert!((10..=11).contains(&play.listened_seconds));
+        assert!(!play.counted);
+        assert!(!queued(play.id, &mut conn));
+
+        // plays reported only once skipped cannot claim more than the unknown length
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Skipped,
+            None,
+            Some(recording_id),
+            Some(2_000_000_000),
+            0,
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(play.listened_seconds, 0);
+        assert!(!play.counted);
+    }
+
+    #[test]
+    fn moves_plays_through_their_states() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (user, recording_id) = listener(&mut conn);
+
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Started,
+            None,
+            Some(recording_id),
+            None,
+            60,
+            &mut conn,
+        )
+        .unwrap();
+
+        // started events always begin a new play
+        let result = report(
+            &user,
+            PlaybackEvent::Started,
+            Some(play.id),
+            None,
+            None,
+            30,
+            &mut conn,
+        );
+        assert!(matches!(result, Err(ApiError::Validation(_))));
+
+        // events cannot come before the play started
+        let result = report(
+            &user,
+            PlaybackEvent::Progressed,
+            Some(play.id),
+            None,
+            Some(5),
+            120,
+            &mut conn,
+        );
+        assert!(matches!(result, Err(ApiError::Validation(_))));
+
+        // finishing counts the play however little was listened
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Finished,
+            Some(play.id),
+            None,
+            Some(30),
+            30,
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(play.state, "finished");
+        assert!((30..=31).contains(&play.listened_seconds));
+        assert!(play.counted);
+        assert!(queued(play.id, &mut conn));
+
+        // and nothing can happen to it after that
+        let result = report(
+            &user,
+            PlaybackEvent::Progressed,
+            Some(play.id),
+            None,
+            Some(40),
+            0,
+            &mut conn,
+        );
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+
+        // clients that only report finished plays give the recording instead
+        let play: Play = report(
+            &user,
+            PlaybackEvent::Finished,
+            None,
+            Some(recording_id),
+            None,
+            0,
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(play.state, "finished");
+        assert!(play.counted);
+    }
+}
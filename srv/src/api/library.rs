@@ -0,0 +1,746 @@
+use crate::api::auth::{require_permission, Authorized, Caller};
+use crate::api::editmusic::double_option;
+use crate::api::get::{ListRequest, Page};
+use crate::error::ApiError;
+use crate::insert;
+use crate::models::DbLibraryEntry;
+use crate::permission::{requires, Permission};
+use crate::Response;
+
+use actix_web::web::{Data, Json, Query};
+use actix_web::{get, patch, web, HttpResponse};
+use chrono::{NaiveDateTime, SubsecRound};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Kind of catalog entry a user can star or rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityType {
+    Recording,
+    Release,
+    Piece,
+    Performer,
+    Composer,
+    Songwriter,
+}
+
+impl EntityType {
+    /// Name of the kind of entry in messages
+    fn name(&self) -> &'static str {
+        match self {
+            EntityType::Recording => "Recording",
+            EntityType::Release => "Release",
+            EntityType::Piece => "Piece",
+            EntityType::Performer => "Performer",
+            EntityType::Composer => "Composer",
+            EntityType::Songwriter => "Songwriter",
+        }
+    }
+}
+
+/// A catalog view that can show whether the caller starred and rated it
+pub trait LibraryView {
+    /// Kind of catalog entry the view shows
+    const ENTITY_TYPE: EntityType;
+
+    /// Id of the catalog entry
+    fn id(&self) -> i32;
+
+    /// Show the star and rating of the caller
+    fn set_state(&mut self, favorite: bool, rating: Option<i16>);
+}
+
+/// A request naming one catalog entry
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EntityRequest {
+    pub entity_type: EntityType,
+    pub id: i32,
+}
+
+/// A request to star or unstar a catalog entry and to set or clear its rating, leaving out
+/// what should stay as it is
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateLibraryRequest {
+    pub entity_type: EntityType,
+    pub id: i32,
+    pub favorite: Option<bool>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub rating: Option<Option<i16>>,
+}
+
+/// A request for the favorites of the caller, or of another user for an administrator
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FavoritesRequest {
+    pub user_id: Option<i32>,
+    pub entity_type: Option<EntityType>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Whether a user starred a catalog entry and how they rated it
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LibraryState {
+    pub entity_type: EntityType,
+    pub id: i32,
+    pub favorite: bool,
+    pub favorited_at: Option<NaiveDateTime>,
+    pub rating: Option<i16>,
+}
+
+/// A starred catalog entry
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Favorite {
+    pub entity_type: EntityType,
+    pub id: i32,
+    pub name: String,
+    pub favorited_at: NaiveDateTime,
+    pub rating: Option<i16>,
+}
+
+/// The catalog entry a library entry is about
+fn entry_entity(entry: &DbLibraryEntry) -> Option<(EntityType, i32)> {
+    [
+        (EntityType::Recording, entry.recording_id),
+        (EntityType::Release, entry.release_id),
+        (EntityType::Piece, entry.piece_id),
+        (EntityType::Performer, entry.performer_id),
+        (EntityType::Composer, entry.composer_id),
+        (EntityType::Songwriter, entry.songwriter_id),
+    ]
+    .into_iter()
+    .find_map(|(entity_type, id)| id.map(|id| (entity_type, id)))
+}
+
+/// Select the library entries of a user for some catalog entries of one kind
+fn entries_query(
+    user_id: i32,
+    entity_type: EntityType,
+    ids: Vec<i32>,
+) -> crate::schema::library_entries::BoxedQuery<'static, Pg> {
+    use crate::schema::library_entries;
+
+    let query = library_entries::dsl::library_entries
+        .filter(library_entries::dsl::user_id.eq(user_id))
+        .into_boxed();
+    match entity_type {
+        EntityType::Recording => query.filter(library_entries::dsl::recording_id.eq_any(ids)),
+        EntityType::Release => query.filter(library_entries::dsl::release_id.eq_any(ids)),
+        EntityType::Piece => query.filter(library_entries::dsl::piece_id.eq_any(ids)),
+        EntityType::Performer => query.filter(library_entries::dsl::performer_id.eq_any(ids)),
+        EntityType::Composer => query.filter(library_entries::dsl::composer_id.eq_any(ids)),
+        EntityType::Songwriter => query.filter(library_entries::dsl::songwriter_id.eq_any(ids)),
+    }
+}
+
+/// Whether a catalog entry exists
+fn db_entityexists(
+    entity_type: EntityType,
+    id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<bool, ApiError> {
+    use crate::schema::{composers, performers, pieces, recordings, releases, songwriters};
+    use diesel::dsl::exists;
+
+    let found: bool = match entity_type {
+        EntityType::Recording => {
+            diesel::select(exists(recordings::dsl::recordings.find(id))).get_result(conn)?
+        }
+        EntityType::Release => {
+            diesel::select(exists(releases::dsl::releases.find(id))).get_result(conn)?
+        }
+        EntityType::Piece => {
+            diesel::select(exists(pieces::dsl::pieces.find(id))).get_result(conn)?
+        }
+        EntityType::Performer => {
+            diesel::select(exists(performers::dsl::performers.find(id))).get_result(conn)?
+        }
+        EntityType::Composer => {
+            diesel::select(exists(composers::dsl::composers.find(id))).get_result(conn)?
+        }
+        EntityType::Songwriter => {
+            diesel::select(exists(songwriters::dsl::songwriters.find(id))).get_result(conn)?
+        }
+    };
+    Ok(found)
+}
+
+/// Get the names of some catalog entries of one kind
+fn db_entitynames(
+    entity_type: EntityType,
+    ids: &[i32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<HashMap<i32, String>, ApiError> {
+    use crate::schema::{composers, performers, pieces, recordings, releases, songwriters};
+
+    let names: Vec<(i32, String)> = match entity_type {
+        EntityType::Recording => recordings::dsl::recordings
+            .filter(recordings::dsl::id.eq_any(ids))
+            .select((recordings::dsl::id, recordings::dsl::piece_name))
+            .load(conn)?,
+        EntityType::Release => releases::dsl::releases
+            .filter(releases::dsl::id.eq_any(ids))
+            .select((releases::dsl::id, releases::dsl::name))
+            .load(conn)?,
+        EntityType::Piece => pieces::dsl::pieces
+            .filter(pieces::dsl::id.eq_any(ids))
+            .select((pieces::dsl::id, pieces::dsl::name))
+            .load(conn)?,
+        EntityType::Performer => performers::dsl::performers
+            .filter(performers::dsl::id.eq_any(ids))
+            .select((performers::dsl::id, performers::dsl::name))
+            .load(conn)?,
+        EntityType::Composer => composers::dsl::composers
+            .filter(composers::dsl::id.eq_any(ids))
+            .select((composers::dsl::id, composers::dsl::name))
+            .load(conn)?,
+        EntityType::Songwriter => songwriters::dsl::songwriters
+            .filter(songwriters::dsl::id.eq_any(ids))
+            .select((songwriters::dsl::id, songwriters::dsl::name))
+            .load(conn)?,
+    };
+    Ok(names.into_iter().collect())
+}
+
+/// Show the stars and ratings of the caller on catalog views, leaving them out for guests
+pub(crate) fn db_applystates<T: LibraryView>(
+    caller: &Caller,
+    views: &mut [T],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    let Some(user) = caller.user() else {
+        return Ok(());
+    };
+    let ids: Vec<i32> = views.iter().map(LibraryView::id).collect();
+    let states: HashMap<i32, (bool, Option<i16>)> = entries_query(user.id, T::ENTITY_TYPE, ids)
+        .select(DbLibraryEntry::as_select())
+        .load::<DbLibraryEntry>(conn)?
+        .into_iter()
+        .filter_map(|entry| {
+            entry_entity(&entry).map(|(_, id)| (id, (entry.favorited_at.is_some(), entry.rating)))
+        })
+        .collect();
+    for view in views.iter_mut() {
+        let (favorite, rating) = states.get(&view.id()).copied().unwrap_or((false, None));
+        view.set_state(favorite, rating);
+    }
+    Ok(())
+}
+
+/// Get whether the caller starred a catalog entry and how they rated it
+fn db_getlibrarystate(
+    caller: Caller,
+    entity_req: EntityRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<LibraryState, ApiError> {
+    let user_id: i32 = caller.require_user()?.id;
+    if !db_entityexists(entity_req.entity_type, entity_req.id, conn)? {
+        return Err(ApiError::not_found(entity_req.entity_type.name()));
+    }
+
+    let entry: Option<DbLibraryEntry> =
+        entries_query(user_id, entity_req.entity_type, vec![entity_req.id])
+            .select(DbLibraryEntry::as_select())
+            .first::<DbLibraryEntry>(conn)
+            .optional()?;
+    Ok(LibraryState {
+        entity_type: entity_req.entity_type,
+        id: entity_req.id,
+        favorite: entry
+            .as_ref()
+            .is_some_and(|entry| entry.favorited_at.is_some()),
+        favorited_at: entry.as_ref().and_then(|entry| entry.favorited_at),
+        rating: entry.and_then(|entry| entry.rating),
+    })
+}
+
+/// Star or unstar a catalog entry for the caller and set or clear their rating of it. Entries
+/// neither starred nor rated are not kept.
+fn db_updatelibrarystate(
+    caller: Caller,
+    update_req: UpdateLibraryRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<LibraryState, ApiError> {
+    use crate::schema::library_entries;
+
+    let user_id: i32 = caller.require_user()?.id;
+    if update_req.favorite.is_none() && update_req.rating.is_none() {
+        return Err(ApiError::Validation("No changes given".to_string()));
+    }
+    if update_req
+        .rating
+        .flatten()
+        .is_some_and(|rating| !(1..=5).contains(&rating))
+    {
+        return Err(ApiError::Validation(
+            "Rating must be between 1 and 5".to_string(),
+        ));
+    }
+    let (entity_type, id) = (update_req.entity_type, update_req.id);
+    if !db_entityexists(entity_type, id, conn)? {
+        return Err(ApiError::not_found(entity_type.name()));
+    }
+
+    // the database keeps timestamps to the microsecond
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc().trunc_subsecs(6);
+    conn.transaction::<LibraryState, ApiError, _>(|conn| {
+        let entry: Option<DbLibraryEntry> = entries_query(user_id, entity_type, vec![id])
+            .select(DbLibraryEntry::as_select())
+            .first::<DbLibraryEntry>(conn)
+            .optional()?;
+
+        // starring an entry again keeps when it was first starred
+        let favorited_at: Option<NaiveDateTime> = match update_req.favorite {
+            Some(true) => entry
+                .as_ref()
+                .and_then(|entry| entry.favorited_at)
+                .or(Some(now)),
+            Some(false) => None,
+            None => entry.as_ref().and_then(|entry| entry.favorited_at),
+        };
+        let rating: Option<i16> = match update_req.rating {
+            Some(rating) => rating,
+            None => entry.as_ref().and_then(|entry| entry.rating),
+        };
+
+        match (entry, favorited_at.is_some() || rating.is_some()) {
+            (Some(entry), true) => {
+                diesel::update(library_entries::dsl::library_entries.find(entry.id))
+                    .set((
+                        library_entries::dsl::favorited_at.eq(favorited_at),
+                        library_entries::dsl::rating.eq(rating),
+                        library_entries::dsl::updated_at.eq(now),
+                    ))
+                    .execute(conn)?;
+            }
+            (Some(entry), false) => {
+                diesel::delete(library_entries::dsl::library_entries.find(entry.id))
+                    .execute(conn)?;
+            }
+            (None, true) => {
+                let mut new_entry = insert::NewLibraryEntry {
+                    user_id,
+                    favorited_at,
+                    rating,
+                    ..Default::default()
+                };
+                match entity_type {
+                    EntityType::Recording => new_entry.recording_id = Some(id),
+                    EntityType::Release => new_entry.release_id = Some(id),
+                    EntityType::Piece => new_entry.piece_id = Some(id),
+                    EntityType::Performer => new_entry.performer_id = Some(id),
+                    EntityType::Composer => new_entry.composer_id = Some(id),
+                    EntityType::Songwriter => new_entry.songwriter_id = Some(id),
+                }
+                diesel::insert_into(library_entries::dsl::library_entries)
+                    .values(&new_entry)
+                    .execute(conn)?;
+            }
+            (None, false) => {}
+        }
+
+        Ok(LibraryState {
+            entity_type,
+            id,
+            favorite: favorited_at.is_some(),
+            favorited_at,
+            rating,
+        })
+    })
+}
+
+/// Get one page of the favorites of a user, most recently starred first
+fn db_listfavorites(
+    caller: Caller,
+    favorites_req: FavoritesRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Page<Favorite>, ApiError> {
+    use crate::schema::library_entries;
+
+    let own_id: i32 = caller.require_user()?.id;
+    let user_id: i32 = match favorites_req.user_id {
+        Some(user_id) if user_id != own_id => {
+            require_permission(&caller, Permission::UsersManage, conn)?;
+            user_id
+        }
+        _ => own_id,
+    };
+    let (limit, offset) = ListRequest {
+        limit: favorites_req.limit,
+        offset: favorites_req.offset,
+        ..Default::default()
+    }
+    .page()?;
+
+    let favorites_query = || {
+        let query = library_entries::dsl::library_entries
+            .filter(library_entries::dsl::user_id.eq(user_id))
+            .filter(library_entries::dsl::favorited_at.is_not_null())
+            .into_boxed();
+        match favorites_req.entity_type {
+            None => query,
+            Some(EntityType::Recording) => {
+                query.filter(library_entries::dsl::recording_id.is_not_null())
+            }
+            Some(EntityType::Release) => {
+                query.filter(library_entries::dsl::release_id.is_not_null())
+            }
+            Some(EntityType::Piece) => query.filter(library_entries::dsl::piece_id.is_not_null()),
+            Some(EntityType::Performer) => {
+                query.filter(library_entries::dsl::performer_id.is_not_null())
+            }
+            Some(EntityType::Composer) => {
+                query.filter(library_entries::dsl::composer_id.is_not_null())
+            }
+            Some(EntityType::Songwriter) => {
+                query.filter(library_entries::dsl::songwriter_id.is_not_null())
+            }
+        }
+    };
+    let total: i64 = favorites_query().count().get_result(conn)?;
+    let entries: Vec<DbLibraryEntry> = favorites_query()
+        .order((
+            library_entries::dsl::favorited_at.desc(),
+            library_entries::dsl::id.desc(),
+        ))
+        .limit(limit)
+        .offset(offset)
+        .select(DbLibraryEntry::as_select())
+        .load::<DbLibraryEntry>(conn)?;
+
+    // get the names of the starred entries with one query for each kind
+    let mut ids: HashMap<EntityType, Vec<i32>> = HashMap::new();
+    for (entity_type, id) in entries.iter().filter_map(entry_entity) {
+        ids.entry(entity_type).or_default().push(id);
+    }
+    let mut names: HashMap<(EntityType, i32), String> = HashMap::new();
+    for (entity_type, ids) in ids {
+        for (id, name) in db_entitynames(entity_type, &ids, conn)? {
+            names.insert((entity_type, id), name);
+        }
+    }
+
+    let favorites: Vec<Favorite> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (entity_type, id) = entry_entity(&entry)?;
+            Some(Favorite {
+                entity_type,
+                id,
+                name: names.remove(&(entity_type, id)).unwrap_or_default(),
+                favorited_at: entry.favorited_at?,
+                rating: entry.rating,
+            })
+        })
+        .collect();
+    Ok(Page::new(favorites, total, limit, offset))
+}
+
+/// Get whether the current user starred a catalog entry and how they rated it
+#[get("/library/get")]
+pub async fn getlibrarystate(
+    caller: Authorized<requires::CatalogRead>,
+    entity_req: Query<EntityRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the state from the database
+    let mut conn = pool.get()?;
+    let state = web::block(move || {
+        db_getlibrarystate(caller.into_inner(), entity_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the state on success
+    Ok(HttpResponse::Ok().json(Response::success(state)))
+}
+
+/// Star, unstar or rate a catalog entry for the current user
+#[patch("/library/update")]
+pub async fn updatelibrarystate(
+    caller: Authorized<(requires::LibraryEdit, requires::CatalogRead)>,
+    update_req: Json<UpdateLibraryRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // store the state in the database
+    let mut conn = pool.get()?;
+    let state = web::block(move || {
+        db_updatelibrarystate(caller.into_inner(), update_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the new state on success
+    Ok(HttpResponse::Ok().json(Response::success(state)))
+}
+
+/// List the favorites of the current user, or of another user for an administrator
+#[get("/library/favorites")]
+pub async fn listfavorites(
+    caller: Authorized<requires::CatalogRead>,
+    favorites_req: Query<FavoritesRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the favorites from the database
+    let mut conn = pool.get()?;
+    let favorites = web::block(move || {
+        db_listfavorites(caller.into_inner(), favorites_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the favorites on success
+    Ok(HttpResponse::Ok().json(Response::success(favorites)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::Credential;
+    use crate::models::User;
+    use crate::testing::connect;
+
+    /// Add a user with the given roles, returning their id
+    fn user(
+        username: &str,
+        roles: &[&str],
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> i32 {
+        use crate::schema::{user_roles, users};
+
+        let user_id: i32 = diesel::insert_into(users::table)
+            .values((
+                users::username.eq(username),
+                users::password_hash.eq("unused"),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap();
+        for role in roles {
+            diesel::insert_into(user_roles::table)
+                .values((user_roles::user_id.eq(user_id), user_roles::role.eq(*role)))
+                .execute(conn)
+                .unwrap();
+        }
+        user_id
+    }
+
+    /// Sign in as a user
+    fn caller(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::users;
+
+        let user: User = users::table.find(user_id).first(conn).unwrap();
+        Caller::authenticated(Credential::Password, user)
+    }
+
+    /// Add a piece and a release, returning their ids
+    fn catalog(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> (i32, i32) {
+        use crate::schema::{pieces, releases};
+
+        let piece_id: i32 = diesel::insert_into(pieces::table)
+            .values(pieces::name.eq("Winterreise"))
+            .returning(pieces::id)
+            .get_result(conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("Winterreise (1962)"))
+            .returning(releases::id)
+            .get_result(conn)
+            .unwrap();
+        (piece_id, release_id)
+    }
+
+    /// Star, unstar or rate an entry as a user
+    fn update(
+        user_id: i32,
+        entity_type: EntityType,
+        id: i32,
+        favorite: Option<bool>,
+        rating: Option<Option<i16>>,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<LibraryState, ApiError> {
+        let update_req = UpdateLibraryRequest {
+            entity_type,
+            id,
+            favorite,
+            rating,
+        };
+        db_updatelibrarystate(caller(user_id, conn), update_req, conn)
+    }
+
+    /// The number of library entries a user has
+    fn entries(user_id: i32, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i64 {
+        use crate::schema::library_entries;
+
+        library_entries::table
+            .filter(library_entries::user_id.eq(user_id))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn sets_and_clears_entries() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("library-test", &[], &mut conn);
+        let (_, release_id) = catalog(&mut conn);
+        let release = EntityType::Release;
+
+        let starred: LibraryState =
+            update(user_id, release, release_id, Some(true), None, &mut conn).unwrap();
+        assert!(starred.favorite);
+        assert!(starred.favorited_at.is_some());
+        assert_eq!(starred.rating, None);
+
+        // starring again keeps when it was first starred, rating keeps the star
+        let again: LibraryState =
+            update(user_id, release, release_id, Some(true), None, &mut conn).unwrap();
+        assert_eq!(again.favorited_at, starred.favorited_at);
+        let rated: LibraryState =
+            update(user_id, release, release_id, None, Some(Some(4)), &mut conn).unwrap();
+        assert_eq!((rated.favorite, rated.rating), (true, Some(4)));
+
+        // unstarring keeps the rating, and the entry goes once neither is left
+        update(user_id, release, release_id, Some(false), None, &mut conn).unwrap();
+        let entity_req = EntityRequest {
+            entity_type: release,
+            id: release_id,
+        };
+        let state: LibraryState =
+            db_getlibrarystate(caller(user_id, &mut conn), entity_req.clone(), &mut conn).unwrap();
+        assert_eq!((state.favorite, state.rating), (false, Some(4)));
+        assert_eq!(entries(user_id, &mut conn), 1);
+
+        update(user_id, release, release_id, None, Some(None), &mut conn).unwrap();
+        assert_eq!(entries(user_id, &mut conn), 0);
+        let state: LibraryState =
+            db_getlibrarystate(caller(user_id, &mut conn), entity_req, &mut conn).unwrap();
+        assert_eq!((state.favorite, state.rating), (false, None));
+    }
+
+    #[test]
+    fn refuses_ratings_out_of_range() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("library-test", &[], &mut conn);
+        let (piece_id, _) = catalog(&mut conn);
+        let piece = EntityType::Piece;
+
+        for rating in [0, 6, -1] {
+            let err = update(
+                user_id,
+                piece,
+                piece_id,
+                None,
+                Some(Some(rating)),
+                &mut conn,
+            );
+            assert!(matches!(err, Err(ApiError::Validation(_))));
+        }
+        for rating in [1, 5] {
+            let state: LibraryState = update(
+                user_id,
+                piece,
+                piece_id,
+                None,
+                Some(Some(rating)),
+                &mut conn,
+            )
+            .unwrap();
+            assert_eq!(state.rating, Some(rating));
+        }
+
+        let err = update(user_id, piece, piece_id, None, None, &mut conn);
+        assert!(matches!(err, Err(ApiError::Validation(_))));
+        let err = update(user_id, piece, -1, Some(true), None, &mut conn);
+        assert!(matches!(err, Err(ApiError::NotFound(_))));
+    }
+
+    #[test]
+    fn lists_favorites_most_recent_first() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("library-test", &[], &mut conn);
+        let admin_id: i32 = user("library-test-admin", &["admin"], &mut conn);
+        let (piece_id, release_id) = catalog(&mut conn);
+        let (other_piece_id, _) = catalog(&mut conn);
+
+        update(
+            user_id,
+            EntityType::Piece,
+            piece_id,
+            Some(true),
+            Some(Some(5)),
+            &mut conn,
+        )
+        .unwrap();
+        update(
+            user_id,
+            EntityType::Release,
+            release_id,
+            Some(true),
+            None,
+            &mut conn,
+        )
+        .unwrap();
+        // rated but not starred, so not a favorite
+        update(
+            user_id,
+            EntityType::Piece,
+            other_piece_id,
+            None,
+            Some(Some(2)),
+            &mut conn,
+        )
+        .unwrap();
+
+        let favorites: Page<Favorite> = db_listfavorites(
+            caller(user_id, &mut conn),
+            FavoritesRequest::default(),
+            &mut conn,
+        )
+        .unwrap();
+        assert_eq!(favorites.total, 2);
+        let listed: Vec<(EntityType, i32, &str, Option<i16>)> = favorites
+            .items
+            .iter()
+            .map(|favorite| {
+                (
+                    favorite.entity_type,
+                    favorite.id,
+                    favorite.name.as_str(),
+                    favorite.rating,
+                )
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                (EntityType::Release, release_id, "Winterreise (1962)", None),
+                (EntityType::Piece, piece_id, "Winterreise", Some(5)),
+            ]
+        );
+
+        // only administrators see the favorites of others
+        let pieces = FavoritesRequest {
+            user_id: Some(user_id),
+            entity_type: Some(EntityType::Piece),
+            ..Default::default()
+        };
+        let favorites: Page<Favorite> =
+            db_listfavorites(caller(admin_id, &mut conn), pieces.clone(), &mut conn).unwrap();
+        assert_eq!(favorites.total, 1);
+        assert_eq!(favorites.items[0].id, piece_id);
+
+        let other_id: i32 = user("library-test-other", &[], &mut conn);
+        let err = db_listfavorites(caller(other_id, &mut conn), pieces, &mut conn);
+        assert!(matches!(err, Err(ApiError::Forbidden(_))));
+    }
+}
@@ -0,0 +1,505 @@
+use crate::api::auth::Authorized;
+use crate::api::get::{ListRequest, Page};
+use crate::api::sessions::Client;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::insert;
+use crate::models::{LoginFailure, LoginThrottle};
+use crate::permission::requires;
+use crate::Response;
+
+use actix_web::web::{Data, Json, Query};
+use actix_web::{delete, get, web, HttpResponse};
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Serialize};
+
+// failed logins allowed for a username before each further attempt is delayed
+const FREE_ATTEMPTS: i32 = 3;
+
+// seconds to wait after the first delayed attempt, doubling with every further failure
+const BACKOFF_BASE: i64 = 1;
+
+// an address is shared by every user behind it, so it may fail this many times as often
+const ADDRESS_FACTOR: i32 = 5;
+
+// days failed logins are kept in the audit trail
+const FAILURE_RETENTION_DAYS: i64 = 90;
+
+// longest attempted username kept
+const MAX_USERNAME_LEN: usize = 255;
+
+// reason a login is recorded as failed
+const REASON_INVALID_CREDENTIALS: &str = "invalid_credentials";
+
+/// What failed logins are counted against
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockoutScope {
+    Username,
+    Ip,
+}
+
+impl LockoutScope {
+    /// Name of the scope as stored in the login_throttles table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockoutScope::Username => "username",
+            LockoutScope::Ip => "ip",
+        }
+    }
+
+    /// Look up a scope by its stored name
+    pub fn parse(name: &str) -> Option<LockoutScope> {
+        [LockoutScope::Username, LockoutScope::Ip]
+            .into_iter()
+            .find(|scope| scope.as_str() == name)
+    }
+}
+
+/// Limits on failed logins, taken from the server configuration
+#[derive(Clone, Copy, Debug)]
+pub struct Throttling {
+    pub threshold: i32,
+    pub lockout: i64,
+}
+
+impl Throttling {
+    /// Read the limits from the server configuration
+    pub fn from_config(config: &Config) -> Self {
+        Throttling {
+            threshold: config.login_lockout_threshold,
+            lockout: config.login_lockout_duration,
+        }
+    }
+
+    /// Seconds before another login is allowed after a number of recent failures, growing
+    /// exponentially until the subject is locked out
+    fn delay(&self, scope: LockoutScope, failures: i32) -> i64 {
+        let factor: i32 = match scope {
+            LockoutScope::Username => 1,
+            LockoutScope::Ip => ADDRESS_FACTOR,
+        };
+        if failures >= self.threshold.saturating_mul(factor) {
+            return self.lockout;
+        }
+        let doublings: i32 = failures - FREE_ATTEMPTS.saturating_mul(factor);
+        if doublings <= 0 {
+            return 0;
+        }
+        (BACKOFF_BASE << (doublings - 1).min(30)).min(self.lockout)
+    }
+}
+
+/// A request from an administrator to clear the failures counted against a username or address
+#[derive(Deserialize, Serialize)]
+pub struct ClearLockoutRequest {
+    pub scope: LockoutScope,
+    pub subject: String,
+}
+
+/// A request for a page of failed logins, optionally for one username or address
+#[derive(Deserialize, Serialize)]
+pub struct LoginFailuresRequest {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub username: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// Recent failed logins counted against a username or address
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Lockout {
+    pub scope: LockoutScope,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: NaiveDateTime,
+    pub blocked_until: NaiveDateTime,
+    pub refused: i32,
+    pub locked: bool,
+}
+
+/// The username and address a login is throttled by
+fn subjects(username: &str, client: &Client) -> Vec<(LockoutScope, String)> {
+    let mut subjects: Vec<(LockoutScope, String)> = vec![(
+        LockoutScope::Username,
+        username.chars().take(MAX_USERNAME_LEN).collect(),
+    )];
+    if let Some(ip_address) = &client.ip_address {
+        subjects.push((LockoutScope::Ip, ip_address.clone()));
+    }
+    subjects
+}
+
+/// Add a failed login to the audit trail, dropping entries past their retention
+fn db_auditfailure(
+    username: &str,
+    client: &Client,
+    reason: &str,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::login_failures;
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    diesel::delete(login_failures::dsl::login_failures.filter(
+        login_failures::dsl::attempted_at.lt(now - Duration::days(FAILURE_RETENTION_DAYS)),
+    ))
+    .execute(conn)?;
+
+    let new_failure = insert::NewLoginFailure {
+        username: username.chars().take(MAX_USERNAME_LEN).collect(),
+        ip_address: client.ip_address.clone(),
+        user_agent: client.user_agent.clone(),
+        reason: reason.to_string(),
+    };
+    diesel::insert_into(login_failures::dsl::login_failures)
+        .values(&new_failure)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Count a login attempt against its username and the address it came from before it is
+/// verified, so concurrent attempts cannot all be verified before any of them is counted.
+/// Attempts made while either is waiting out a backoff or lockout are refused without being
+/// counted as failures or audited, only tallied on the blocking counters.
+pub(crate) fn db_reservelogin(
+    username: &str,
+    client: &Client,
+    throttling: Throttling,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::login_throttles;
+
+    // failures are forgotten once none have happened for the lockout duration
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    let forget_before: NaiveDateTime = now - Duration::seconds(throttling.lockout);
+    diesel::delete(
+        login_throttles::dsl::login_throttles
+            .filter(login_throttles::dsl::last_failure_at.le(forget_before))
+            .filter(login_throttles::dsl::blocked_until.le(now)),
+    )
+    .execute(conn)?;
+
+    let subjects: Vec<(LockoutScope, String)> = subjects(username, client);
+    let blocked_until: Option<NaiveDateTime> = conn.transaction(|conn| {
+        // lock the counters, always in the same order, so concurrent attempts are all counted
+        let mut throttles: Vec<LoginThrottle> = Vec::new();
+        for (scope, subject) in &subjects {
+            diesel::insert_into(login_throttles::dsl::login_throttles)
+                .values((
+                    login_throttles::dsl::scope.eq(scope.as_str()),
+                    login_throttles::dsl::subject.eq(subject),
+                    login_throttles::dsl::last_failure_at.eq(now),
+                    login_throttles::dsl::blocked_until.eq(now),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            let throttle: LoginThrottle = login_throttles::dsl::login_throttles
+                .find((scope.as_str(), subject))
+                .for_update()
+                .first::<LoginThrottle>(conn)?;
+            throttles.push(throttle);
+        }
+        let blocked_until: Option<NaiveDateTime> = throttles
+            .iter()
+            .map(|throttle| throttle.blocked_until)
+            .filter(|blocked_until| *blocked_until > now)
+            .max();
+        if blocked_until.is_some() {
+            // a caller hammering away while blocked only bumps a tally, so it cannot fill the
+            // audit trail at request rate
+            for ((scope, subject), throttle) in subjects.iter().zip(&throttles) {
+                if throttle.blocked_until > now {
+                    diesel::update(
+                        login_throttles::dsl::login_throttles.find((scope.as_str(), subject)),
+                    )
+                    .set(login_throttles::dsl::refused.eq(throttle.refused.saturating_add(1)))
+                    .execute(conn)?;
+                }
+            }
+            return Ok::<Option<NaiveDateTime>, ApiError>(blocked_until);
+        }
+
+        for ((scope, subject), throttle) in subjects.iter().zip(throttles) {
+            let (failures, refused): (i32, i32) = match throttle.last_failure_at <= forget_before {
+                true => (1, 0),
+                false => (throttle.failures.saturating_add(1), throttle.refused),
+            };
+            let blocked_until: NaiveDateTime =
+                now + Duration::seconds(throttling.delay(*scope, failures));
+            diesel::update(login_throttles::dsl::login_throttles.find((scope.as_str(), subject)))
+                .set((
+                    login_throttles::dsl::failures.eq(failures),
+                    login_throttles::dsl::last_failure_at.eq(now),
+                    login_throttles::dsl::blocked_until.eq(blocked_until),
+                    login_throttles::dsl::refused.eq(refused),
+                ))
+                .execute(conn)?;
+        }
+        Ok(None)
+    })?;
+
+    match blocked_until {
+        Some(until) => {
+            // refused attempts do not extend the wait
+            let retry_after: i64 = (until - now).num_milliseconds().saturating_add(999) / 1000;
+            Err(ApiError::TooManyRequests(retry_after.max(1)))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Record a login with a wrong username or password in the audit trail. The attempt was already
+/// counted when it was reserved.
+pub(crate) fn db_loginfailed(
+    username: &str,
+    client: &Client,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    db_auditfailure(username, client, REASON_INVALID_CREDENTIALS, conn)
+}
+
+/// Forget the failures counted against a username once it signs in. The address only gets back
+/// the attempt reserved for this login, so one working account cannot be used to keep guessing
+/// others.
+pub(crate) fn db_loginsucceeded(
+    username: &str,
+    client: &Client,
+    throttling: Throttling,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::login_throttles;
+
+    diesel::delete(
+        login_throttles::dsl::login_throttles
+            .filter(login_throttles::dsl::scope.eq(LockoutScope::Username.as_str()))
+            .filter(login_throttles::dsl::subject.eq(username)),
+    )
+    .execute(conn)?;
+
+    let Some(ip_address) = &client.ip_address else {
+        return Ok(());
+    };
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    conn.transaction::<(), ApiError, _>(|conn| {
+        let throttle: Option<LoginThrottle> = login_throttles::dsl::login_throttles
+            .find((LockoutScope::Ip.as_str(), ip_address))
+            .for_update()
+            .first::<LoginThrottle>(conn)
+            .optional()?;
+        if let Some(throttle) = throttle {
+            // the wait is shortened to what the remaining failures call for, never extended
+            let failures: i32 = (throttle.failures - 1).max(0);
+            let blocked_until: NaiveDateTime = throttle
+                .blocked_until
+                .min(now + Duration::seconds(throttling.delay(LockoutScope::Ip, failures)));
+            diesel::update(
+                login_throttles::dsl::login_throttles.find((LockoutScope::Ip.as_str(), ip_address)),
+            )
+            .set((
+                login_throttles::dsl::failures.eq(failures),
+                login_throttles::dsl::blocked_until.eq(blocked_until),
+            ))
+            .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Get every username and address with recent failures, locked out ones first
+fn db_listlockouts(
+    throttling: Throttling,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Lockout>, ApiError> {
+    use crate::schema::login_throttles;
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    let forget_before: NaiveDateTime = now - Duration::seconds(throttling.lockout);
+    let throttles: Vec<LoginThrottle> = login_throttles::dsl::login_throttles
+        .filter(
+            login_throttles::dsl::last_failure_at
+                .gt(forget_before)
+                .or(login_throttles::dsl::blocked_until.gt(now)),
+        )
+        .order((
+            login_throttles::dsl::blocked_until.desc(),
+            login_throttles::dsl::last_failure_at.desc(),
+        ))
+        .load::<LoginThrottle>(conn)?;
+
+    Ok(throttles
+        .into_iter()
+        .filter_map(|throttle| {
+            // scopes this server no longer knows about are left out
+            Some(Lockout {
+                scope: LockoutScope::parse(&throttle.scope)?,
+                locked: throttle.blocked_until > now,
+                subject: throttle.subject,
+                failures: throttle.failures,
+                last_failure_at: throttle.last_failure_at,
+                blocked_until: throttle.blocked_until,
+                refused: throttle.refused,
+            })
+        })
+        .collect())
+}
+
+/// Clear the failures counted against a username or address, lifting any lockout
+fn db_clearlockout(
+    clear_req: ClearLockoutRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::login_throttles;
+
+    let delete = diesel::delete(
+        login_throttles::dsl::login_throttles
+            .filter(login_throttles::dsl::scope.eq(clear_req.scope.as_str()))
+            .filter(login_throttles::dsl::subject.eq(clear_req.subject)),
+    );
+    match delete.execute(conn)? {
+        0 => Err(ApiError::not_found("Lockout")),
+        _ => Ok(()),
+    }
+}
+
+/// Get a page of failed logins, most recent first
+fn db_listloginfailures(
+    failures_req: LoginFailuresRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Page<LoginFailure>, ApiError> {
+    use crate::schema::login_failures;
+
+    let (limit, offset) = ListRequest {
+        limit: failures_req.limit,
+        offset: failures_req.offset,
+        ..Default::default()
+    }
+    .page()?;
+
+    let mut query = login_failures::dsl::login_failures.into_boxed();
+    let mut count = login_failures::dsl::login_failures.into_boxed();
+    if let Some(username) = &failures_req.username {
+        query = query.filter(login_failures::dsl::username.eq(username));
+        count = count.filter(login_failures::dsl::username.eq(username));
+    }
+    if let Some(ip_address) = &failures_req.ip_address {
+        query = query.filter(login_failures::dsl::ip_address.eq(ip_address));
+        count = count.filter(login_failures::dsl::ip_address.eq(ip_address));
+    }
+
+    let total: i64 = count.count().get_result(conn)?;
+    let failures: Vec<LoginFailure> = query
+        .order((
+            login_failures::dsl::attempted_at.desc(),
+            login_failures::dsl::id.desc(),
+        ))
+        .limit(limit)
+        .offset(offset)
+        .load::<LoginFailure>(conn)?;
+    Ok(Page::new(failures, total, limit, offset))
+}
+
+/// List the usernames and addresses with recent failed logins
+#[get("/auth/lockouts")]
+pub async fn listlockouts(
+    _caller: Authorized<requires::UsersManage>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // get the lockouts from the database
+    let mut conn = pool.get()?;
+    let throttling: Throttling = Throttling::from_config(&config);
+    let lockouts = web::block(move || db_listlockouts(throttling, &mut conn)).await??;
+
+    // return the lockouts on success
+    Ok(HttpResponse::Ok().json(Response::success(lockouts)))
+}
+
+/// Clear the failed logins counted against a username or address
+#[delete("/auth/lockouts")]
+pub async fn clearlockout(
+    _caller: Authorized<requires::UsersManage>,
+    clear_req: Json<ClearLockoutRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the lockout from the database
+    let mut conn = pool.get()?;
+    web::block(move || db_clearlockout(clear_req.into_inner(), &mut conn)).await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// List a page of the audit trail of failed logins
+#[get("/auth/loginfailures")]
+pub async fn listloginfailures(
+    _caller: Authorized<requires::UsersManage>,
+    failures_req: Query<LoginFailuresRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the failed logins from the database
+    let mut conn = pool.get()?;
+    let failures =
+        web::block(move || db_listloginfailures(failures_req.into_inner(), &mut conn)).await??;
+
+    // return the failed logins on success
+    Ok(HttpResponse::Ok().json(Response::success(failures)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+
+    #[test]
+    fn tallies_refused_attempts_without_auditing_them() {
+        use crate::schema::login_failures;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let client = Client {
+            user_agent: None,
+            ip_address: Some("192.0.2.17".to_string()),
+        };
+        let throttling = Throttling {
+            threshold: 1,
+            lockout: 600,
+        };
+        let audited = |conn: &mut PooledConnection<ConnectionManager<PgConnection>>| -> i64 {
+            login_failures::dsl::login_failures
+                .filter(login_failures::dsl::username.eq("hammer"))
+                .count()
+                .get_result(conn)
+                .unwrap()
+        };
+
+        // the first failure locks the username out
+        db_reservelogin("hammer", &client, throttling, &mut conn).unwrap();
+        db_loginfailed("hammer", &client, &mut conn).unwrap();
+        assert_eq!(audited(&mut conn), 1);
+
+        for _ in 0..5 {
+            let refused = db_reservelogin("hammer", &client, throttling, &mut conn);
+            assert!(matches!(refused, Err(ApiError::TooManyRequests(_))));
+        }
+        assert_eq!(audited(&mut conn), 1);
+
+        let lockouts: Vec<Lockout> = db_listlockouts(throttling, &mut conn).unwrap();
+        let lockout: &Lockout = lockouts
+            .iter()
+            .find(|lockout| lockout.scope == LockoutScope::Username && lockout.subject == "hammer")
+            .unwrap();
+        assert!(lockout.locked);
+        assert_eq!(lockout.failures, 1);
+        assert_eq!(lockout.refused, 5);
+
+        // the address is not blocked yet, so its tally is left alone
+        let address: &Lockout = lockouts
+            .iter()
+            .find(|lockout| lockout.scope == LockoutScope::Ip && lockout.subject == "192.0.2.17")
+            .unwrap();
+        assert_eq!(address.refused, 0);
+    }
+}
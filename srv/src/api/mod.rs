@@ -1,4 +1,18 @@
 pub mod addmusic;
+pub mod apikeys;
 pub mod auth;
+pub mod editmusic;
 pub mod get;
+pub mod history;
+pub mod library;
+pub mod lockouts;
+pub mod playlists;
+pub mod roles;
+pub mod scan;
+pub mod scrobbling;
 pub mod search;
+pub mod sessions;
+pub mod stream;
+pub mod subsonic;
+pub mod upload;
+pub mod users;
@@ -0,0 +1,1474 @@
+use crate::api::addmusic::missing_ids;
+use crate::api::auth::{Authorized, Caller};
+use crate::api::editmusic::double_option;
+use crate::api::get::{db_assemblerecordings, ListRequest, Page, Recording};
+use crate::api::subsonic::escape_xml;
+use crate::error::{ApiError, UnknownIds};
+use crate::media::{MediaKind, MediaName};
+use crate::models::{DbPlaylist, DbPlaylistEntry, DbRecording};
+use crate::permission::requires;
+use crate::{insert, update, IdRequest, Response};
+
+use actix_web::http::header;
+use actix_web::web::{Data, Json, Query};
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use chrono::NaiveDateTime;
+use diesel::dsl::count_star;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::sql_types::{Array, Integer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// longest name a playlist can be given
+const MAX_NAME_LEN: usize = 255;
+
+// most entries a playlist can hold
+const MAX_ENTRIES: usize = 10_000;
+
+// name given to imported playlists that carry none
+const DEFAULT_IMPORT_NAME: &str = "Imported playlist";
+
+// path recordings are streamed from, which exported playlists point at
+const STREAM_PATH: &str = "/music/stream/";
+
+/// A request to create a playlist owned by the current user
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AddPlaylistRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub public: Option<bool>,
+}
+
+/// A request for one page of the playlists the caller can see, optionally only those of one user
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PlaylistsRequest {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub owner_id: Option<i32>,
+}
+
+/// A request to rename a playlist, or change its description or whether it is public
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdatePlaylistRequest {
+    pub id: i32,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub description: Option<Option<String>>,
+    pub public: Option<bool>,
+}
+
+/// A request to add recordings to a playlist, before the entry at a position or at the end
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AddEntriesRequest {
+    pub playlist_id: i32,
+    pub recording_ids: Vec<i32>,
+    pub position: Option<i32>,
+}
+
+/// A request to remove entries from a playlist, or to put every entry of it in a new order
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EntriesRequest {
+    pub playlist_id: i32,
+    pub entry_ids: Vec<i32>,
+}
+
+/// A request to replace the users a playlist is shared with
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SharePlaylistRequest {
+    pub playlist_id: i32,
+    pub user_ids: Vec<i32>,
+}
+
+/// A file format playlists can be exported to and imported from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// Content type of files in this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "audio/x-mpegurl; charset=utf-8",
+            PlaylistFormat::Xspf => "application/xspf+xml; charset=utf-8",
+        }
+    }
+
+    /// Extension of files in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+}
+
+/// A request to export a playlist as a file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportPlaylistRequest {
+    pub id: i32,
+    pub format: PlaylistFormat,
+}
+
+/// A request to import a playlist from a file, guessing its format when none is given
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImportPlaylistRequest {
+    pub name: Option<String>,
+    pub format: Option<PlaylistFormat>,
+}
+
+/// A playlist without its entries
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Playlist {
+    pub id: i32,
+    pub owner_id: i32,
+    pub owner: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub public: bool,
+    pub entry_count: i64,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// A recording in a playlist, at a position counted from one
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PlaylistEntry {
+    pub id: i32,
+    pub position: i32,
+    pub added_at: NaiveDateTime,
+    pub recording: Recording,
+}
+
+/// A playlist with its entries in order. Only the owner sees who it is shared with.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PlaylistDetails {
+    #[serde(flatten)]
+    pub playlist: Playlist,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared_with: Option<Vec<i32>>,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+/// A track of an exported playlist
+struct ExportTrack {
+    recording_id: i32,
+    title: String,
+    creator: String,
+    album: String,
+    track_number: i32,
+}
+
+/// Check a playlist name, returning it without surrounding whitespace
+fn valid_name(name: &str) -> Result<String, ApiError> {
+    let name: String = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(ApiError::Validation(format!(
+            "Name must be between 1 and {} characters",
+            MAX_NAME_LEN
+        )));
+    }
+    Ok(name)
+}
+
+/// The playlists a user, or a guest when there is none, can see: public ones, their own and
+/// those shared with them
+fn visible_query(user_id: Option<i32>) -> crate::schema::playlists::BoxedQuery<'static, Pg> {
+    use crate::schema::{playlist_shares, playlists};
+
+    match user_id {
+        Some(user_id) => playlists::dsl::playlists
+            .filter(
+                playlists::dsl::public
+                    .eq(true)
+                    .or(playlists::dsl::owner_id.eq(user_id))
+                    .or(playlists::dsl::id.eq_any(
+                        playlist_shares::dsl::playlist_shares
+                            .filter(playlist_shares::dsl::user_id.eq(user_id))
+                            .select(playlist_shares::dsl::playlist_id),
+                    )),
+            )
+            .into_boxed(),
+        None => playlists::dsl::playlists
+            .filter(playlists::dsl::public.eq(true))
+            .into_boxed(),
+    }
+}
+
+/// Get a playlist the caller can see
+fn db_visibleplaylist(
+    caller: &Caller,
+    playlist_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<DbPlaylist, ApiError> {
+    use crate::schema::playlists;
+
+    // playlists the caller cannot see are reported as missing, so their ids give nothing away
+    visible_query(caller.user().map(|user| user.id))
+        .filter(playlists::dsl::id.eq(playlist_id))
+        .first::<DbPlaylist>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::not_found("Playlist"))
+}
+
+/// Get a playlist the caller owns and may therefore change
+fn db_ownedplaylist(
+    caller: &Caller,
+    playlist_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<DbPlaylist, ApiError> {
+    let user_id: i32 = caller.require_user()?.id;
+    let playlist: DbPlaylist = db_visibleplaylist(caller, playlist_id, conn)?;
+    if playlist.owner_id != user_id {
+        return Err(ApiError::Forbidden(
+            "Only the owner of a playlist can change it".to_string(),
+        ));
+    }
+    Ok(playlist)
+}
+
+/// Note that a playlist has changed
+fn db_touchplaylist(
+    playlist_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::playlists;
+
+    diesel::update(playlists::dsl::playlists.find(playlist_id))
+        .set(playlists::dsl::updated_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Lock a playlist until the end of the transaction, so that changes to its entries are made one
+/// after another
+fn db_lockplaylist(
+    playlist_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::playlists;
+
+    playlists::dsl::playlists
+        .find(playlist_id)
+        .select(playlists::dsl::id)
+        .for_update()
+        .first::<i32>(conn)?;
+    Ok(())
+}
+
+/// Get the entry ids of a playlist in order
+fn db_entryids(
+    playlist_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<i32>, ApiError> {
+    use crate::schema::playlist_entries;
+
+    let entry_ids: Vec<i32> = playlist_entries::dsl::playlist_entries
+        .filter(playlist_entries::dsl::playlist_id.eq(playlist_id))
+        .order(playlist_entries::dsl::position.asc())
+        .select(playlist_entries::dsl::id)
+        .load::<i32>(conn)?;
+    Ok(entry_ids)
+}
+
+/// Number the entries of a playlist in the given order, starting from one
+fn db_renumberentries(
+    playlist_id: i32,
+    entry_ids: &[i32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    // positions are only checked for clashes once every entry has moved
+    diesel::sql_query(
+        "UPDATE playlist_entries SET position = ordered.position::integer
+        FROM unnest($1) WITH ORDINALITY AS ordered(id, position)
+        WHERE playlist_entries.id = ordered.id AND playlist_entries.playlist_id = $2",
+    )
+    .bind::<Array<Integer>, _>(entry_ids)
+    .bind::<Integer, _>(playlist_id)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Add recordings to a playlist before the entry at a position, or at the end. Every recording
+/// must exist.
+fn db_insertentries(
+    playlist_id: i32,
+    recording_ids: &[i32],
+    position: Option<i32>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{playlist_entries, recordings};
+
+    db_lockplaylist(playlist_id, conn)?;
+    let found: Vec<i32> = recordings::dsl::recordings
+        .filter(recordings::dsl::id.eq_any(recording_ids))
+        .select(recordings::dsl::id)
+        .load::<i32>(conn)?;
+    let unknown_ids = UnknownIds {
+        recording_ids: missing_ids(recording_ids, &found),
+        ..Default::default()
+    };
+    if !unknown_ids.is_empty() {
+        return Err(ApiError::UnknownIds(Box::new(unknown_ids)));
+    }
+
+    let mut entry_ids: Vec<i32> = db_entryids(playlist_id, conn)?;
+    if entry_ids.len() + recording_ids.len() > MAX_ENTRIES {
+        return Err(ApiError::Validation(format!(
+            "Playlists can hold at most {} entries",
+            MAX_ENTRIES
+        )));
+    }
+    let index: usize = match position {
+        None => entry_ids.len(),
+        Some(position) if (1..=entry_ids.len() as i32 + 1).contains(&position) => {
+            position as usize - 1
+        }
+        Some(_) => {
+            return Err(ApiError::Validation(format!(
+                "Position must be between 1 and {}",
+                entry_ids.len() + 1
+            )))
+        }
+    };
+
+    // deleted recordings take their entries with them, so the gaps they leave are closed first
+    db_renumberentries(playlist_id, &entry_ids, conn)?;
+
+    // append the new entries, then move them into place if they go before existing ones
+    let new_entries: Vec<insert::NewPlaylistEntry> = recording_ids
+        .iter()
+        .enumerate()
+        .map(|(offset, recording_id)| insert::NewPlaylistEntry {
+            playlist_id,
+            recording_id: *recording_id,
+            position: (entry_ids.len() + offset + 1) as i32,
+        })
+        .collect();
+    let new_ids: Vec<i32> = diesel::insert_into(playlist_entries::dsl::playlist_entries)
+        .values(&new_entries)
+        .returning(playlist_entries::dsl::id)
+        .get_results::<i32>(conn)?;
+    if index < entry_ids.len() {
+        entry_ids.splice(index..index, new_ids);
+        db_renumberentries(playlist_id, &entry_ids, conn)?;
+    }
+    Ok(())
+}
+
+/// Describe playlists along with their owners and number of entries
+fn db_describeplaylists(
+    db_playlists: Vec<DbPlaylist>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Playlist>, ApiError> {
+    use crate::schema::{playlist_entries, users};
+
+    let playlist_ids: Vec<i32> = db_playlists.iter().map(|playlist| playlist.id).collect();
+    let owner_ids: Vec<i32> = db_playlists
+        .iter()
+        .map(|playlist| playlist.owner_id)
+        .collect();
+    let owners: HashMap<i32, String> = users::dsl::users
+        .filter(users::dsl::id.eq_any(&owner_ids))
+        .select((users::dsl::id, users::dsl::username))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect();
+    let entry_counts: HashMap<i32, i64> = playlist_entries::dsl::playlist_entries
+        .filter(playlist_entries::dsl::playlist_id.eq_any(&playlist_ids))
+        .group_by(playlist_entries::dsl::playlist_id)
+        .select((playlist_entries::dsl::playlist_id, count_star()))
+        .load::<(i32, i64)>(conn)?
+        .into_iter()
+        .collect();
+
+    let playlists: Vec<Playlist> = db_playlists
+        .into_iter()
+        .map(|playlist| Playlist {
+            id: playlist.id,
+            owner_id: playlist.owner_id,
+            owner: owners.get(&playlist.owner_id).cloned().unwrap_or_default(),
+            name: playlist.name,
+            description: playlist.description,
+            public: playlist.public,
+            entry_count: entry_counts.get(&playlist.id).copied().unwrap_or(0),
+            created_at: playlist.created_at,
+            updated_at: playlist.updated_at,
+        })
+        .collect();
+    Ok(playlists)
+}
+
+/// Get a playlist with its entries, and who it is shared with when the caller owns it
+fn db_playlistdetails(
+    caller: &Caller,
+    db_playlist: DbPlaylist,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<PlaylistDetails, ApiError> {
+    use crate::schema::{playlist_entries, playlist_shares, recordings};
+
+    let rows: Vec<(DbPlaylistEntry, DbRecording)> = playlist_entries::dsl::playlist_entries
+        .inner_join(recordings::dsl::recordings)
+        .filter(playlist_entries::dsl::playlist_id.eq(db_playlist.id))
+        .order(playlist_entries::dsl::position.asc())
+        .load::<(DbPlaylistEntry, DbRecording)>(conn)?;
+    let (db_entries, db_recordings): (Vec<DbPlaylistEntry>, Vec<DbRecording>) =
+        rows.into_iter().unzip();
+    let recordings: Vec<Recording> = db_assemblerecordings(db_recordings, conn)?;
+    let entries: Vec<PlaylistEntry> = db_entries
+        .into_iter()
+        .zip(recordings)
+        .map(|(db_entry, recording)| PlaylistEntry {
+            id: db_entry.id,
+            position: db_entry.position,
+            added_at: db_entry.added_at,
+            recording,
+        })
+        .collect();
+
+    let owned: bool = caller
+        .user()
+        .is_some_and(|user| user.id == db_playlist.owner_id);
+    let shared_with: Option<Vec<i32>> = match owned {
+        true => Some(
+            playlist_shares::dsl::playlist_shares
+                .filter(playlist_shares::dsl::playlist_id.eq(db_playlist.id))
+                .order(playlist_shares::dsl::user_id.asc())
+                .select(playlist_shares::dsl::user_id)
+                .load::<i32>(conn)?,
+        ),
+        false => None,
+    };
+
+    let playlist: Playlist = db_describeplaylists(vec![db_playlist], conn)?
+        .pop()
+        .ok_or_else(|| ApiError::not_found("Playlist"))?;
+    Ok(PlaylistDetails {
+        playlist,
+        shared_with,
+        entries,
+    })
+}
+
+/// Create an empty playlist owned by the caller
+fn db_addplaylist(
+    caller: Caller,
+    add_req: AddPlaylistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Playlist, ApiError> {
+    use crate::schema::playlists;
+
+    let user_id: i32 = caller.require_user()?.id;
+    let new_playlist = insert::NewPlaylist {
+        owner_id: user_id,
+        name: valid_name(&add_req.name)?,
+        description: add_req.description,
+        public: add_req.public.unwrap_or(false),
+    };
+    let db_playlist: DbPlaylist = diesel::insert_into(playlists::dsl::playlists)
+        .values(&new_playlist)
+        .get_result::<DbPlaylist>(conn)?;
+
+    db_describeplaylists(vec![db_playlist], conn)?
+        .pop()
+        .ok_or(ApiError::Internal)
+}
+
+/// Get one page of the playlists the caller can see, most recently changed first
+fn db_listplaylists(
+    caller: Caller,
+    playlists_req: PlaylistsRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Page<Playlist>, ApiError> {
+    use crate::schema::playlists;
+
+    let (limit, offset) = ListRequest {
+        limit: playlists_req.limit,
+        offset: playlists_req.offset,
+        ..Default::default()
+    }
+    .page()?;
+
+    let user_id: Option<i32> = caller.user().map(|user| user.id);
+    let mut count_query = visible_query(user_id);
+    let mut page_query = visible_query(user_id);
+    if let Some(owner_id) = playlists_req.owner_id {
+        count_query = count_query.filter(playlists::dsl::owner_id.eq(owner_id));
+        page_query = page_query.filter(playlists::dsl::owner_id.eq(owner_id));
+    }
+    let total: i64 = count_query.count().get_result(conn)?;
+    let db_playlists: Vec<DbPlaylist> = page_query
+        .order((playlists::dsl::updated_at.desc(), playlists::dsl::id.desc()))
+        .limit(limit)
+        .offset(offset)
+        .load::<DbPlaylist>(conn)?;
+
+    let items: Vec<Playlist> = db_describeplaylists(db_playlists, conn)?;
+    Ok(Page::new(items, total, limit, offset))
+}
+
+/// Get a playlist the caller can see, with its entries
+fn db_getplaylist(
+    caller: Caller,
+    playlist_req: IdRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<PlaylistDetails, ApiError> {
+    let db_playlist: DbPlaylist = db_visibleplaylist(&caller, playlist_req.id, conn)?;
+    db_playlistdetails(&caller, db_playlist, conn)
+}
+
+/// Rename a playlist of the caller, or change its description or visibility
+fn db_updateplaylist(
+    caller: Caller,
+    update_req: UpdatePlaylistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::playlists;
+
+    db_ownedplaylist(&caller, update_req.id, conn)?;
+    let changes = update::UpdatePlaylist {
+        name: update_req.name.as_deref().map(valid_name).transpose()?,
+        description: update_req.description,
+        public: update_req.public,
+    };
+    if !changes.is_empty() {
+        diesel::update(playlists::dsl::playlists.find(update_req.id))
+            .set((
+                &changes,
+                playlists::dsl::updated_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Delete a playlist of the caller along with its entries
+fn db_deleteplaylist(
+    caller: Caller,
+    delete_req: IdRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::playlists;
+
+    db_ownedplaylist(&caller, delete_req.id, conn)?;
+    diesel::delete(playlists::dsl::playlists.find(delete_req.id)).execute(conn)?;
+    Ok(())
+}
+
+/// Add recordings to a playlist of the caller
+fn db_addentries(
+    caller: Caller,
+    add_req: AddEntriesRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    if add_req.recording_ids.is_empty() {
+        return Err(ApiError::Validation(
+            "At least one recording must be given".to_string(),
+        ));
+    }
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        db_ownedplaylist(&caller, add_req.playlist_id, conn)?;
+        db_insertentries(
+            add_req.playlist_id,
+            &add_req.recording_ids,
+            add_req.position,
+            conn,
+        )?;
+        db_touchplaylist(add_req.playlist_id, conn)
+    })
+}
+
+/// Remove entries from a playlist of the caller, closing the gaps they leave
+fn db_removeentries(
+    caller: Caller,
+    remove_req: EntriesRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::playlist_entries;
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        db_ownedplaylist(&caller, remove_req.playlist_id, conn)?;
+        db_lockplaylist(remove_req.playlist_id, conn)?;
+        let entry_ids: Vec<i32> = db_entryids(remove_req.playlist_id, conn)?;
+        if remove_req.entry_ids.is_empty()
+            || remove_req
+                .entry_ids
+                .iter()
+                .any(|entry_id| !entry_ids.contains(entry_id))
+        {
+            return Err(ApiError::Validation(
+                "Entry ids must be entries of the playlist".to_string(),
+            ));
+        }
+
+        diesel::delete(
+            playlist_entries::dsl::playlist_entries
+                .filter(playlist_entries::dsl::id.eq_any(&remove_req.entry_ids)),
+        )
+        .execute(conn)?;
+        let remaining: Vec<i32> = entry_ids
+            .into_iter()
+            .filter(|entry_id| !remove_req.entry_ids.contains(entry_id))
+            .collect();
+        db_renumberentries(remove_req.playlist_id, &remaining, conn)?;
+        db_touchplaylist(remove_req.playlist_id, conn)
+    })
+}
+
+/// Put every entry of a playlist of the caller in a new order
+fn db_reorderentries(
+    caller: Caller,
+    reorder_req: EntriesRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    conn.transaction::<(), ApiError, _>(|conn| {
+        db_ownedplaylist(&caller, reorder_req.playlist_id, conn)?;
+        db_lockplaylist(reorder_req.playlist_id, conn)?;
+
+        // every entry of the playlist must be listed exactly once
+        let mut entry_ids: Vec<i32> = db_entryids(reorder_req.playlist_id, conn)?;
+        let mut requested_ids: Vec<i32> = reorder_req.entry_ids.clone();
+        entry_ids.sort_unstable();
+        requested_ids.sort_unstable();
+        if entry_ids != requested_ids {
+            return Err(ApiError::Validation(
+                "Entry ids must list every entry of the playlist once".to_string(),
+            ));
+        }
+
+        db_renumberentries(reorder_req.playlist_id, &reorder_req.entry_ids, conn)?;
+        db_touchplaylist(reorder_req.playlist_id, conn)
+    })
+}
+
+/// Replace the users a playlist of the caller is shared with
+fn db_shareplaylist(
+    caller: Caller,
+    share_req: SharePlaylistRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{playlist_shares, users};
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        let playlist: DbPlaylist = db_ownedplaylist(&caller, share_req.playlist_id, conn)?;
+        let found: Vec<i32> = users::dsl::users
+            .filter(users::dsl::id.eq_any(&share_req.user_ids))
+            .select(users::dsl::id)
+            .load::<i32>(conn)?;
+        let unknown_ids = UnknownIds {
+            user_ids: missing_ids(&share_req.user_ids, &found),
+            ..Default::default()
+        };
+        if !unknown_ids.is_empty() {
+            return Err(ApiError::UnknownIds(Box::new(unknown_ids)));
+        }
+
+        // the owner always sees their own playlist, so they are never listed
+        let shares: Vec<(i32, i32)> = found
+            .into_iter()
+            .filter(|user_id| *user_id != playlist.owner_id)
+            .map(|user_id| (playlist.id, user_id))
+            .collect();
+        diesel::delete(
+            playlist_shares::dsl::playlist_shares
+                .filter(playlist_shares::dsl::playlist_id.eq(playlist.id)),
+        )
+        .execute(conn)?;
+        if shares.is_empty() {
+            return Ok(());
+        }
+        diesel::insert_into(playlist_shares::dsl::playlist_shares)
+            .values(
+                shares
+                    .iter()
+                    .map(|(playlist_id, user_id)| {
+                        (
+                            playlist_shares::dsl::playlist_id.eq(playlist_id),
+                            playlist_shares::dsl::user_id.eq(user_id),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Get a playlist the caller can see along with what its exported tracks show of each recording
+fn db_exportplaylist(
+    caller: Caller,
+    playlist_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(DbPlaylist, Vec<ExportTrack>), ApiError> {
+    use crate::schema::{
+        performers, playlist_entries, recording_performers, recordings, release_performers,
+        releases,
+    };
+
+    let db_playlist: DbPlaylist = db_visibleplaylist(&caller, playlist_id, conn)?;
+    let rows: Vec<(i32, String, i32, String, i32)> = playlist_entries::dsl::playlist_entries
+        .inner_join(recordings::dsl::recordings.inner_join(releases::dsl::releases))
+        .filter(playlist_entries::dsl::playlist_id.eq(playlist_id))
+        .order(playlist_entries::dsl::position.asc())
+        .select((
+            recordings::dsl::id,
+            recordings::dsl::piece_name,
+            releases::dsl::id,
+            releases::dsl::name,
+            recordings::dsl::track_number,
+        ))
+        .load::<(i32, String, i32, String, i32)>(conn)?;
+
+    // recordings are credited to their own performers, or to those of their release
+    let recording_ids: Vec<i32> = rows.iter().map(|row| row.0).collect();
+    let release_ids: Vec<i32> = rows.iter().map(|row| row.2).collect();
+    let mut recording_performers: HashMap<i32, Vec<String>> = HashMap::new();
+    for (recording_id, name) in recording_performers::dsl::recording_performers
+        .inner_join(performers::dsl::performers)
+        .filter(recording_performers::dsl::recording_id.eq_any(&recording_ids))
+        .order(performers::dsl::name.asc())
+        .select((
+            recording_performers::dsl::recording_id,
+            performers::dsl::name,
+        ))
+        .load::<(i32, String)>(conn)?
+    {
+        recording_performers
+            .entry(recording_id)
+            .or_default()
+            .push(name);
+    }
+    let mut release_performers: HashMap<i32, Vec<String>> = HashMap::new();
+    for (release_id, name) in release_performers::dsl::release_performers
+        .inner_join(performers::dsl::performers)
+        .filter(release_performers::dsl::release_id.eq_any(&release_ids))
+        .order(performers::dsl::name.asc())
+        .select((release_performers::dsl::release_id, performers::dsl::name))
+        .load::<(i32, String)>(conn)?
+    {
+        release_performers.entry(release_id).or_default().push(name);
+    }
+
+    let tracks: Vec<ExportTrack> = rows
+        .into_iter()
+        .map(
+            |(recording_id, title, release_id, album, track_number)| ExportTrack {
+                recording_id,
+                title,
+                creator: recording_performers
+                    .get(&recording_id)
+                    .or_else(|| release_performers.get(&release_id))
+                    .map(|names| names.join(", "))
+                    .unwrap_or_default(),
+                album,
+                track_number,
+            },
+        )
+        .collect();
+    Ok((db_playlist, tracks))
+}
+
+/// Write a playlist as an extended M3U file in UTF-8
+fn write_m3u8(playlist: &DbPlaylist, tracks: &[ExportTrack], base_url: &str) -> String {
+    // directives are single lines, so line breaks in names are flattened
+    let line = |text: &str| text.replace(['\r', '\n'], " ");
+    let mut m3u8: String = format!("#EXTM3U\n#PLAYLIST:{}\n", line(&playlist.name));
+    for track in tracks {
+        let title: String = match track.creator.is_empty() {
+            true => track.title.clone(),
+            false => format!("{} - {}", track.creator, track.title),
+        };
+        m3u8.push_str(&format!(
+            "#EXTINF:-1,{}\n#EXTALB:{}\n{}{}{}\n",
+            line(&title),
+            line(&track.album),
+            base_url,
+            STREAM_PATH,
+            track.recording_id
+        ));
+    }
+    m3u8
+}
+
+/// Write a playlist as an XSPF document
+fn write_xspf(playlist: &DbPlaylist, tracks: &[ExportTrack], base_url: &str) -> String {
+    let mut xspf: String = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    xspf.push_str(&format!(
+        "  <title>{}</title>\n",
+        escape_xml(&playlist.name)
+    ));
+    if let Some(description) = &playlist.description {
+        xspf.push_str(&format!(
+            "  <annotation>{}</annotation>\n",
+            escape_xml(description)
+        ));
+    }
+    xspf.push_str("  <trackList>\n");
+    for track in tracks {
+        xspf.push_str(&format!(
+            "    <track>\n      <location>{}{}{}</location>\n      <identifier>{}</identifier>\n      <title>{}</title>\n",
+            escape_xml(base_url),
+            STREAM_PATH,
+            track.recording_id,
+            MediaName {
+                kind: MediaKind::Recording,
+                id: track.recording_id,
+            },
+            escape_xml(&track.title)
+        ));
+        if !track.creator.is_empty() {
+            xspf.push_str(&format!(
+                "      <creator>{}</creator>\n",
+                escape_xml(&track.creator)
+            ));
+        }
+        xspf.push_str(&format!(
+            "      <album>{}</album>\n      <trackNum>{}</trackNum>\n    </track>\n",
+            escape_xml(&track.album),
+            track.track_number
+        ));
+    }
+    xspf.push_str("  </trackList>\n</playlist>\n");
+    xspf
+}
+
+/// Undo the escaping of XML text
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The text inside each element with a tag, in order. Attributes of the opening tag are
+/// skipped, but this is no XML parser: namespace prefixes, CDATA sections and elements nested
+/// inside one with the same tag are not understood.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open: String = format!("<{}", tag);
+    let close: String = format!("</{}>", tag);
+    let mut elements: Vec<&str> = Vec::new();
+    let mut rest: &str = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        // the tag must end here, so that looking for <track> does not find <trackList>
+        if !rest.starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace()) {
+            continue;
+        }
+        let Some(open_end) = rest.find('>') else {
+            break;
+        };
+        let empty: bool = rest[..open_end].ends_with('/');
+        rest = &rest[open_end + 1..];
+        if empty {
+            elements.push("");
+            continue;
+        }
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        elements.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    elements
+}
+
+/// Read the title and track locations of an extended M3U file
+fn read_m3u8(content: &str) -> (Option<String>, Vec<String>) {
+    let mut title: Option<String> = None;
+    let mut locations: Vec<String> = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            title = Some(name.trim().to_string());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            locations.push(line.to_string());
+        }
+    }
+    (title, locations)
+}
+
+/// Read the title and track locations of an XSPF document, preferring the recording
+/// identifiers written on export over locations
+fn read_xspf(content: &str) -> (Option<String>, Vec<String>) {
+    let (head, track_list) = content.split_once("<trackList").unwrap_or((content, ""));
+    let title: Option<String> = xml_elements(head, "title")
+        .first()
+        .map(|title| unescape_xml(title.trim()));
+    let locations: Vec<String> = xml_elements(track_list, "track")
+        .into_iter()
+        .map(|track| {
+            xml_elements(track, "identifier")
+                .into_iter()
+                .chain(xml_elements(track, "location"))
+                .map(|location| unescape_xml(location.trim()))
+                .find(|location| recording_id(location).is_some())
+                .unwrap_or_default()
+        })
+        .collect();
+    (title, locations)
+}
+
+/// The recording a playlist location refers to: a stream URL, a recording media name such as
+/// `recording-12`, or a bare recording id
+fn recording_id(location: &str) -> Option<i32> {
+    let path: &str = location.split(['?', '#']).next()?.trim_end_matches('/');
+    let last: &str = path.rsplit('/').next()?;
+    if let Some(media_name) = MediaName::parse(last) {
+        return (media_name.kind == MediaKind::Recording).then_some(media_name.id);
+    }
+    let streamed: bool = path == last || path.ends_with(&format!("{}{}", STREAM_PATH, last));
+    match streamed && !last.is_empty() && last.bytes().all(|b| b.is_ascii_digit()) {
+        true => last.parse().ok(),
+        false => None,
+    }
+}
+
+/// Create a playlist for the caller from the contents of a playlist file
+fn db_importplaylist(
+    caller: Caller,
+    import_req: ImportPlaylistRequest,
+    content: String,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<PlaylistDetails, ApiError> {
+    use crate::schema::playlists;
+
+    let user_id: i32 = caller.require_user()?.id;
+
+    // files without a stated format are XSPF if they look like XML
+    let content: &str = content.trim_start_matches('\u{feff}');
+    let format: PlaylistFormat =
+        import_req
+            .format
+            .unwrap_or_else(|| match content.trim_start().starts_with('<') {
+                true => PlaylistFormat::Xspf,
+                false => PlaylistFormat::M3u8,
+            });
+    let (title, locations) = match format {
+        PlaylistFormat::M3u8 => read_m3u8(content),
+        PlaylistFormat::Xspf => read_xspf(content),
+    };
+    let recording_ids: Vec<i32> = locations
+        .iter()
+        .enumerate()
+        .map(|(index, location)| {
+            recording_id(location).ok_or_else(|| {
+                ApiError::Validation(format!(
+                    "Track {} of the playlist does not refer to a recording",
+                    index + 1
+                ))
+            })
+        })
+        .collect::<Result<Vec<i32>, ApiError>>()?;
+    let name: String = valid_name(
+        import_req
+            .name
+            .or(title.filter(|title| !title.trim().is_empty()))
+            .as_deref()
+            .unwrap_or(DEFAULT_IMPORT_NAME),
+    )?;
+
+    let db_playlist: DbPlaylist = conn.transaction::<DbPlaylist, ApiError, _>(|conn| {
+        let new_playlist = insert::NewPlaylist {
+            owner_id: user_id,
+            name,
+            description: None,
+            public: false,
+        };
+        let db_playlist: DbPlaylist = diesel::insert_into(playlists::dsl::playlists)
+            .values(&new_playlist)
+            .get_result::<DbPlaylist>(conn)?;
+        if !recording_ids.is_empty() {
+            db_insertentries(db_playlist.id, &recording_ids, None, conn)?;
+        }
+        Ok(db_playlist)
+    })?;
+    db_playlistdetails(&caller, db_playlist, conn)
+}
+
+/// Create a playlist for the current user
+#[post("/playlists/add")]
+pub async fn addplaylist(
+    caller: Authorized<requires::PlaylistsEdit>,
+    add_req: Json<AddPlaylistRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // insert the playlist into the database
+    let mut conn = pool.get()?;
+    let playlist =
+        web::block(move || db_addplaylist(caller.into_inner(), add_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the playlist on success
+    Ok(HttpResponse::Created().json(Response::success(playlist)))
+}
+
+/// List the playlists the current user can see
+#[get("/playlists/list")]
+pub async fn listplaylists(
+    caller: Authorized<requires::CatalogRead>,
+    playlists_req: Query<PlaylistsRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the playlists from the database
+    let mut conn = pool.get()?;
+    let playlists = web::block(move || {
+        db_listplaylists(caller.into_inner(), playlists_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the playlists on success
+    Ok(HttpResponse::Ok().json(Response::success(playlists)))
+}
+
+/// Get a playlist with its entries
+#[get("/playlists/get")]
+pub async fn getplaylist(
+    caller: Authorized<requires::CatalogRead>,
+    playlist_req: Query<IdRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the playlist from the database
+    let mut conn = pool.get()?;
+    let playlist = web::block(move || {
+        db_getplaylist(caller.into_inner(), playlist_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return the playlist on success
+    Ok(HttpResponse::Ok().json(Response::success(playlist)))
+}
+
+/// Rename a playlist, or change its description or visibility
+#[patch("/playlists/update")]
+pub async fn updateplaylist(
+    caller: Authorized<(requires::PlaylistsEdit, requires::CatalogRead)>,
+    update_req: Json<UpdatePlaylistRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the changes in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_updateplaylist(caller.into_inner(), update_req.into_inner(), &mut conn))
+        .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Delete a playlist
+#[delete("/playlists/delete")]
+pub async fn deleteplaylist(
+    caller: Authorized<(requires::PlaylistsEdit, requires::CatalogRead)>,
+    delete_req: Json<IdRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the deletion in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deleteplaylist(caller.into_inner(), delete_req.into_inner(), &mut conn))
+        .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Add recordings to a playlist
+#[post("/playlists/add/entries")]
+pub async fn addentries(
+    caller: Authorized<(requires::PlaylistsEdit, requires::CatalogRead)>,
+    add_req: Json<AddEntriesRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // insert the entries into the database
+    let mut conn = pool.get()?;
+    web::block(move || db_addentries(caller.into_inner(), add_req.into_inner(), &mut conn))
+        .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Remove entries from a playlist
+#[delete("/playlists/delete/entries")]
+pub async fn removeentries(
+    caller: Authorized<(requires::PlaylistsEdit, requires::CatalogRead)>,
+    remove_req: Json<EntriesRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the removal in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_removeentries(caller.into_inner(), remove_req.into_inner(), &mut conn))
+        .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Put the entries of a playlist in a new order
+#[patch("/playlists/update/entries")]
+pub async fn reorderentries(
+    caller: Authorized<(requires::PlaylistsEdit, requires::CatalogRead)>,
+    reorder_req: Json<EntriesRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the new order in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_reorderentries(caller.into_inner(), reorder_req.into_inner(), &mut conn))
+        .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Choose the users a playlist is shared with
+#[put("/playlists/shares")]
+pub async fn shareplaylist(
+    caller: Authorized<(requires::PlaylistsEdit, requires::CatalogRead)>,
+    share_req: Json<SharePlaylistRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // store the shares in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_shareplaylist(caller.into_inner(), share_req.into_inner(), &mut conn))
+        .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Download a playlist as an M3U8 or XSPF file pointing at the stream of each recording
+#[get("/playlists/export")]
+pub async fn exportplaylist(
+    req: HttpRequest,
+    caller: Authorized<requires::CatalogRead>,
+    export_req: Query<ExportPlaylistRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the playlist and its tracks from the database
+    let mut conn = pool.get()?;
+    let playlist_id: i32 = export_req.id;
+    let (playlist, tracks) =
+        web::block(move || db_exportplaylist(caller.into_inner(), playlist_id, &mut conn))
+            .await??;
+
+    // point at this server as the client reached it
+    let base_url: String = format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    );
+    let format: PlaylistFormat = export_req.format;
+    let body: String = match format {
+        PlaylistFormat::M3u8 => write_m3u8(&playlist, &tracks, &base_url),
+        PlaylistFormat::Xspf => write_xspf(&playlist, &tracks, &base_url),
+    };
+    let file_name: String = playlist
+        .name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == ' ' || c == '-' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}.{}\"",
+                file_name.trim(),
+                format.extension()
+            ),
+        ))
+        .body(body))
+}
+
+/// Create a playlist from an uploaded M3U8 or XSPF file
+#[post("/playlists/import")]
+pub async fn importplaylist(
+    caller: Authorized<requires::PlaylistsEdit>,
+    import_req: Query<ImportPlaylistRequest>,
+    content: String,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // insert the playlist and its entries into the database
+    let mut conn = pool.get()?;
+    let playlist = web::block(move || {
+        db_importplaylist(
+            caller.into_inner(),
+            import_req.into_inner(),
+            content,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    // return the playlist on success
+    Ok(HttpResponse::Created().json(Response::success(playlist)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+
+    /// Add a playlist holding three recordings of a release, giving its id and theirs
+    fn playlist(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> (i32, Vec<i32>) {
+        use crate::schema::{pieces, playlists, recordings, releases, users};
+
+        let user_id: i32 = diesel::insert_into(users::table)
+            .values((
+                users::username.eq("playlist-test"),
+                users::password_hash.eq("unused"),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap();
+        let piece_id: i32 = diesel::insert_into(pieces::table)
+            .values(pieces::name.eq("Goldberg Variations"))
+            .returning(pieces::id)
+            .get_result(conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("Goldberg Variations (1981)"))
+            .returning(releases::id)
+            .get_result(conn)
+            .unwrap();
+        let recording_ids: Vec<i32> = (1..=3)
+            .map(|track_number| {
+                diesel::insert_into(recordings::table)
+                    .values((
+                        recordings::piece_name.eq(format!("Variation {}", track_number)),
+                        recordings::piece_id.eq(piece_id),
+                        recordings::release_id.eq(release_id),
+                        recordings::track_number.eq(track_number),
+                    ))
+                    .returning(recordings::id)
+                    .get_result(conn)
+                    .unwrap()
+            })
+            .collect();
+        let playlist_id: i32 = diesel::insert_into(playlists::table)
+            .values((playlists::owner_id.eq(user_id), playlists::name.eq("Bach")))
+            .returning(playlists::id)
+            .get_result(conn)
+            .unwrap();
+        db_insertentries(playlist_id, &recording_ids, None, conn).unwrap();
+        (playlist_id, recording_ids)
+    }
+
+    /// The recordings of a playlist with their positions, in order
+    fn entries(
+        playlist_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Vec<(i32, i32)> {
+        use crate::schema::playlist_entries;
+
+        playlist_entries::table
+            .filter(playlist_entries::playlist_id.eq(playlist_id))
+            .order(playlist_entries::position.asc())
+            .select((playlist_entries::position, playlist_entries::recording_id))
+            .load::<(i32, i32)>(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn appends_after_a_recording_is_deleted() {
+        use crate::schema::recordings;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (playlist_id, recording_ids) = playlist(&mut conn);
+
+        // deleting the recording in the middle takes its entry along, leaving a gap
+        diesel::delete(recordings::table.find(recording_ids[1]))
+            .execute(&mut conn)
+            .unwrap();
+        assert_eq!(
+            entries(playlist_id, &mut conn),
+            vec![(1, recording_ids[0]), (3, recording_ids[2])]
+        );
+
+        // appending closes the gap rather than clashing with the last entry
+        db_insertentries(playlist_id, &[recording_ids[0]], None, &mut conn).unwrap();
+        assert_eq!(
+            entries(playlist_id, &mut conn),
+            vec![
+                (1, recording_ids[0]),
+                (2, recording_ids[2]),
+                (3, recording_ids[0])
+            ]
+        );
+
+        // and entries still go where they are asked to
+        db_insertentries(playlist_id, &[recording_ids[2]], Some(1), &mut conn).unwrap();
+        assert_eq!(
+            entries(playlist_id, &mut conn),
+            vec![
+                (1, recording_ids[2]),
+                (2, recording_ids[0]),
+                (3, recording_ids[2]),
+                (4, recording_ids[0])
+            ]
+        );
+    }
+
+    /// A playlist named as given, as stored
+    fn stored_playlist(name: &str) -> DbPlaylist {
+        let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+        DbPlaylist {
+            id: 1,
+            owner_id: 1,
+            name: name.to_string(),
+            description: Some("Keyboard works\n& more".to_string()),
+            public: false,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Tracks of recordings with awkward titles, albums and performers
+    fn export_tracks() -> Vec<ExportTrack> {
+        vec![
+            ExportTrack {
+                recording_id: 12,
+                title: "Aria <da capo>".to_string(),
+                creator: "Glenn Gould & friends".to_string(),
+                album: "Goldberg\nVariations".to_string(),
+                track_number: 1,
+            },
+            ExportTrack {
+                recording_id: 345,
+                title: "Variation 1 \"a 1 Clav.\"".to_string(),
+                creator: String::new(),
+                album: "Bach &lt; Händel".to_string(),
+                track_number: 1002,
+            },
+        ]
+    }
+
+    /// The recordings a list of playlist locations refer to
+    fn recording_ids(locations: &[String]) -> Vec<Option<i32>> {
+        locations
+            .iter()
+            .map(|location| recording_id(location))
+            .collect()
+    }
+
+    #[test]
+    fn reads_back_written_m3u8() {
+        let playlist: DbPlaylist = stored_playlist("Bach & <Gould>\nlive");
+        let m3u8: String = write_m3u8(&playlist, &export_tracks(), "https://music.example/api");
+        assert_eq!(
+            m3u8.lines()
+                .filter(|line| line.starts_with("#EXTINF"))
+                .count(),
+            2
+        );
+        assert!(m3u8.contains("#EXTALB:Goldberg Variations\n"));
+
+        // line breaks in the name are flattened, everything else is kept as it is
+        let (title, locations) = read_m3u8(&m3u8);
+        assert_eq!(title.as_deref(), Some("Bach & <Gould> live"));
+        assert_eq!(
+            locations,
+            vec![
+                "https://music.example/api/music/stream/12",
+                "https://music.example/api/music/stream/345"
+            ]
+        );
+        assert_eq!(recording_ids(&locations), vec![Some(12), Some(345)]);
+    }
+
+    #[test]
+    fn reads_back_written_xspf() {
+        let playlist: DbPlaylist = stored_playlist("Bach & <Gould>\nlive");
+        let xspf: String = write_xspf(&playlist, &export_tracks(), "https://music.example/api");
+        assert!(xspf.contains("<title>Bach &amp; &lt;Gould&gt;\nlive</title>"));
+        assert!(xspf.contains("<album>Bach &amp;lt; Händel</album>"));
+
+        let (title, locations) = read_xspf(&xspf);
+        assert_eq!(title.as_deref(), Some("Bach & <Gould>\nlive"));
+        assert_eq!(locations, vec!["recording-12", "recording-345"]);
+        assert_eq!(recording_ids(&locations), vec![Some(12), Some(345)]);
+    }
+
+    #[test]
+    fn reads_tracks_by_identifier_or_location() {
+        let xspf: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title lang="de">Fr&#252;hling &amp; Sommer</title>
+  <trackList>
+    <track><identifier>recording-7</identifier></track>
+    <track id="second">
+      <location>https://music.example/music/stream/8?token=abc&amp;format=flac</location>
+      <trackNum>8</trackNum>
+    </track>
+    <track><identifier/><location>https://music.example/music/stream/9#t=30</location></track>
+    <track><location>https://elsewhere.example/song.mp3</location></track>
+  </trackList>
+</playlist>"#;
+        let (title, locations) = read_xspf(xspf);
+        assert_eq!(title.as_deref(), Some("Fr&#252;hling & Sommer"));
+        assert_eq!(
+            recording_ids(&locations),
+            vec![Some(7), Some(8), Some(9), None]
+        );
+
+        let m3u8: &str = "#EXTM3U\r\n\
+            #EXTINF:-1,Gould - Aria\r\n\
+            https://music.example/music/stream/10?token=abc&format=flac\r\n\
+            \r\n\
+            recording-11\r\n\
+            https://music.example/music/image/release-12\r\n";
+        let (title, locations) = read_m3u8(m3u8);
+        assert_eq!(title, None);
+        assert_eq!(recording_ids(&locations), vec![Some(10), Some(11), None]);
+    }
+
+    #[test]
+    fn refuses_imports_of_tracks_that_are_not_recordings() {
+        use crate::api::auth::Credential;
+        use crate::models::User;
+        use crate::schema::{playlists, users};
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user: User = diesel::insert_into(users::table)
+            .values((
+                users::username.eq("playlist-importer"),
+                users::password_hash.eq("unused"),
+            ))
+            .get_result(&mut conn)
+            .unwrap();
+        let user_id: i32 = user.id;
+        let caller: Caller = Caller::authenticated(Credential::Password, user);
+        let import_req = ImportPlaylistRequest {
+            name: None,
+            format: None,
+        };
+        let content: String =
+            "#EXTM3U\nrecording-1\nhttps://music.example/music/image/release-1\n".to_string();
+
+        let result = db_importplaylist(caller, import_req, content, &mut conn);
+        assert!(
+            matches!(result, Err(ApiError::Validation(message)) if message.starts_with("Track 2 "))
+        );
+        let created: i64 = playlists::table
+            .filter(playlists::owner_id.eq(user_id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(created, 0);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::api::auth::{Authorized, Caller};
+use crate::error::ApiError;
+use crate::insert;
+use crate::models::Role;
+use crate::permission::{requires, Permission, ADMIN_ROLE};
+use crate::Response;
+
+use actix_web::web::{Data, Json};
+use actix_web::{get, put, web, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::sql_types::BigInt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+// advisory lock held while deciding whether anyone can manage users, both when creating the
+// first admin and when a change could leave nobody able to
+const MANAGERS_LOCK: i64 = 0x616c6c6567726f;
+
+/// A request to replace the permissions granted by a role
+#[derive(Deserialize, Serialize)]
+pub struct RolePermissionsRequest {
+    pub role: String,
+    pub permissions: Vec<Permission>,
+}
+
+/// A request to replace the roles granted to a user
+#[derive(Deserialize, Serialize)]
+pub struct UserRolesRequest {
+    pub user_id: i32,
+    pub roles: Vec<String>,
+}
+
+/// A role along with the permissions it grants
+#[derive(Serialize, Deserialize)]
+pub struct RoleInfo {
+    #[serde(flatten)]
+    pub role: Role,
+    pub permissions: Vec<Permission>,
+}
+
+/// Check that every named role exists, returning the names without duplicates
+pub(crate) fn db_requireroles(
+    roles: Vec<String>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<String>, ApiError> {
+    use crate::schema::roles;
+
+    let roles: BTreeSet<String> = roles.into_iter().collect();
+    let known: Vec<String> = roles::dsl::roles
+        .filter(roles::dsl::name.eq_any(&roles))
+        .select(roles::dsl::name)
+        .load::<String>(conn)?;
+    let unknown: Vec<&str> = roles
+        .iter()
+        .filter(|role| !known.contains(role))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(ApiError::Validation(format!(
+            "Unknown roles: {}",
+            unknown.join(", ")
+        )));
+    }
+    Ok(roles.into_iter().collect())
+}
+
+/// Count the enabled users that can manage users
+pub(crate) fn db_countmanagers(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<i64, ApiError> {
+    use crate::schema::{role_permissions, user_roles, users};
+
+    // disabled users cannot sign in, so they do not count
+    let managers: i64 = user_roles::dsl::user_roles
+        .inner_join(users::dsl::users)
+        .inner_join(
+            role_permissions::dsl::role_permissions
+                .on(role_permissions::dsl::role.eq(user_roles::dsl::role)),
+        )
+        .filter(role_permissions::dsl::permission.eq(Permission::UsersManage.as_str()))
+        .filter(users::dsl::disabled_at.is_null())
+        .select(diesel::dsl::count_distinct(user_roles::dsl::user_id))
+        .get_result(conn)?;
+    Ok(managers)
+}
+
+/// Wait for any other transaction deciding who can manage users, holding the lock until the
+/// current transaction ends
+pub(crate) fn db_lockmanagers(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+        .bind::<BigInt, _>(MANAGERS_LOCK)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Check that at least one user can still manage users, so an administrator cannot lock
+/// everyone out of the server. Called inside the transaction making the change, which keeps the
+/// lock so a concurrent change counts only once this one is committed or rolled back.
+pub(crate) fn db_requiremanager(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    db_lockmanagers(conn)?;
+    match db_countmanagers(conn)? {
+        0 => Err(ApiError::Conflict(
+            "At least one user must keep the users.manage permission".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Get every role and the permissions it grants
+fn db_listroles(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<RoleInfo>, ApiError> {
+    use crate::schema::{role_permissions, roles};
+
+    // load the roles, then group their permissions by role name
+    let roles: Vec<Role> = roles::dsl::roles
+        .order(roles::dsl::name.asc())
+        .load::<Role>(conn)?;
+    let rows: Vec<(String, String)> = role_permissions::dsl::role_permissions
+        .select((
+            role_permissions::dsl::role,
+            role_permissions::dsl::permission,
+        ))
+        .load::<(String, String)>(conn)?;
+    let mut granted: HashMap<String, Vec<Permission>> = HashMap::new();
+    for (role, permission) in rows {
+        // permissions this server no longer knows about are left out
+        if let Some(permission) = Permission::parse(&permission) {
+            granted.entry(role).or_default().push(permission);
+        }
+    }
+
+    Ok(roles
+        .into_iter()
+        .map(|role| {
+            let mut permissions: Vec<Permission> = granted.remove(&role.name).unwrap_or_default();
+            permissions.sort();
+            RoleInfo { role, permissions }
+        })
+        .collect())
+}
+
+/// Replace the permissions granted by a role
+fn db_setrolepermissions(
+    caller: Caller,
+    permissions_req: RolePermissionsRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::role_permissions;
+
+    // widening a role can hand out the admin's own powers, so an API key is not enough
+    caller.require_session()?;
+    let role: String = db_requireroles(vec![permissions_req.role], conn)?
+        .pop()
+        .ok_or_else(|| ApiError::not_found("Role"))?;
+
+    // the admin role always keeps the permission to manage users
+    let permissions: BTreeSet<Permission> = permissions_req.permissions.into_iter().collect();
+    if role == ADMIN_ROLE && !permissions.contains(&Permission::UsersManage) {
+        return Err(ApiError::Conflict(
+            "The admin role cannot lose the users.manage permission".to_string(),
+        ));
+    }
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        diesel::delete(
+            role_permissions::dsl::role_permissions.filter(role_permissions::dsl::role.eq(&role)),
+        )
+        .execute(conn)?;
+        let rows: Vec<_> = permissions
+            .iter()
+            .map(|permission| {
+                (
+                    role_permissions::dsl::role.eq(&role),
+                    role_permissions::dsl::permission.eq(permission.as_str()),
+                )
+            })
+            .collect();
+        diesel::insert_into(role_permissions::dsl::role_permissions)
+            .values(&rows)
+            .execute(conn)?;
+        db_requiremanager(conn)
+    })
+}
+
+/// Replace the roles granted to a user
+fn db_setuserroles(
+    caller: Caller,
+    roles_req: UserRolesRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{user_roles, users};
+
+    // granting roles can make anyone an admin, so an API key is not enough
+    caller.require_session()?;
+    let roles: Vec<String> = db_requireroles(roles_req.roles, conn)?;
+
+    // make sure the user exists before touching their roles
+    let user_id: i32 = users::dsl::users
+        .filter(users::dsl::id.eq(roles_req.user_id))
+        .select(users::dsl::id)
+        .first::<i32>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::not_found("User"))?;
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        diesel::delete(user_roles::dsl::user_roles.filter(user_roles::dsl::user_id.eq(user_id)))
+            .execute(conn)?;
+        let new_roles: Vec<insert::NewUserRole> = roles
+            .into_iter()
+            .map(|role| insert::NewUserRole { user_id, role })
+            .collect();
+        diesel::insert_into(user_roles::dsl::user_roles)
+            .values(&new_roles)
+            .execute(conn)?;
+        db_requiremanager(conn)
+    })
+}
+
+/// List every role and the permissions it grants
+#[get("/auth/roles")]
+pub async fn listroles(
+    _caller: Authorized<requires::UsersManage>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the roles from the database
+    let mut conn = pool.get()?;
+    let roles = web::block(move || db_listroles(&mut conn)).await??;
+
+    // return the roles on success
+    Ok(HttpResponse::Ok().json(Response::success(roles)))
+}
+
+/// Replace the permissions granted by a role
+#[put("/auth/roles/permissions")]
+pub async fn setrolepermissions(
+    caller: Authorized<requires::UsersManage>,
+    permissions_req: Json<RolePermissionsRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the new permissions in the database
+    let mut conn = pool.get()?;
+    web::block(move || {
+        db_setrolepermissions(caller.into_inner(), permissions_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Replace the roles granted to a user
+#[put("/auth/users/roles")]
+pub async fn setuserroles(
+    caller: Authorized<requires::UsersManage>,
+    roles_req: Json<UserRolesRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // apply the new roles in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_setuserroles(caller.into_inner(), roles_req.into_inner(), &mut conn))
+        .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::{db_userroles, Credential};
+    use crate::api::sessions::{db_createsession, Client};
+    use crate::models::User;
+    use crate::permission::DEFAULT_ROLE;
+    use crate::testing::connect;
+
+    /// Add a user with the given roles, returning their id
+    fn user(
+        username: &str,
+        roles: &[&str],
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> i32 {
+        use crate::schema::{user_roles, users};
+
+        let user_id: i32 = diesel::insert_into(users::table)
+            .values((
+                users::username.eq(username),
+                users::password_hash.eq("unused"),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap();
+        for role in roles {
+            diesel::insert_into(user_roles::table)
+                .values((user_roles::user_id.eq(user_id), user_roles::role.eq(*role)))
+                .execute(conn)
+                .unwrap();
+        }
+        user_id
+    }
+
+    /// Sign in as a user with a session
+    fn caller(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::users;
+
+        let client = Client {
+            user_agent: None,
+            ip_address: None,
+        };
+        let (_, session) = db_createsession(user_id, client, 3600, conn).unwrap();
+        let user: User = users::table.find(user_id).first(conn).unwrap();
+        Caller::authenticated(Credential::Session(session), user)
+    }
+
+    /// The permissions a role grants
+    fn permissions(
+        role: &str,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Vec<Permission> {
+        db_listroles(conn)
+            .unwrap()
+            .into_iter()
+            .find(|info| info.role.name == role)
+            .unwrap()
+            .permissions
+    }
+
+    #[test]
+    fn keeps_users_manage_on_the_last_managing_role() {
+        use crate::schema::role_permissions;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        // the only manager is an editor, whose role is given users.manage
+        diesel::insert_into(role_permissions::table)
+            .values((
+                role_permissions::role.eq("editor"),
+                role_permissions::permission.eq(Permission::UsersManage.as_str()),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        let editor_id: i32 = user("roles-test-editor", &["editor"], &mut conn);
+        let granted: Vec<Permission> = permissions("editor", &mut conn);
+
+        let permissions_req = RolePermissionsRequest {
+            role: "editor".to_string(),
+            permissions: vec![Permission::CatalogRead],
+        };
+        let result =
+            db_setrolepermissions(caller(editor_id, &mut conn), permissions_req, &mut conn);
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+        assert_eq!(permissions("editor", &mut conn), granted);
+
+        // the admin role never loses it, even while others could manage users
+        let admin_id: i32 = user("roles-test-admin", &[ADMIN_ROLE], &mut conn);
+        let permissions_req = RolePermissionsRequest {
+            role: ADMIN_ROLE.to_string(),
+            permissions: vec![Permission::CatalogRead],
+        };
+        let result = db_setrolepermissions(caller(admin_id, &mut conn), permissions_req, &mut conn);
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+
+        // once an admin can manage users, the editor role can lose the permission
+        let permissions_req = RolePermissionsRequest {
+            role: "editor".to_string(),
+            permissions: vec![Permission::CatalogRead],
+        };
+        db_setrolepermissions(caller(admin_id, &mut conn), permissions_req, &mut conn).unwrap();
+        assert_eq!(
+            permissions("editor", &mut conn),
+            vec![Permission::CatalogRead]
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_manager_in_a_managing_role() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let admin_id: i32 = user("roles-test-admin", &[ADMIN_ROLE], &mut conn);
+
+        let roles_req = UserRolesRequest {
+            user_id: admin_id,
+            roles: vec![DEFAULT_ROLE.to_string()],
+        };
+        let result = db_setuserroles(caller(admin_id, &mut conn), roles_req, &mut conn);
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+        assert_eq!(
+            db_userroles(admin_id, &mut conn).unwrap(),
+            vec![ADMIN_ROLE.to_string()]
+        );
+
+        // with a second admin, the first can step down
+        let other_id: i32 = user("roles-test-other", &[ADMIN_ROLE], &mut conn);
+        let roles_req = UserRolesRequest {
+            user_id: admin_id,
+            roles: vec![DEFAULT_ROLE.to_string()],
+        };
+        db_setuserroles(caller(other_id, &mut conn), roles_req, &mut conn).unwrap();
+        assert_eq!(
+            db_userroles(admin_id, &mut conn).unwrap(),
+            vec![DEFAULT_ROLE.to_string()]
+        );
+    }
+}
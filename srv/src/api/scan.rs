@@ -0,0 +1,153 @@
+use crate::api::auth::Authorized;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::media;
+use crate::permission::requires;
+use crate::scanner::{self, ScanReport};
+use crate::Response;
+
+use actix_web::web::{Data, Json};
+use actix_web::{get, post, HttpResponse};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use serde::{Deserialize, Serialize};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A request to scan a directory under the media root, or the whole media root if none is given.
+/// A rescan only re-reads files that changed since they were last scanned.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScanRequest {
+    pub path: Option<String>,
+    pub rescan: Option<bool>,
+}
+
+/// Progress of the latest scan, which runs in the background since walking a large library takes
+/// far longer than a request should
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScanStatus {
+    pub running: bool,
+    pub path: Option<String>,
+    pub rescan: bool,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+    pub report: Option<ScanReport>,
+    pub error: Option<String>,
+}
+
+/// The one scan the server runs at a time, shared by every worker
+#[derive(Clone, Default)]
+pub struct ScanJob {
+    status: Arc<Mutex<ScanStatus>>,
+}
+
+impl ScanJob {
+    /// Copy out the status of the latest scan
+    fn status(&self) -> ScanStatus {
+        self.status
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Mark a scan as started unless one is already running
+    fn start(&self, scan_req: &ScanRequest) -> Result<ScanStatus, ApiError> {
+        let mut status = self.status.lock().unwrap_or_else(|err| err.into_inner());
+        if status.running {
+            return Err(ApiError::Conflict("A scan is already running".to_string()));
+        }
+        *status = ScanStatus {
+            running: true,
+            path: scan_req.path.clone(),
+            rescan: scan_req.rescan.unwrap_or(false),
+            started_at: Some(chrono::Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        Ok(status.clone())
+    }
+
+    /// Record how the running scan ended
+    fn finish(&self, outcome: Result<ScanReport, String>) {
+        let mut status = self.status.lock().unwrap_or_else(|err| err.into_inner());
+        status.running = false;
+        status.finished_at = Some(chrono::Utc::now().naive_utc());
+        match outcome {
+            Ok(report) => status.report = Some(report),
+            Err(err) => status.error = Some(err),
+        }
+    }
+}
+
+/// Find the directory a scan request covers
+fn scan_directory(scan_req: &ScanRequest, media_root: &Path) -> Result<PathBuf, ApiError> {
+    // only directories under the media root can be scanned
+    let directory: PathBuf = match &scan_req.path {
+        Some(path) => media::resolve(media_root, path)
+            .ok_or_else(|| ApiError::Validation("Path is outside the media root".to_string()))?,
+        None => media_root.to_path_buf(),
+    };
+    if !directory.is_dir() {
+        return Err(ApiError::not_found("Directory"));
+    }
+    Ok(directory)
+}
+
+/// Scan a directory of the library on a connection of its own, recording the report once done
+fn run_scan(
+    job: ScanJob,
+    pool: Pool<ConnectionManager<PgConnection>>,
+    media_root: PathBuf,
+    directory: PathBuf,
+    rescan: bool,
+) {
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut conn = pool.get().map_err(|err| err.to_string())?;
+        Ok(scanner::scan_library(
+            &media_root,
+            &directory,
+            rescan,
+            &mut conn,
+        ))
+    }))
+    .unwrap_or_else(|_| Err("The scan stopped unexpectedly".to_string()));
+    if let Err(err) = &outcome {
+        log::error!("Failed to scan {}: {}", directory.display(), err);
+    }
+    job.finish(outcome);
+}
+
+/// Start scanning the library for audio files to import into the catalog, unless a scan is
+/// already running
+#[post("/music/scan")]
+pub async fn scanlibrary(
+    _caller: Authorized<requires::LibraryScan>,
+    scan_req: Json<ScanRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+    job: Data<ScanJob>,
+) -> Result<HttpResponse, ApiError> {
+    // start the scan in the background
+    let directory: PathBuf = scan_directory(&scan_req, &config.media_root)?;
+    let status: ScanStatus = job.start(&scan_req)?;
+    let job: ScanJob = job.get_ref().clone();
+    let pool = pool.get_ref().clone();
+    let media_root: PathBuf = config.media_root.clone();
+    let rescan: bool = status.rescan;
+    thread::spawn(move || run_scan(job, pool, media_root, directory, rescan));
+
+    // return the started scan
+    Ok(HttpResponse::Accepted().json(Response::success(status)))
+}
+
+/// Get the progress of the latest scan, and its report once it finished
+#[get("/music/scan")]
+pub async fn scanstatus(
+    _caller: Authorized<requires::LibraryScan>,
+    job: Data<ScanJob>,
+) -> Result<HttpResponse, ApiError> {
+    // return the status of the latest scan
+    Ok(HttpResponse::Ok().json(Response::success(job.status())))
+}
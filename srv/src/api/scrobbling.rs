@@ -0,0 +1,345 @@
+use crate::api::auth::{Authorized, Caller};
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::models::DbScrobbleTarget;
+use crate::password;
+use crate::permission::requires;
+use crate::scrobbler::{self, LISTENBRAINZ_URL};
+use crate::update;
+use crate::Response;
+
+use actix_web::web::{Data, Json};
+use actix_web::{delete, get, put, web, HttpResponse};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Serialize};
+
+// longest submission URL that can be given
+const MAX_URL_LEN: usize = 2048;
+
+// longest token that can be given
+const MAX_TOKEN_LEN: usize = 255;
+
+/// A request to forward the plays of the current user to a ListenBrainz-compatible server. Any
+/// field may be left out to keep what was already given.
+#[derive(Deserialize, Serialize)]
+pub struct ScrobbleTargetRequest {
+    pub submit_url: Option<String>,
+    pub token: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+/// Where the plays of a user are forwarded, without the token, and how forwarding is going
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScrobbleTarget {
+    pub submit_url: String,
+    pub enabled: bool,
+    pub pending: i64,
+    pub last_submitted_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Get where the caller forwards their plays
+fn db_getscrobbletarget(
+    caller: Caller,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<ScrobbleTarget, ApiError> {
+    use crate::schema::{scrobble_outbox, scrobble_targets};
+
+    let user_id: i32 = caller.require_user()?.id;
+    let target: DbScrobbleTarget = scrobble_targets::dsl::scrobble_targets
+        .find(user_id)
+        .select(DbScrobbleTarget::as_select())
+        .first::<DbScrobbleTarget>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::not_found("Scrobble target"))?;
+    let pending: i64 = scrobble_outbox::dsl::scrobble_outbox
+        .filter(scrobble_outbox::dsl::user_id.eq(user_id))
+        .count()
+        .get_result(conn)?;
+    Ok(ScrobbleTarget {
+        submit_url: target.submit_url,
+        enabled: target.enabled,
+        pending,
+        last_submitted_at: target.last_submitted_at,
+        last_error: target.last_error,
+        created_at: target.created_at,
+        updated_at: target.updated_at,
+    })
+}
+
+/// Set where the caller forwards their plays, starting with ListenBrainz itself unless told
+/// otherwise
+fn db_setscrobbletarget(
+    caller: Caller,
+    target_req: ScrobbleTargetRequest,
+    secret: Option<[u8; 32]>,
+    allow_private: bool,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<ScrobbleTarget, ApiError> {
+    use crate::schema::scrobble_targets;
+
+    // tokens are credentials for another service, so only a session may hand them over
+    let (_, user) = caller.require_session()?;
+    let user_id: i32 = user.id;
+    let key: [u8; 32] = secret.ok_or_else(|| {
+        ApiError::Conflict("Scrobble forwarding is not enabled on this server".to_string())
+    })?;
+    let submit_url: Option<String> = match target_req.submit_url {
+        Some(submit_url) => {
+            let submit_url: &str = submit_url.trim();
+            if !(submit_url.starts_with("https://") || submit_url.starts_with("http://"))
+                || submit_url.len() > MAX_URL_LEN
+            {
+                return Err(ApiError::Validation(format!(
+                    "Submission URL must be an http or https URL of at most {} characters",
+                    MAX_URL_LEN
+                )));
+            }
+            scrobbler::check_url(submit_url, allow_private)?;
+            Some(submit_url.to_string())
+        }
+        None => None,
+    };
+    let encrypted_token: Option<String> = match target_req.token {
+        Some(token) => {
+            let token: &str = token.trim();
+            if token.is_empty() || token.len() > MAX_TOKEN_LEN {
+                return Err(ApiError::Validation(format!(
+                    "Token must be between 1 and {} characters",
+                    MAX_TOKEN_LEN
+                )));
+            }
+            Some(password::encrypt(&key, token)?)
+        }
+        None => None,
+    };
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    conn.transaction::<(), ApiError, _>(|conn| {
+        let exists: bool = scrobble_targets::dsl::scrobble_targets
+            .find(user_id)
+            .select(scrobble_targets::dsl::user_id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+            .is_some();
+        match (exists, encrypted_token) {
+            (false, None) => Err(ApiError::Validation(
+                "A token is required to start forwarding plays".to_string(),
+            )),
+            (false, Some(encrypted_token)) => {
+                diesel::insert_into(scrobble_targets::dsl::scrobble_targets)
+                    .values((
+                        scrobble_targets::dsl::user_id.eq(user_id),
+                        scrobble_targets::dsl::submit_url
+                            .eq(submit_url.as_deref().unwrap_or(LISTENBRAINZ_URL)),
+                        scrobble_targets::dsl::encrypted_token.eq(encrypted_token),
+                        scrobble_targets::dsl::enabled.eq(target_req.enabled.unwrap_or(true)),
+                        scrobble_targets::dsl::created_at.eq(now),
+                        scrobble_targets::dsl::updated_at.eq(now),
+                    ))
+                    .execute(conn)?;
+                Ok(())
+            }
+            (true, encrypted_token) => {
+                // a new token or address deserves a fresh try, so the last error is cleared
+                let changes = update::UpdateScrobbleTarget {
+                    submit_url,
+                    encrypted_token,
+                    enabled: target_req.enabled,
+                    last_error: Some(None),
+                    updated_at: now,
+                };
+                diesel::update(scrobble_targets::dsl::scrobble_targets.find(user_id))
+                    .set(&changes)
+                    .execute(conn)?;
+                Ok(())
+            }
+        }
+    })?;
+    db_retryscrobbles(user_id, now, conn)?;
+    db_getscrobbletarget(caller, conn)
+}
+
+/// Make the plays waiting to be forwarded by a user due straight away
+fn db_retryscrobbles(
+    user_id: i32,
+    now: NaiveDateTime,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::scrobble_outbox;
+
+    diesel::update(scrobble_outbox::dsl::scrobble_outbox)
+        .filter(scrobble_outbox::dsl::user_id.eq(user_id))
+        .filter(scrobble_outbox::dsl::next_attempt_at.gt(now))
+        .set(scrobble_outbox::dsl::next_attempt_at.eq(now))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Stop forwarding the plays of the caller, dropping those still waiting
+fn db_deletescrobbletarget(
+    caller: Caller,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::scrobble_targets;
+
+    // like setting the target, stopping it is left to a session rather than an API key
+    let (_, user) = caller.require_session()?;
+    let user_id: i32 = user.id;
+    let deleted: usize =
+        diesel::delete(scrobble_targets::dsl::scrobble_targets.find(user_id)).execute(conn)?;
+    match deleted {
+        0 => Err(ApiError::not_found("Scrobble target")),
+        _ => Ok(()),
+    }
+}
+
+/// Get where the current user forwards their plays
+#[get("/history/scrobbling")]
+pub async fn getscrobbletarget(
+    caller: Authorized<requires::MediaStream>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the target from the database
+    let mut conn = pool.get()?;
+    let target = web::block(move || db_getscrobbletarget(caller.into_inner(), &mut conn)).await??;
+
+    // return the target on success
+    Ok(HttpResponse::Ok().json(Response::success(target)))
+}
+
+/// Forward the plays of the current user to a ListenBrainz-compatible server
+#[put("/history/scrobbling")]
+pub async fn setscrobbletarget(
+    caller: Authorized<requires::MediaStream>,
+    target_req: Json<ScrobbleTargetRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // store the target in the database
+    let mut conn = pool.get()?;
+    let secret: Option<[u8; 32]> = config.scrobble_secret;
+    let allow_private: bool = config.scrobble_allow_private;
+    let target = web::block(move || {
+        db_setscrobbletarget(
+            caller.into_inner(),
+            target_req.into_inner(),
+            secret,
+            allow_private,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    // return the target on success
+    Ok(HttpResponse::Ok().json(Response::success(target)))
+}
+
+/// Stop forwarding the plays of the current user
+#[delete("/history/scrobbling")]
+pub async fn deletescrobbletarget(
+    caller: Authorized<requires::MediaStream>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the target from the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deletescrobbletarget(caller.into_inner(), &mut conn)).await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::Credential;
+    use crate::models::{DbSession, User};
+    use crate::testing::connect;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    /// Add a user, giving their id
+    fn user(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::users;
+
+        diesel::insert_into(users::table)
+            .values((
+                users::username.eq("scrobbling-test"),
+                users::password_hash.eq("unused"),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Set the target of a user signed in with a session
+    fn set(
+        user_id: i32,
+        target_req: ScrobbleTargetRequest,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<ScrobbleTarget, ApiError> {
+        use crate::schema::users;
+
+        let user: User = users::table.find(user_id).first(conn).unwrap();
+        let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+        let session = DbSession {
+            id: 0,
+            user_id,
+            token_hash: String::new(),
+            created_at: now,
+            last_used_at: now,
+            expires_at: now,
+            user_agent: None,
+            ip_address: None,
+        };
+        let caller: Caller = Caller::authenticated(Credential::Session(session), user);
+        db_setscrobbletarget(caller, target_req, Some(KEY), false, conn)
+    }
+
+    #[test]
+    fn keeps_what_an_update_leaves_out() {
+        use crate::schema::scrobble_targets;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user(&mut conn);
+        let target_req = ScrobbleTargetRequest {
+            submit_url: None,
+            token: Some("first-token".to_string()),
+            enabled: None,
+        };
+        let target: ScrobbleTarget = set(user_id, target_req, &mut conn).unwrap();
+        assert_eq!(target.submit_url, LISTENBRAINZ_URL);
+        assert!(target.enabled);
+        diesel::update(scrobble_targets::table.find(user_id))
+            .set(scrobble_targets::last_error.eq("Target answered 503"))
+            .execute(&mut conn)
+            .unwrap();
+
+        // only what is given changes, and the last error is cleared
+        let target_req = ScrobbleTargetRequest {
+            submit_url: None,
+            token: None,
+            enabled: Some(false),
+        };
+        let target: ScrobbleTarget = set(user_id, target_req, &mut conn).unwrap();
+        assert_eq!(target.submit_url, LISTENBRAINZ_URL);
+        assert!(!target.enabled);
+        assert_eq!(target.last_error, None);
+        let encrypted_token: String = scrobble_targets::table
+            .find(user_id)
+            .select(scrobble_targets::encrypted_token)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(
+            password::decrypt(&KEY, &encrypted_token).as_deref(),
+            Some("first-token")
+        );
+    }
+}
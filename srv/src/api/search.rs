@@ -1,535 +1,1178 @@
-use crate::api::get::{Piece, Recording, Release};
-use crate::models::{
-    Admin, Composer, DbPiece, DbRecording, DbRelease, Performer, Songwriter, User,
+use crate::api::auth::Authorized;
+use crate::api::get::{
+    db_assemblepieces, db_assemblerecordings, db_assemblereleases, Piece, Recording, Release,
 };
+use crate::error::ApiError;
+use crate::models::{Composer, DbPiece, DbRecording, DbRelease, Performer, Songwriter};
+use crate::permission::requires;
+use crate::Response;
 
 use actix_web::web::{Data, Json};
 use actix_web::{post, web, HttpResponse};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::sql_types::{Array, BigInt, Bool, Float4, Integer, Text};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
-/// Generic response to denote whether operation was successful
-#[derive(Deserialize, Serialize)]
-pub struct Response<T> {
-    pub success: bool,
-    pub message: T,
-}
+// minimum word similarity for a name to match a query containing typos
+const FUZZY_THRESHOLD: &str = "0.4";
+
+// most results returned by a single search
+const MAX_RESULTS: i64 = 100;
+
+// results returned for each kind of entry in a unified search when no limit is given
+const DEFAULT_UNIFIED_LIMIT: i64 = 10;
 
 /// A search request with a search query
 #[derive(Deserialize, Serialize)]
 pub struct SearchRequest {
     pub query: String,
-    pub token: String,
-}
-
-impl SearchRequest {
-    /// Convert the search query into a query that can return results with words between the search terms
-    fn to_query(&self) -> String {
-        return format!(
-            "%{}%",
-            self.query
-                .split_whitespace()
-                .collect::<Vec<&str>>()
-                .join("%")
-        );
+    pub mode: Option<SearchMode>,
+}
+
+/// How recordings and releases are matched against a search. By name only matches their own
+/// names, while related matches every term against the names of the pieces, composers,
+/// songwriters, performers and releases they are linked to, so that a search like
+/// "beethoven 7 kleiber" finds the recordings satisfying all of its terms together.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Name,
+    Related,
+}
+
+/// Kinds of entries that can be searched
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchType {
+    Performer,
+    Composer,
+    Songwriter,
+    Piece,
+    Release,
+    Recording,
+}
+
+impl SearchType {
+    /// Every kind of entry, in the order results are grouped
+    pub const ALL: [SearchType; 6] = [
+        SearchType::Performer,
+        SearchType::Composer,
+        SearchType::Songwriter,
+        SearchType::Piece,
+        SearchType::Release,
+        SearchType::Recording,
+    ];
+}
+
+/// A search across several kinds of entries, by default all of them. Each kind returns at most
+/// `limit` results unless it has its own entry in `limits`.
+#[derive(Deserialize, Serialize)]
+pub struct UnifiedSearchRequest {
+    pub query: String,
+    pub types: Option<Vec<SearchType>>,
+    pub mode: Option<SearchMode>,
+    pub limit: Option<i64>,
+    pub limits: Option<HashMap<SearchType, i64>>,
+}
+
+impl UnifiedSearchRequest {
+    /// Validate the requested kinds of entries, returning each along with its limit
+    fn searches(&self) -> Result<Vec<(SearchType, i64)>, ApiError> {
+        let mut types: Vec<SearchType> = match &self.types {
+            Some(types) if types.is_empty() => {
+                return Err(ApiError::Validation(
+                    "At least one type must be searched".to_string(),
+                ))
+            }
+            Some(types) => types.clone(),
+            None => SearchType::ALL.to_vec(),
+        };
+        types.sort_unstable();
+        types.dedup();
+
+        // every limit must be within the bounds of a single search
+        let default_limit: i64 = self.limit.unwrap_or(DEFAULT_UNIFIED_LIMIT);
+        let limits: Vec<i64> = types
+            .iter()
+            .map(|search_type| {
+                self.limits
+                    .as_ref()
+                    .and_then(|limits| limits.get(search_type).copied())
+                    .unwrap_or(default_limit)
+            })
+            .collect();
+        if limits
+            .iter()
+            .any(|limit| !(1..=MAX_RESULTS).contains(limit))
+        {
+            return Err(ApiError::Validation(format!(
+                "Limits must be between 1 and {}",
+                MAX_RESULTS
+            )));
+        }
+        Ok(types.into_iter().zip(limits).collect())
     }
 }
 
-/// Given a token, return whether the user is an admin
-fn check_admin(
-    token: String,
-    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> bool {
-    use crate::schema::admin;
-    use crate::schema::users;
-
-    // check if the token is valid
-    let user_res = users::dsl::users
-        .filter(users::dsl::session.eq(Some(token)))
-        .first::<User>(conn);
-    let user: User = match user_res {
-        Ok(_) => user_res.unwrap(),
-        Err(_) => {
-            return false;
+/// A search result of any kind, tagged with the kind of entry it is
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SearchEntity {
+    Performer(SearchResult<Performer>),
+    Composer(SearchResult<Composer>),
+    Songwriter(SearchResult<Songwriter>),
+    Piece(SearchResult<Piece>),
+    Release(SearchResult<Release>),
+    Recording(SearchResult<Recording>),
+}
+
+impl SearchEntity {
+    /// Relevance of the result to the search
+    fn rank(&self) -> f32 {
+        match self {
+            SearchEntity::Performer(result) => result.rank,
+            SearchEntity::Composer(result) => result.rank,
+            SearchEntity::Songwriter(result) => result.rank,
+            SearchEntity::Piece(result) => result.rank,
+            SearchEntity::Release(result) => result.rank,
+            SearchEntity::Recording(result) => result.rank,
         }
-    };
+    }
+
+    /// Relevance of the result to the search, to be adjusted
+    fn rank_mut(&mut self) -> &mut f32 {
+        match self {
+            SearchEntity::Performer(result) => &mut result.rank,
+            SearchEntity::Composer(result) => &mut result.rank,
+            SearchEntity::Songwriter(result) => &mut result.rank,
+            SearchEntity::Piece(result) => &mut result.rank,
+            SearchEntity::Release(result) => &mut result.rank,
+            SearchEntity::Recording(result) => &mut result.rank,
+        }
+    }
 
-    // check if the user is an admin
-    let admin_user_res = admin::dsl::admin
-        .filter(admin::dsl::username.eq(&user.username))
-        .first::<Admin>(conn);
-    match admin_user_res {
-        Ok(_) => (),
-        Err(_) => {
-            return false;
+    /// Id of the entry within its kind
+    fn id(&self) -> i32 {
+        match self {
+            SearchEntity::Performer(result) => result.item.id,
+            SearchEntity::Composer(result) => result.item.id,
+            SearchEntity::Songwriter(result) => result.item.id,
+            SearchEntity::Piece(result) => result.item.id,
+            SearchEntity::Release(result) => result.item.id,
+            SearchEntity::Recording(result) => result.item.id,
         }
+    }
+}
+
+/// Results of a search across several kinds of entries. `results` ranks every result against
+/// each other, relative to the best result found the same way, while `groups` lists the ids of
+/// each kind's results in their own order.
+#[derive(Serialize)]
+pub struct UnifiedSearchResponse {
+    pub results: Vec<SearchEntity>,
+    pub groups: BTreeMap<SearchType, Vec<i32>>,
+}
+
+/// Convert a search query into a pattern matching names with words between the search terms.
+/// Wildcards within the terms are escaped with a backslash, so they only match themselves.
+pub(crate) fn to_pattern(query: &str) -> String {
+    format!(
+        "%{}%",
+        query
+            .split_whitespace()
+            .map(|term| term
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_"))
+            .collect::<Vec<String>>()
+            .join("%")
+    )
+}
+
+/// Columns of a table that are searched
+struct SearchTable {
+    table: &'static str,
+    name: &'static str,
+    description: &'static str,
+}
+
+// the searched columns of each table, recordings have no description
+const PERFORMERS: SearchTable = SearchTable {
+    table: "performers",
+    name: "name",
+    description: "entry.description",
+};
+const COMPOSERS: SearchTable = SearchTable {
+    table: "composers",
+    name: "name",
+    description: "entry.description",
+};
+const SONGWRITERS: SearchTable = SearchTable {
+    table: "songwriters",
+    name: "name",
+    description: "entry.description",
+};
+const PIECES: SearchTable = SearchTable {
+    table: "pieces",
+    name: "name",
+    description: "entry.description",
+};
+const RELEASES: SearchTable = SearchTable {
+    table: "releases",
+    name: "name",
+    description: "entry.description",
+};
+const RECORDINGS: SearchTable = SearchTable {
+    table: "recordings",
+    name: "piece_name",
+    description: "NULL",
+};
+
+/// Entries searched by the names of everything they are linked to. Building the document of an
+/// entry is expensive, so it is only built for the candidates whose linked names, found through
+/// their indexed documents, could together match the search.
+struct RelatedTable {
+    linked: &'static str,
+    documents: &'static str,
+}
+
+// the names linked to each recording that contain a term of the search, and the document of each
+// candidate made of its own name and the names of everything it is linked to
+const RECORDING_DOCUMENTS: RelatedTable = RelatedTable {
+    linked: "
+    SELECT recordings.id AS id,
+        allegro_document(recordings.piece_name, NULL) AS document
+    FROM recordings, terms
+    WHERE allegro_document(recordings.piece_name, NULL) @@ terms.query
+    UNION ALL
+    SELECT recordings.id AS id,
+        allegro_document(pieces.name, pieces.description) AS document
+    FROM pieces
+    JOIN recordings ON recordings.piece_id = pieces.id, terms
+    WHERE allegro_document(pieces.name, pieces.description) @@ terms.query
+    UNION ALL
+    SELECT recordings.id AS id,
+        allegro_document(composers.name, composers.description) AS document
+    FROM composers
+    JOIN piece_composers ON piece_composers.composer_id = composers.id
+    JOIN recordings ON recordings.piece_id = piece_composers.piece_id, terms
+    WHERE allegro_document(composers.name, composers.description) @@ terms.query
+    UNION ALL
+    SELECT recordings.id AS id,
+        allegro_document(songwriters.name, songwriters.description) AS document
+    FROM songwriters
+    JOIN piece_songwriters ON piece_songwriters.songwriter_id = songwriters.id
+    JOIN recordings ON recordings.piece_id = piece_songwriters.piece_id, terms
+    WHERE allegro_document(songwriters.name, songwriters.description) @@ terms.query
+    UNION ALL
+    SELECT recording_performers.recording_id AS id,
+        allegro_document(performers.name, performers.description) AS document
+    FROM performers
+    JOIN recording_performers ON recording_performers.performer_id = performers.id, terms
+    WHERE allegro_document(performers.name, performers.description) @@ terms.query
+    UNION ALL
+    SELECT recordings.id AS id,
+        allegro_document(performers.name, performers.description) AS document
+    FROM performers
+    JOIN release_performers ON release_performers.performer_id = performers.id
+    JOIN recordings ON recordings.release_id = release_performers.release_id, terms
+    WHERE allegro_document(performers.name, performers.description) @@ terms.query
+    UNION ALL
+    SELECT recordings.id AS id,
+        allegro_document(releases.name, releases.description) AS document
+    FROM releases
+    JOIN recordings ON recordings.release_id = releases.id, terms
+    WHERE allegro_document(releases.name, releases.description) @@ terms.query",
+    documents: "
+    SELECT recordings.id,
+        setweight(to_tsvector('allegro', recordings.piece_name), 'A')
+            || setweight(to_tsvector('allegro', pieces.name), 'A')
+            || setweight(to_tsvector('allegro', coalesce(credits.writers, '')), 'B')
+            || setweight(to_tsvector('allegro', coalesce(credits.performers, '')), 'B')
+            || setweight(to_tsvector('allegro', releases.name), 'C') AS document,
+        concat_ws(' / ', recordings.piece_name, credits.writers, credits.performers, releases.name)
+            AS summary
+    FROM candidates
+    JOIN recordings ON recordings.id = candidates.id
+    JOIN pieces ON pieces.id = recordings.piece_id
+    JOIN releases ON releases.id = recordings.release_id
+    CROSS JOIN LATERAL (
+        SELECT
+            (SELECT string_agg(writers.name, ', ' ORDER BY writers.name)
+                FROM (
+                    SELECT composers.name FROM piece_composers
+                    JOIN composers ON composers.id = piece_composers.composer_id
+                    WHERE piece_composers.piece_id = recordings.piece_id
+                    UNION
+                    SELECT songwriters.name FROM piece_songwriters
+                    JOIN songwriters ON songwriters.id = piece_songwriters.songwriter_id
+                    WHERE piece_songwriters.piece_id = recordings.piece_id
+                ) AS writers) AS writers,
+            (SELECT string_agg(credited.name, ', ' ORDER BY credited.name)
+                FROM (
+                    SELECT performers.name FROM recording_performers
+                    JOIN performers ON performers.id = recording_performers.performer_id
+                    WHERE recording_performers.recording_id = recordings.id
+                    UNION
+                    SELECT performers.name FROM release_performers
+                    JOIN performers ON performers.id = release_performers.performer_id
+                    WHERE release_performers.release_id = recordings.release_id
+                ) AS credited) AS performers
+    ) AS credits",
+};
+
+// the names on each release that contain a term of the search, and the document of each candidate
+// made of its own name and the names of everything on it
+const RELEASE_DOCUMENTS: RelatedTable = RelatedTable {
+    linked: "
+    SELECT releases.id AS id,
+        allegro_document(releases.name, releases.description) AS document
+    FROM releases, terms
+    WHERE allegro_document(releases.name, releases.description) @@ terms.query
+    UNION ALL
+    SELECT recordings.release_id AS id,
+        allegro_document(recordings.piece_name, NULL) AS document
+    FROM recordings, terms
+    WHERE allegro_document(recordings.piece_name, NULL) @@ terms.query
+    UNION ALL
+    SELECT recordings.release_id AS id,
+        allegro_document(composers.name, composers.description) AS document
+    FROM composers
+    JOIN piece_composers ON piece_composers.composer_id = composers.id
+    JOIN recordings ON recordings.piece_id = piece_composers.piece_id, terms
+    WHERE allegro_document(composers.name, composers.description) @@ terms.query
+    UNION ALL
+    SELECT recordings.release_id AS id,
+        allegro_document(songwriters.name, songwriters.description) AS document
+    FROM songwriters
+    JOIN piece_songwriters ON piece_songwriters.songwriter_id = songwriters.id
+    JOIN recordings ON recordings.piece_id = piece_songwriters.piece_id, terms
+    WHERE allegro_document(songwriters.name, songwriters.description) @@ terms.query
+    UNION ALL
+    SELECT release_performers.release_id AS id,
+        allegro_document(performers.name, performers.description) AS document
+    FROM performers
+    JOIN release_performers ON release_performers.performer_id = performers.id, terms
+    WHERE allegro_document(performers.name, performers.description) @@ terms.query
+    UNION ALL
+    SELECT recordings.release_id AS id,
+        allegro_document(performers.name, performers.description) AS document
+    FROM performers
+    JOIN recording_performers ON recording_performers.performer_id = performers.id
+    JOIN recordings ON recordings.id = recording_performers.recording_id, terms
+    WHERE allegro_document(performers.name, performers.description) @@ terms.query",
+    documents: "
+    SELECT releases.id,
+        setweight(to_tsvector('allegro', releases.name), 'A')
+            || setweight(to_tsvector('allegro', coalesce(credits.performers, '')), 'B')
+            || setweight(to_tsvector('allegro', coalesce(credits.writers, '')), 'C')
+            || setweight(to_tsvector('allegro', coalesce(credits.pieces, '')), 'C') AS document,
+        concat_ws(' / ', releases.name, credits.performers, credits.writers, credits.pieces)
+            AS summary
+    FROM candidates
+    JOIN releases ON releases.id = candidates.id
+    CROSS JOIN LATERAL (
+        SELECT
+            (SELECT string_agg(DISTINCT recordings.piece_name, ', ')
+                FROM recordings
+                WHERE recordings.release_id = releases.id) AS pieces,
+            (SELECT string_agg(writers.name, ', ' ORDER BY writers.name)
+                FROM (
+                    SELECT composers.name FROM recordings
+                    JOIN piece_composers ON piece_composers.piece_id = recordings.piece_id
+                    JOIN composers ON composers.id = piece_composers.composer_id
+                    WHERE recordings.release_id = releases.id
+                    UNION
+                    SELECT songwriters.name FROM recordings
+                    JOIN piece_songwriters ON piece_songwriters.piece_id = recordings.piece_id
+                    JOIN songwriters ON songwriters.id = piece_songwriters.songwriter_id
+                    WHERE recordings.release_id = releases.id
+                ) AS writers) AS writers,
+            (SELECT string_agg(credited.name, ', ' ORDER BY credited.name)
+                FROM (
+                    SELECT performers.name FROM release_performers
+                    JOIN performers ON performers.id = release_performers.performer_id
+                    WHERE release_performers.release_id = releases.id
+                    UNION
+                    SELECT performers.name FROM recordings
+                    JOIN recording_performers
+                        ON recording_performers.recording_id = recordings.id
+                    JOIN performers ON performers.id = recording_performers.performer_id
+                    WHERE recordings.release_id = releases.id
+                ) AS credited) AS performers
+    ) AS credits",
+};
+
+/// Whether a search only excludes terms, leaving no terms to look up in the indexes
+#[derive(QueryableByName)]
+struct SearchTerms {
+    #[diesel(sql_type = Bool)]
+    unindexed: bool,
+}
+
+/// An entry matching a search, with its relevance and highlighted text
+#[derive(QueryableByName)]
+struct SearchHit {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Float4)]
+    rank: f32,
+    #[diesel(sql_type = Text)]
+    highlight: String,
+}
+
+/// A search result ranked by relevance, with the matched words wrapped in `<mark>` tags
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchResult<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub rank: f32,
+    pub highlight: String,
+}
+
+/// The words of a search that are matched against names despite typos, leaving out the quotes,
+/// exclusions and alternatives of the search syntax
+fn fuzzy_words(search: &str) -> Vec<String> {
+    search
+        .split_whitespace()
+        .filter(|word| !word.starts_with('-') && !word.eq_ignore_ascii_case("or"))
+        .map(|word| word.trim_matches('"').to_string())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Find the entries of a table matching a search, most relevant first. Entries match on the
+/// words of their name and description regardless of accents, or on a name that is similar to
+/// the query, or to each of its words, despite typos.
+fn db_searchhits(
+    search_table: &SearchTable,
+    search: &str,
+    limit: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchHit>, ApiError> {
+    // a typo in one word should not keep the other words from matching, so besides the whole
+    // query each word is compared on its own
+    let query: String = format!(
+        "WITH search AS (
+            SELECT websearch_to_tsquery('allegro', $1) AS query,
+                allegro_unaccent(lower($1)) AS term,
+                ARRAY(SELECT allegro_unaccent(lower(word)) FROM unnest($4::text[]) AS word)
+                    AS words,
+                allegro_unaccent(lower($2)) AS pattern
+        )
+        SELECT entry.id,
+            (ts_rank(allegro_document(entry.{name}, {description}), search.query)
+                + greatest(word_similarity(search.term, allegro_unaccent(lower(entry.{name}))),
+                    (SELECT avg(word_similarity(word, allegro_unaccent(lower(entry.{name}))))
+                        FROM unnest(search.words) AS word)))::real
+                AS rank,
+            ts_headline('allegro', concat_ws(' ', entry.{name}, {description}), search.query,
+                'StartSel=<mark>, StopSel=</mark>') AS highlight
+        FROM {table} AS entry, search
+        WHERE allegro_document(entry.{name}, {description}) @@ search.query
+            OR search.term <% allegro_unaccent(lower(entry.{name}))
+            OR (search.words[1] <% allegro_unaccent(lower(entry.{name}))
+                AND NOT EXISTS (SELECT 1 FROM unnest(search.words[2:]) AS word
+                    WHERE NOT word <% allegro_unaccent(lower(entry.{name}))))
+            OR allegro_unaccent(lower(entry.{name})) LIKE search.pattern ESCAPE '\\'
+        ORDER BY rank DESC, entry.id
+        LIMIT $3",
+        table = search_table.table,
+        name = search_table.name,
+        description = search_table.description,
+    );
+
+    // the similarity threshold only applies to this search
+    let hits: Vec<SearchHit> = conn.transaction(|conn| {
+        diesel::sql_query("SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)")
+            .bind::<Text, _>(FUZZY_THRESHOLD)
+            .execute(conn)?;
+        diesel::sql_query(query)
+            .bind::<Text, _>(search)
+            .bind::<Text, _>(to_pattern(search))
+            .bind::<BigInt, _>(limit)
+            .bind::<Array<Text>, _>(fuzzy_words(search))
+            .load::<SearchHit>(conn)
+    })?;
+
+    Ok(hits)
+}
+
+/// Find the entries whose related documents contain every term of a search, most relevant first
+fn db_relatedhits(
+    related_table: &RelatedTable,
+    search: &str,
+    limit: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchHit>, ApiError> {
+    // a search that only excludes terms would have to build the document of every entry
+    let unindexed: bool = diesel::sql_query(
+        "SELECT querytree(websearch_to_tsquery('allegro', $1)) = 'T' AS unindexed",
+    )
+    .bind::<Text, _>(search)
+    .get_result::<SearchTerms>(conn)?
+    .unindexed;
+    if unindexed {
+        return Err(ApiError::Validation(
+            "Related searches require at least one term to look for".to_string(),
+        ));
+    }
+
+    // an entry can only match if the names containing a term of the search match its required
+    // terms together
+    let candidates: String = format!(
+        "SELECT linked.id FROM ({linked}) AS linked, terms
+        GROUP BY linked.id, terms.required
+        HAVING string_agg(linked.document::text, ' ')::tsvector @@ terms.required::tsquery",
+        linked = related_table.linked,
+    );
+
+    let query: String = format!(
+        "WITH search AS (
+            SELECT websearch_to_tsquery('allegro', $1) AS query
+        ),
+        terms AS (
+            SELECT regexp_replace(querytree(search.query), '&|<[0-9-]+>', '|', 'g')::tsquery
+                    AS query,
+                querytree(search.query) AS required
+            FROM search
+        ),
+        candidates AS ({candidates}),
+        documents AS ({documents})
+        SELECT documents.id,
+            ts_rank(documents.document, search.query)::real AS rank,
+            ts_headline('allegro', documents.summary, search.query,
+                'StartSel=<mark>, StopSel=</mark>, HighlightAll=true') AS highlight
+        FROM documents, search
+        WHERE documents.document @@ search.query
+        ORDER BY rank DESC, documents.id
+        LIMIT $2",
+        candidates = candidates,
+        documents = related_table.documents,
+    );
+    let hits: Vec<SearchHit> = diesel::sql_query(query)
+        .bind::<Text, _>(search)
+        .bind::<BigInt, _>(limit)
+        .load::<SearchHit>(conn)?;
+
+    Ok(hits)
+}
+
+/// Pair each search hit with its entry, keeping the order of the hits
+fn rank_results<T>(
+    hits: Vec<SearchHit>,
+    items: Vec<T>,
+    item_id: impl Fn(&T) -> i32,
+) -> Vec<SearchResult<T>> {
+    let mut items: HashMap<i32, T> = items
+        .into_iter()
+        .map(|item| (item_id(&item), item))
+        .collect();
+    hits.into_iter()
+        .filter_map(|hit| {
+            items.remove(&hit.id).map(|item| SearchResult {
+                item,
+                rank: hit.rank,
+                highlight: hit.highlight,
+            })
+        })
+        .collect()
+}
+
+/// Find the recordings most relevant to a search
+fn db_findrecordings(
+    search: &str,
+    limit: i64,
+    mode: SearchMode,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchResult<Recording>>, ApiError> {
+    use crate::schema::recordings;
+
+    let hits: Vec<SearchHit> = match mode {
+        SearchMode::Name => db_searchhits(&RECORDINGS, search, limit, conn)?,
+        SearchMode::Related => db_relatedhits(&RECORDING_DOCUMENTS, search, limit, conn)?,
     };
+    let db_recordings: Vec<DbRecording> = recordings::dsl::recordings
+        .filter(recordings::dsl::id.eq_any(hits.iter().map(|hit| hit.id)))
+        .load::<DbRecording>(conn)?;
 
-    return true;
+    let recordings: Vec<Recording> = db_assemblerecordings(db_recordings, conn)?;
+    Ok(rank_results(hits, recordings, |recording| recording.id))
 }
 
 /// Search for a recording in the index and return a list of relevant recordings
 fn db_searchrecording<T>(
     search_req: SearchRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Recording>> {
-    use crate::schema::{recording_performers, recordings};
-
-    // check for admin privileges
-    let is_admin: bool = check_admin(search_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: Vec::new(),
-        };
-    }
-
+) -> Result<Vec<SearchResult<Recording>>, ApiError> {
     // search for the recording in the database
-    let recording_res = recordings::dsl::recordings
-        .filter(recordings::dsl::piece_name.ilike(search_req.to_query()))
-        .load::<DbRecording>(conn);
-    let db_recordings: Vec<DbRecording> = match recording_res {
-        Ok(_) => recording_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
-    };
-
-    // for each recording, get the performers
-    let mut recordings: Vec<Recording> = Vec::new();
-    for db_recording in db_recordings {
-        // get all performer IDs for this recording
-        let performer_ids: Vec<i32> = recording_performers::dsl::recording_performers
-            .filter(recording_performers::dsl::recording_id.eq(db_recording.id))
-            .select(recording_performers::dsl::performer_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // construct the full recording object
-        let recording = Recording {
-            id: db_recording.id,
-            piece_name: db_recording.piece_name,
-            piece_id: db_recording.piece_id,
-            release_id: db_recording.release_id,
-            performer_ids,
-            track_number: db_recording.track_number,
-            file_path: db_recording.file_path,
-        };
+    db_findrecordings(
+        &search_req.query,
+        MAX_RESULTS,
+        search_req.mode.unwrap_or_default(),
+        conn,
+    )
+}
 
-        // add the recording to the list of recordings
-        recordings.push(recording);
-    }
+/// Find the releases most relevant to a search
+fn db_findreleases(
+    search: &str,
+    limit: i64,
+    mode: SearchMode,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchResult<Release>>, ApiError> {
+    use crate::schema::releases;
 
-    return Response {
-        success: true,
-        message: recordings,
+    let hits: Vec<SearchHit> = match mode {
+        SearchMode::Name => db_searchhits(&RELEASES, search, limit, conn)?,
+        SearchMode::Related => db_relatedhits(&RELEASE_DOCUMENTS, search, limit, conn)?,
     };
+    let db_releases: Vec<DbRelease> = releases::dsl::releases
+        .filter(releases::dsl::id.eq_any(hits.iter().map(|hit| hit.id)))
+        .load::<DbRelease>(conn)?;
+
+    let releases: Vec<Release> = db_assemblereleases(db_releases, conn)?;
+    Ok(rank_results(hits, releases, |release| release.id))
 }
 
 /// Search for a release in the index and return a list of relevant releases
 fn db_searchrelease<T>(
     search_req: SearchRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Release>> {
-    use crate::schema::{recordings, release_performers, releases};
-
-    // check for admin privileges
-    let is_admin: bool = check_admin(search_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: Vec::new(),
-        };
-    }
-
+) -> Result<Vec<SearchResult<Release>>, ApiError> {
     // search for the release in the database
-    let release_res = releases::dsl::releases
-        .filter(releases::dsl::name.ilike(search_req.to_query()))
-        .load::<DbRelease>(conn);
-    let db_releases: Vec<DbRelease> = match release_res {
-        Ok(_) => release_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
-    };
+    db_findreleases(
+        &search_req.query,
+        MAX_RESULTS,
+        search_req.mode.unwrap_or_default(),
+        conn,
+    )
+}
 
-    // for each release, get the performers and recordings
-    let mut releases: Vec<Release> = Vec::new();
-    for db_release in db_releases {
-        // get all performer IDs for this release
-        let performer_ids: Vec<i32> = release_performers::dsl::release_performers
-            .filter(release_performers::dsl::release_id.eq(db_release.id))
-            .select(release_performers::dsl::performer_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // get IDs of all recordings on this release, if they exist
-        let recording_ids: Vec<i32> = recordings::dsl::recordings
-            .filter(recordings::dsl::release_id.eq(db_release.id))
-            .select(recordings::dsl::id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // construct the full release object
-        let release = Release {
-            id: db_release.id,
-            name: db_release.name,
-            description: db_release.description,
-            image_path: db_release.image_path,
-            recording_ids: if recording_ids.is_empty() {
-                None
-            } else {
-                Some(recording_ids)
-            },
-            performer_ids,
-        };
+/// Find the pieces most relevant to a search
+fn db_findpieces(
+    search: &str,
+    limit: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchResult<Piece>>, ApiError> {
+    use crate::schema::pieces;
 
-        // push to the list of releases
-        releases.push(release);
-    }
+    let hits: Vec<SearchHit> = db_searchhits(&PIECES, search, limit, conn)?;
+    let db_pieces: Vec<DbPiece> = pieces::dsl::pieces
+        .filter(pieces::dsl::id.eq_any(hits.iter().map(|hit| hit.id)))
+        .load::<DbPiece>(conn)?;
 
-    return Response {
-        success: true,
-        message: releases,
-    };
+    let pieces: Vec<Piece> = db_assemblepieces(db_pieces, conn)?;
+    Ok(rank_results(hits, pieces, |piece| piece.id))
 }
 
 /// Search for a piece in the index and return a list of relevant pieces
 fn db_searchpiece<T>(
     search_req: SearchRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Piece>> {
-    use crate::schema::{piece_composers, piece_songwriters, pieces};
-
-    // check for admin privileges
-    let is_admin: bool = check_admin(search_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: Vec::new(),
-        };
-    }
-
+) -> Result<Vec<SearchResult<Piece>>, ApiError> {
     // search for the piece in the database
-    let piece_res = pieces::dsl::pieces
-        .filter(pieces::dsl::name.ilike(search_req.to_query()))
-        .load::<DbPiece>(conn);
-    let db_pieces: Vec<DbPiece> = match piece_res {
-        Ok(_) => piece_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
-    };
+    db_findpieces(&search_req.query, MAX_RESULTS, conn)
+}
 
-    // for each piece, get the composers, songwriters, and performers
-    let mut pieces: Vec<Piece> = Vec::new();
-    for db_piece in db_pieces {
-        // get all composer IDs for this piece
-        let composer_ids: Vec<i32> = piece_composers::dsl::piece_composers
-            .filter(piece_composers::dsl::piece_id.eq(db_piece.id))
-            .select(piece_composers::dsl::composer_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // get all songwriter IDs for this piece
-        let songwriter_ids: Vec<i32> = piece_songwriters::dsl::piece_songwriters
-            .filter(piece_songwriters::dsl::piece_id.eq(db_piece.id))
-            .select(piece_songwriters::dsl::songwriter_id)
-            .load::<i32>(conn)
-            .unwrap_or_default();
-
-        // construct the full piece object
-        let piece = Piece {
-            id: db_piece.id,
-            name: db_piece.name,
-            movements: db_piece.movements,
-            description: db_piece.description,
-            composer_ids,
-            songwriter_ids: if songwriter_ids.is_empty() {
-                None
-            } else {
-                Some(songwriter_ids)
-            },
-        };
+/// Find the songwriters most relevant to a search
+fn db_findsongwriters(
+    search: &str,
+    limit: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchResult<Songwriter>>, ApiError> {
+    use crate::schema::songwriters;
 
-        // push to the list of pieces
-        pieces.push(piece);
-    }
+    let hits: Vec<SearchHit> = db_searchhits(&SONGWRITERS, search, limit, conn)?;
+    let songwriters: Vec<Songwriter> = songwriters::dsl::songwriters
+        .filter(songwriters::dsl::id.eq_any(hits.iter().map(|hit| hit.id)))
+        .load::<Songwriter>(conn)?;
 
-    Response {
-        success: true,
-        message: pieces,
-    }
+    Ok(rank_results(hits, songwriters, |songwriter| songwriter.id))
 }
 
 /// Search a songwriter in the songwriters index, and return a list of relevant songwriters
 fn db_searchsongwriter<T>(
     search_req: SearchRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Songwriter>> {
-    use crate::schema::songwriters;
+) -> Result<Vec<SearchResult<Songwriter>>, ApiError> {
+    // search for the songwriter in the database
+    db_findsongwriters(&search_req.query, MAX_RESULTS, conn)
+}
 
-    // check for admin privileges
-    let is_admin: bool = check_admin(search_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: Vec::new(),
-        };
-    }
+/// Find the composers most relevant to a search
+fn db_findcomposers(
+    search: &str,
+    limit: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchResult<Composer>>, ApiError> {
+    use crate::schema::composers;
 
-    // search for the songwriter in the database
-    let songwriter_res = songwriters::dsl::songwriters
-        .filter(songwriters::dsl::name.ilike(search_req.to_query()))
-        .load::<Songwriter>(conn);
-    let songwriter: Vec<Songwriter> = match songwriter_res {
-        Ok(_) => songwriter_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
-    };
+    let hits: Vec<SearchHit> = db_searchhits(&COMPOSERS, search, limit, conn)?;
+    let composers: Vec<Composer> = composers::dsl::composers
+        .filter(composers::dsl::id.eq_any(hits.iter().map(|hit| hit.id)))
+        .load::<Composer>(conn)?;
 
-    Response {
-        success: true,
-        message: songwriter,
-    }
+    Ok(rank_results(hits, composers, |composer| composer.id))
 }
 
 /// Search a composer in the composers index, and return a list of relevant composers
 fn db_searchcomposer<T>(
     search_req: SearchRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Composer>> {
-    use crate::schema::composers;
+) -> Result<Vec<SearchResult<Composer>>, ApiError> {
+    // search for the composer in the database
+    db_findcomposers(&search_req.query, MAX_RESULTS, conn)
+}
 
-    // check for admin privileges
-    let is_admin: bool = check_admin(search_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: Vec::new(),
-        };
-    }
+/// Find the performers most relevant to a search
+fn db_findperformers(
+    search: &str,
+    limit: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<SearchResult<Performer>>, ApiError> {
+    use crate::schema::performers;
 
-    // search for the composer in the database
-    let composer_res = composers::dsl::composers
-        .filter(composers::dsl::name.ilike(search_req.to_query()))
-        .load::<Composer>(conn);
-    let composer: Vec<Composer> = match composer_res {
-        Ok(_) => composer_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
-        }
-    };
+    let hits: Vec<SearchHit> = db_searchhits(&PERFORMERS, search, limit, conn)?;
+    let performers: Vec<Performer> = performers::dsl::performers
+        .filter(performers::dsl::id.eq_any(hits.iter().map(|hit| hit.id)))
+        .load::<Performer>(conn)?;
 
-    Response {
-        success: true,
-        message: composer,
-    }
+    Ok(rank_results(hits, performers, |performer| performer.id))
 }
 
 /// Search a performer in the performers index, and return a list of relevant performers
 fn db_searchperformer<T>(
     search_req: SearchRequest,
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-) -> Response<Vec<Performer>> {
-    use crate::schema::performers;
+) -> Result<Vec<SearchResult<Performer>>, ApiError> {
+    // search for the performer in the database
+    db_findperformers(&search_req.query, MAX_RESULTS, conn)
+}
 
-    // check for admin privileges
-    let is_admin: bool = check_admin(search_req.token.clone(), conn);
-    if !is_admin {
-        return Response {
-            success: false,
-            message: Vec::new(),
+/// Search every requested kind of entry, ranking all of the results together
+fn db_searchall(
+    search_req: UnifiedSearchRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<UnifiedSearchResponse, ApiError> {
+    // search each kind of entry separately, keeping the order within each kind
+    let search: &str = &search_req.query;
+    let mode: SearchMode = search_req.mode.unwrap_or_default();
+    let mut groups: BTreeMap<SearchType, Vec<i32>> = BTreeMap::new();
+    let mut found_by: Vec<(SearchMode, SearchEntity)> = Vec::new();
+    for (search_type, limit) in search_req.searches()? {
+        // only releases and recordings can be searched by related names
+        let found_mode: SearchMode = match search_type {
+            SearchType::Release | SearchType::Recording => mode,
+            _ => SearchMode::Name,
+        };
+        let found: Vec<SearchEntity> = match search_type {
+            SearchType::Performer => db_findperformers(search, limit, conn)?
+                .into_iter()
+                .map(SearchEntity::Performer)
+                .collect(),
+            SearchType::Composer => db_findcomposers(search, limit, conn)?
+                .into_iter()
+                .map(SearchEntity::Composer)
+                .collect(),
+            SearchType::Songwriter => db_findsongwriters(search, limit, conn)?
+                .into_iter()
+                .map(SearchEntity::Songwriter)
+                .collect(),
+            SearchType::Piece => db_findpieces(search, limit, conn)?
+                .into_iter()
+                .map(SearchEntity::Piece)
+                .collect(),
+            SearchType::Release => db_findreleases(search, limit, mode, conn)?
+                .into_iter()
+                .map(SearchEntity::Release)
+                .collect(),
+            SearchType::Recording => db_findrecordings(search, limit, mode, conn)?
+                .into_iter()
+                .map(SearchEntity::Recording)
+                .collect(),
         };
+        groups.insert(search_type, found.iter().map(SearchEntity::id).collect());
+        found_by.extend(found.into_iter().map(|result| (found_mode, result)));
     }
 
-    // search for the performer in the database
-    let performer_res = performers::dsl::performers
-        .filter(performers::dsl::name.ilike(search_req.to_query()))
-        .load::<Performer>(conn);
-    let performer: Vec<Performer> = match performer_res {
-        Ok(_) => performer_res.unwrap(),
-        Err(_) => {
-            return Response {
-                success: false,
-                message: Vec::new(),
-            };
+    // ranks by name and by related names are on different scales, so each is made relative to
+    // the best result found the same way before every result is ranked against each other
+    for searched in [SearchMode::Name, SearchMode::Related] {
+        let best: f32 = found_by
+            .iter()
+            .filter(|(found_mode, _)| *found_mode == searched)
+            .map(|(_, result)| result.rank())
+            .fold(0.0, f32::max);
+        if best > 0.0 {
+            found_by
+                .iter_mut()
+                .filter(|(found_mode, _)| *found_mode == searched)
+                .for_each(|(_, result)| *result.rank_mut() /= best);
         }
-    };
-
-    Response {
-        success: true,
-        message: performer,
     }
+    let mut results: Vec<SearchEntity> = found_by.into_iter().map(|(_, result)| result).collect();
+    results.sort_by(|a, b| b.rank().total_cmp(&a.rank()));
+
+    Ok(UnifiedSearchResponse { results, groups })
 }
 
 /// Search for an artist
 #[post("/music/search/performer")]
 pub async fn searchperformer(
+    _caller: Authorized<requires::CatalogSearch>,
     search_req: Json<SearchRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
-    // get the searchperformer response from database
-    let mut conn = pool.get().expect("Connection pool error");
+) -> Result<HttpResponse, ApiError> {
+    // get the searchartist response from database
+    let mut conn = pool.get()?;
     let searchartist_response = web::block(move || {
         db_searchperformer::<Vec<Performer>>(search_req.into_inner(), &mut conn)
     })
-    .await;
-
-    // return the appropriate response and handle errors
-    match searchartist_response {
-        Ok(response) => {
-            // handle case where server successfully processes the request
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(response)
-        }
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(searchartist_response)))
 }
 
 /// Search for a composer
 #[post("/music/search/composer")]
 pub async fn searchcomposer(
+    _caller: Authorized<requires::CatalogSearch>,
     search_req: Json<SearchRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the searchcomposer response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let searchcomposer_response =
         web::block(move || db_searchcomposer::<Vec<Composer>>(search_req.into_inner(), &mut conn))
-            .await;
-
-    // return the appropriate response and handle errors
-    match searchcomposer_response {
-        Ok(response) => HttpResponse::Created()
-            // handle case where server successfully processes the request
-            .content_type("application/json")
-            .json(response),
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(searchcomposer_response)))
 }
 
 /// Search for a songwriter
 #[post("/music/search/songwriter")]
 pub async fn searchsongwriter(
+    _caller: Authorized<requires::CatalogSearch>,
     search_req: Json<SearchRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the searchsongwriter response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let searchsongwriter_response = web::block(move || {
         db_searchsongwriter::<Vec<Songwriter>>(search_req.into_inner(), &mut conn)
     })
-    .await;
-
-    // return the appropriate response and handle errors
-    match searchsongwriter_response {
-        Ok(response) => HttpResponse::Created()
-            // handle case where server successfully processes the request
-            .content_type("application/json")
-            .json(response),
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(searchsongwriter_response)))
 }
 
 /// Search for a piece
 #[post("/music/search/piece")]
 pub async fn searchpiece(
+    _caller: Authorized<requires::CatalogSearch>,
     search_req: Json<SearchRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the searchpiece response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let searchpiece_response =
-        web::block(move || db_searchpiece::<Vec<Piece>>(search_req.into_inner(), &mut conn)).await;
-
-    // return the appropriate response and handle errors
-    match searchpiece_response {
-        Ok(response) => HttpResponse::Created()
-            // handle case where server successfully processes the request
-            .content_type("application/json")
-            .json(response),
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+        web::block(move || db_searchpiece::<Vec<Piece>>(search_req.into_inner(), &mut conn))
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(searchpiece_response)))
 }
 
 /// Search for a release
 #[post("/music/search/release")]
 pub async fn searchrelease(
+    _caller: Authorized<requires::CatalogSearch>,
     search_req: Json<SearchRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the searchrelease response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let searchrelease_response =
         web::block(move || db_searchrelease::<Vec<Release>>(search_req.into_inner(), &mut conn))
-            .await;
-
-    // return the appropriate response and handle errors
-    match searchrelease_response {
-        Ok(response) => HttpResponse::Created()
-            // handle case where server successfully processes the request
-            .content_type("application/json")
-            .json(response),
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
-        }
-    }
+            .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(searchrelease_response)))
 }
 
 /// Search for a recording
 #[post("/music/search/recording")]
 pub async fn searchrecording(
+    _caller: Authorized<requires::CatalogSearch>,
     search_req: Json<SearchRequest>,
     pool: Data<Pool<ConnectionManager<PgConnection>>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
     // get the searchrecording response from database
-    let mut conn = pool.get().expect("Connection pool error");
+    let mut conn = pool.get()?;
     let searchrecording_response = web::block(move || {
         db_searchrecording::<Vec<Recording>>(search_req.into_inner(), &mut conn)
     })
-    .await;
-
-    // return the appropriate response and handle errors
-    match searchrecording_response {
-        Ok(response) => HttpResponse::Created()
-            // handle case where server successfully processes the request
-            .content_type("application/json")
-            .json(response),
-        _ => {
-            // handle case where server error occurs
-            HttpResponse::InternalServerError().finish()
+    .await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(searchrecording_response)))
+}
+
+/// Search every kind of entry at once
+#[post("/music/search")]
+pub async fn searchall(
+    _caller: Authorized<requires::CatalogSearch>,
+    search_req: Json<UnifiedSearchRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the search response from database
+    let mut conn = pool.get()?;
+    let search_response =
+        web::block(move || db_searchall(search_req.into_inner(), &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Ok().json(Response::success(search_response)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+
+    /// Add a piece with a name
+    fn piece(name: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::pieces;
+
+        diesel::insert_into(pieces::table)
+            .values(pieces::name.eq(name))
+            .returning(pieces::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Add a performer with a name
+    fn performer(name: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::performers;
+
+        diesel::insert_into(performers::table)
+            .values(performers::name.eq(name))
+            .returning(performers::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Add a recording of Beethoven's seventh symphony conducted by Carlos Kleiber, giving its id
+    fn recording(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::{
+            composers, piece_composers, recording_performers, recordings, releases,
+        };
+
+        let composer_id: i32 = diesel::insert_into(composers::table)
+            .values(composers::name.eq("Ludwig van Beethoven"))
+            .returning(composers::id)
+            .get_result(conn)
+            .unwrap();
+        let piece_id: i32 = piece("Symphony No. 7 in A major", conn);
+        diesel::insert_into(piece_composers::table)
+            .values((
+                piece_composers::piece_id.eq(piece_id),
+                piece_composers::composer_id.eq(composer_id),
+            ))
+            .execute(conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("Wiener Philharmoniker 1976"))
+            .returning(releases::id)
+            .get_result(conn)
+            .unwrap();
+        let recording_id: i32 = diesel::insert_into(recordings::table)
+            .values((
+                recordings::piece_name.eq("Allegretto"),
+                recordings::piece_id.eq(piece_id),
+                recordings::release_id.eq(release_id),
+                recordings::track_number.eq(2),
+            ))
+            .returning(recordings::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(recording_performers::table)
+            .values((
+                recording_performers::recording_id.eq(recording_id),
+                recording_performers::performer_id.eq(performer("Carlos Kleiber", conn)),
+            ))
+            .execute(conn)
+            .unwrap();
+        recording_id
+    }
+
+    /// Search every kind of entry in the given mode
+    fn search_all(query: &str, mode: SearchMode) -> UnifiedSearchRequest {
+        UnifiedSearchRequest {
+            query: query.to_string(),
+            types: None,
+            mode: Some(mode),
+            limit: None,
+            limits: None,
         }
     }
+
+    #[test]
+    fn leaves_search_syntax_out_of_fuzzy_words() {
+        assert_eq!(
+            fuzzy_words(r#"  "Symphny sevnth" or Beethovn -Liszt " "#),
+            vec!["Symphny", "sevnth", "Beethovn"]
+        );
+    }
+
+    #[test]
+    fn matches_each_word_despite_typos() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let symphony: i32 = piece(
+            "Beethoven: Symphony No. 7 in A major, the Seventh",
+            &mut conn,
+        );
+        let requiem: i32 = piece("Requiem in D minor", &mut conn);
+
+        // the query as a whole is too far from the name, but each of its words is close
+        let ids = |search: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>| {
+            db_searchhits(&PIECES, search, MAX_RESULTS, conn)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit.id)
+                .collect::<Vec<i32>>()
+        };
+        let hits: Vec<i32> = ids("Symphny sevnth", &mut conn);
+        assert!(hits.contains(&symphony));
+        assert!(!hits.contains(&requiem));
+
+        // every word has to be close, not just one of them
+        assert!(!ids("Symphny Requiem", &mut conn).contains(&symphony));
+        assert!(ids("Reqiem minor", &mut conn).contains(&requiem));
+    }
+
+    #[test]
+    fn searches_only_the_requested_types_up_to_their_limits() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        for name in ["Quartetto Zyxwel", "Zyxwel Ensemble", "Zyxwel Consort"] {
+            performer(name, &mut conn);
+        }
+        piece("Zyxwel Suite", &mut conn);
+        piece("Zyxwel Partita", &mut conn);
+
+        let search_req = UnifiedSearchRequest {
+            types: Some(vec![
+                SearchType::Piece,
+                SearchType::Performer,
+                SearchType::Piece,
+            ]),
+            limit: Some(5),
+            limits: Some(HashMap::from([(SearchType::Performer, 2)])),
+            ..search_all("zyxwel", SearchMode::Name)
+        };
+        let response: UnifiedSearchResponse = db_searchall(search_req, &mut conn).unwrap();
+        assert_eq!(
+            response.groups.keys().copied().collect::<Vec<SearchType>>(),
+            vec![SearchType::Performer, SearchType::Piece]
+        );
+        assert_eq!(response.groups[&SearchType::Performer].len(), 2);
+        assert_eq!(response.groups[&SearchType::Piece].len(), 2);
+        assert_eq!(response.results.len(), 4);
+        assert!(response
+            .results
+            .iter()
+            .all(|result| matches!(result, SearchEntity::Performer(_) | SearchEntity::Piece(_))));
+
+        let search_req = UnifiedSearchRequest {
+            types: Some(Vec::new()),
+            ..search_all("zyxwel", SearchMode::Name)
+        };
+        assert!(matches!(
+            db_searchall(search_req, &mut conn),
+            Err(ApiError::Validation(_))
+        ));
+        let search_req = UnifiedSearchRequest {
+            limits: Some(HashMap::from([(SearchType::Piece, MAX_RESULTS + 1)])),
+            ..search_all("zyxwel", SearchMode::Name)
+        };
+        assert!(matches!(
+            db_searchall(search_req, &mut conn),
+            Err(ApiError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn ranks_name_and_related_results_together() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let recording_id: i32 = recording(&mut conn);
+        // a name only close to the search ranks below the names that contain it
+        performer("Kleiberg", &mut conn);
+
+        let search_req = UnifiedSearchRequest {
+            types: Some(vec![SearchType::Performer, SearchType::Recording]),
+            ..search_all("kleiber", SearchMode::Related)
+        };
+        let response: UnifiedSearchResponse = db_searchall(search_req, &mut conn).unwrap();
+        assert_eq!(response.groups[&SearchType::Recording], vec![recording_id]);
+        assert!(response.groups[&SearchType::Performer].len() >= 2);
+
+        // the best result of each mode ranks first, so performers found by name and recordings
+        // found by related names are interleaved rather than one kind following the other
+        let ranks: Vec<f32> = response.results.iter().map(SearchEntity::rank).collect();
+        assert!(ranks.iter().all(|rank| *rank > 0.0 && *rank <= 1.0));
+        assert!(ranks.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(matches!(response.results[0], SearchEntity::Performer(_)));
+        assert!(matches!(
+            &response.results[1],
+            SearchEntity::Recording(result) if result.item.id == recording_id && result.rank == 1.0
+        ));
+        assert!(matches!(
+            response.results.last(),
+            Some(SearchEntity::Performer(result)) if result.rank < 1.0
+        ));
+    }
+
+    #[test]
+    fn finds_recordings_by_the_names_linked_to_them() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let recording_id: i32 = recording(&mut conn);
+        let ids = |search: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>| {
+            db_relatedhits(&RECORDING_DOCUMENTS, search, MAX_RESULTS, conn)
+                .map(|hits| hits.into_iter().map(|hit| hit.id).collect::<Vec<i32>>())
+        };
+
+        // the composer, the piece and the performer are each linked to the recording
+        assert_eq!(
+            ids("beethoven 7 kleiber", &mut conn).unwrap(),
+            vec![recording_id]
+        );
+        assert_eq!(
+            ids("beethoven -karajan", &mut conn).unwrap(),
+            vec![recording_id]
+        );
+
+        // every term has to be found in something linked to it
+        assert!(ids("beethoven 7 karajan", &mut conn).unwrap().is_empty());
+        assert!(ids("zyxwel", &mut conn).unwrap().is_empty());
+
+        // only excluding terms leaves nothing to look up
+        assert!(matches!(
+            ids("-karajan", &mut conn),
+            Err(ApiError::Validation(message)) if message.contains("at least one term")
+        ));
+    }
 }
@@ -0,0 +1,423 @@
+use crate::api::auth::{session_cookie, Authorized, Caller};
+use crate::error::ApiError;
+use crate::insert;
+use crate::models::DbSession;
+use crate::password;
+use crate::permission::requires;
+use crate::Response;
+
+use actix_web::http::header;
+use actix_web::web::{Data, Json};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Serialize};
+
+// longest user agent kept for a session
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// The device a session was created from
+pub struct Client {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl Client {
+    /// Describe the client of a request from its user agent and the address it connected from
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let user_agent: Option<String> = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LEN).collect());
+        let ip_address: Option<String> = req.peer_addr().map(|addr| addr.ip().to_string());
+        Client {
+            user_agent,
+            ip_address,
+        }
+    }
+}
+
+/// A request to revoke one of the user's own sessions
+#[derive(Deserialize, Serialize)]
+pub struct RevokeSessionRequest {
+    pub id: i32,
+}
+
+/// A request from an administrator to revoke every session of a user
+#[derive(Deserialize, Serialize)]
+pub struct RevokeUserSessionsRequest {
+    pub user_id: i32,
+}
+
+/// A session as shown to its owner, without its token
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Session {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub current: bool,
+}
+
+/// Start a new session for a user, clearing out any of their sessions that have expired. The
+/// token is returned alongside the session, since only its hash is stored.
+pub(crate) fn db_createsession(
+    user_id: i32,
+    client: Client,
+    lifetime: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(String, DbSession), ApiError> {
+    use crate::schema::sessions;
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    diesel::delete(
+        sessions::dsl::sessions
+            .filter(sessions::dsl::user_id.eq(user_id))
+            .filter(sessions::dsl::expires_at.le(now)),
+    )
+    .execute(conn)?;
+
+    let token: String = password::generate_token()?;
+    let new_session = insert::NewSession {
+        user_id,
+        token_hash: password::hash_token(&token),
+        expires_at: now + Duration::seconds(lifetime),
+        user_agent: client.user_agent,
+        ip_address: client.ip_address,
+    };
+    let session: DbSession = diesel::insert_into(sessions::dsl::sessions)
+        .values(&new_session)
+        .get_result::<DbSession>(conn)?;
+    Ok((token, session))
+}
+
+/// End the session of the caller
+fn db_logout(
+    caller: Caller,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::sessions;
+
+    let (session, _) = caller.require_session()?;
+    diesel::delete(sessions::dsl::sessions.filter(sessions::dsl::id.eq(session.id)))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Get every unexpired session of the caller, most recently used first
+fn db_listsessions(
+    caller: Caller,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Session>, ApiError> {
+    use crate::schema::sessions;
+
+    let (current, user) = caller.require_session()?;
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    let db_sessions: Vec<DbSession> = sessions::dsl::sessions
+        .filter(sessions::dsl::user_id.eq(user.id))
+        .filter(sessions::dsl::expires_at.gt(now))
+        .order((sessions::dsl::last_used_at.desc(), sessions::dsl::id.desc()))
+        .load::<DbSession>(conn)?;
+
+    Ok(db_sessions
+        .into_iter()
+        .map(|session| Session {
+            id: session.id,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            expires_at: session.expires_at,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            current: session.id == current.id,
+        })
+        .collect())
+}
+
+/// Revoke one of the sessions of the caller
+fn db_revokesession(
+    caller: Caller,
+    revoke_req: RevokeSessionRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::sessions;
+
+    // users can only revoke their own sessions, so any other id is not found
+    let (_, user) = caller.require_session()?;
+    let delete = diesel::delete(
+        sessions::dsl::sessions
+            .filter(sessions::dsl::id.eq(revoke_req.id))
+            .filter(sessions::dsl::user_id.eq(user.id)),
+    );
+    match delete.execute(conn)? {
+        0 => Err(ApiError::not_found("Session")),
+        _ => Ok(()),
+    }
+}
+
+/// Revoke every session of a user, returning how many were revoked
+fn db_revokeusersessions(
+    revoke_req: RevokeUserSessionsRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<usize, ApiError> {
+    use crate::schema::{sessions, users};
+
+    // make sure the user exists before revoking their sessions
+    let user_id: i32 = users::dsl::users
+        .filter(users::dsl::id.eq(revoke_req.user_id))
+        .select(users::dsl::id)
+        .first::<i32>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::not_found("User"))?;
+    let revoked: usize =
+        diesel::delete(sessions::dsl::sessions.filter(sessions::dsl::user_id.eq(user_id)))
+            .execute(conn)?;
+    Ok(revoked)
+}
+
+/// End the current session, clearing the session cookie
+#[post("/auth/logout")]
+pub async fn logout(
+    req: HttpRequest,
+    caller: Authorized<requires::Anyone>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the session from the database
+    let mut conn = pool.get()?;
+    web::block(move || db_logout(caller.into_inner(), &mut conn)).await??;
+
+    // return success
+    Ok(HttpResponse::Ok()
+        .cookie(session_cookie(&req, "", 0))
+        .json(Response::success(String::new())))
+}
+
+/// List the sessions of the current user
+#[get("/auth/sessions")]
+pub async fn listsessions(
+    caller: Authorized<requires::Anyone>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the sessions from the database
+    let mut conn = pool.get()?;
+    let sessions = web::block(move || db_listsessions(caller.into_inner(), &mut conn)).await??;
+
+    // return the sessions on success
+    Ok(HttpResponse::Ok().json(Response::success(sessions)))
+}
+
+/// Revoke one of the sessions of the current user
+#[delete("/auth/sessions")]
+pub async fn revokesession(
+    caller: Authorized<requires::Anyone>,
+    revoke_req: Json<RevokeSessionRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the session from the database
+    let mut conn = pool.get()?;
+    web::block(move || db_revokesession(caller.into_inner(), revoke_req.into_inner(), &mut conn))
+        .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Revoke every session of a user
+#[delete("/auth/users/sessions")]
+pub async fn revokeusersessions(
+    _caller: Authorized<requires::UsersManage>,
+    revoke_req: Json<RevokeUserSessionsRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the sessions from the database
+    let mut conn = pool.get()?;
+    let revoked =
+        web::block(move || db_revokeusersessions(revoke_req.into_inner(), &mut conn)).await??;
+
+    // return the number of revoked sessions
+    Ok(HttpResponse::Ok().json(Response::success(revoked)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::{self, Credential};
+    use crate::models::User;
+    use crate::testing::connect;
+    use chrono::SubsecRound;
+
+    /// Add a user, giving their id
+    fn user(username: &str, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> i32 {
+        use crate::schema::users;
+
+        diesel::insert_into(users::table)
+            .values((
+                users::username.eq(username),
+                users::password_hash.eq("unused"),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Start an hour long session for a user
+    fn session(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (String, DbSession) {
+        let client = Client {
+            user_agent: None,
+            ip_address: None,
+        };
+        db_createsession(user_id, client, 3600, conn).unwrap()
+    }
+
+    /// Sign in as a user with one of their sessions
+    fn caller(
+        session: DbSession,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::users;
+
+        let user: User = users::table.find(session.user_id).first(conn).unwrap();
+        Caller::authenticated(Credential::Session(session), user)
+    }
+
+    /// Whether a session is still stored
+    fn exists(id: i32, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> bool {
+        use crate::schema::sessions;
+
+        sessions::table
+            .find(id)
+            .first::<DbSession>(conn)
+            .optional()
+            .unwrap()
+            .is_some()
+    }
+
+    #[test]
+    fn renews_sessions_as_they_are_used() {
+        use crate::schema::sessions;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("sessions-test", &mut conn);
+        let (token, session) = session(user_id, &mut conn);
+
+        // a session last used ten minutes ago has fifty minutes of its hour left
+        let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+        diesel::update(sessions::table.find(session.id))
+            .set((
+                sessions::last_used_at.eq(now - Duration::minutes(10)),
+                sessions::expires_at.eq(now + Duration::minutes(50)),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        let (renewed, _) = auth::require_session(&token, &mut conn).unwrap();
+        assert!(renewed.last_used_at >= now);
+        assert_eq!(
+            renewed.expires_at - renewed.last_used_at,
+            Duration::hours(1)
+        );
+        let stored: DbSession = sessions::table.find(session.id).first(&mut conn).unwrap();
+        assert_eq!(stored.last_used_at, renewed.last_used_at.trunc_subsecs(6));
+        assert_eq!(stored.expires_at, renewed.expires_at.trunc_subsecs(6));
+
+        // using it again straight away leaves it as it is
+        let (again, _) = auth::require_session(&token, &mut conn).unwrap();
+        assert_eq!(again.last_used_at, stored.last_used_at);
+        assert_eq!(again.expires_at, stored.expires_at);
+
+        // an expired session is refused rather than renewed
+        diesel::update(sessions::table.find(session.id))
+            .set(sessions::expires_at.eq(now - Duration::minutes(1)))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(matches!(
+            auth::require_session(&token, &mut conn),
+            Err(ApiError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn logs_out_only_the_current_session() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("sessions-test", &mut conn);
+        let (_, current) = session(user_id, &mut conn);
+        let (_, other) = session(user_id, &mut conn);
+
+        let current_id: i32 = current.id;
+        let caller: Caller = caller(current, &mut conn);
+        db_logout(caller, &mut conn).unwrap();
+        assert!(!exists(current_id, &mut conn));
+        assert!(exists(other.id, &mut conn));
+    }
+
+    #[test]
+    fn hides_sessions_of_other_users_from_revocation() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let owner_id: i32 = user("sessions-owner", &mut conn);
+        let other_id: i32 = user("sessions-other", &mut conn);
+        let (_, owned) = session(owner_id, &mut conn);
+        let (_, current) = session(other_id, &mut conn);
+
+        let caller: Caller = caller(current, &mut conn);
+        let revoke_req = RevokeSessionRequest { id: owned.id };
+        assert!(matches!(
+            db_revokesession(caller, revoke_req, &mut conn),
+            Err(ApiError::NotFound(_))
+        ));
+        assert!(exists(owned.id, &mut conn));
+    }
+
+    #[test]
+    fn revokes_every_session_of_known_users_only() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("sessions-test", &mut conn);
+        session(user_id, &mut conn);
+        session(user_id, &mut conn);
+
+        let revoke_req = RevokeUserSessionsRequest { user_id };
+        assert_eq!(db_revokeusersessions(revoke_req, &mut conn).unwrap(), 2);
+        let revoke_req = RevokeUserSessionsRequest { user_id: -1 };
+        assert!(matches!(
+            db_revokeusersessions(revoke_req, &mut conn),
+            Err(ApiError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn clears_expired_sessions_on_sign_in() {
+        use crate::schema::sessions;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user_id: i32 = user("sessions-test", &mut conn);
+        let other_id: i32 = user("sessions-other", &mut conn);
+        let (_, expired) = session(user_id, &mut conn);
+        let (_, unexpired) = session(user_id, &mut conn);
+        let (_, other) = session(other_id, &mut conn);
+        let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+        diesel::update(sessions::table.filter(sessions::id.eq_any([expired.id, other.id])))
+            .set(sessions::expires_at.eq(now - Duration::minutes(1)))
+            .execute(&mut conn)
+            .unwrap();
+
+        // only expired sessions of the user signing in are cleared
+        session(user_id, &mut conn);
+        assert!(!exists(expired.id, &mut conn));
+        assert!(exists(unexpired.id, &mut conn));
+        assert!(exists(other.id, &mut conn));
+    }
+}
@@ -0,0 +1,355 @@
+use crate::api::auth::Authorized;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::media;
+use crate::permission::requires;
+
+use actix_web::body::SizedStream;
+use actix_web::http::header::{self, EntityTag, IfRange, Range};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, Data, Path};
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use futures_util::stream::{self, Stream};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// size of each chunk read from disk while streaming
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// An opened audio file along with the metadata needed to serve it
+struct AudioFile {
+    file: File,
+    len: u64,
+    modified: SystemTime,
+    content_type: &'static str,
+}
+
+impl AudioFile {
+    /// Open an audio file and determine its content type from its contents
+    fn open(path: &std::path::Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "not a file"));
+        }
+
+        // sniff the first bytes of the file, then rewind
+        let mut header = [0u8; 12];
+        let read = file.read(&mut header)?;
+        file.seek(SeekFrom::Start(0))?;
+        let content_type =
+            media::sniff_audio(&header[..read]).unwrap_or("application/octet-stream");
+
+        Ok(AudioFile {
+            file,
+            len: metadata.len(),
+            modified: metadata.modified()?,
+            content_type,
+        })
+    }
+
+    /// Modification time of the file in whole seconds since the epoch
+    fn modified_secs(&self) -> u64 {
+        self.modified
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// Entity tag derived from the modification time, to the nanosecond, and size of the file.
+    /// A time without a fraction of a second may come from a filesystem that only keeps whole
+    /// seconds, where a rewrite of the same size within that second would keep the tag, so the
+    /// tag is weak and partial responses are not resumed against it.
+    fn etag(&self) -> EntityTag {
+        let nanos: u32 = self
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(0);
+        let tag: String = format!("{:x}.{:x}-{:x}", self.modified_secs(), nanos, self.len);
+        match nanos {
+            0 => EntityTag::new_weak(tag),
+            _ => EntityTag::new_strong(tag),
+        }
+    }
+
+    /// Check whether an If-Range precondition still matches the file on disk
+    fn if_range_matches(&self, req: &HttpRequest) -> bool {
+        if !req.headers().contains_key(header::IF_RANGE) {
+            return true;
+        }
+
+        // an unparseable precondition never matches, so the full file is sent, and neither does
+        // a weak tag, which strong_eq refuses on either side
+        match req.get_header::<IfRange>() {
+            Some(IfRange::EntityTag(tag)) => tag.strong_eq(&self.etag()),
+            Some(IfRange::Date(date)) => {
+                let date: SystemTime = date.into();
+                date.duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_secs() == self.modified_secs())
+                    .unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+
+    /// Build the response for the file, honoring Range and If-Range headers
+    fn into_response(self, req: &HttpRequest) -> HttpResponse {
+        let mut res = HttpResponse::Ok();
+        res.insert_header((header::CONTENT_TYPE, self.content_type))
+            .insert_header((header::ACCEPT_RANGES, "bytes"))
+            .insert_header(header::ETag(self.etag()))
+            .insert_header(header::LastModified(self.modified.into()));
+
+        // only serve a partial response if the client's copy is still current
+        let mut offset: u64 = 0;
+        let mut length: u64 = self.len;
+        if let Some(Range::Bytes(specs)) = req.get_header::<Range>() {
+            if self.if_range_matches(req) {
+                match specs
+                    .iter()
+                    .find_map(|spec| spec.to_satisfiable_range(self.len))
+                {
+                    Some((start, end)) => {
+                        offset = start;
+                        length = end - start + 1;
+                        res.status(StatusCode::PARTIAL_CONTENT).insert_header((
+                            header::CONTENT_RANGE,
+                            format!("bytes {}-{}/{}", start, end, self.len),
+                        ));
+                    }
+                    None => {
+                        let content_range = format!("bytes */{}", self.len);
+                        return res
+                            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                            .insert_header((header::CONTENT_RANGE, content_range))
+                            .finish();
+                    }
+                }
+            }
+        }
+
+        res.body(SizedStream::new(
+            length,
+            read_chunks(self.file, offset, length),
+        ))
+    }
+}
+
+/// Read a byte range of a file as a stream of chunks, doing the disk reads off the async runtime
+fn read_chunks(
+    file: File,
+    offset: u64,
+    length: u64,
+) -> impl Stream<Item = Result<Bytes, io::Error>> {
+    stream::unfold(
+        (Some(file), offset, length),
+        |(file, offset, remaining)| async move {
+            let mut file: File = file?;
+            if remaining == 0 {
+                return None;
+            }
+
+            // read the next chunk on the blocking thread pool
+            let chunk_len: u64 = remaining.min(CHUNK_SIZE);
+            let chunk = web::block(move || -> io::Result<(File, Vec<u8>)> {
+                let mut buf = vec![0u8; chunk_len as usize];
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut buf)?;
+                Ok((file, buf))
+            })
+            .await;
+
+            match chunk {
+                Ok(Ok((file, buf))) => Some((
+                    Ok(Bytes::from(buf)),
+                    (Some(file), offset + chunk_len, remaining - chunk_len),
+                )),
+                Ok(Err(err)) => Some((Err(err), (None, 0, 0))),
+                Err(err) => Some((Err(io::Error::other(err)), (None, 0, 0))),
+            }
+        },
+    )
+}
+
+/// Get the file path of a recording by id, if the recording has a file
+pub(crate) fn db_getfilepath(
+    recording_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Option<String> {
+    use crate::schema::recordings;
+
+    recordings::dsl::recordings
+        .filter(recordings::dsl::id.eq(recording_id))
+        .select(recordings::dsl::file_path)
+        .first::<Option<String>>(conn)
+        .ok()
+        .flatten()
+}
+
+/// Stream the audio file at a stored path, serving the range a request asks for
+pub(crate) async fn stream_file(
+    req: &HttpRequest,
+    media_root: &std::path::Path,
+    file_path: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    // resolve the file path against the media root
+    let full_path = file_path
+        .and_then(|file_path| media::resolve(media_root, &file_path))
+        .ok_or_else(|| ApiError::not_found("Recording audio"))?;
+
+    // open the file and stream the requested range of it
+    let audio_file: AudioFile = web::block(move || AudioFile::open(&full_path))
+        .await?
+        .map_err(|_| ApiError::not_found("Recording audio"))?;
+    Ok(audio_file.into_response(req))
+}
+
+/// Stream the audio file of a recording, with support for seeking through range requests
+#[get("/music/stream/{id}")]
+pub async fn streamrecording(
+    req: HttpRequest,
+    _caller: Authorized<requires::MediaStream>,
+    recording_id: Path<i32>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // get the file path of the recording from the database
+    let mut conn = pool.get()?;
+    let recording_id: i32 = recording_id.into_inner();
+    let file_path: Option<String> =
+        web::block(move || db_getfilepath(recording_id, &mut conn)).await?;
+
+    stream_file(&req, &config.media_root, file_path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+    use std::fs;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const CONTENTS: &[u8] = b"0123456789";
+
+    /// Open a temporary file holding the test contents, removing it from disk once opened
+    fn audio_file() -> AudioFile {
+        let path = std::env::temp_dir().join(format!("allegro-{}.bin", Uuid::new_v4()));
+        fs::write(&path, CONTENTS).unwrap();
+        let audio_file: AudioFile = AudioFile::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        audio_file
+    }
+
+    /// Serve a file for a request with the given headers
+    async fn respond(
+        audio_file: AudioFile,
+        headers: &[(header::HeaderName, &str)],
+    ) -> (StatusCode, Option<String>, Bytes) {
+        let mut req = TestRequest::default();
+        for (name, value) in headers {
+            req = req.insert_header((name.clone(), *value));
+        }
+        let res: HttpResponse = audio_file.into_response(&req.to_http_request());
+        let status: StatusCode = res.status();
+        let content_range: Option<String> = res
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body: Bytes = to_bytes(res.into_body()).await.unwrap();
+        (status, content_range, body)
+    }
+
+    #[actix_web::test]
+    async fn serves_a_single_range() {
+        let (status, content_range, body) =
+            respond(audio_file(), &[(header::RANGE, "bytes=2-5")]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 2-5/10"));
+        assert_eq!(&body[..], b"2345");
+    }
+
+    #[actix_web::test]
+    async fn serves_a_suffix_range() {
+        let (status, content_range, body) =
+            respond(audio_file(), &[(header::RANGE, "bytes=-3")]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 7-9/10"));
+        assert_eq!(&body[..], b"789");
+    }
+
+    #[actix_web::test]
+    async fn rejects_an_unsatisfiable_range() {
+        let (status, content_range, body) =
+            respond(audio_file(), &[(header::RANGE, "bytes=20-30")]).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(content_range.as_deref(), Some("bytes */10"));
+        assert!(body.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn sends_the_full_file_when_if_range_does_not_match() {
+        let current: AudioFile = audio_file();
+        let etag: String = current.etag().to_string();
+        let (status, _, _) = respond(
+            current,
+            &[(header::RANGE, "bytes=2-5"), (header::IF_RANGE, &etag)],
+        )
+        .await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+
+        let (status, content_range, body) = respond(
+            audio_file(),
+            &[
+                (header::RANGE, "bytes=2-5"),
+                (header::IF_RANGE, "\"changed\""),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_range, None);
+        assert_eq!(&body[..], CONTENTS);
+    }
+
+    #[actix_web::test]
+    async fn tells_rewrites_within_a_second_apart() {
+        let mut first: AudioFile = audio_file();
+        let mut second: AudioFile = audio_file();
+        first.modified = UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000);
+        second.modified = UNIX_EPOCH + Duration::new(1_700_000_000, 750_000_000);
+        assert!(!first.etag().weak);
+        assert!(!first.etag().strong_eq(&second.etag()));
+
+        // a stale tag from earlier in the same second no longer resumes the download
+        let etag: String = first.etag().to_string();
+        let (status, _, body) = respond(
+            second,
+            &[(header::RANGE, "bytes=2-5"), (header::IF_RANGE, &etag)],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], CONTENTS);
+    }
+
+    #[actix_web::test]
+    async fn never_resumes_against_whole_second_times() {
+        let mut current: AudioFile = audio_file();
+        current.modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert!(current.etag().weak);
+
+        let etag: String = current.etag().to_string();
+        let (status, content_range, body) = respond(
+            current,
+            &[(header::RANGE, "bytes=2-5"), (header::IF_RANGE, &etag)],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_range, None);
+        assert_eq!(&body[..], CONTENTS);
+    }
+}
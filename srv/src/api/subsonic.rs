@@ -0,0 +1,1601 @@
+use crate::api::apikeys::{require_apikey, API_KEY_PREFIX};
+use crate::api::auth::{require_permission, Authorized, Caller, Credential};
+use crate::api::lockouts::{db_loginfailed, db_loginsucceeded, db_reservelogin, Throttling};
+use crate::api::search::to_pattern;
+use crate::api::sessions::Client;
+use crate::api::stream::{db_getfilepath, stream_file};
+use crate::api::upload::serve_image;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::media::{MediaKind, MediaName};
+use crate::models::{DbRecording, DbRelease, User};
+use crate::password;
+use crate::permission::{requires, Permission};
+use crate::Response;
+
+use actix_web::error::BlockingError;
+use actix_web::web::{Data, Form, Path, Query};
+use actix_web::{delete, post, route, web, HttpRequest, HttpResponse};
+use chrono::NaiveDateTime;
+use diesel::dsl::{count_star, And, IsNotNull};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PoolError, PooledConnection};
+use diesel::result::Error as DieselError;
+use diesel::sql_types::{BigInt, Bool, Integer, Nullable, Text};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::Hash;
+
+diesel::define_sql_function! {
+    /// Lowercase a string, used to match names regardless of case
+    fn lower(x: Text) -> Text;
+}
+
+diesel::define_sql_function! {
+    /// Strip the accents from a string, used to match names regardless of accents
+    fn allegro_unaccent(x: Text) -> Text;
+}
+
+// version of the Subsonic API the responses follow
+const API_VERSION: &str = "1.16.1";
+
+// name the server gives itself to OpenSubsonic clients
+const SERVER_TYPE: &str = "allegro";
+
+// namespace of Subsonic XML responses
+const XML_NAMESPACE: &str = "http://subsonic.org/restapi";
+
+// articles ignored when sorting and indexing artists by name
+const IGNORED_ARTICLES: &str = "The El La Los Las Le Les";
+
+// number of results of each kind a search returns unless asked for more, and the most it returns
+const DEFAULT_SEARCH_COUNT: i64 = 20;
+const MAX_SEARCH_COUNT: i64 = 500;
+
+// number of random bytes in a generated Subsonic password
+const SUBSONIC_PASSWORD_LEN: usize = 12;
+
+// error codes defined by the Subsonic API
+const ERROR_GENERIC: i32 = 0;
+const ERROR_MISSING_PARAMETER: i32 = 10;
+const ERROR_WRONG_CREDENTIALS: i32 = 40;
+const ERROR_TOKEN_UNSUPPORTED: i32 = 41;
+const ERROR_CONFLICTING_AUTH: i32 = 43;
+const ERROR_INVALID_API_KEY: i32 = 44;
+const ERROR_NOT_AUTHORIZED: i32 = 50;
+const ERROR_NOT_FOUND: i32 = 70;
+
+/// A Subsonic password generated for the current user, the only time it is shown
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SubsonicPassword {
+    pub username: String,
+    pub password: String,
+}
+
+/// An error in the form Subsonic clients expect, with one of the codes of the Subsonic API
+#[derive(Debug, Serialize)]
+struct SubsonicError {
+    code: i32,
+    message: String,
+}
+
+impl SubsonicError {
+    fn new(code: i32, message: &str) -> Self {
+        SubsonicError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<ApiError> for SubsonicError {
+    fn from(err: ApiError) -> Self {
+        let code: i32 = match err {
+            ApiError::BadRequest(_) | ApiError::Validation(_) => ERROR_MISSING_PARAMETER,
+            ApiError::Unauthorized(_) | ApiError::TooManyRequests(_) => ERROR_WRONG_CREDENTIALS,
+            ApiError::Forbidden(_) => ERROR_NOT_AUTHORIZED,
+            ApiError::NotFound(_) => ERROR_NOT_FOUND,
+            _ => ERROR_GENERIC,
+        };
+        SubsonicError {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<BlockingError> for SubsonicError {
+    fn from(err: BlockingError) -> Self {
+        ApiError::from(err).into()
+    }
+}
+
+impl From<DieselError> for SubsonicError {
+    fn from(err: DieselError) -> Self {
+        ApiError::from(err).into()
+    }
+}
+
+impl From<PoolError> for SubsonicError {
+    fn from(err: PoolError) -> Self {
+        ApiError::from(err).into()
+    }
+}
+
+/// Format of a Subsonic response, XML unless the client asks for JSON
+#[derive(Clone, Copy)]
+enum Format {
+    Xml,
+    Json,
+}
+
+impl Format {
+    fn parse(format: Option<&str>) -> Self {
+        match format {
+            Some("json") => Format::Json,
+            _ => Format::Xml,
+        }
+    }
+}
+
+/// The query parameters of a Subsonic request
+struct Params(HashMap<String, String>);
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    fn require(&self, name: &str) -> Result<&str, SubsonicError> {
+        self.get(name).ok_or_else(|| {
+            SubsonicError::new(
+                ERROR_MISSING_PARAMETER,
+                &format!("Required parameter is missing: {}", name),
+            )
+        })
+    }
+
+    /// A whole number, if given
+    fn number(&self, name: &str) -> Result<Option<i64>, SubsonicError> {
+        self.get(name)
+            .map(|value| {
+                value.parse::<i64>().map_err(|_| {
+                    SubsonicError::new(
+                        ERROR_MISSING_PARAMETER,
+                        &format!("Parameter {} must be a number", name),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// A number of results to return
+    fn count(&self, name: &str, default: i64) -> Result<i64, SubsonicError> {
+        Ok(self
+            .number(name)?
+            .map_or(default, |count| count.clamp(0, MAX_SEARCH_COUNT)))
+    }
+
+    /// A number of results to skip when paging, which may go past any number of results
+    fn offset(&self, name: &str) -> Result<i64, SubsonicError> {
+        match self.number(name)? {
+            Some(offset) if offset < 0 => Err(SubsonicError::new(
+                ERROR_MISSING_PARAMETER,
+                &format!("Parameter {} must not be negative", name),
+            )),
+            offset => Ok(offset.unwrap_or(0)),
+        }
+    }
+
+    /// The id of an entity of one of the given kinds
+    fn id(&self, kinds: &[MediaKind], entity: &str) -> Result<MediaName, SubsonicError> {
+        MediaName::parse(self.require("id")?)
+            .filter(|name| kinds.contains(&name.kind))
+            .ok_or_else(|| ApiError::not_found(entity).into())
+    }
+}
+
+/// How a caller proves who they are without a session
+enum Proof {
+    Token { token: String, salt: String },
+    Password(String),
+}
+
+/// What a Subsonic method answers with
+enum Answer {
+    Fields(Map<String, Value>),
+    Audio(Option<String>),
+    Image(MediaName),
+}
+
+/// An artist, either a performer or a composer
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Artist {
+    id: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover_art: Option<String>,
+    album_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<Vec<Album>>,
+}
+
+/// The artists starting with one letter
+#[derive(Debug, Serialize)]
+struct Index {
+    name: String,
+    artist: Vec<Artist>,
+}
+
+/// All artists, grouped by the letter their names start with
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Indexes {
+    ignored_articles: &'static str,
+    index: Vec<Index>,
+}
+
+/// A release, presented as an album
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Album {
+    id: String,
+    name: String,
+    artist: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artist_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover_art: Option<String>,
+    song_count: i64,
+    duration: i64,
+    created: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    song: Option<Vec<Song>>,
+}
+
+/// A recording, presented as a song
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Song {
+    id: String,
+    parent: String,
+    is_dir: bool,
+    title: String,
+    album: String,
+    album_id: String,
+    artist: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artist_id: Option<String>,
+    track: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover_art: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suffix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<&'static str>,
+    #[serde(rename = "type")]
+    media_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_composer: Option<String>,
+}
+
+/// The results of a search
+#[derive(Debug, Serialize)]
+struct SearchResult {
+    artist: Vec<Artist>,
+    album: Vec<Album>,
+    song: Vec<Song>,
+}
+
+// a performer credited on a release or recording, by id and name
+type Credit = (i32, String);
+
+// recordings with an audio file that can be streamed
+type Playable = And<
+    crate::schema::recordings::dsl::available,
+    IsNotNull<crate::schema::recordings::dsl::file_path>,
+>;
+
+/// Filter for the recordings that can be streamed, the only ones shown as songs
+fn playable() -> Playable {
+    use crate::schema::recordings;
+
+    recordings::dsl::available.and(recordings::dsl::file_path.is_not_null())
+}
+
+/// The Subsonic id of a catalog entry, which is also the name of its media files
+fn media_id(kind: MediaKind, id: i32) -> String {
+    MediaName { kind, id }.to_string()
+}
+
+/// Format a time the way Subsonic clients parse it
+fn timestamp(time: NaiveDateTime) -> String {
+    format!("{}Z", time.format("%Y-%m-%dT%H:%M:%S%.3f"))
+}
+
+/// Join the names of several artists into one
+fn join_names<'a>(names: impl Iterator<Item = &'a String>) -> String {
+    names.map(String::as_str).collect::<Vec<&str>>().join(", ")
+}
+
+/// Group rows by their first column, keeping their order
+fn group<K: Hash + Eq, V>(rows: Vec<(K, V)>) -> HashMap<K, Vec<V>> {
+    let mut groups: HashMap<K, Vec<V>> = HashMap::new();
+    for (key, value) in rows {
+        groups.entry(key).or_default().push(value);
+    }
+    groups
+}
+
+/// The content type of an audio file from its extension
+fn content_type(suffix: &str) -> Option<&'static str> {
+    match suffix {
+        "flac" => Some("audio/flac"),
+        "mp3" => Some("audio/mpeg"),
+        "ogg" | "oga" | "opus" => Some("audio/ogg"),
+        "m4a" | "mp4" => Some("audio/mp4"),
+        "wav" => Some("audio/wav"),
+        _ => None,
+    }
+}
+
+/// The name an artist is sorted and indexed by, without a leading article
+fn sort_name(name: &str) -> &str {
+    IGNORED_ARTICLES
+        .split_whitespace()
+        .find_map(|article| {
+            let (start, rest) = name.split_at_checked(article.len())?;
+            match start.eq_ignore_ascii_case(article) {
+                true => rest.strip_prefix(' '),
+                false => None,
+            }
+        })
+        .unwrap_or(name)
+        .trim_start()
+}
+
+/// Escape text for use in an XML attribute or element
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The text of a JSON value that is neither an object nor an array
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+/// Write a JSON value as XML the way Subsonic lays out its responses: fields holding plain
+/// values become attributes, objects become child elements and arrays repeat their element
+fn write_xml(xml: &mut String, name: &str, value: &Value) {
+    match value {
+        Value::Null => {}
+        Value::Array(items) => {
+            for item in items {
+                write_xml(xml, name, item);
+            }
+        }
+        Value::Object(fields) => {
+            xml.push_str(&format!("<{}", name));
+            for (key, field) in fields {
+                if let Some(text) = scalar_text(field) {
+                    xml.push_str(&format!(" {}=\"{}\"", key, escape_xml(&text)));
+                }
+            }
+            let children: Vec<(&String, &Value)> = fields
+                .iter()
+                .filter(|(_, field)| field.is_object() || field.is_array())
+                .collect();
+            if children.is_empty() {
+                xml.push_str("/>");
+                return;
+            }
+            xml.push('>');
+            for (key, child) in children {
+                write_xml(xml, key, child);
+            }
+            xml.push_str(&format!("</{}>", name));
+        }
+        scalar => {
+            let text: String = scalar_text(scalar).unwrap_or_default();
+            xml.push_str(&format!("<{0}>{1}</{0}>", name, escape_xml(&text)));
+        }
+    }
+}
+
+/// Wrap the fields of an answer, or an error, in a Subsonic response
+fn respond(format: Format, answer: Result<Map<String, Value>, SubsonicError>) -> HttpResponse {
+    let mut response: Map<String, Value> = Map::new();
+    response.insert("version".to_string(), API_VERSION.into());
+    response.insert("type".to_string(), SERVER_TYPE.into());
+    response.insert(
+        "serverVersion".to_string(),
+        env!("CARGO_PKG_VERSION").into(),
+    );
+    response.insert("openSubsonic".to_string(), true.into());
+    match answer {
+        Ok(fields) => {
+            response.insert("status".to_string(), "ok".into());
+            response.extend(fields);
+        }
+        Err(err) => {
+            response.insert("status".to_string(), "failed".into());
+            response.insert("error".to_string(), serde_json::json!(err));
+        }
+    }
+
+    // clients expect errors with a successful status, and read the status from the body
+    match format {
+        Format::Json => {
+            HttpResponse::Ok().json(serde_json::json!({ "subsonic-response": response }))
+        }
+        Format::Xml => {
+            response.insert("xmlns".to_string(), XML_NAMESPACE.into());
+            let mut xml: String = r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string();
+            write_xml(&mut xml, "subsonic-response", &Value::Object(response));
+            HttpResponse::Ok()
+                .content_type("text/xml; charset=utf-8")
+                .body(xml)
+        }
+    }
+}
+
+/// Answer with a single field holding a value
+fn answer<T: Serialize>(name: &str, value: T) -> Result<Answer, SubsonicError> {
+    let value: Value = serde_json::to_value(value).map_err(|_| ApiError::Internal)?;
+    let mut fields: Map<String, Value> = Map::new();
+    fields.insert(name.to_string(), value);
+    Ok(Answer::Fields(fields))
+}
+
+/// Get the decrypted Subsonic password of a user, if they have one
+fn db_subsonicpassword(
+    user_id: i32,
+    key: &[u8; 32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Option<String>, ApiError> {
+    use crate::schema::subsonic_passwords;
+
+    let stored: Option<String> = subsonic_passwords::dsl::subsonic_passwords
+        .filter(subsonic_passwords::dsl::user_id.eq(user_id))
+        .select(subsonic_passwords::dsl::encrypted_password)
+        .first::<String>(conn)
+        .optional()?;
+    Ok(stored.and_then(|stored| password::decrypt(key, &stored)))
+}
+
+/// Authenticate a Subsonic request by API key, by token and salt or by password. Passwords
+/// are the Subsonic password or an API key, never the account password, which would cost a
+/// full hash on every request. Wrong passwords count towards the same backoff and lockout as
+/// failed logins.
+fn db_authenticate(
+    params: &Params,
+    client: Client,
+    throttling: Throttling,
+    secret: Option<[u8; 32]>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Caller, SubsonicError> {
+    use crate::schema::users;
+
+    if let Some(key) = params.get("apiKey") {
+        if params.get("u").is_some() {
+            return Err(SubsonicError::new(
+                ERROR_CONFLICTING_AUTH,
+                "Multiple conflicting authentication mechanisms provided",
+            ));
+        }
+        let (api_key, user) = require_apikey(key, conn)
+            .map_err(|_| SubsonicError::new(ERROR_INVALID_API_KEY, "Invalid API key"))?;
+        return Ok(Caller::authenticated(Credential::ApiKey(api_key), user));
+    }
+
+    // work out the proof before touching the database, so malformed requests are not counted
+    let username: &str = params.require("u")?;
+    let proof: Proof = match (params.get("t"), params.get("s"), params.get("p")) {
+        (Some(token), Some(salt), _) => Proof::Token {
+            token: token.to_lowercase(),
+            salt: salt.to_string(),
+        },
+        (_, _, Some(given)) => match given.strip_prefix("enc:") {
+            Some(encoded) => hex::decode(encoded)
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .map(Proof::Password)
+                .ok_or_else(|| {
+                    SubsonicError::new(ERROR_WRONG_CREDENTIALS, "Wrong username or password")
+                })?,
+            None => Proof::Password(given.to_string()),
+        },
+        _ => {
+            return Err(SubsonicError::new(
+                ERROR_MISSING_PARAMETER,
+                "Required parameter is missing: either p, or t and s",
+            ))
+        }
+    };
+
+    // clients that only know how to send a password may send an API key as one
+    if let Proof::Password(given) = &proof {
+        if given.starts_with(API_KEY_PREFIX) {
+            return match require_apikey(given, conn) {
+                Ok((api_key, user)) if user.username == username => {
+                    Ok(Caller::authenticated(Credential::ApiKey(api_key), user))
+                }
+                _ => Err(SubsonicError::new(
+                    ERROR_WRONG_CREDENTIALS,
+                    "Wrong username or password",
+                )),
+            };
+        }
+    }
+    if secret.is_none() {
+        return Err(match proof {
+            Proof::Token { .. } => SubsonicError::new(
+                ERROR_TOKEN_UNSUPPORTED,
+                "Token authentication is not supported by this server, use an API key instead",
+            ),
+            Proof::Password(_) => SubsonicError::new(
+                ERROR_WRONG_CREDENTIALS,
+                "Subsonic passwords are not supported by this server, use an API key instead",
+            ),
+        });
+    }
+
+    db_reservelogin(username, &client, throttling, conn)?;
+    let user: Option<User> = users::dsl::users
+        .filter(users::dsl::username.eq(username))
+        .first::<User>(conn)
+        .optional()?;
+    // unknown usernames go through the same lookup and comparison, so they take as long to
+    // refuse as wrong passwords
+    let subsonic_password: Option<String> = match &secret {
+        Some(key) => db_subsonicpassword(user.as_ref().map_or(0, |user| user.id), key, conn)?,
+        None => None,
+    };
+
+    // both tokens and passwords are checked against the Subsonic password
+    let stored: &str = subsonic_password.as_deref().unwrap_or_default();
+    let matches: bool = match &proof {
+        Proof::Token { token, salt } => {
+            password::secrets_match(token, &password::md5_token(stored, salt))
+        }
+        Proof::Password(given) => {
+            password::secrets_match(&password::hash_token(given), &password::hash_token(stored))
+        }
+    };
+    let valid: bool = subsonic_password.is_some() && matches;
+    let user: User = match user {
+        Some(user) if valid => user,
+        _ => {
+            db_loginfailed(username, &client, conn)?;
+            return Err(SubsonicError::new(
+                ERROR_WRONG_CREDENTIALS,
+                "Wrong username or password",
+            ));
+        }
+    };
+    db_loginsucceeded(username, &client, throttling, conn)?;
+    if user.disabled_at.is_some() {
+        return Err(SubsonicError::new(
+            ERROR_NOT_AUTHORIZED,
+            "Account is disabled",
+        ));
+    }
+    Ok(Caller::authenticated(Credential::Password, user))
+}
+
+/// Get the ids of the releases each performer appears on, credited on the release itself or
+/// on one of its recordings
+fn db_performerreleases(
+    performer_ids: Option<&[i32]>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<HashMap<i32, BTreeSet<i32>>, ApiError> {
+    use crate::schema::{recording_performers, recordings, release_performers};
+
+    let mut credited = release_performers::dsl::release_performers
+        .select((
+            release_performers::dsl::performer_id,
+            release_performers::dsl::release_id,
+        ))
+        .into_boxed();
+    let mut recorded = recording_performers::dsl::recording_performers
+        .inner_join(recordings::dsl::recordings)
+        .select((
+            recording_performers::dsl::performer_id,
+            recordings::dsl::release_id,
+        ))
+        .into_boxed();
+    if let Some(performer_ids) = performer_ids {
+        credited = credited.filter(release_performers::dsl::performer_id.eq_any(performer_ids));
+        recorded = recorded.filter(recording_performers::dsl::performer_id.eq_any(performer_ids));
+    }
+
+    let mut rows: Vec<(i32, i32)> = credited.load::<(i32, i32)>(conn)?;
+    rows.extend(recorded.load::<(i32, i32)>(conn)?);
+    let mut releases: HashMap<i32, BTreeSet<i32>> = HashMap::new();
+    for (performer_id, release_id) in rows {
+        releases.entry(performer_id).or_default().insert(release_id);
+    }
+    Ok(releases)
+}
+
+/// Get the ids of the releases holding recordings of each composer's pieces
+fn db_composerreleases(
+    composer_ids: Option<&[i32]>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<HashMap<i32, BTreeSet<i32>>, ApiError> {
+    use crate::schema::{piece_composers, recordings};
+
+    let mut query = piece_composers::dsl::piece_composers
+        .inner_join(
+            recordings::dsl::recordings
+                .on(recordings::dsl::piece_id.eq(piece_composers::dsl::piece_id)),
+        )
+        .select((
+            piece_composers::dsl::composer_id,
+            recordings::dsl::release_id,
+        ))
+        .into_boxed();
+    if let Some(composer_ids) = composer_ids {
+        query = query.filter(piece_composers::dsl::composer_id.eq_any(composer_ids));
+    }
+
+    let mut releases: HashMap<i32, BTreeSet<i32>> = HashMap::new();
+    for (composer_id, release_id) in query.load::<(i32, i32)>(conn)? {
+        releases.entry(composer_id).or_default().insert(release_id);
+    }
+    Ok(releases)
+}
+
+/// A performer or composer as listed among the artists
+#[derive(QueryableByName)]
+struct ArtistRow {
+    #[diesel(sql_type = Bool)]
+    composer: bool,
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Text)]
+    name: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    image_path: Option<String>,
+}
+
+/// Get the performers and composers whose names match a pattern, or all of them, sorted by name
+/// and paged when given a limit
+fn db_artists(
+    pattern: Option<String>,
+    limit: Option<i64>,
+    offset: i64,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Artist>, ApiError> {
+    // names are sorted without their leading article, byte by byte like sort_name would
+    let articles: String = IGNORED_ARTICLES
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join("|");
+    let rows: Vec<ArtistRow> = diesel::sql_query(
+        "SELECT composer, id, name, image_path FROM (
+            SELECT false AS composer, id, name, image_path FROM performers
+            WHERE $1::text IS NULL
+                OR allegro_unaccent(lower(name)) LIKE allegro_unaccent(lower($1)) ESCAPE '\\'
+            UNION ALL
+            SELECT true AS composer, id, name, image_path FROM composers
+            WHERE $1::text IS NULL
+                OR allegro_unaccent(lower(name)) LIKE allegro_unaccent(lower($1)) ESCAPE '\\'
+        ) AS artists
+        ORDER BY lower(ltrim(regexp_replace(name, '^(' || $2 || ') ', '', 'i'))) COLLATE \"C\",
+            composer, id
+        LIMIT $3 OFFSET $4",
+    )
+    .bind::<Nullable<Text>, _>(pattern)
+    .bind::<Text, _>(articles)
+    .bind::<Nullable<BigInt>, _>(limit)
+    .bind::<BigInt, _>(offset)
+    .load::<ArtistRow>(conn)?;
+
+    // only count the releases of the artists on the page, unless all of them are listed
+    let ids = |composer: bool| -> Vec<i32> {
+        rows.iter()
+            .filter(|row| row.composer == composer)
+            .map(|row| row.id)
+            .collect()
+    };
+    let (performer_ids, composer_ids): (Vec<i32>, Vec<i32>) = (ids(false), ids(true));
+    let performer_releases = db_performerreleases(limit.map(|_| &performer_ids[..]), conn)?;
+    let composer_releases = db_composerreleases(limit.map(|_| &composer_ids[..]), conn)?;
+
+    let artists: Vec<Artist> = rows
+        .into_iter()
+        .map(|row| {
+            let (kind, releases) = match row.composer {
+                true => (MediaKind::Composer, &composer_releases),
+                false => (MediaKind::Performer, &performer_releases),
+            };
+            Artist {
+                id: media_id(kind, row.id),
+                name: row.name,
+                cover_art: row.image_path.map(|_| media_id(kind, row.id)),
+                album_count: releases.get(&row.id).map_or(0, BTreeSet::len),
+                album: None,
+            }
+        })
+        .collect();
+    Ok(artists)
+}
+
+/// Get the performers shown as the artist of each release: those credited on the release, or
+/// failing that those on its recordings
+fn db_releaseartists(
+    release_ids: &[i32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<HashMap<i32, Vec<Credit>>, ApiError> {
+    use crate::schema::{performers, recording_performers, recordings, release_performers};
+
+    let credited: Vec<(i32, i32, String)> = release_performers::dsl::release_performers
+        .inner_join(performers::dsl::performers)
+        .filter(release_performers::dsl::release_id.eq_any(release_ids))
+        .select((
+            release_performers::dsl::release_id,
+            performers::dsl::id,
+            performers::dsl::name,
+        ))
+        .order((release_performers::dsl::release_id, performers::dsl::name))
+        .load::<(i32, i32, String)>(conn)?;
+    let recorded: Vec<(i32, i32, String)> = recording_performers::dsl::recording_performers
+        .inner_join(recordings::dsl::recordings)
+        .inner_join(performers::dsl::performers)
+        .filter(recordings::dsl::release_id.eq_any(release_ids))
+        .select((
+            recordings::dsl::release_id,
+            performers::dsl::id,
+            performers::dsl::name,
+        ))
+        .distinct()
+        .order((
+            recordings::dsl::release_id,
+            performers::dsl::name,
+            performers::dsl::id,
+        ))
+        .load::<(i32, i32, String)>(conn)?;
+
+    let split = |rows: Vec<(i32, i32, String)>| {
+        rows.into_iter()
+            .map(|(release_id, performer_id, name)| (release_id, (performer_id, name)))
+            .collect::<Vec<(i32, Credit)>>()
+    };
+    let mut artists: HashMap<i32, Vec<Credit>> = group(split(credited));
+    for (release_id, credits) in group(split(recorded)) {
+        artists.entry(release_id).or_insert(credits);
+    }
+    Ok(artists)
+}
+
+/// Present releases as albums, without their songs
+fn db_albums(
+    releases: Vec<DbRelease>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Album>, ApiError> {
+    use crate::schema::recordings;
+
+    let release_ids: Vec<i32> = releases.iter().map(|release| release.id).collect();
+    let artists: HashMap<i32, Vec<Credit>> = db_releaseartists(&release_ids, conn)?;
+    let song_counts: HashMap<i32, i64> = recordings::dsl::recordings
+        .filter(recordings::dsl::release_id.eq_any(&release_ids))
+        .filter(playable())
+        .group_by(recordings::dsl::release_id)
+        .select((recordings::dsl::release_id, count_star()))
+        .load::<(i32, i64)>(conn)?
+        .into_iter()
+        .collect();
+
+    let albums: Vec<Album> = releases
+        .into_iter()
+        .map(|release| {
+            let credits: &[Credit] = artists.get(&release.id).map_or(&[], Vec::as_slice);
+            Album {
+                id: media_id(MediaKind::Release, release.id),
+                name: release.name,
+                artist: join_names(credits.iter().map(|(_, name)| name)),
+                artist_id: credits
+                    .first()
+                    .map(|(id, _)| media_id(MediaKind::Performer, *id)),
+                cover_art: release
+                    .image_path
+                    .map(|_| media_id(MediaKind::Release, release.id)),
+                song_count: song_counts.get(&release.id).copied().unwrap_or(0),
+                duration: 0,
+                created: timestamp(release.created_at),
+                song: None,
+            }
+        })
+        .collect();
+    Ok(albums)
+}
+
+/// Present recordings as songs, taking their album and artists from their release
+fn db_songs(
+    recordings: Vec<DbRecording>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<Song>, ApiError> {
+    use crate::schema::{composers, performers, piece_composers, recording_performers, releases};
+
+    let recording_ids: Vec<i32> = recordings.iter().map(|recording| recording.id).collect();
+    let piece_ids: Vec<i32> = recordings
+        .iter()
+        .map(|recording| recording.piece_id)
+        .collect();
+    let release_ids: Vec<i32> = recordings
+        .iter()
+        .map(|recording| recording.release_id)
+        .collect::<BTreeSet<i32>>()
+        .into_iter()
+        .collect();
+
+    let release_rows: HashMap<i32, DbRelease> = releases::dsl::releases
+        .filter(releases::dsl::id.eq_any(&release_ids))
+        .load::<DbRelease>(conn)?
+        .into_iter()
+        .map(|release| (release.id, release))
+        .collect();
+    let release_artists: HashMap<i32, Vec<Credit>> = db_releaseartists(&release_ids, conn)?;
+    let performer_rows: Vec<(i32, Credit)> = recording_performers::dsl::recording_performers
+        .inner_join(performers::dsl::performers)
+        .filter(recording_performers::dsl::recording_id.eq_any(&recording_ids))
+        .select((
+            recording_performers::dsl::recording_id,
+            (performers::dsl::id, performers::dsl::name),
+        ))
+        .order((
+            recording_performers::dsl::recording_id,
+            performers::dsl::name,
+        ))
+        .load::<(i32, Credit)>(conn)?;
+    let recording_artists: HashMap<i32, Vec<Credit>> = group(performer_rows);
+    let composer_rows: Vec<(i32, String)> = piece_composers::dsl::piece_composers
+        .inner_join(composers::dsl::composers)
+        .filter(piece_composers::dsl::piece_id.eq_any(&piece_ids))
+        .select((piece_composers::dsl::piece_id, composers::dsl::name))
+        .order((piece_composers::dsl::piece_id, composers::dsl::name))
+        .load::<(i32, String)>(conn)?;
+    let piece_composers: HashMap<i32, Vec<String>> = group(composer_rows);
+
+    let songs: Vec<Song> = recordings
+        .into_iter()
+        .filter_map(|recording| {
+            let release: &DbRelease = release_rows.get(&recording.release_id)?;
+            let credits: &[Credit] = recording_artists
+                .get(&recording.id)
+                .or_else(|| release_artists.get(&recording.release_id))
+                .map_or(&[], Vec::as_slice);
+            let suffix: Option<String> = recording
+                .file_path
+                .as_deref()
+                .and_then(|file_path| std::path::Path::new(file_path).extension())
+                .map(|extension| extension.to_string_lossy().to_lowercase());
+            Some(Song {
+                id: media_id(MediaKind::Recording, recording.id),
+                parent: media_id(MediaKind::Release, release.id),
+                is_dir: false,
+                title: recording.piece_name,
+                album: release.name.clone(),
+                album_id: media_id(MediaKind::Release, release.id),
+                artist: join_names(credits.iter().map(|(_, name)| name)),
+                artist_id: credits
+                    .first()
+                    .map(|(id, _)| media_id(MediaKind::Performer, *id)),
+                track: recording.track_number,
+                cover_art: release
+                    .image_path
+                    .as_ref()
+                    .map(|_| media_id(MediaKind::Release, release.id)),
+                size: recording.file_size,
+                content_type: suffix.as_deref().and_then(content_type),
+                suffix,
+                media_type: "music",
+                display_composer: piece_composers
+                    .get(&recording.piece_id)
+                    .map(|names| join_names(names.iter())),
+            })
+        })
+        .collect();
+    Ok(songs)
+}
+
+/// Get every artist, indexed by the first letter of their name
+fn db_getartists(
+    caller: &Caller,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Answer, SubsonicError> {
+    require_permission(caller, Permission::CatalogRead, conn)?;
+
+    // artists are already sorted, so each letter keeps them in order
+    let mut letters: BTreeMap<String, Vec<Artist>> = BTreeMap::new();
+    for artist in db_artists(None, None, 0, conn)? {
+        let letter: String = sort_name(&artist.name)
+            .chars()
+            .next()
+            .filter(|first| first.is_alphabetic())
+            .map_or("#".to_string(), |first| first.to_uppercase().collect());
+        letters.entry(letter).or_default().push(artist);
+    }
+    let indexes = Indexes {
+        ignored_articles: IGNORED_ARTICLES,
+        index: letters
+            .into_iter()
+            .map(|(name, artist)| Index { name, artist })
+            .collect(),
+    };
+    answer("artists", indexes)
+}
+
+/// Get a performer or composer along with the albums they appear on
+fn db_getartist(
+    caller: &Caller,
+    params: &Params,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Answer, SubsonicError> {
+    use crate::schema::{composers, performers, releases};
+
+    require_permission(caller, Permission::CatalogRead, conn)?;
+    let artist_id: MediaName = params.id(&[MediaKind::Performer, MediaKind::Composer], "Artist")?;
+    let (name, image_path, release_ids) = match artist_id.kind {
+        MediaKind::Performer => {
+            let (name, image_path) = performers::dsl::performers
+                .filter(performers::dsl::id.eq(artist_id.id))
+                .select((performers::dsl::name, performers::dsl::image_path))
+                .first::<(String, Option<String>)>(conn)?;
+            let releases = db_performerreleases(Some(&[artist_id.id]), conn)?;
+            (name, image_path, releases.into_values().next())
+        }
+        _ => {
+            let (name, image_path) = composers::dsl::composers
+                .filter(composers::dsl::id.eq(artist_id.id))
+                .select((composers::dsl::name, composers::dsl::image_path))
+                .first::<(String, Option<String>)>(conn)?;
+            let releases = db_composerreleases(Some(&[artist_id.id]), conn)?;
+            (name, image_path, releases.into_values().next())
+        }
+    };
+
+    let release_ids: Vec<i32> = release_ids.unwrap_or_default().into_iter().collect();
+    let release_rows: Vec<DbRelease> = releases::dsl::releases
+        .filter(releases::dsl::id.eq_any(&release_ids))
+        .order((releases::dsl::name, releases::dsl::id))
+        .load::<DbRelease>(conn)?;
+    let albums: Vec<Album> = db_albums(release_rows, conn)?;
+    let artist = Artist {
+        id: artist_id.to_string(),
+        name,
+        cover_art: image_path.map(|_| artist_id.to_string()),
+        album_count: albums.len(),
+        album: Some(albums),
+    };
+    answer("artist", artist)
+}
+
+/// Get an album along with its songs in track order
+fn db_getalbum(
+    caller: &Caller,
+    params: &Params,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Answer, SubsonicError> {
+    use crate::schema::{recordings, releases};
+
+    require_permission(caller, Permission::CatalogRead, conn)?;
+    let release_id: i32 = params.id(&[MediaKind::Release], "Album")?.id;
+    let release: DbRelease = releases::dsl::releases
+        .filter(releases::dsl::id.eq(release_id))
+        .first::<DbRelease>(conn)?;
+    let recording_rows: Vec<DbRecording> = recordings::dsl::recordings
+        .filter(recordings::dsl::release_id.eq(release_id))
+        .filter(playable())
+        .order((recordings::dsl::track_number, recordings::dsl::id))
+        .load::<DbRecording>(conn)?;
+
+    let songs: Vec<Song> = db_songs(recording_rows, conn)?;
+    let mut album: Album = db_albums(vec![release], conn)?
+        .pop()
+        .ok_or(ApiError::Internal)?;
+    album.song = Some(songs);
+    answer("album", album)
+}
+
+/// Get a single song
+fn db_getsong(
+    caller: &Caller,
+    params: &Params,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Answer, SubsonicError> {
+    use crate::schema::recordings;
+
+    require_permission(caller, Permission::CatalogRead, conn)?;
+    let recording_id: i32 = params.id(&[MediaKind::Recording], "Song")?.id;
+    let recording: DbRecording = recordings::dsl::recordings
+        .filter(recordings::dsl::id.eq(recording_id))
+        .filter(playable())
+        .first::<DbRecording>(conn)?;
+    let song: Song = db_songs(vec![recording], conn)?
+        .pop()
+        .ok_or(ApiError::Internal)?;
+    answer("song", song)
+}
+
+/// Search artists, albums and songs by name, each paged separately. An empty query, which
+/// clients send to sync the whole library, matches everything.
+fn db_search(
+    caller: &Caller,
+    params: &Params,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Answer, SubsonicError> {
+    use crate::schema::{recordings, releases};
+
+    require_permission(caller, Permission::CatalogSearch, conn)?;
+    let query: &str = params.require("query")?.trim().trim_matches('"').trim();
+    let pattern: Option<String> = match query.is_empty() {
+        true => None,
+        false => Some(to_pattern(query)),
+    };
+
+    let artists: Vec<Artist> = db_artists(
+        pattern.clone(),
+        Some(params.count("artistCount", DEFAULT_SEARCH_COUNT)?),
+        params.offset("artistOffset")?,
+        conn,
+    )?;
+
+    let mut release_query = releases::dsl::releases.into_boxed();
+    let mut recording_query = recordings::dsl::recordings.filter(playable()).into_boxed();
+    if let Some(pattern) = pattern {
+        release_query = release_query.filter(
+            allegro_unaccent(lower(releases::dsl::name))
+                .like(allegro_unaccent(lower(pattern.clone())))
+                .escape('\\'),
+        );
+        recording_query = recording_query.filter(
+            allegro_unaccent(lower(recordings::dsl::piece_name))
+                .like(allegro_unaccent(lower(pattern)))
+                .escape('\\'),
+        );
+    }
+    let release_rows: Vec<DbRelease> = release_query
+        .order((releases::dsl::name, releases::dsl::id))
+        .limit(params.count("albumCount", DEFAULT_SEARCH_COUNT)?)
+        .offset(params.offset("albumOffset")?)
+        .load::<DbRelease>(conn)?;
+    let recording_rows: Vec<DbRecording> = recording_query
+        .order((recordings::dsl::piece_name, recordings::dsl::id))
+        .limit(params.count("songCount", DEFAULT_SEARCH_COUNT)?)
+        .offset(params.offset("songOffset")?)
+        .load::<DbRecording>(conn)?;
+
+    let result = SearchResult {
+        artist: artists,
+        album: db_albums(release_rows, conn)?,
+        song: db_songs(recording_rows, conn)?,
+    };
+    answer("searchResult3", result)
+}
+
+/// Authenticate a Subsonic request and answer its method from the database
+fn db_subsonic(
+    method: &str,
+    params: Params,
+    client: Client,
+    throttling: Throttling,
+    secret: Option<[u8; 32]>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Answer, SubsonicError> {
+    let caller: Caller = db_authenticate(&params, client, throttling, secret, conn)?;
+    match method {
+        "ping" => Ok(Answer::Fields(Map::new())),
+        "getLicense" => answer("license", serde_json::json!({ "valid": true })),
+        "getOpenSubsonicExtensions" => answer(
+            "openSubsonicExtensions",
+            serde_json::json!([{ "name": "apiKeyAuthentication", "versions": [1] }]),
+        ),
+        "getArtists" => db_getartists(&caller, conn),
+        "getArtist" => db_getartist(&caller, &params, conn),
+        "getAlbum" => db_getalbum(&caller, &params, conn),
+        "getSong" => db_getsong(&caller, &params, conn),
+        "search3" => db_search(&caller, &params, conn),
+        "stream" | "download" => {
+            require_permission(&caller, Permission::MediaStream, conn)?;
+            let recording_id: i32 = params.id(&[MediaKind::Recording], "Song")?.id;
+            Ok(Answer::Audio(db_getfilepath(recording_id, conn)))
+        }
+        "getCoverArt" => {
+            require_permission(&caller, Permission::CatalogRead, conn)?;
+            let media_name: MediaName = MediaName::parse(params.require("id")?)
+                .filter(|media_name| !media_name.kind.is_audio())
+                .ok_or_else(|| ApiError::not_found("Cover art"))?;
+            Ok(Answer::Image(media_name))
+        }
+        _ => Err(SubsonicError::new(
+            ERROR_NOT_FOUND,
+            &format!("Unknown method {}", method),
+        )),
+    }
+}
+
+/// Answer a Subsonic request, streaming files once the database has been consulted
+async fn handle(
+    req: &HttpRequest,
+    method: String,
+    params: Params,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, SubsonicError> {
+    let format: Format = Format::parse(params.get("f"));
+    let mut conn = pool.get()?;
+    let client: Client = Client::from_request(req);
+    let throttling: Throttling = Throttling::from_config(&config);
+    let secret: Option<[u8; 32]> = config.subsonic_secret;
+    let method: String = method.trim_end_matches(".view").to_string();
+    let answer: Answer =
+        web::block(move || db_subsonic(&method, params, client, throttling, secret, &mut conn))
+            .await??;
+
+    match answer {
+        Answer::Fields(fields) => Ok(respond(format, Ok(fields))),
+        Answer::Audio(file_path) => Ok(stream_file(req, &config.media_root, file_path).await?),
+        Answer::Image(media_name) => Ok(serve_image(&config.media_root, media_name).await?),
+    }
+}
+
+/// Answer a request to the Subsonic API, so Subsonic and OpenSubsonic clients can browse and
+/// stream the library
+#[route("/rest/{method}", method = "GET", method = "POST")]
+pub async fn subsonic(
+    req: HttpRequest,
+    method: Path<String>,
+    params: Query<HashMap<String, String>>,
+    form: Option<Form<HashMap<String, String>>>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // clients may post parameters as a form instead of putting them in the query
+    let mut params: HashMap<String, String> = params.into_inner();
+    if let Some(form) = form {
+        params.extend(form.into_inner());
+    }
+    let params: Params = Params(params);
+    let format: Format = Format::parse(params.get("f"));
+
+    // failures are reported in the body of a successful response, as clients expect
+    match handle(&req, method.into_inner(), params, pool, config).await {
+        Ok(response) => Ok(response),
+        Err(err) => Ok(respond(format, Err(err))),
+    }
+}
+
+/// Generate a Subsonic password for the caller, replacing any they had
+fn db_createsubsonicpassword(
+    caller: Caller,
+    secret: Option<[u8; 32]>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<SubsonicPassword, ApiError> {
+    use crate::schema::subsonic_passwords;
+
+    let (_, user) = caller.require_session()?;
+    let key: [u8; 32] = secret.ok_or_else(|| {
+        ApiError::Conflict("Subsonic passwords are not enabled on this server".to_string())
+    })?;
+    let subsonic_password: String = password::generate_secret(SUBSONIC_PASSWORD_LEN)?;
+    let encrypted_password: String = password::encrypt(&key, &subsonic_password)?;
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    diesel::insert_into(subsonic_passwords::dsl::subsonic_passwords)
+        .values((
+            subsonic_passwords::dsl::user_id.eq(user.id),
+            subsonic_passwords::dsl::encrypted_password.eq(&encrypted_password),
+            subsonic_passwords::dsl::created_at.eq(now),
+        ))
+        .on_conflict(subsonic_passwords::dsl::user_id)
+        .do_update()
+        .set((
+            subsonic_passwords::dsl::encrypted_password.eq(&encrypted_password),
+            subsonic_passwords::dsl::created_at.eq(now),
+        ))
+        .execute(conn)?;
+    Ok(SubsonicPassword {
+        username: user.username.clone(),
+        password: subsonic_password,
+    })
+}
+
+/// Remove the Subsonic password of the caller
+fn db_deletesubsonicpassword(
+    caller: Caller,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::subsonic_passwords;
+
+    let (_, user) = caller.require_session()?;
+    let deleted: usize = diesel::delete(
+        subsonic_passwords::dsl::subsonic_passwords
+            .filter(subsonic_passwords::dsl::user_id.eq(user.id)),
+    )
+    .execute(conn)?;
+    match deleted {
+        0 => Err(ApiError::not_found("Subsonic password")),
+        _ => Ok(()),
+    }
+}
+
+/// Generate a password for Subsonic clients that use token authentication
+#[post("/auth/subsonic")]
+pub async fn createsubsonicpassword(
+    caller: Authorized<requires::Anyone>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // store the password in the database
+    let mut conn = pool.get()?;
+    let secret: Option<[u8; 32]> = config.subsonic_secret;
+    let subsonic_password =
+        web::block(move || db_createsubsonicpassword(caller.into_inner(), secret, &mut conn))
+            .await??;
+
+    // return the password, which cannot be shown again
+    Ok(HttpResponse::Created().json(Response::success(subsonic_password)))
+}
+
+/// Remove the Subsonic password of the current user
+#[delete("/auth/subsonic")]
+pub async fn deletesubsonicpassword(
+    caller: Authorized<requires::Anyone>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the password from the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deletesubsonicpassword(caller.into_inner(), &mut conn)).await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+
+    const SECRET: [u8; 32] = [7; 32];
+
+    /// Parameters as a client would send them
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        Params(
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    /// Add a user with a Subsonic password
+    fn user(
+        username: &str,
+        subsonic_password: &str,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> User {
+        use crate::schema::{subsonic_passwords, users};
+
+        let user: User = diesel::insert_into(users::table)
+            .values((
+                users::username.eq(username),
+                users::password_hash.eq("unused"),
+            ))
+            .get_result::<User>(conn)
+            .unwrap();
+        diesel::insert_into(subsonic_passwords::table)
+            .values((
+                subsonic_passwords::user_id.eq(user.id),
+                subsonic_passwords::encrypted_password.eq(password::encrypt(
+                    &SECRET,
+                    subsonic_password,
+                )
+                .unwrap()),
+            ))
+            .execute(conn)
+            .unwrap();
+        user
+    }
+
+    /// Authenticate a request from a fixed address
+    fn authenticate(
+        params: &Params,
+        secret: Option<[u8; 32]>,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Caller, SubsonicError> {
+        let client = Client {
+            user_agent: Some("DSub".to_string()),
+            ip_address: Some("198.51.100.4".to_string()),
+        };
+        let throttling = Throttling {
+            threshold: 10,
+            lockout: 60,
+        };
+        db_authenticate(params, client, throttling, secret, conn)
+    }
+
+    /// The code of a failed authentication
+    fn code(result: Result<Caller, SubsonicError>) -> i32 {
+        result.err().expect("authentication should fail").code
+    }
+
+    /// The status, content type and body of a response
+    async fn body(res: HttpResponse) -> (StatusCode, String, String) {
+        let status: StatusCode = res.status();
+        let content_type: String = res
+            .headers()
+            .get(actix_web::http::header::CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let body = to_bytes(res.into_body()).await.unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(
+            escape_xml(r#"Tom & Jerry's <"Overture">"#),
+            "Tom &amp; Jerry&apos;s &lt;&quot;Overture&quot;&gt;"
+        );
+        assert_eq!(escape_xml("Dvořák"), "Dvořák");
+    }
+
+    #[test]
+    fn writes_fields_as_attributes_and_children() {
+        let value: Value = serde_json::json!({
+            "name": "Brahms & Sons",
+            "count": 2,
+            "starred": null,
+            "artist": { "id": "performer-1", "starred": true },
+            "genre": ["Romantic", "Chamber"],
+            "song": [{ "id": "recording-1" }, { "id": "recording-2" }],
+        });
+        let mut xml: String = String::new();
+        write_xml(&mut xml, "album", &value);
+        assert_eq!(
+            xml,
+            concat!(
+                r#"<album count="2" name="Brahms &amp; Sons">"#,
+                r#"<artist id="performer-1" starred="true"/>"#,
+                r#"<genre>Romantic</genre><genre>Chamber</genre>"#,
+                r#"<song id="recording-1"/><song id="recording-2"/>"#,
+                r#"</album>"#
+            )
+        );
+
+        let mut xml: String = String::new();
+        write_xml(&mut xml, "album", &serde_json::json!({}));
+        assert_eq!(xml, "<album/>");
+    }
+
+    #[test]
+    fn sorts_names_without_their_article() {
+        assert_eq!(sort_name("The Beatles"), "Beatles");
+        assert_eq!(sort_name("los Lobos"), "Lobos");
+        assert_eq!(sort_name("Les  Arts Florissants"), "Arts Florissants");
+        assert_eq!(sort_name("Theodorakis"), "Theodorakis");
+        assert_eq!(sort_name("The"), "The");
+        assert_eq!(sort_name("Ólafur Arnalds"), "Ólafur Arnalds");
+    }
+
+    #[test]
+    fn reads_parameters() {
+        let params: Params = params(&[
+            ("query", "bach"),
+            ("songCount", "900"),
+            ("artistCount", "-3"),
+            ("albumOffset", "-1"),
+            ("songOffset", "many"),
+            ("id", "release-12"),
+        ]);
+        assert_eq!(params.require("query").unwrap(), "bach");
+        assert_eq!(
+            params.require("u").unwrap_err().code,
+            ERROR_MISSING_PARAMETER
+        );
+        assert_eq!(params.count("songCount", 20).unwrap(), MAX_SEARCH_COUNT);
+        assert_eq!(params.count("artistCount", 20).unwrap(), 0);
+        assert_eq!(params.count("albumCount", 20).unwrap(), 20);
+        assert_eq!(params.offset("artistOffset").unwrap(), 0);
+        assert_eq!(
+            params.offset("albumOffset").unwrap_err().code,
+            ERROR_MISSING_PARAMETER
+        );
+        assert_eq!(
+            params.offset("songOffset").unwrap_err().code,
+            ERROR_MISSING_PARAMETER
+        );
+        assert_eq!(params.id(&[MediaKind::Release], "Album").unwrap().id, 12);
+        assert_eq!(
+            params.id(&[MediaKind::Recording], "Song").unwrap_err().code,
+            ERROR_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn maps_api_errors_to_subsonic_codes() {
+        let code = |err: ApiError| SubsonicError::from(err).code;
+        assert_eq!(
+            code(ApiError::Validation(String::new())),
+            ERROR_MISSING_PARAMETER
+        );
+        assert_eq!(code(ApiError::unauthorized()), ERROR_WRONG_CREDENTIALS);
+        assert_eq!(code(ApiError::TooManyRequests(5)), ERROR_WRONG_CREDENTIALS);
+        assert_eq!(
+            code(ApiError::Forbidden(String::new())),
+            ERROR_NOT_AUTHORIZED
+        );
+        assert_eq!(code(ApiError::not_found("Song")), ERROR_NOT_FOUND);
+        assert_eq!(code(ApiError::Internal), ERROR_GENERIC);
+    }
+
+    #[actix_web::test]
+    async fn wraps_answers_in_a_json_envelope() {
+        let mut fields: Map<String, Value> = Map::new();
+        fields.insert("license".to_string(), serde_json::json!({ "valid": true }));
+        let (status, content_type, body) = body(respond(Format::Json, Ok(fields))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/json");
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let response: &Value = &body["subsonic-response"];
+        assert_eq!(response["status"], "ok");
+        assert_eq!(response["version"], API_VERSION);
+        assert_eq!(response["openSubsonic"], true);
+        assert_eq!(response["license"]["valid"], true);
+        assert!(response.get("xmlns").is_none());
+    }
+
+    #[actix_web::test]
+    async fn reports_errors_in_a_successful_xml_envelope() {
+        let err: SubsonicError = SubsonicError::new(ERROR_WRONG_CREDENTIALS, "Wrong <password>");
+        let (status, content_type, body) = body(respond(Format::Xml, Err(err))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/xml; charset=utf-8");
+        assert!(body.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><subsonic-response "#));
+        assert!(body.contains(r#" status="failed""#));
+        assert!(body.contains(&format!(r#" xmlns="{}""#, XML_NAMESPACE)));
+        assert!(body.ends_with(
+            r#"><error code="40" message="Wrong &lt;password&gt;"/></subsonic-response>"#
+        ));
+    }
+
+    #[test]
+    fn accepts_tokens_and_passwords() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user: User = user("clara", "sesame", &mut conn);
+        let caller_id =
+            |result: Result<Caller, SubsonicError>| result.ok().unwrap().user().unwrap().id;
+
+        // clients may send the token in upper case
+        let token: String = password::md5_token("sesame", "c19b2d").to_uppercase();
+        let token = params(&[("u", "clara"), ("t", &token), ("s", "c19b2d")]);
+        assert_eq!(
+            caller_id(authenticate(&token, Some(SECRET), &mut conn)),
+            user.id
+        );
+
+        let plain = params(&[("u", "clara"), ("p", "sesame")]);
+        assert_eq!(
+            caller_id(authenticate(&plain, Some(SECRET), &mut conn)),
+            user.id
+        );
+
+        let encoded: String = format!("enc:{}", hex::encode("sesame"));
+        let encoded = params(&[("u", "clara"), ("p", &encoded)]);
+        assert_eq!(
+            caller_id(authenticate(&encoded, Some(SECRET), &mut conn)),
+            user.id
+        );
+    }
+
+    #[test]
+    fn refuses_wrong_credentials() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        user("clara", "sesame", &mut conn);
+
+        let token: String = password::md5_token("sesame", "c19b2d");
+        let wrong_salt = params(&[("u", "clara"), ("t", &token), ("s", "c19b2e")]);
+        assert_eq!(
+            code(authenticate(&wrong_salt, Some(SECRET), &mut conn)),
+            ERROR_WRONG_CREDENTIALS
+        );
+        let wrong_password = params(&[("u", "clara"), ("p", "sesame!")]);
+        assert_eq!(
+            code(authenticate(&wrong_password, Some(SECRET), &mut conn)),
+            ERROR_WRONG_CREDENTIALS
+        );
+        let bad_encoding = params(&[("u", "clara"), ("p", "enc:zz")]);
+        assert_eq!(
+            code(authenticate(&bad_encoding, Some(SECRET), &mut conn)),
+            ERROR_WRONG_CREDENTIALS
+        );
+        let unknown = params(&[("u", "robert"), ("p", "sesame")]);
+        assert_eq!(
+            code(authenticate(&unknown, Some(SECRET), &mut conn)),
+            ERROR_WRONG_CREDENTIALS
+        );
+    }
+
+    #[test]
+    fn reports_why_authentication_failed() {
+        use crate::schema::users;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let user: User = user("clara", "sesame", &mut conn);
+        let token: String = password::md5_token("sesame", "c19b2d");
+        let token = params(&[("u", "clara"), ("t", &token), ("s", "c19b2d")]);
+
+        assert_eq!(
+            code(authenticate(
+                &params(&[("p", "sesame")]),
+                Some(SECRET),
+                &mut conn
+            )),
+            ERROR_MISSING_PARAMETER
+        );
+        assert_eq!(
+            code(authenticate(
+                &params(&[("u", "clara")]),
+                Some(SECRET),
+                &mut conn
+            )),
+            ERROR_MISSING_PARAMETER
+        );
+        assert_eq!(
+            code(authenticate(&token, None, &mut conn)),
+            ERROR_TOKEN_UNSUPPORTED
+        );
+        assert_eq!(
+            code(authenticate(
+                &params(&[("apiKey", "ak_x"), ("u", "clara")]),
+                Some(SECRET),
+                &mut conn
+            )),
+            ERROR_CONFLICTING_AUTH
+        );
+        assert_eq!(
+            code(authenticate(
+                &params(&[("apiKey", "ak_x")]),
+                Some(SECRET),
+                &mut conn
+            )),
+            ERROR_INVALID_API_KEY
+        );
+
+        diesel::update(users::table.find(user.id))
+            .set(users::disabled_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(&mut conn)
+            .unwrap();
+        assert_eq!(
+            code(authenticate(&token, Some(SECRET), &mut conn)),
+            ERROR_NOT_AUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use crate::api::auth::Authorized;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::media::{self, Fingerprint, MediaKind, MediaName};
+use crate::permission::requires;
+use crate::Response;
+
+use actix_multipart::{Field, Multipart};
+use actix_web::http::header;
+use actix_web::web::{Data, Path};
+use actix_web::{get, post, web, HttpResponse};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use futures_util::StreamExt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// number of leading bytes used to determine the type of an uploaded file
+const SNIFF_LEN: usize = 12;
+
+/// A multipart upload whose file has been staged in a temporary file under the media root. The
+/// temporary file is removed when the upload is dropped without having been stored.
+struct StagedUpload {
+    temp_path: PathBuf,
+    stored: bool,
+}
+
+impl Drop for StagedUpload {
+    fn drop(&mut self) {
+        if !self.stored {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Stream a multipart file field into a temporary file, enforcing the size limit and file type
+async fn stage_file(
+    field: &mut Field,
+    temp_path: PathBuf,
+    kind: MediaKind,
+    limit: u64,
+) -> Result<(), ApiError> {
+    // reject declared types that cannot match before reading anything
+    if let Some(mime) = field.content_type() {
+        let expected = if kind.is_audio() { "audio" } else { "image" };
+        if mime.type_().as_str() != expected && mime.essence_str() != "application/octet-stream" {
+            return Err(ApiError::UnsupportedMediaType);
+        }
+    }
+
+    // create the temporary file
+    let create_path = temp_path.clone();
+    let mut file: File = web::block(move || File::create(create_path))
+        .await
+        .map_err(|_| ApiError::Internal)?
+        .map_err(|_| ApiError::Internal)?;
+
+    // write each chunk to the file, keeping the first bytes to check the file type
+    let mut size: u64 = 0;
+    let mut header: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(|_| ApiError::BadRequest("Malformed upload".to_string()))?;
+        size += chunk.len() as u64;
+        if size > limit {
+            return Err(ApiError::PayloadTooLarge);
+        }
+        if header.len() < SNIFF_LEN {
+            let needed = (SNIFF_LEN - header.len()).min(chunk.len());
+            header.extend_from_slice(&chunk[..needed]);
+        }
+        file = web::block(move || file.write_all(&chunk).map(|_| file))
+            .await
+            .map_err(|_| ApiError::Internal)?
+            .map_err(|_| ApiError::Internal)?;
+    }
+
+    // make sure the contents are actually of the expected type
+    let sniffed = if kind.is_audio() {
+        media::sniff_audio(&header)
+    } else {
+        media::sniff_image(&header)
+    };
+    if size == 0 || sniffed.is_none() {
+        return Err(ApiError::UnsupportedMediaType);
+    }
+
+    // flush the file to disk before it can be renamed into place
+    web::block(move || file.sync_all())
+        .await
+        .map_err(|_| ApiError::Internal)?
+        .map_err(|_| ApiError::Internal)
+}
+
+/// Read the file field of a multipart upload, staging the file on disk
+async fn read_upload(
+    payload: &mut Multipart,
+    media_name: MediaName,
+    config: &Config,
+) -> Result<StagedUpload, ApiError> {
+    let limit: u64 = if media_name.kind.is_audio() {
+        config.max_audio_size
+    } else {
+        config.max_image_size
+    };
+
+    // stage the file next to its final location so that renaming it is atomic
+    let media_root: PathBuf = config.media_root.clone();
+    web::block(move || fs::create_dir_all(media_root))
+        .await
+        .map_err(|_| ApiError::Internal)?
+        .map_err(|_| ApiError::Internal)?;
+    let temp_name: String = format!(".{}.{}.part", media_name, Uuid::new_v4());
+    let upload = StagedUpload {
+        temp_path: config.media_root.join(temp_name),
+        stored: false,
+    };
+
+    let mut staged: bool = false;
+    while let Some(field) = payload.next().await {
+        let mut field =
+            field.map_err(|_| ApiError::BadRequest("Malformed multipart body".to_string()))?;
+        match field.name() {
+            Some("file") if !staged => {
+                let kind = media_name.kind;
+                stage_file(&mut field, upload.temp_path.clone(), kind, limit).await?;
+                staged = true;
+            }
+            _ => {
+                // drain fields that are not part of the upload
+                while let Some(chunk) = field.next().await {
+                    if chunk.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // the file is required
+    match staged {
+        true => Ok(upload),
+        false => Err(ApiError::BadRequest("Upload requires a file".to_string())),
+    }
+}
+
+/// Check whether the entity that owns a media file exists
+fn db_ownerexists(
+    media_name: MediaName,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<bool, ApiError> {
+    use crate::schema::{composers, performers, recordings, releases, songwriters};
+
+    let count: QueryResult<i64> = match media_name.kind {
+        MediaKind::Recording => recordings::dsl::recordings
+            .filter(recordings::dsl::id.eq(media_name.id))
+            .count()
+            .get_result(conn),
+        MediaKind::Release => releases::dsl::releases
+            .filter(releases::dsl::id.eq(media_name.id))
+            .count()
+            .get_result(conn),
+        MediaKind::Performer => performers::dsl::performers
+            .filter(performers::dsl::id.eq(media_name.id))
+            .count()
+            .get_result(conn),
+        MediaKind::Composer => composers::dsl::composers
+            .filter(composers::dsl::id.eq(media_name.id))
+            .count()
+            .get_result(conn),
+        MediaKind::Songwriter => songwriters::dsl::songwriters
+            .filter(songwriters::dsl::id.eq(media_name.id))
+            .count()
+            .get_result(conn),
+    };
+
+    Ok(count? > 0)
+}
+
+/// Point the owner of a media file at its name, recording the fingerprint of audio files
+fn db_setmediapath(
+    media_name: MediaName,
+    fingerprint: Option<Fingerprint>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<usize> {
+    use crate::schema::{composers, performers, recordings, releases, songwriters};
+
+    let path: String = media_name.to_string();
+    match media_name.kind {
+        MediaKind::Recording => diesel::update(recordings::dsl::recordings.find(media_name.id))
+            .set((
+                recordings::dsl::file_path.eq(path),
+                recordings::dsl::file_size.eq(fingerprint.as_ref().map(|f| f.stat.size)),
+                recordings::dsl::file_modified.eq(fingerprint.as_ref().map(|f| f.stat.modified)),
+                recordings::dsl::file_hash.eq(fingerprint.map(|f| f.hash)),
+                recordings::dsl::available.eq(true),
+            ))
+            .execute(conn),
+        MediaKind::Release => diesel::update(releases::dsl::releases.find(media_name.id))
+            .set(releases::dsl::image_path.eq(path))
+            .execute(conn),
+        MediaKind::Performer => diesel::update(performers::dsl::performers.find(media_name.id))
+            .set(performers::dsl::image_path.eq(path))
+            .execute(conn),
+        MediaKind::Composer => diesel::update(composers::dsl::composers.find(media_name.id))
+            .set(composers::dsl::image_path.eq(path))
+            .execute(conn),
+        MediaKind::Songwriter => diesel::update(songwriters::dsl::songwriters.find(media_name.id))
+            .set(songwriters::dsl::image_path.eq(path))
+            .execute(conn),
+    }
+}
+
+/// Move a staged upload into place and commit its path, only after the file is on disk
+fn db_commitupload(
+    mut upload: StagedUpload,
+    media_name: MediaName,
+    media_root: PathBuf,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<String, ApiError> {
+    // make sure the upload belongs to an existing entity
+    let no_owner = || {
+        ApiError::NotFound(format!(
+            "No {} found for this upload",
+            media_name.kind.prefix()
+        ))
+    };
+    if !db_ownerexists(media_name, conn)? {
+        return Err(no_owner());
+    }
+
+    // atomically replace any previous file with the staged one
+    let final_path: PathBuf = media_root.join(media_name.to_string());
+    if let Err(err) = fs::rename(&upload.temp_path, &final_path) {
+        log::error!("failed to store {}: {}", final_path.display(), err);
+        return Err(ApiError::Internal);
+    }
+    upload.stored = true;
+
+    // fingerprint audio files so later rescans can tell whether they changed
+    let fingerprint: Option<Fingerprint> = if media_name.kind.is_audio() {
+        Fingerprint::read(&final_path).ok()
+    } else {
+        None
+    };
+
+    // the file now exists, so it is safe to point the database at it, unless its owner was
+    // deleted in the meantime and nothing is left to point at it
+    if db_setmediapath(media_name, fingerprint, conn)? == 0 {
+        if let Err(err) = fs::remove_file(&final_path) {
+            log::error!("failed to remove {}: {}", final_path.display(), err);
+        }
+        return Err(no_owner());
+    }
+    Ok(media_name.to_string())
+}
+
+/// Upload the audio of a recording or the image of a release or artist, under its generated name
+#[post("/music/upload/{name}")]
+pub async fn uploadmedia(
+    _caller: Authorized<requires::MediaUpload>,
+    name: Path<String>,
+    mut payload: Multipart,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // determine what the upload is for from its generated name
+    let media_name: MediaName = MediaName::parse(&name)
+        .ok_or_else(|| ApiError::NotFound("Invalid upload name".to_string()))?;
+
+    // stage the uploaded file on disk
+    let upload: StagedUpload = read_upload(&mut payload, media_name, &config).await?;
+
+    // get the upload response from database
+    let mut conn = pool.get()?;
+    let media_root: PathBuf = config.media_root.clone();
+    let upload_response: String =
+        web::block(move || db_commitupload(upload, media_name, media_root, &mut conn)).await??;
+
+    // return the response on success
+    Ok(HttpResponse::Created().json(Response::success(upload_response)))
+}
+
+/// Read an uploaded image from the media root
+pub(crate) async fn serve_image(
+    media_root: &std::path::Path,
+    media_name: MediaName,
+) -> Result<HttpResponse, ApiError> {
+    let path: PathBuf = media_root.join(media_name.to_string());
+    let image: Vec<u8> = web::block(move || fs::read(path))
+        .await?
+        .map_err(|_| ApiError::not_found("Image"))?;
+
+    // images are replaced in place, so clients must revalidate them
+    let content_type: &str = media::sniff_image(&image).unwrap_or("application/octet-stream");
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(image))
+}
+
+/// Get an uploaded image of a release or artist by its generated name
+#[get("/music/image/{name}")]
+pub async fn getimage(
+    _caller: Authorized<requires::CatalogRead>,
+    name: Path<String>,
+    config: Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    // only images can be fetched here, audio is streamed separately
+    let media_name: MediaName = match MediaName::parse(&name) {
+        Some(media_name) if !media_name.kind.is_audio() => media_name,
+        _ => return Err(ApiError::not_found("Image")),
+    };
+
+    // read the image from the media root
+    serve_image(&config.media_root, media_name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+    use actix_web::error::PayloadError;
+    use actix_web::http::header::{HeaderMap, HeaderValue};
+    use actix_web::web::Bytes;
+    use futures_util::stream;
+
+    const BOUNDARY: &str = "allegro-boundary";
+
+    /// Configure uploads into an empty media root of their own, of at most the given size
+    fn config(limit: u64) -> Config {
+        let media_root: PathBuf = std::env::temp_dir().join(format!("allegro-{}", Uuid::new_v4()));
+        Config {
+            media_root,
+            max_audio_size: limit,
+            max_image_size: limit,
+            ..Config::from_env()
+        }
+    }
+
+    /// Build a multipart body holding a file of the given type and contents
+    fn multipart(content_type: &str, contents: &[u8]) -> Multipart {
+        let mut body: Vec<u8> = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\n\
+             Content-Type: {}\r\n\r\n",
+            BOUNDARY, content_type
+        )
+        .into_bytes();
+        body.extend_from_slice(contents);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&format!("multipart/form-data; boundary={}", BOUNDARY)).unwrap(),
+        );
+        let chunks = stream::iter([Ok::<Bytes, PayloadError>(Bytes::from(body))]);
+        Multipart::new(&headers, chunks)
+    }
+
+    /// Names of the files left in a media root
+    fn files(media_root: &std::path::Path) -> Vec<String> {
+        fs::read_dir(media_root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn refuses_files_over_the_size_limit() {
+        let config: Config = config(16);
+        let media_name = MediaName {
+            kind: MediaKind::Recording,
+            id: 1,
+        };
+        let mut payload: Multipart = multipart("audio/flac", &[b'f'; 64]);
+        let result = read_upload(&mut payload, media_name, &config).await;
+        assert!(matches!(result, Err(ApiError::PayloadTooLarge)));
+        assert!(files(&config.media_root).is_empty());
+        fs::remove_dir_all(&config.media_root).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn refuses_files_that_are_not_of_their_type() {
+        let config: Config = config(1024);
+        let media_name = MediaName {
+            kind: MediaKind::Recording,
+            id: 1,
+        };
+        let mut payload: Multipart = multipart("audio/flac", b"<html>not audio</html>");
+        let result = read_upload(&mut payload, media_name, &config).await;
+        assert!(matches!(result, Err(ApiError::UnsupportedMediaType)));
+        assert!(files(&config.media_root).is_empty());
+        fs::remove_dir_all(&config.media_root).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn stores_nothing_for_a_missing_owner() {
+        use crate::schema::performers;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        // the id of a performer that no longer exists
+        let id: i32 = diesel::insert_into(performers::table)
+            .values(performers::name.eq("Glenn Gould"))
+            .returning(performers::id)
+            .get_result(&mut conn)
+            .unwrap();
+        diesel::delete(performers::table.find(id))
+            .execute(&mut conn)
+            .unwrap();
+
+        let config: Config = config(1024);
+        let media_name = MediaName {
+            kind: MediaKind::Performer,
+            id,
+        };
+        let mut payload: Multipart = multipart("image/jpeg", &[0xFF, 0xD8, 0xFF, 0xE0, 0, 16]);
+        let upload: StagedUpload = read_upload(&mut payload, media_name, &config)
+            .await
+            .unwrap();
+        assert_eq!(files(&config.media_root).len(), 1);
+
+        let media_root: PathBuf = config.media_root.clone();
+        let result = db_commitupload(upload, media_name, media_root, &mut conn);
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+        assert!(files(&config.media_root).is_empty());
+        fs::remove_dir_all(&config.media_root).unwrap();
+    }
+}
@@ -0,0 +1,708 @@
+use crate::api::auth::{db_userroles, Authorized, Caller};
+use crate::api::get::{ListRequest, Page};
+use crate::api::roles::db_requiremanager;
+use crate::error::ApiError;
+use crate::insert;
+use crate::models::User;
+use crate::password::{self, Verification};
+use crate::permission::{requires, Permission, ADMIN_ROLE, GUEST_ROLE};
+use crate::Response;
+
+use actix_web::web::{Data, Json, Query};
+use actix_web::{delete, get, put, web, HttpResponse};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// A request from a user to change their own password
+#[derive(Deserialize, Serialize)]
+pub struct ChangePasswordRequest {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// A request from an administrator to set a new password for a user
+#[derive(Deserialize, Serialize)]
+pub struct ResetPasswordRequest {
+    pub user_id: i32,
+    pub new_password: String,
+}
+
+/// A request for a page of users
+#[derive(Deserialize, Serialize)]
+pub struct UsersRequest {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// A request from an administrator to disable or enable a user
+#[derive(Deserialize, Serialize)]
+pub struct DisableUserRequest {
+    pub user_id: i32,
+    pub disabled: bool,
+}
+
+/// A request from an administrator to grant or revoke the admin role
+#[derive(Deserialize, Serialize)]
+pub struct AdminRequest {
+    pub user_id: i32,
+    pub admin: bool,
+}
+
+/// A request from an administrator to delete a user
+#[derive(Deserialize, Serialize)]
+pub struct DeleteUserRequest {
+    pub user_id: i32,
+}
+
+/// A user's account as shown to themselves or an administrator
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Account {
+    pub id: i32,
+    pub username: String,
+    pub created_at: NaiveDateTime,
+    pub disabled_at: Option<NaiveDateTime>,
+    pub roles: Vec<String>,
+}
+
+/// The account of the current user along with everything they are permitted to do
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Profile {
+    #[serde(flatten)]
+    pub account: Account,
+    pub permissions: Vec<Permission>,
+}
+
+/// Check that a new password is acceptable
+fn require_password(new_password: &str) -> Result<(), ApiError> {
+    match new_password.is_empty() {
+        true => Err(ApiError::Validation("Password cannot be empty".to_string())),
+        false => Ok(()),
+    }
+}
+
+/// Get a user by id, or a not found error
+fn db_requireuser(
+    user_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<User, ApiError> {
+    use crate::schema::users;
+
+    users::dsl::users
+        .filter(users::dsl::id.eq(user_id))
+        .first::<User>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::not_found("User"))
+}
+
+/// Replace the password of a user, ending their sessions other than the one kept, and revoking
+/// their API keys and Subsonic password when the password is reset for them
+fn db_setpassword(
+    user_id: i32,
+    new_password: &str,
+    keep_session: Option<i32>,
+    revoke_credentials: bool,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{api_keys, sessions, subsonic_passwords, users};
+
+    require_password(new_password)?;
+    let password_hash: String = password::hash(new_password)?;
+    conn.transaction::<(), ApiError, _>(|conn| {
+        diesel::update(users::dsl::users.filter(users::dsl::id.eq(user_id)))
+            .set((
+                users::dsl::password_hash.eq(password_hash),
+                users::dsl::salt.eq(None::<String>),
+            ))
+            .execute(conn)?;
+
+        // anyone signed in with the old password is signed out
+        diesel::delete(
+            sessions::dsl::sessions
+                .filter(sessions::dsl::user_id.eq(user_id))
+                .filter(sessions::dsl::id.ne(keep_session.unwrap_or(0))),
+        )
+        .execute(conn)?;
+
+        // a reset account may have been taken over, so nothing made with it keeps working
+        if revoke_credentials {
+            diesel::delete(api_keys::dsl::api_keys.filter(api_keys::dsl::user_id.eq(user_id)))
+                .execute(conn)?;
+            diesel::delete(subsonic_passwords::dsl::subsonic_passwords.find(user_id))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Refuse an action an administrator tries to take against their own account
+fn require_other(caller: &Caller, user_id: i32, message: &str) -> Result<(), ApiError> {
+    match caller.user() {
+        Some(user) if user.id == user_id => Err(ApiError::Conflict(message.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Get the profile of the caller
+fn db_me(
+    caller: Caller,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Profile, ApiError> {
+    use crate::schema::role_permissions;
+
+    let user: &User = caller.require_user()?;
+    let roles: Vec<String> = db_userroles(user.id, conn)?;
+
+    // signed in users are also granted whatever a guest may do
+    let mut granting: Vec<String> = roles.clone();
+    granting.push(GUEST_ROLE.to_string());
+    let granted: Vec<String> = role_permissions::dsl::role_permissions
+        .filter(role_permissions::dsl::role.eq_any(&granting))
+        .select(role_permissions::dsl::permission)
+        .distinct()
+        .load::<String>(conn)?;
+    let permissions: BTreeSet<Permission> = granted
+        .iter()
+        .filter_map(|permission| Permission::parse(permission))
+        .collect();
+
+    Ok(Profile {
+        account: Account {
+            id: user.id,
+            username: user.username.clone(),
+            created_at: user.created_at,
+            disabled_at: user.disabled_at,
+            roles,
+        },
+        permissions: permissions.into_iter().collect(),
+    })
+}
+
+/// Change the password of the caller after checking their current one
+fn db_changepassword(
+    caller: Caller,
+    password_req: ChangePasswordRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    let (session, user) = caller.require_session()?;
+    let verification: Verification = password::verify(
+        &password_req.old_password,
+        &user.password_hash,
+        user.salt.as_deref(),
+    );
+    if verification == Verification::Invalid {
+        return Err(ApiError::Forbidden(
+            "Current password is incorrect".to_string(),
+        ));
+    }
+
+    // the device making the change stays signed in
+    db_setpassword(
+        user.id,
+        &password_req.new_password,
+        Some(session.id),
+        false,
+        conn,
+    )
+}
+
+/// Set a new password for a user
+fn db_resetpassword(
+    caller: Caller,
+    password_req: ResetPasswordRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    // whoever can reset a password can sign in as that user, so an API key is not enough
+    let (session, caller_user) = caller.require_session()?;
+    let user: User = db_requireuser(password_req.user_id, conn)?;
+    let keep_session: Option<i32> = (caller_user.id == user.id).then_some(session.id);
+    db_setpassword(
+        user.id,
+        &password_req.new_password,
+        keep_session,
+        true,
+        conn,
+    )
+}
+
+/// Get a page of users along with their roles, ordered by username
+fn db_listusers(
+    users_req: UsersRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Page<Account>, ApiError> {
+    use crate::schema::{user_roles, users};
+
+    let (limit, offset) = ListRequest {
+        limit: users_req.limit,
+        offset: users_req.offset,
+        ..Default::default()
+    }
+    .page()?;
+
+    let total: i64 = users::dsl::users.count().get_result(conn)?;
+    let users: Vec<User> = users::dsl::users
+        .order((users::dsl::username.asc(), users::dsl::id.asc()))
+        .limit(limit)
+        .offset(offset)
+        .load::<User>(conn)?;
+
+    // load the roles of the whole page at once
+    let user_ids: Vec<i32> = users.iter().map(|user| user.id).collect();
+    let rows: Vec<(i32, String)> = user_roles::dsl::user_roles
+        .filter(user_roles::dsl::user_id.eq_any(&user_ids))
+        .order(user_roles::dsl::role.asc())
+        .load::<(i32, String)>(conn)?;
+    let mut roles: HashMap<i32, Vec<String>> = HashMap::new();
+    for (user_id, role) in rows {
+        roles.entry(user_id).or_default().push(role);
+    }
+
+    let accounts: Vec<Account> = users
+        .into_iter()
+        .map(|user| Account {
+            roles: roles.remove(&user.id).unwrap_or_default(),
+            id: user.id,
+            username: user.username,
+            created_at: user.created_at,
+            disabled_at: user.disabled_at,
+        })
+        .collect();
+    Ok(Page::new(accounts, total, limit, offset))
+}
+
+/// Disable a user, signing them out everywhere, or enable them again
+fn db_disableuser(
+    caller: Caller,
+    disable_req: DisableUserRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{sessions, users};
+
+    caller.require_session()?;
+    require_other(
+        &caller,
+        disable_req.user_id,
+        "Users cannot disable their own account",
+    )?;
+    let user: User = db_requireuser(disable_req.user_id, conn)?;
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        if !disable_req.disabled {
+            diesel::update(users::dsl::users.filter(users::dsl::id.eq(user.id)))
+                .set(users::dsl::disabled_at.eq(None::<NaiveDateTime>))
+                .execute(conn)?;
+            return Ok(());
+        }
+
+        // disabling an already disabled user keeps the original time
+        let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+        diesel::update(users::dsl::users.filter(users::dsl::id.eq(user.id)))
+            .set(users::dsl::disabled_at.eq(user.disabled_at.unwrap_or(now)))
+            .execute(conn)?;
+        diesel::delete(sessions::dsl::sessions.filter(sessions::dsl::user_id.eq(user.id)))
+            .execute(conn)?;
+        db_requiremanager(conn)
+    })
+}
+
+/// Grant or revoke the admin role of a user
+fn db_setadmin(
+    caller: Caller,
+    admin_req: AdminRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::user_roles;
+
+    caller.require_session()?;
+    let user: User = db_requireuser(admin_req.user_id, conn)?;
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        if admin_req.admin {
+            let new_role = insert::NewUserRole {
+                user_id: user.id,
+                role: ADMIN_ROLE.to_string(),
+            };
+            diesel::insert_into(user_roles::dsl::user_roles)
+                .values(&new_role)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            return Ok(());
+        }
+
+        diesel::delete(
+            user_roles::dsl::user_roles
+                .filter(user_roles::dsl::user_id.eq(user.id))
+                .filter(user_roles::dsl::role.eq(ADMIN_ROLE)),
+        )
+        .execute(conn)?;
+        db_requiremanager(conn)
+    })
+}
+
+/// Delete a user along with their roles and sessions
+fn db_deleteuser(
+    caller: Caller,
+    delete_req: DeleteUserRequest,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::users;
+
+    caller.require_session()?;
+    require_other(
+        &caller,
+        delete_req.user_id,
+        "Users cannot delete their own account",
+    )?;
+
+    conn.transaction::<(), ApiError, _>(|conn| {
+        let deleted: usize =
+            diesel::delete(users::dsl::users.filter(users::dsl::id.eq(delete_req.user_id)))
+                .execute(conn)?;
+        if deleted == 0 {
+            return Err(ApiError::not_found("User"));
+        }
+        db_requiremanager(conn)
+    })
+}
+
+/// Get the profile, roles and permissions of the current user
+#[get("/auth/me")]
+pub async fn me(
+    caller: Authorized<requires::Anyone>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the profile from the database
+    let mut conn = pool.get()?;
+    let profile = web::block(move || db_me(caller.into_inner(), &mut conn)).await??;
+
+    // return the profile on success
+    Ok(HttpResponse::Ok().json(Response::success(profile)))
+}
+
+/// Change the password of the current user
+#[put("/auth/me/password")]
+pub async fn changepassword(
+    caller: Authorized<requires::Anyone>,
+    password_req: Json<ChangePasswordRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // store the new password in the database
+    let mut conn = pool.get()?;
+    web::block(move || {
+        db_changepassword(caller.into_inner(), password_req.into_inner(), &mut conn)
+    })
+    .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Set a new password for a user
+#[put("/auth/users/password")]
+pub async fn resetpassword(
+    caller: Authorized<requires::UsersManage>,
+    password_req: Json<ResetPasswordRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // store the new password in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_resetpassword(caller.into_inner(), password_req.into_inner(), &mut conn))
+        .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// List a page of users
+#[get("/auth/users")]
+pub async fn listusers(
+    _caller: Authorized<requires::UsersManage>,
+    users_req: Query<UsersRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // get the users from the database
+    let mut conn = pool.get()?;
+    let users = web::block(move || db_listusers(users_req.into_inner(), &mut conn)).await??;
+
+    // return the users on success
+    Ok(HttpResponse::Ok().json(Response::success(users)))
+}
+
+/// Disable or enable a user
+#[put("/auth/users/disabled")]
+pub async fn disableuser(
+    caller: Authorized<requires::UsersManage>,
+    disable_req: Json<DisableUserRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // update the user in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_disableuser(caller.into_inner(), disable_req.into_inner(), &mut conn))
+        .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Grant or revoke the admin role of a user
+#[put("/auth/users/admin")]
+pub async fn setadmin(
+    caller: Authorized<requires::UsersManage>,
+    admin_req: Json<AdminRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // update the roles in the database
+    let mut conn = pool.get()?;
+    web::block(move || db_setadmin(caller.into_inner(), admin_req.into_inner(), &mut conn))
+        .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+/// Delete a user
+#[delete("/auth/users")]
+pub async fn deleteuser(
+    caller: Authorized<requires::UsersManage>,
+    delete_req: Json<DeleteUserRequest>,
+    pool: Data<Pool<ConnectionManager<PgConnection>>>,
+) -> Result<HttpResponse, ApiError> {
+    // remove the user from the database
+    let mut conn = pool.get()?;
+    web::block(move || db_deleteuser(caller.into_inner(), delete_req.into_inner(), &mut conn))
+        .await??;
+
+    // return success
+    Ok(HttpResponse::Ok().json(Response::success(String::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::apikeys::require_apikey;
+    use crate::api::auth::Credential;
+    use crate::api::sessions::{db_createsession, Client};
+    use crate::models::{DbApiKey, DbSession};
+    use crate::permission::DEFAULT_ROLE;
+    use crate::testing::connect;
+
+    /// Add a user with the given roles
+    fn user(
+        username: &str,
+        roles: &[&str],
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> User {
+        use crate::schema::{user_roles, users};
+
+        let user: User = diesel::insert_into(users::table)
+            .values((
+                users::username.eq(username),
+                users::password_hash.eq("unused"),
+            ))
+            .get_result::<User>(conn)
+            .unwrap();
+        for role in roles {
+            diesel::insert_into(user_roles::table)
+                .values((user_roles::user_id.eq(user.id), user_roles::role.eq(*role)))
+                .execute(conn)
+                .unwrap();
+        }
+        user
+    }
+
+    /// Sign in as a user with an API key of the admin scope
+    fn api_key_caller(
+        user: User,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::api_keys;
+
+        let api_key: DbApiKey = diesel::insert_into(api_keys::table)
+            .values((
+                api_keys::user_id.eq(user.id),
+                api_keys::name.eq("script"),
+                api_keys::key_hash.eq(password::hash_token("ak_users-test")),
+                api_keys::prefix.eq("ak_users"),
+                api_keys::scope.eq("admin"),
+            ))
+            .get_result::<DbApiKey>(conn)
+            .unwrap();
+        Caller::authenticated(Credential::ApiKey(api_key), user)
+    }
+
+    /// Start a session for a user
+    fn session(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> DbSession {
+        let client = Client {
+            user_agent: None,
+            ip_address: None,
+        };
+        db_createsession(user_id, client, 3600, conn).unwrap().1
+    }
+
+    /// Sign in as a user with a new session
+    fn session_caller(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Caller {
+        use crate::schema::users;
+
+        let user: User = users::table.find(user_id).first(conn).unwrap();
+        let session: DbSession = session(user_id, conn);
+        Caller::authenticated(Credential::Session(session), user)
+    }
+
+    /// Give a user an API key and a Subsonic password
+    fn credentials(user_id: i32, conn: &mut PooledConnection<ConnectionManager<PgConnection>>) {
+        use crate::schema::{api_keys, subsonic_passwords};
+
+        diesel::insert_into(api_keys::table)
+            .values((
+                api_keys::user_id.eq(user_id),
+                api_keys::name.eq("script"),
+                api_keys::key_hash.eq(password::hash_token("ak_users-other")),
+                api_keys::prefix.eq("ak_users-ot"),
+                api_keys::scope.eq("read"),
+            ))
+            .execute(conn)
+            .unwrap();
+        diesel::insert_into(subsonic_passwords::table)
+            .values((
+                subsonic_passwords::user_id.eq(user_id),
+                subsonic_passwords::encrypted_password.eq("unused"),
+            ))
+            .execute(conn)
+            .unwrap();
+    }
+
+    /// How many sessions, API keys and Subsonic passwords a user has
+    fn signed_in(
+        user_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (i64, i64, i64) {
+        use crate::schema::{api_keys, sessions, subsonic_passwords};
+
+        let sessions: i64 = sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        let api_keys: i64 = api_keys::table
+            .filter(api_keys::user_id.eq(user_id))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        let subsonic_passwords: i64 = subsonic_passwords::table
+            .filter(subsonic_passwords::user_id.eq(user_id))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        (sessions, api_keys, subsonic_passwords)
+    }
+
+    #[test]
+    fn refuses_takeovers_with_an_api_key() {
+        use crate::schema::{user_roles, users};
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let admin: User = user("users-test-admin", &[ADMIN_ROLE], &mut conn);
+        let other: User = user("users-test-other", &[], &mut conn);
+
+        let reset = ResetPasswordRequest {
+            user_id: other.id,
+            new_password: "taken over".to_string(),
+        };
+        let caller: Caller = api_key_caller(admin, &mut conn);
+        let result = db_resetpassword(caller, reset, &mut conn);
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+        let password_hash: String = users::table
+            .find(other.id)
+            .select(users::password_hash)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(password_hash, "unused");
+
+        let admin: User = users::table
+            .filter(users::username.eq("users-test-admin"))
+            .first(&mut conn)
+            .unwrap();
+        let grant = AdminRequest {
+            user_id: other.id,
+            admin: true,
+        };
+        let caller: Caller = Caller::authenticated(Credential::Password, admin);
+        let result = db_setadmin(caller, grant, &mut conn);
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+        let roles: i64 = user_roles::table
+            .filter(user_roles::user_id.eq(other.id))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(roles, 0);
+    }
+
+    #[test]
+    fn disabling_signs_a_user_out_and_refuses_their_keys() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let admin_id: i32 = user("users-test-admin", &[ADMIN_ROLE], &mut conn).id;
+        let other: User = user("users-test-other", &[DEFAULT_ROLE], &mut conn);
+        session(other.id, &mut conn);
+        session(other.id, &mut conn);
+        credentials(other.id, &mut conn);
+        assert!(require_apikey("ak_users-other", &mut conn).is_ok());
+
+        let disable = DisableUserRequest {
+            user_id: other.id,
+            disabled: true,
+        };
+        let caller: Caller = session_caller(admin_id, &mut conn);
+        db_disableuser(caller, disable, &mut conn).unwrap();
+        assert_eq!(signed_in(other.id, &mut conn), (0, 1, 1));
+        assert!(matches!(
+            require_apikey("ak_users-other", &mut conn),
+            Err(ApiError::Unauthorized(_))
+        ));
+
+        // enabling the user again brings their keys back, but not their sessions
+        let enable = DisableUserRequest {
+            user_id: other.id,
+            disabled: false,
+        };
+        let caller: Caller = session_caller(admin_id, &mut conn);
+        db_disableuser(caller, enable, &mut conn).unwrap();
+        assert!(require_apikey("ak_users-other", &mut conn).is_ok());
+        assert_eq!(signed_in(other.id, &mut conn), (0, 1, 1));
+    }
+
+    #[test]
+    fn resetting_a_password_revokes_every_credential() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let admin_id: i32 = user("users-test-admin", &[ADMIN_ROLE], &mut conn).id;
+        let other: User = user("users-test-other", &[DEFAULT_ROLE], &mut conn);
+        session(other.id, &mut conn);
+        credentials(other.id, &mut conn);
+        assert_eq!(signed_in(other.id, &mut conn), (1, 1, 1));
+
+        let reset = ResetPasswordRequest {
+            user_id: other.id,
+            new_password: "a new password".to_string(),
+        };
+        let caller: Caller = session_caller(admin_id, &mut conn);
+        db_resetpassword(caller, reset, &mut conn).unwrap();
+        assert_eq!(signed_in(other.id, &mut conn), (0, 0, 0));
+        assert!(matches!(
+            require_apikey("ak_users-other", &mut conn),
+            Err(ApiError::Unauthorized(_))
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Server configuration read from the environment
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub media_root: PathBuf,
+    pub max_audio_size: u64,
+    pub max_image_size: u64,
+    pub session_lifetime: i64,
+    pub login_lockout_threshold: i32,
+    pub login_lockout_duration: i64,
+    pub require_setup_token: bool,
+    pub setup_token: Option<String>,
+    pub subsonic_secret: Option<[u8; 32]>,
+    pub scrobble_secret: Option<[u8; 32]>,
+    pub scrobble_interval: u64,
+    pub scrobble_allow_private: bool,
+}
+
+impl Config {
+    /// Read the configuration from environment variables, falling back to defaults
+    pub fn from_env() -> Self {
+        let media_root: PathBuf = env::var("MEDIA_ROOT")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("media"));
+
+        // upload limits are given in bytes
+        let max_audio_size: u64 = env_or("MAX_AUDIO_SIZE", 300 * 1024 * 1024);
+        let max_image_size: u64 = env_or("MAX_IMAGE_SIZE", 20 * 1024 * 1024);
+
+        // sessions expire after this many seconds without being used
+        let session_lifetime: i64 = env_or("SESSION_LIFETIME", 7 * 24 * 60 * 60);
+
+        // failed logins for a username before it is locked out, and the lockout in seconds
+        let login_lockout_threshold: i32 = env_or("LOGIN_LOCKOUT_THRESHOLD", 10);
+        let login_lockout_duration: i64 = env_or("LOGIN_LOCKOUT_DURATION", 15 * 60);
+
+        // creating the first admin needs a setup token, fixed here or generated at startup
+        let require_setup_token: bool = env_or("REQUIRE_SETUP_TOKEN", true);
+        let setup_token: Option<String> = env::var("SETUP_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+
+        // key encrypting Subsonic passwords, given as 64 hex characters, without which Subsonic
+        // clients cannot use token authentication
+        let subsonic_secret: Option<[u8; 32]> = env::var("SUBSONIC_SECRET")
+            .ok()
+            .and_then(|secret| hex::decode(secret.trim()).ok())
+            .and_then(|secret| secret.try_into().ok());
+
+        // key encrypting the tokens users give for forwarding plays, given as 64 hex characters,
+        // without which plays are not forwarded
+        let scrobble_secret: Option<[u8; 32]> = env::var("SCROBBLE_SECRET")
+            .ok()
+            .and_then(|secret| hex::decode(secret.trim()).ok())
+            .and_then(|secret| secret.try_into().ok());
+
+        // seconds between attempts to submit the plays waiting to be forwarded
+        let scrobble_interval: u64 = env_or("SCROBBLE_INTERVAL", 30);
+
+        // whether plays may be forwarded to loopback and private addresses, such as a stand-in
+        // server for testing, which users could otherwise use to reach into the server's network
+        let scrobble_allow_private: bool = env_or("SCROBBLE_ALLOW_PRIVATE", false);
+
+        Config {
+            media_root,
+            max_audio_size,
+            max_image_size,
+            session_lifetime,
+            login_lockout_threshold,
+            login_lockout_duration,
+            require_setup_token,
+            setup_token,
+            subsonic_secret,
+            scrobble_secret,
+            scrobble_interval,
+            scrobble_allow_private,
+        }
+    }
+}
+
+/// Parse an environment variable, using the default if it is missing or invalid
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,322 @@
+use crate::permission::Permission;
+
+use actix_web::error::BlockingError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use diesel::r2d2::PoolError;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Ids referenced by a request that do not exist in the database
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UnknownIds {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub composer_ids: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub songwriter_ids: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub performer_ids: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub piece_ids: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub release_ids: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recording_ids: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_ids: Vec<i32>,
+}
+
+impl UnknownIds {
+    /// Whether every referenced id exists
+    pub fn is_empty(&self) -> bool {
+        self.composer_ids.is_empty()
+            && self.songwriter_ids.is_empty()
+            && self.performer_ids.is_empty()
+            && self.piece_ids.is_empty()
+            && self.release_ids.is_empty()
+            && self.recording_ids.is_empty()
+            && self.user_ids.is_empty()
+    }
+}
+
+/// Errors returned by the API, each with an HTTP status and a stable error code
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    Validation(String),
+    UnknownIds(Box<UnknownIds>),
+    TooManyRequests(i64),
+    Unavailable,
+    Internal,
+}
+
+/// Body sent to the client when a request fails
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ErrorBody {
+    pub success: bool,
+    pub error: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unknown_ids: Option<UnknownIds>,
+}
+
+impl ApiError {
+    /// Machine readable code identifying the kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PayloadTooLarge => "payload_too_large",
+            ApiError::UnsupportedMediaType => "unsupported_media_type",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::UnknownIds(_) => "unknown_ids",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::Unavailable => "service_unavailable",
+            ApiError::Internal => "internal_error",
+        }
+    }
+
+    /// A request without a valid session
+    pub fn unauthorized() -> Self {
+        ApiError::Unauthorized("Invalid or missing token".to_string())
+    }
+
+    /// A request from a user whose roles do not grant a permission
+    pub fn forbidden(permission: Permission) -> Self {
+        ApiError::Forbidden(format!("User lacks the {} permission", permission))
+    }
+
+    /// A request for an entry that does not exist
+    pub fn not_found(entity: &str) -> Self {
+        ApiError::NotFound(format!("{} not found", entity))
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Validation(message) => write!(f, "{}", message),
+            ApiError::PayloadTooLarge => write!(f, "File is too large"),
+            ApiError::UnsupportedMediaType => write!(f, "Unsupported file type"),
+            ApiError::UnknownIds(_) => write!(f, "Request references ids that do not exist"),
+            ApiError::TooManyRequests(retry_after) => write!(
+                f,
+                "Too many failed login attempts, try again in {} seconds",
+                retry_after
+            ),
+            ApiError::Unavailable => write!(f, "Database is unavailable"),
+            ApiError::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Validation(_) | ApiError::UnknownIds(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let unknown_ids: Option<UnknownIds> = match self {
+            ApiError::UnknownIds(unknown_ids) => Some(*unknown_ids.clone()),
+            _ => None,
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        // throttled clients are told when they may try again
+        if let ApiError::TooManyRequests(retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(ErrorBody {
+            success: false,
+            error: self.code().to_string(),
+            message: self.to_string(),
+            unknown_ids,
+        })
+    }
+}
+
+impl From<DieselError> for ApiError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => ApiError::NotFound("Entry not found".to_string()),
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ApiError::Conflict("Entry conflicts with an existing entry".to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                ApiError::Validation("Request refers to an entry that does not exist".to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ClosedConnection, _) => {
+                ApiError::Unavailable
+            }
+            err => {
+                log::error!("database error: {}", err);
+                ApiError::Internal
+            }
+        }
+    }
+}
+
+impl From<PoolError> for ApiError {
+    fn from(err: PoolError) -> Self {
+        log::error!("connection pool error: {}", err);
+        ApiError::Unavailable
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(_: BlockingError) -> Self {
+        ApiError::Internal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use serde_json::{json, Value};
+
+    /// The status, Retry-After header and JSON body of the response to an error
+    async fn respond(err: ApiError) -> (StatusCode, Option<String>, Value) {
+        let res: HttpResponse = err.error_response();
+        let status: StatusCode = res.status();
+        let retry_after: Option<String> = res
+            .headers()
+            .get(header::RETRY_AFTER)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = to_bytes(res.into_body()).await.unwrap();
+        (status, retry_after, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn responds_with_the_status_and_code_of_each_error() {
+        let cases: Vec<(ApiError, StatusCode, &str, &str)> = vec![
+            (
+                ApiError::BadRequest("Malformed body".to_string()),
+                StatusCode::BAD_REQUEST,
+                "bad_request",
+                "Malformed body",
+            ),
+            (
+                ApiError::unauthorized(),
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "Invalid or missing token",
+            ),
+            (
+                ApiError::forbidden(Permission::UsersManage),
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "User lacks the users.manage permission",
+            ),
+            (
+                ApiError::not_found("Piece"),
+                StatusCode::NOT_FOUND,
+                "not_found",
+                "Piece not found",
+            ),
+            (
+                ApiError::Conflict("Name is taken".to_string()),
+                StatusCode::CONFLICT,
+                "conflict",
+                "Name is taken",
+            ),
+            (
+                ApiError::PayloadTooLarge,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                "File is too large",
+            ),
+            (
+                ApiError::UnsupportedMediaType,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "Unsupported file type",
+            ),
+            (
+                ApiError::Validation("Limit is too large".to_string()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_failed",
+                "Limit is too large",
+            ),
+            (
+                ApiError::TooManyRequests(30),
+                StatusCode::TOO_MANY_REQUESTS,
+                "too_many_requests",
+                "Too many failed login attempts, try again in 30 seconds",
+            ),
+            (
+                ApiError::Unavailable,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "service_unavailable",
+                "Database is unavailable",
+            ),
+            (
+                ApiError::Internal,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+                "Internal server error",
+            ),
+        ];
+        for (err, status, code, message) in cases {
+            assert_eq!(err.status_code(), status);
+            let throttled: bool = matches!(err, ApiError::TooManyRequests(_));
+            let (responded, retry_after, body) = respond(err).await;
+            assert_eq!(responded, status);
+            assert_eq!(retry_after.as_deref(), throttled.then_some("30"));
+            assert_eq!(
+                body,
+                json!({ "success": false, "error": code, "message": message })
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn lists_unknown_ids_by_kind() {
+        let unknown_ids = UnknownIds {
+            piece_ids: vec![3],
+            recording_ids: vec![7, 9],
+            ..Default::default()
+        };
+        let err = ApiError::UnknownIds(Box::new(unknown_ids));
+        assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, retry_after, body) = respond(err).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(retry_after, None);
+        assert_eq!(
+            body,
+            json!({
+                "success": false,
+                "error": "unknown_ids",
+                "message": "Request references ids that do not exist",
+                "unknown_ids": { "piece_ids": [3], "recording_ids": [7, 9] },
+            })
+        );
+    }
+}
@@ -7,16 +7,47 @@ use diesel::prelude::*;
 pub struct NewUser {
     pub username: String,
     pub password_hash: String,
-    pub salt: String,
-    pub session: Option<String>,
-    pub created_at: Option<NaiveDateTime>,
 }
 
-/// Represents a new user to insert into the admin table
+/// Represents a new session to insert into the sessions table
 #[derive(Insertable)]
-#[diesel(table_name = crate::schema::admin)]
-pub struct NewAdmin {
+#[diesel(table_name = crate::schema::sessions)]
+pub struct NewSession {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// Represents a new API key to insert into the api_keys table
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::api_keys)]
+pub struct NewApiKey {
+    pub user_id: i32,
+    pub name: String,
+    pub key_hash: String,
+    pub prefix: String,
+    pub scope: String,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// Represents a role granted to a user in the user_roles table
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::user_roles)]
+pub struct NewUserRole {
+    pub user_id: i32,
+    pub role: String,
+}
+
+/// Represents a failed login to record in the login_failures table
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::login_failures)]
+pub struct NewLoginFailure {
     pub username: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: String,
 }
 
 /// Represents a new composer to insert into the composers table
@@ -73,4 +104,62 @@ pub struct NewRecording {
     pub release_id: i32,
     pub track_number: i32,
     pub file_path: Option<String>,
+    pub file_size: Option<i64>,
+    pub file_modified: Option<NaiveDateTime>,
+    pub file_hash: Option<String>,
+}
+
+/// Represents a new playlist to insert into the playlists table
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::playlists)]
+pub struct NewPlaylist {
+    pub owner_id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub public: bool,
+}
+
+/// Represents a new entry to insert into the playlist_entries table
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::playlist_entries)]
+pub struct NewPlaylistEntry {
+    pub playlist_id: i32,
+    pub recording_id: i32,
+    pub position: i32,
+}
+
+/// Represents a new play to insert into the plays table
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::plays)]
+pub struct NewPlay {
+    pub user_id: i32,
+    pub recording_id: i32,
+    pub started_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Represents a new event to insert into the play_events table
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::play_events)]
+pub struct NewPlayEvent {
+    pub play_id: i32,
+    pub event: String,
+    pub position_seconds: i32,
+    pub occurred_at: NaiveDateTime,
+}
+
+/// Represents a new entry to insert into the library_entries table, with exactly one of the
+/// catalog ids set
+#[derive(Default, Insertable)]
+#[diesel(table_name = crate::schema::library_entries)]
+pub struct NewLibraryEntry {
+    pub user_id: i32,
+    pub recording_id: Option<i32>,
+    pub release_id: Option<i32>,
+    pub piece_id: Option<i32>,
+    pub performer_id: Option<i32>,
+    pub composer_id: Option<i32>,
+    pub songwriter_id: Option<i32>,
+    pub favorited_at: Option<NaiveDateTime>,
+    pub rating: Option<i16>,
 }
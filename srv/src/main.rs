@@ -1,16 +1,29 @@
 use actix_cors::Cors;
-use actix_web::web::Data;
+use actix_web::web::{Data, JsonConfig, QueryConfig};
 use actix_web::{middleware, App, HttpServer};
+use config::Config;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use dotenvy::dotenv;
+use error::ApiError;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::{env, io};
 
 pub mod api;
+pub mod config;
+pub mod error;
 pub mod insert;
+pub mod media;
 pub mod models;
+pub mod password;
+pub mod permission;
+pub mod scanner;
 pub mod schema;
+pub mod scrobbler;
+#[cfg(test)]
+pub mod testing;
+pub mod update;
 
 /// Generic response to denote whether operation was successful
 #[derive(Debug, Deserialize, Serialize)]
@@ -19,6 +32,16 @@ pub struct Response<T> {
     pub message: T,
 }
 
+impl<T> Response<T> {
+    /// Create a successful response
+    pub fn success(message: T) -> Self {
+        Response {
+            success: true,
+            message,
+        }
+    }
+}
+
 /// A generic id request from a user
 #[derive(Deserialize, Serialize)]
 pub struct IdRequest {
@@ -38,6 +61,56 @@ async fn main() -> io::Result<()> {
         .build(manager)
         .expect("Failed to create pool.");
 
+    // read the server configuration
+    let mut config: Config = Config::from_env();
+
+    // scan the library instead of serving if requested, defaulting to the whole media root
+    let args: Vec<String> = env::args().collect();
+    let command: Option<&str> = args.get(1).map(String::as_str);
+    if command == Some("scan") || command == Some("rescan") {
+        let directory: PathBuf = args
+            .get(2)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| config.media_root.clone());
+        let rescan: bool = command == Some("rescan");
+        let mut conn = pool.get().expect("Connection pool error");
+        let report = scanner::scan_library(&config.media_root, &directory, rescan, &mut conn);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    // creating the first admin needs the setup token unless it is turned off
+    if !config.require_setup_token {
+        config.setup_token = None;
+    } else if config.setup_token.is_none() {
+        config.setup_token =
+            Some(password::generate_token().expect("Failed to generate setup token"));
+    }
+
+    // show the setup token while nobody can manage users
+    let mut conn = pool.get().expect("Connection pool error");
+    if api::roles::db_countmanagers(&mut conn).expect("Failed to count managers") == 0 {
+        match &config.setup_token {
+            Some(token) => log::warn!("No admin exists yet, create one with setup token {}", token),
+            None => log::warn!("No admin exists yet, the first user to sign up becomes one"),
+        }
+    }
+    drop(conn);
+
+    // forward the plays users have queued, if their tokens can be read
+    match config.scrobble_secret {
+        Some(key) => {
+            let pool = pool.clone();
+            let interval: u64 = config.scrobble_interval.max(1);
+            let allow_private: bool = config.scrobble_allow_private;
+            std::thread::spawn(move || scrobbler::run(pool, key, interval, allow_private));
+        }
+        None => log::info!("SCROBBLE_SECRET is not set, plays will not be forwarded"),
+    }
+
+    // scans run in the background, one at a time across every worker
+    let scan_job: Data<api::scan::ScanJob> = Data::new(api::scan::ScanJob::default());
+
     // create a new API server
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -47,15 +120,63 @@ async fn main() -> io::Result<()> {
             .max_age(3600);
         App::new()
             .app_data(Data::new(pool.clone()))
-            .wrap(middleware::Logger::default())
+            .app_data(Data::new(config.clone()))
+            .app_data(scan_job.clone())
+            .app_data(JsonConfig::default().error_handler(|err, _| {
+                // malformed bodies get the same error shape as every other failure
+                ApiError::BadRequest(err.to_string()).into()
+            }))
+            .app_data(
+                QueryConfig::default()
+                    .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+            )
+            // log paths without their query, where Subsonic clients put their credentials
+            .wrap(
+                middleware::Logger::new(
+                    r#"%a "%{method}xi %U" %s %b "%{Referer}i" "%{User-Agent}i" %T"#,
+                )
+                .custom_request_replace("method", |req| req.method().to_string()),
+            )
             .wrap(cors)
             .service(api::auth::adduser)
             .service(api::auth::countuser)
             .service(api::auth::login)
+            .service(api::auth::setupstatus)
+            .service(api::apikeys::createapikey)
+            .service(api::apikeys::listapikeys)
+            .service(api::apikeys::revokeapikey)
+            .service(api::lockouts::clearlockout)
+            .service(api::lockouts::listlockouts)
+            .service(api::lockouts::listloginfailures)
+            .service(api::sessions::listsessions)
+            .service(api::sessions::logout)
+            .service(api::sessions::revokesession)
+            .service(api::sessions::revokeusersessions)
+            .service(api::roles::listroles)
+            .service(api::roles::setrolepermissions)
+            .service(api::roles::setuserroles)
+            .service(api::users::changepassword)
+            .service(api::users::deleteuser)
+            .service(api::users::disableuser)
+            .service(api::users::listusers)
+            .service(api::users::me)
+            .service(api::users::resetpassword)
+            .service(api::users::setadmin)
+            .service(api::subsonic::createsubsonicpassword)
+            .service(api::subsonic::deletesubsonicpassword)
             .service(api::addmusic::addartist)
             .service(api::addmusic::addpiece)
             .service(api::addmusic::addrecording)
             .service(api::addmusic::addrelease)
+            .service(api::editmusic::deleteartist)
+            .service(api::editmusic::deletepiece)
+            .service(api::editmusic::deleterecording)
+            .service(api::editmusic::deleterelease)
+            .service(api::editmusic::reordertracks)
+            .service(api::editmusic::updateartist)
+            .service(api::editmusic::updatepiece)
+            .service(api::editmusic::updaterecording)
+            .service(api::editmusic::updaterelease)
             .service(api::get::getcomposer)
             .service(api::get::getcomposers)
             .service(api::get::getperformer)
@@ -68,12 +189,40 @@ async fn main() -> io::Result<()> {
             .service(api::get::getreleases)
             .service(api::get::getsongwriter)
             .service(api::get::getsongwriters)
+            .service(api::history::countplays)
+            .service(api::history::listplays)
+            .service(api::history::reportevent)
+            .service(api::history::totalplays)
+            .service(api::scan::scanlibrary)
+            .service(api::scan::scanstatus)
+            .service(api::library::getlibrarystate)
+            .service(api::library::listfavorites)
+            .service(api::library::updatelibrarystate)
+            .service(api::playlists::addentries)
+            .service(api::playlists::addplaylist)
+            .service(api::playlists::deleteplaylist)
+            .service(api::playlists::exportplaylist)
+            .service(api::playlists::getplaylist)
+            .service(api::playlists::importplaylist)
+            .service(api::playlists::listplaylists)
+            .service(api::playlists::removeentries)
+            .service(api::playlists::reorderentries)
+            .service(api::playlists::shareplaylist)
+            .service(api::playlists::updateplaylist)
+            .service(api::scrobbling::deletescrobbletarget)
+            .service(api::scrobbling::getscrobbletarget)
+            .service(api::scrobbling::setscrobbletarget)
+            .service(api::search::searchall)
             .service(api::search::searchcomposer)
             .service(api::search::searchperformer)
             .service(api::search::searchpiece)
             .service(api::search::searchrecording)
             .service(api::search::searchrelease)
             .service(api::search::searchsongwriter)
+            .service(api::stream::streamrecording)
+            .service(api::subsonic::subsonic)
+            .service(api::upload::getimage)
+            .service(api::upload::uploadmedia)
     })
     .bind("0.0.0.0:9000")?
     .run()
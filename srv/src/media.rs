@@ -0,0 +1,171 @@
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+// size of each read while hashing a file
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The kind of entity that owns a media file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Recording,
+    Release,
+    Performer,
+    Composer,
+    Songwriter,
+}
+
+impl MediaKind {
+    /// Prefix used when generating file names for this kind of media
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            MediaKind::Recording => "recording",
+            MediaKind::Release => "release",
+            MediaKind::Performer => "performer",
+            MediaKind::Composer => "composer",
+            MediaKind::Songwriter => "songwriter",
+        }
+    }
+
+    /// Whether files of this kind are audio rather than images
+    pub fn is_audio(&self) -> bool {
+        *self == MediaKind::Recording
+    }
+}
+
+/// A generated media file name, such as `recording-12` or `release-3`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MediaName {
+    pub kind: MediaKind,
+    pub id: i32,
+}
+
+impl MediaName {
+    /// Parse a generated media file name into the kind and id of its owner
+    pub fn parse(name: &str) -> Option<Self> {
+        let (prefix, id) = name.split_once('-')?;
+        let kind = match prefix {
+            "recording" => MediaKind::Recording,
+            "release" => MediaKind::Release,
+            "performer" => MediaKind::Performer,
+            "composer" => MediaKind::Composer,
+            "songwriter" => MediaKind::Songwriter,
+            _ => return None,
+        };
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(MediaName {
+            kind,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for MediaName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.kind.prefix(), self.id)
+    }
+}
+
+/// Resolve a stored file path against the media root, refusing paths that would escape it
+pub fn resolve(media_root: &Path, file_path: &str) -> Option<PathBuf> {
+    let relative = Path::new(file_path);
+    if file_path.is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    Some(media_root.join(relative))
+}
+
+/// Size and modification time of a file, which are cheap to read and change whenever it is rewritten
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: i64,
+    pub modified: NaiveDateTime,
+}
+
+impl FileStat {
+    /// Read the size and modification time of a file
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let metadata = path.metadata()?;
+        let modified: DateTime<Utc> = metadata.modified()?.into();
+
+        // the database keeps timestamps to the microsecond
+        Ok(FileStat {
+            size: metadata.len() as i64,
+            modified: modified.naive_utc().trunc_subsecs(6),
+        })
+    }
+}
+
+/// Stat and content hash of a file, used to notice when it changes or moves
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub stat: FileStat,
+    pub hash: String,
+}
+
+impl Fingerprint {
+    /// Read the stat of a file and hash its contents
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let stat = FileStat::read(path)?;
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; HASH_BUFFER_SIZE];
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+
+        Ok(Fingerprint {
+            stat,
+            hash: hex::encode(hasher.finalize()),
+        })
+    }
+}
+
+/// Determine the MIME type of an audio file from its leading bytes
+pub fn sniff_audio(header: &[u8]) -> Option<&'static str> {
+    if header.starts_with(b"fLaC") {
+        Some("audio/flac")
+    } else if header.starts_with(b"OggS") {
+        Some("audio/ogg")
+    } else if header.starts_with(b"ID3")
+        || (header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0)
+    {
+        Some("audio/mpeg")
+    } else if header.len() >= 12 && &header[4..8] == b"ftyp" {
+        Some("audio/mp4")
+    } else if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE" {
+        Some("audio/wav")
+    } else {
+        None
+    }
+}
+
+/// Determine the MIME type of an image file from its leading bytes
+pub fn sniff_image(header: &[u8]) -> Option<&'static str> {
+    if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if header.starts_with(&[0x89, b'P', b'N', b'G']) {
+        Some("image/png")
+    } else if header.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
@@ -9,17 +9,38 @@ pub struct User {
     pub id: i32,
     pub username: String,
     pub password_hash: String,
-    pub salt: String,
-    pub session: Option<String>,
-    pub created_at: Option<NaiveDateTime>,
+    pub salt: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub disabled_at: Option<NaiveDateTime>,
 }
 
 #[derive(Queryable, Selectable)]
-#[diesel(table_name = crate::schema::users)]
+#[diesel(table_name = crate::schema::sessions)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
-pub struct Admin {
+pub struct DbSession {
     pub id: i32,
-    pub username: String,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::api_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub key_hash: String,
+    pub prefix: String,
+    pub scope: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
 }
 
 #[derive(Queryable, Selectable, Serialize, Deserialize)]
@@ -30,6 +51,7 @@ pub struct Performer {
     pub name: String,
     pub description: Option<String>,
     pub image_path: Option<String>,
+    pub created_at: NaiveDateTime,
 }
 
 impl Performer {
@@ -40,6 +62,7 @@ impl Performer {
             name: "".to_string(),
             description: None,
             image_path: None,
+            created_at: NaiveDateTime::default(),
         }
     }
 }
@@ -52,6 +75,7 @@ pub struct Composer {
     pub name: String,
     pub description: Option<String>,
     pub image_path: Option<String>,
+    pub created_at: NaiveDateTime,
 }
 
 impl Composer {
@@ -62,6 +86,7 @@ impl Composer {
             name: "".to_string(),
             description: None,
             image_path: None,
+            created_at: NaiveDateTime::default(),
         }
     }
 }
@@ -74,6 +99,7 @@ pub struct Songwriter {
     pub name: String,
     pub description: Option<String>,
     pub image_path: Option<String>,
+    pub created_at: NaiveDateTime,
 }
 
 impl Songwriter {
@@ -84,6 +110,7 @@ impl Songwriter {
             name: "".to_string(),
             description: None,
             image_path: None,
+            created_at: NaiveDateTime::default(),
         }
     }
 }
@@ -96,6 +123,7 @@ pub struct DbPiece {
     pub name: String,
     pub movements: Option<i32>,
     pub description: Option<String>,
+    pub created_at: NaiveDateTime,
 }
 
 impl DbPiece {
@@ -106,6 +134,7 @@ impl DbPiece {
             name: "".to_string(),
             movements: None,
             description: None,
+            created_at: NaiveDateTime::default(),
         }
     }
 }
@@ -118,6 +147,7 @@ pub struct DbRelease {
     pub name: String,
     pub description: Option<String>,
     pub image_path: Option<String>,
+    pub created_at: NaiveDateTime,
 }
 
 impl DbRelease {
@@ -128,6 +158,7 @@ impl DbRelease {
             name: "".to_string(),
             description: None,
             image_path: None,
+            created_at: NaiveDateTime::default(),
         }
     }
 }
@@ -166,6 +197,10 @@ pub struct DbRecording {
     pub release_id: i32,
     pub track_number: i32,
     pub file_path: Option<String>,
+    pub file_size: Option<i64>,
+    pub file_modified: Option<NaiveDateTime>,
+    pub file_hash: Option<String>,
+    pub available: bool,
 }
 
 impl DbRecording {
@@ -178,6 +213,10 @@ impl DbRecording {
             release_id: -1,
             track_number: -1,
             file_path: None,
+            file_size: None,
+            file_modified: None,
+            file_hash: None,
+            available: false,
         }
     }
 }
@@ -189,3 +228,104 @@ pub struct RecordingPerformer {
     pub recording_id: i32,
     pub performer_id: i32,
 }
+
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::roles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Role {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::login_failures)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LoginFailure {
+    pub id: i32,
+    pub username: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: String,
+    pub attempted_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::login_throttles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LoginThrottle {
+    pub scope: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: NaiveDateTime,
+    pub blocked_until: NaiveDateTime,
+    pub refused: i32,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::playlists)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbPlaylist {
+    pub id: i32,
+    pub owner_id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub public: bool,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::playlist_entries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbPlaylistEntry {
+    pub id: i32,
+    pub playlist_id: i32,
+    pub recording_id: i32,
+    pub position: i32,
+    pub added_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::plays)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbPlay {
+    pub id: i32,
+    pub user_id: i32,
+    pub recording_id: i32,
+    pub state: String,
+    pub listened_seconds: i32,
+    pub started_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::scrobble_targets)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbScrobbleTarget {
+    pub user_id: i32,
+    pub submit_url: String,
+    pub encrypted_token: String,
+    pub enabled: bool,
+    pub last_submitted_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::library_entries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbLibraryEntry {
+    pub id: i32,
+    pub user_id: i32,
+    pub recording_id: Option<i32>,
+    pub release_id: Option<i32>,
+    pub piece_id: Option<i32>,
+    pub performer_id: Option<i32>,
+    pub composer_id: Option<i32>,
+    pub songwriter_id: Option<i32>,
+    pub favorited_at: Option<NaiveDateTime>,
+    pub rating: Option<i16>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
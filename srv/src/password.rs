@@ -0,0 +1,208 @@
+use crate::error::ApiError;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params};
+use md5::Md5;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+use std::num::NonZeroU32;
+
+// parameters of the PBKDF2 hashes stored before passwords moved to Argon2id
+static LEGACY_PBKDF2_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+const LEGACY_PBKDF2_ITER: u32 = 100_000;
+
+// Argon2id hash of a random password nobody knows, made with the current parameters. Logins
+// for unknown usernames are checked against it so they take as long as wrong passwords.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$rVWsJHnV7QZVOy/FT+0voA$9IjVFohHx31sLiijoXL2zRVbKnbA2St1g+IOXQLifTY";
+
+// number of random bytes in a session token
+const TOKEN_LEN: usize = 32;
+
+/// Outcome of checking a password against the hash stored for a user
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    ValidOutdated,
+}
+
+/// The Argon2id hasher with the parameters new hashes are made with
+fn hasher() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        Params::default(),
+    )
+}
+
+/// Hash a password into a PHC string recording the algorithm, its parameters and the salt
+pub fn hash(password: &str) -> Result<String, ApiError> {
+    let salt: SaltString = SaltString::generate(&mut OsRng);
+    hasher()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| {
+            log::error!("password hashing failed: {}", err);
+            ApiError::Internal
+        })
+}
+
+/// Check a password against a stored hash, which is either a PHC string or a hex encoded PBKDF2
+/// hash with a separate salt from before Argon2id was used
+pub fn verify(password: &str, stored: &str, legacy_salt: Option<&str>) -> Verification {
+    // hashes that are not PHC strings can only be legacy PBKDF2 hashes
+    let hash: PasswordHash = match PasswordHash::new(stored) {
+        Ok(hash) => hash,
+        Err(_) => return verify_legacy(password, stored, legacy_salt),
+    };
+    if hasher()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Verification::Invalid;
+    }
+
+    // hashes made with other costs are upgraded to the current ones. Parameters read back from a
+    // hash always state their output length, so only the costs are compared.
+    let wanted: Params = hasher().params().clone();
+    let current: bool = hash.algorithm == Algorithm::Argon2id.ident()
+        && Params::try_from(&hash).is_ok_and(|params| {
+            params.m_cost() == wanted.m_cost()
+                && params.t_cost() == wanted.t_cost()
+                && params.p_cost() == wanted.p_cost()
+        });
+    match current {
+        true => Verification::Valid,
+        false => Verification::ValidOutdated,
+    }
+}
+
+/// Check a password for a user that does not exist, which never succeeds but takes as long as
+/// checking one that does
+pub fn verify_unknown(password: &str) -> Verification {
+    let _ = verify(password, DUMMY_HASH, None);
+    Verification::Invalid
+}
+
+/// Check a password against a legacy PBKDF2-HMAC-SHA256 hash
+fn verify_legacy(password: &str, stored: &str, salt: Option<&str>) -> Verification {
+    let (Some(salt), Ok(stored)) = (salt, hex::decode(stored)) else {
+        return Verification::Invalid;
+    };
+    match pbkdf2::verify(
+        LEGACY_PBKDF2_ALG,
+        NonZeroU32::new(LEGACY_PBKDF2_ITER).unwrap(),
+        salt.as_bytes(),
+        password.as_bytes(),
+        &stored,
+    ) {
+        Ok(()) => Verification::ValidOutdated,
+        Err(_) => Verification::Invalid,
+    }
+}
+
+/// Generate a new random session token
+pub fn generate_token() -> Result<String, ApiError> {
+    generate_secret(TOKEN_LEN)
+}
+
+/// Generate a random hex encoded secret from a number of random bytes
+pub fn generate_secret(len: usize) -> Result<String, ApiError> {
+    let mut secret = vec![0u8; len];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| ApiError::Internal)?;
+    Ok(hex::encode(secret))
+}
+
+/// Hash a session token for storage, so that a leaked database does not leak usable tokens
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Compare a secret given by a client with the expected one in time that only depends on their
+/// lengths, so a wrong guess does not reveal how much of it was right
+pub fn secrets_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Hash a secret and salt with MD5, as Subsonic clients do to avoid sending passwords
+pub fn md5_token(secret: &str, salt: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(secret.as_bytes());
+    hasher.update(salt.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Encrypt a secret that has to be read back later with AES-256-GCM, storing the nonce in front
+/// of the ciphertext
+pub fn encrypt(key: &[u8; 32], secret: &str) -> Result<String, ApiError> {
+    let key =
+        LessSafeKey::new(UnboundKey::new(&aead::AES_256_GCM, key).map_err(|_| ApiError::Internal)?);
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| ApiError::Internal)?;
+
+    let mut sealed: Vec<u8> = secret.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::empty(),
+        &mut sealed,
+    )
+    .map_err(|_| ApiError::Internal)?;
+    Ok(hex::encode([nonce.as_slice(), &sealed].concat()))
+}
+
+/// Decrypt a secret stored by `encrypt`, or nothing if it was encrypted with another key
+pub fn decrypt(key: &[u8; 32], stored: &str) -> Option<String> {
+    let key = LessSafeKey::new(UnboundKey::new(&aead::AES_256_GCM, key).ok()?);
+    let stored: Vec<u8> = hex::decode(stored).ok()?;
+    if stored.len() < aead::NONCE_LEN {
+        return None;
+    }
+    let (nonce, sealed) = stored.split_at(aead::NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut sealed: Vec<u8> = sealed.to_vec();
+    let secret: &[u8] = key.open_in_place(nonce, Aad::empty(), &mut sealed).ok()?;
+    String::from_utf8(secret.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_verifies_unknown_users() {
+        assert_eq!(verify_unknown(""), Verification::Invalid);
+        assert_eq!(verify_unknown("correct horse"), Verification::Invalid);
+    }
+
+    #[test]
+    fn keeps_fresh_hashes_current() {
+        let stored: String = hash("correct horse").unwrap();
+        assert_eq!(verify("correct horse", &stored, None), Verification::Valid);
+        assert_eq!(
+            verify("battery staple", &stored, None),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn refuses_legacy_hashes_without_their_salt() {
+        assert_eq!(verify("password", "00ff", None), Verification::Invalid);
+        assert_eq!(
+            verify("password", "not hex", Some("salt")),
+            Verification::Invalid
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Role given to the first user, which can never lose the permission to manage users
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Role given to new users when none are requested
+pub const DEFAULT_ROLE: &str = "listener";
+
+/// Role whose permissions apply to requests made without a session
+pub const GUEST_ROLE: &str = "guest";
+
+/// An action on the server that a role may be allowed to perform
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Permission {
+    #[serde(rename = "catalog.read")]
+    CatalogRead,
+    #[serde(rename = "catalog.search")]
+    CatalogSearch,
+    #[serde(rename = "catalog.edit")]
+    CatalogEdit,
+    #[serde(rename = "media.stream")]
+    MediaStream,
+    #[serde(rename = "media.upload")]
+    MediaUpload,
+    #[serde(rename = "library.scan")]
+    LibraryScan,
+    #[serde(rename = "users.manage")]
+    UsersManage,
+    #[serde(rename = "playlists.edit")]
+    PlaylistsEdit,
+    #[serde(rename = "library.edit")]
+    LibraryEdit,
+}
+
+impl Permission {
+    /// Every permission known to the server
+    pub const ALL: [Permission; 9] = [
+        Permission::CatalogRead,
+        Permission::CatalogSearch,
+        Permission::CatalogEdit,
+        Permission::MediaStream,
+        Permission::MediaUpload,
+        Permission::LibraryScan,
+        Permission::UsersManage,
+        Permission::PlaylistsEdit,
+        Permission::LibraryEdit,
+    ];
+
+    /// Name of the permission as stored in the role_permissions table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::CatalogRead => "catalog.read",
+            Permission::CatalogSearch => "catalog.search",
+            Permission::CatalogEdit => "catalog.edit",
+            Permission::MediaStream => "media.stream",
+            Permission::MediaUpload => "media.upload",
+            Permission::LibraryScan => "library.scan",
+            Permission::UsersManage => "users.manage",
+            Permission::PlaylistsEdit => "playlists.edit",
+            Permission::LibraryEdit => "library.edit",
+        }
+    }
+
+    /// Look up a permission by its stored name
+    pub fn parse(name: &str) -> Option<Permission> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == name)
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// What a route requires of its caller, stated as a type in the signature of its handler so that
+/// no route can leave it out
+pub trait Requirement {
+    /// Permissions checked before the handler runs
+    fn permissions() -> Vec<Permission>;
+}
+
+/// Requirements routes state, one for each permission
+pub mod requires {
+    use super::{Permission, Requirement};
+
+    macro_rules! requirement {
+        ($($name:ident),*) => {$(
+            #[doc = concat!("Routes that need the ", stringify!($name), " permission")]
+            pub struct $name;
+
+            impl Requirement for $name {
+                fn permissions() -> Vec<Permission> {
+                    vec![Permission::$name]
+                }
+            }
+        )*};
+    }
+
+    requirement!(
+        CatalogRead,
+        CatalogSearch,
+        CatalogEdit,
+        MediaStream,
+        MediaUpload,
+        LibraryScan,
+        UsersManage,
+        PlaylistsEdit,
+        LibraryEdit
+    );
+
+    /// Routes open to every caller, which decide for themselves who may do what
+    pub struct Anyone;
+
+    impl Requirement for Anyone {
+        fn permissions() -> Vec<Permission> {
+            Vec::new()
+        }
+    }
+
+    /// Routes that need both of two permissions
+    impl<A: Requirement, B: Requirement> Requirement for (A, B) {
+        fn permissions() -> Vec<Permission> {
+            [A::permissions(), B::permissions()].concat()
+        }
+    }
+}
+
+/// How much of its owner's access an API key is given. A key never has more access than the user
+/// it belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    Read,
+    Upload,
+    Admin,
+}
+
+impl ApiKeyScope {
+    /// Name of the scope as stored in the api_keys table
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::Upload => "upload",
+            ApiKeyScope::Admin => "admin",
+        }
+    }
+
+    /// Look up a scope by its stored name
+    pub fn parse(name: &str) -> Option<ApiKeyScope> {
+        [ApiKeyScope::Read, ApiKeyScope::Upload, ApiKeyScope::Admin]
+            .into_iter()
+            .find(|scope| scope.as_str() == name)
+    }
+
+    /// Whether the scope covers a permission
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            ApiKeyScope::Read => matches!(
+                permission,
+                Permission::CatalogRead | Permission::CatalogSearch | Permission::MediaStream
+            ),
+            // importing music means adding entries and media as well as reading them. Catalog
+            // edits include deleting entries, but scans, playlists and libraries are left out.
+            ApiKeyScope::Upload => matches!(
+                permission,
+                Permission::CatalogRead
+                    | Permission::CatalogSearch
+                    | Permission::MediaStream
+                    | Permission::MediaUpload
+                    | Permission::CatalogEdit
+            ),
+            ApiKeyScope::Admin => true,
+        }
+    }
+}
@@ -0,0 +1,1246 @@
+use crate::insert;
+use crate::media::{self, FileStat, Fingerprint};
+use crate::models::DbRecording;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::sql_types::Text;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::File;
+use std::path::{Component, Path};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+use symphonia::core::probe::{Hint, ProbeResult};
+use walkdir::WalkDir;
+
+diesel::define_sql_function! {
+    /// Lowercase a string, used to match names regardless of case
+    fn lower(x: Text) -> Text;
+}
+
+/// File extensions of audio files the scanner will import
+const AUDIO_EXTENSIONS: [&str; 7] = ["flac", "mp3", "ogg", "oga", "opus", "m4a", "mp4"];
+
+/// Linked recordings checked for a missing file at a time
+const UNAVAILABLE_BATCH_SIZE: i64 = 500;
+
+/// Track positions set aside for each disc of a release, so that track 1 of disc 2 is stored as
+/// position 1001 and never collides with track 1 of disc 1
+const DISC_TRACKS: i32 = 1000;
+
+/// Tags read from an audio file that are relevant to the catalog
+#[derive(Debug, Default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub artists: Vec<String>,
+    pub album_artists: Vec<String>,
+    pub performers: Vec<String>,
+    pub composers: Vec<String>,
+    pub work: Option<String>,
+    pub movement_name: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+}
+
+impl TrackTags {
+    /// Merge a list of tags into the tags read so far
+    fn apply(&mut self, tags: &[Tag]) {
+        for tag in tags {
+            let value: String = tag.value.to_string().trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => set_once(&mut self.title, value),
+                Some(StandardTagKey::Album) => set_once(&mut self.album, value),
+                Some(StandardTagKey::Artist) => push_names(&mut self.artists, &value),
+                Some(StandardTagKey::AlbumArtist) => push_names(&mut self.album_artists, &value),
+                Some(StandardTagKey::Composer) => push_names(&mut self.composers, &value),
+                Some(
+                    StandardTagKey::Performer
+                    | StandardTagKey::Conductor
+                    | StandardTagKey::Ensemble,
+                ) => push_names(&mut self.performers, &value),
+                Some(StandardTagKey::MovementName) => set_once(&mut self.movement_name, value),
+                Some(StandardTagKey::TrackNumber) => {
+                    if self.track_number.is_none() {
+                        self.track_number = parse_track_number(&value);
+                    }
+                }
+                Some(StandardTagKey::DiscNumber) => {
+                    if self.disc_number.is_none() {
+                        self.disc_number = parse_track_number(&value);
+                    }
+                }
+                _ => {
+                    // classical tags that have no standard key in every format, id3 keeps the
+                    // work in the content group frame
+                    let key: String = tag.key.to_uppercase();
+                    if key == "WORK" || key.ends_with(":WORK") || key == "©WRK" || key == "TIT1" {
+                        set_once(&mut self.work, value);
+                    } else if key == "MOVEMENTNAME" || key.ends_with(":MOVEMENTNAME") {
+                        set_once(&mut self.movement_name, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Name of the piece the track is a recording of
+    fn piece_name(&self) -> Option<&String> {
+        self.work.as_ref().or(self.title.as_ref())
+    }
+
+    /// Position of the track on the release, counting the discs before it. Files without a disc
+    /// number are taken to be on the first disc.
+    fn position(&self) -> Option<i32> {
+        let track_number: i32 = self.track_number?;
+        let disc_number: i32 = self.disc_number.unwrap_or(1).max(1);
+        (disc_number - 1)
+            .checked_mul(DISC_TRACKS)?
+            .checked_add(track_number)
+    }
+
+    /// Performers of the track, falling back to the track artists
+    fn recording_performers(&self) -> &Vec<String> {
+        if self.performers.is_empty() {
+            &self.artists
+        } else {
+            &self.performers
+        }
+    }
+
+    /// Performers credited on the release, falling back to the track artists
+    fn release_performers(&self) -> &Vec<String> {
+        if self.album_artists.is_empty() {
+            &self.artists
+        } else {
+            &self.album_artists
+        }
+    }
+}
+
+/// Set a tag value unless one was already read
+fn set_once(field: &mut Option<String>, value: String) {
+    if field.is_none() {
+        *field = Some(value);
+    }
+}
+
+/// Read a track or disc number, which may be written as "3/12"
+fn parse_track_number(value: &str) -> Option<i32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+/// Add the names in a tag value, which may hold several names separated by semicolons or nulls
+fn push_names(names: &mut Vec<String>, value: &str) {
+    for name in value.split([';', '\0']).map(str::trim) {
+        if !name.is_empty() && !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            names.push(name.to_string());
+        }
+    }
+}
+
+/// Open an audio file and probe its container format, using the extension as a hint
+fn probe(path: &Path) -> Result<ProbeResult, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+    symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|err| err.to_string())
+}
+
+/// Read the length of an audio file in whole seconds, rounded up, if its container states it
+pub fn read_seconds(path: &Path) -> Option<i32> {
+    let probed = probe(path).ok()?;
+    let track = probed.format.default_track()?;
+    let time = track
+        .codec_params
+        .time_base?
+        .calc_time(track.codec_params.n_frames?);
+    let seconds: u64 = time.seconds + u64::from(time.frac > 0.0);
+    i32::try_from(seconds).ok()
+}
+
+/// Read the catalog tags of an audio file
+pub fn read_tags(path: &Path) -> Result<TrackTags, String> {
+    let mut probed = probe(path)?;
+
+    // tags may be found ahead of the container (ID3) or inside it
+    let mut tags = TrackTags::default();
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            tags.apply(revision.tags());
+        }
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.apply(revision.tags());
+    }
+
+    Ok(tags)
+}
+
+/// Ids of catalog entities touched by a scan
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScanEntities {
+    pub composers: BTreeSet<i32>,
+    pub performers: BTreeSet<i32>,
+    pub pieces: BTreeSet<i32>,
+    pub releases: BTreeSet<i32>,
+    pub recordings: BTreeSet<i32>,
+}
+
+impl ScanEntities {
+    /// Add all ids from another set of entities
+    fn extend(&mut self, other: ScanEntities) {
+        self.composers.extend(other.composers);
+        self.performers.extend(other.performers);
+        self.pieces.extend(other.pieces);
+        self.releases.extend(other.releases);
+        self.recordings.extend(other.recordings);
+    }
+}
+
+/// A file the scanner did not import, and why
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Report of what a scan created, matched to existing rows, and skipped, along with the
+/// recordings whose files changed, moved, or disappeared
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScanReport {
+    pub created: ScanEntities,
+    pub matched: ScanEntities,
+    pub updated: BTreeSet<i32>,
+    pub relinked: BTreeSet<i32>,
+    pub unavailable: BTreeSet<i32>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Entities touched while importing a single file, merged into the report once it commits
+#[derive(Default)]
+struct FileImport {
+    created: ScanEntities,
+    matched: ScanEntities,
+}
+
+/// Find a composer by name, ignoring case, or create one
+fn db_composerid(
+    name: &str,
+    import: &mut FileImport,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<i32> {
+    use crate::schema::composers;
+
+    let existing: Option<i32> = composers::dsl::composers
+        .filter(lower(composers::dsl::name).eq(name.to_lowercase()))
+        .select(composers::dsl::id)
+        .first::<i32>(conn)
+        .optional()?;
+    if let Some(composer_id) = existing {
+        import.matched.composers.insert(composer_id);
+        return Ok(composer_id);
+    }
+
+    let new_composer = insert::NewComposer {
+        name: name.to_string(),
+        description: None,
+        image_path: None,
+    };
+    let composer_id: i32 = diesel::insert_into(composers::dsl::composers)
+        .values(&new_composer)
+        .returning(composers::dsl::id)
+        .get_result(conn)?;
+    import.created.composers.insert(composer_id);
+    Ok(composer_id)
+}
+
+/// Find a performer by name, ignoring case, or create one
+fn db_performerid(
+    name: &str,
+    import: &mut FileImport,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<i32> {
+    use crate::schema::performers;
+
+    let existing: Option<i32> = performers::dsl::performers
+        .filter(lower(performers::dsl::name).eq(name.to_lowercase()))
+        .select(performers::dsl::id)
+        .first::<i32>(conn)
+        .optional()?;
+    if let Some(performer_id) = existing {
+        import.matched.performers.insert(performer_id);
+        return Ok(performer_id);
+    }
+
+    let new_performer = insert::NewPerformer {
+        name: name.to_string(),
+        description: None,
+        image_path: None,
+    };
+    let performer_id: i32 = diesel::insert_into(performers::dsl::performers)
+        .values(&new_performer)
+        .returning(performers::dsl::id)
+        .get_result(conn)?;
+    import.created.performers.insert(performer_id);
+    Ok(performer_id)
+}
+
+/// Find a piece by name sharing one of the given composers, or create one
+fn db_pieceid(
+    name: &str,
+    composer_ids: &[i32],
+    import: &mut FileImport,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<i32> {
+    use crate::schema::{piece_composers, pieces};
+
+    let mut query = pieces::dsl::pieces
+        .filter(lower(pieces::dsl::name).eq(name.to_lowercase()))
+        .select(pieces::dsl::id)
+        .into_boxed();
+    if !composer_ids.is_empty() {
+        query = query.filter(
+            pieces::dsl::id.eq_any(
+                piece_composers::dsl::piece_composers
+                    .filter(piece_composers::dsl::composer_id.eq_any(composer_ids.to_vec()))
+                    .select(piece_composers::dsl::piece_id),
+            ),
+        );
+    }
+    if let Some(piece_id) = query.first::<i32>(conn).optional()? {
+        import.matched.pieces.insert(piece_id);
+        return Ok(piece_id);
+    }
+
+    // insert the piece along with its composers
+    let new_piece = insert::NewPiece {
+        name: name.to_string(),
+        movements: None,
+        description: None,
+    };
+    let piece_id: i32 = diesel::insert_into(pieces::dsl::pieces)
+        .values(&new_piece)
+        .returning(pieces::dsl::id)
+        .get_result(conn)?;
+    for composer_id in composer_ids {
+        diesel::insert_into(piece_composers::table)
+            .values((
+                piece_composers::piece_id.eq(piece_id),
+                piece_composers::composer_id.eq(composer_id),
+            ))
+            .execute(conn)?;
+    }
+    import.created.pieces.insert(piece_id);
+    Ok(piece_id)
+}
+
+/// Find a release by name sharing one of the given performers, or create one
+fn db_releaseid(
+    name: &str,
+    performer_ids: &[i32],
+    import: &mut FileImport,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<i32> {
+    use crate::schema::{release_performers, releases};
+
+    let mut query = releases::dsl::releases
+        .filter(lower(releases::dsl::name).eq(name.to_lowercase()))
+        .select(releases::dsl::id)
+        .into_boxed();
+    if !performer_ids.is_empty() {
+        query = query.filter(
+            releases::dsl::id.eq_any(
+                release_performers::dsl::release_performers
+                    .filter(release_performers::dsl::performer_id.eq_any(performer_ids.to_vec()))
+                    .select(release_performers::dsl::release_id),
+            ),
+        );
+    }
+    if let Some(release_id) = query.first::<i32>(conn).optional()? {
+        import.matched.releases.insert(release_id);
+        return Ok(release_id);
+    }
+
+    // insert the release along with its performers
+    let new_release = insert::NewRelease {
+        name: name.to_string(),
+        description: None,
+        image_path: None,
+    };
+    let release_id: i32 = diesel::insert_into(releases::dsl::releases)
+        .values(&new_release)
+        .returning(releases::dsl::id)
+        .get_result(conn)?;
+    for performer_id in performer_ids {
+        diesel::insert_into(release_performers::table)
+            .values((
+                release_performers::release_id.eq(release_id),
+                release_performers::performer_id.eq(performer_id),
+            ))
+            .execute(conn)?;
+    }
+    import.created.releases.insert(release_id);
+    Ok(release_id)
+}
+
+/// Reasons a single file can fail to import
+enum ImportError {
+    Skip(String),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for ImportError {
+    fn from(err: diesel::result::Error) -> Self {
+        ImportError::Database(err)
+    }
+}
+
+impl ImportError {
+    /// Convert the error into the entry reported for the file
+    fn into_skipped(self, path: String) -> SkippedFile {
+        let reason: String = match self {
+            ImportError::Skip(reason) => reason,
+            ImportError::Database(err) => format!("Database error: {}", err),
+        };
+        SkippedFile { path, reason }
+    }
+}
+
+/// Link a recording to a file, storing its fingerprint and marking it available
+fn db_linkfile(
+    recording_id: i32,
+    file_path: &str,
+    fingerprint: &Fingerprint,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<usize> {
+    use crate::schema::recordings;
+
+    diesel::update(recordings::dsl::recordings.find(recording_id))
+        .set((
+            recordings::dsl::file_path.eq(file_path),
+            recordings::dsl::file_size.eq(fingerprint.stat.size),
+            recordings::dsl::file_modified.eq(fingerprint.stat.modified),
+            recordings::dsl::file_hash.eq(&fingerprint.hash),
+            recordings::dsl::available.eq(true),
+        ))
+        .execute(conn)
+}
+
+/// Check a file already linked to a recording against its stored fingerprint, returning whether
+/// the fingerprint had to be updated
+fn db_checklinkedfile(
+    recording: &DbRecording,
+    path: &Path,
+    rescan: bool,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<bool, ImportError> {
+    let file_path: &str = recording.file_path.as_deref().unwrap_or_default();
+    let stat: FileStat = FileStat::read(path)
+        .map_err(|err| ImportError::Skip(format!("Unreadable file: {}", err)))?;
+    let stat_matches: bool =
+        recording.file_size == Some(stat.size) && recording.file_modified == Some(stat.modified);
+
+    // a rescan trusts the size and modification time, and only hashes files that changed
+    if rescan && stat_matches && recording.file_hash.is_some() && recording.available {
+        return Ok(false);
+    }
+
+    let fingerprint: Fingerprint = Fingerprint::read(path)
+        .map_err(|err| ImportError::Skip(format!("Unreadable file: {}", err)))?;
+    if stat_matches
+        && recording.file_hash.as_ref() == Some(&fingerprint.hash)
+        && recording.available
+    {
+        return Ok(false);
+    }
+    db_linkfile(recording.id, file_path, &fingerprint, conn)?;
+    Ok(true)
+}
+
+/// Relink a recording whose file has disappeared to a new file with the same contents, returning
+/// the id of the relinked recording
+fn db_relinkfile(
+    file_path: &str,
+    fingerprint: &Fingerprint,
+    media_root: &Path,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<Option<i32>> {
+    use crate::schema::recordings;
+
+    let candidates: Vec<(i32, Option<String>)> = recordings::dsl::recordings
+        .filter(recordings::dsl::file_hash.eq(&fingerprint.hash))
+        .filter(recordings::dsl::file_size.eq(fingerprint.stat.size))
+        .order(recordings::dsl::id)
+        .select((recordings::dsl::id, recordings::dsl::file_path))
+        .load(conn)?;
+    for (recording_id, old_path) in candidates {
+        // a recording whose file is still in place has been copied rather than moved
+        let moved: bool = match old_path.and_then(|old_path| media::resolve(media_root, &old_path))
+        {
+            Some(old_path) => !old_path.is_file(),
+            None => true,
+        };
+        if moved {
+            db_linkfile(recording_id, file_path, fingerprint, conn)?;
+            return Ok(Some(recording_id));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Mark recordings linked to files under a directory as unavailable once their files are gone,
+/// returning their ids. Linked recordings are checked a batch at a time, so a large library is
+/// never held in memory at once.
+fn db_markunavailable(
+    media_root: &Path,
+    directory: &str,
+    seen: &HashSet<String>,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> QueryResult<BTreeSet<i32>> {
+    use crate::schema::recordings;
+
+    // only files under the scanned directory that the walk did not find can be missing
+    let prefix: Option<String> = match directory.is_empty() {
+        true => None,
+        false => Some(format!(
+            "{}/%",
+            directory
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        )),
+    };
+
+    let mut missing: BTreeSet<i32> = BTreeSet::new();
+    let mut after: i32 = i32::MIN;
+    loop {
+        let mut query = recordings::dsl::recordings
+            .filter(recordings::dsl::available.eq(true))
+            .filter(recordings::dsl::file_path.is_not_null())
+            .filter(recordings::dsl::id.gt(after))
+            .order(recordings::dsl::id)
+            .limit(UNAVAILABLE_BATCH_SIZE)
+            .select((recordings::dsl::id, recordings::dsl::file_path))
+            .into_boxed();
+        if let Some(prefix) = &prefix {
+            query = query.filter(recordings::dsl::file_path.like(prefix).escape('\\'));
+        }
+        let linked: Vec<(i32, Option<String>)> = query.load(conn)?;
+        let Some((last, _)) = linked.last() else {
+            break;
+        };
+        after = *last;
+
+        let batch: Vec<i32> = linked
+            .into_iter()
+            .filter_map(|(recording_id, file_path)| {
+                let file_path: String = file_path?;
+                if seen.contains(&file_path) {
+                    return None;
+                }
+                match media::resolve(media_root, &file_path) {
+                    Some(path) if path.is_file() => None,
+                    _ => Some(recording_id),
+                }
+            })
+            .collect();
+
+        // the paths are kept, so the files can be relinked if they come back
+        diesel::update(
+            recordings::dsl::recordings.filter(recordings::dsl::id.eq_any(batch.iter().copied())),
+        )
+        .set(recordings::dsl::available.eq(false))
+        .execute(conn)?;
+        missing.extend(batch);
+    }
+
+    Ok(missing)
+}
+
+/// Import the tags of one file as a recording, creating or reusing its related rows
+fn db_importfile(
+    file_path: &str,
+    fingerprint: &Fingerprint,
+    tags: &TrackTags,
+    import: &mut FileImport,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ImportError> {
+    use crate::schema::{recording_performers, recordings};
+
+    // the piece and release are required to place the recording in the catalog
+    let piece_name: &String = tags
+        .piece_name()
+        .ok_or_else(|| ImportError::Skip("Missing title or work tag".to_string()))?;
+    let release_name: &String = tags
+        .album
+        .as_ref()
+        .ok_or_else(|| ImportError::Skip("Missing album tag".to_string()))?;
+
+    // find or create the related artists, piece and release
+    let mut composer_ids: Vec<i32> = Vec::new();
+    for name in &tags.composers {
+        composer_ids.push(db_composerid(name, import, conn)?);
+    }
+    let mut release_performer_ids: Vec<i32> = Vec::new();
+    for name in tags.release_performers() {
+        release_performer_ids.push(db_performerid(name, import, conn)?);
+    }
+    let mut recording_performer_ids: Vec<i32> = Vec::new();
+    for name in tags.recording_performers() {
+        recording_performer_ids.push(db_performerid(name, import, conn)?);
+    }
+    let piece_id: i32 = db_pieceid(piece_name, &composer_ids, import, conn)?;
+    let release_id: i32 = db_releaseid(release_name, &release_performer_ids, import, conn)?;
+
+    // without a track number, append the recording to the end of the release
+    let track_number: i32 = match tags.position() {
+        Some(track_number) => track_number,
+        None => {
+            let last: Option<i32> = recordings::dsl::recordings
+                .filter(recordings::dsl::release_id.eq(release_id))
+                .select(diesel::dsl::max(recordings::dsl::track_number))
+                .first(conn)?;
+            last.unwrap_or(0) + 1
+        }
+    };
+
+    // reuse a recording already at this position on the release
+    let existing: Option<(i32, Option<String>)> = recordings::dsl::recordings
+        .filter(recordings::dsl::release_id.eq(release_id))
+        .filter(recordings::dsl::track_number.eq(track_number))
+        .select((recordings::dsl::id, recordings::dsl::file_path))
+        .first(conn)
+        .optional()?;
+    if let Some((recording_id, existing_path)) = existing {
+        return match existing_path {
+            Some(existing_path) if existing_path != file_path => Err(ImportError::Skip(format!(
+                "Track {} of release {} is already linked to {}",
+                track_number, release_id, existing_path
+            ))),
+            _ => {
+                db_linkfile(recording_id, file_path, fingerprint, conn)?;
+                import.matched.recordings.insert(recording_id);
+                Ok(())
+            }
+        };
+    }
+
+    // insert the recording with its performers
+    let new_recording = insert::NewRecording {
+        piece_name: tags
+            .movement_name
+            .as_ref()
+            .or(tags.title.as_ref())
+            .unwrap_or(piece_name)
+            .clone(),
+        piece_id,
+        release_id,
+        track_number,
+        file_path: Some(file_path.to_string()),
+        file_size: Some(fingerprint.stat.size),
+        file_modified: Some(fingerprint.stat.modified),
+        file_hash: Some(fingerprint.hash.clone()),
+    };
+    let recording_id: i32 = diesel::insert_into(recordings::dsl::recordings)
+        .values(&new_recording)
+        .returning(recordings::dsl::id)
+        .get_result(conn)?;
+    for performer_id in recording_performer_ids {
+        diesel::insert_into(recording_performers::table)
+            .values((
+                recording_performers::recording_id.eq(recording_id),
+                recording_performers::performer_id.eq(performer_id),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    import.created.recordings.insert(recording_id);
+    Ok(())
+}
+
+/// Convert a path relative to the media root into the form stored in the database
+fn relative_file_path(media_root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(media_root).ok()?;
+    let mut parts: Vec<&str> = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Walk a directory under the media root and import every audio file found in it, relinking moved
+/// files and marking recordings whose files are gone as unavailable. A rescan only hashes files
+/// whose size or modification time changed, while a full scan verifies every file.
+pub fn scan_library(
+    media_root: &Path,
+    directory: &Path,
+    rescan: bool,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> ScanReport {
+    use crate::schema::recordings;
+
+    let mut report = ScanReport::default();
+
+    // compare canonical paths, so the directory may be given relative to anywhere
+    let (media_root, directory) = match (media_root.canonicalize(), directory.canonicalize()) {
+        (Ok(media_root), Ok(directory)) if directory.starts_with(&media_root) => {
+            (media_root, directory)
+        }
+        _ => {
+            report.skipped.push(SkippedFile {
+                path: directory.display().to_string(),
+                reason: "Directory does not exist under the media root".to_string(),
+            });
+            return report;
+        }
+    };
+
+    let mut seen: HashSet<String> = HashSet::new();
+    for entry in WalkDir::new(&directory)
+        .follow_links(true)
+        .sort_by_file_name()
+    {
+        // skip entries that cannot be read, hidden files, and non-audio files
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                report.skipped.push(SkippedFile {
+                    path: err
+                        .path()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let path: &Path = entry.path();
+        let is_audio: bool = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+            .unwrap_or(false);
+        if !entry.file_type().is_file()
+            || !is_audio
+            || entry.file_name().to_string_lossy().starts_with('.')
+        {
+            continue;
+        }
+
+        // files are stored relative to the media root
+        let file_path: String = match relative_file_path(&media_root, path) {
+            Some(file_path) => file_path,
+            None => {
+                report.skipped.push(SkippedFile {
+                    path: path.display().to_string(),
+                    reason: "Path is not valid UTF-8 under the media root".to_string(),
+                });
+                continue;
+            }
+        };
+
+        seen.insert(file_path.clone());
+
+        // files that are already linked to a recording only need their fingerprint checked
+        let linked: Option<DbRecording> = match recordings::dsl::recordings
+            .filter(recordings::dsl::file_path.eq(&file_path))
+            .first(conn)
+            .optional()
+        {
+            Ok(linked) => linked,
+            Err(err) => {
+                report
+                    .skipped
+                    .push(ImportError::Database(err).into_skipped(file_path));
+                continue;
+            }
+        };
+        if let Some(recording) = linked {
+            match db_checklinkedfile(&recording, path, rescan, conn) {
+                Ok(true) => {
+                    report.updated.insert(recording.id);
+                }
+                Ok(false) => {
+                    report.matched.recordings.insert(recording.id);
+                }
+                Err(err) => report.skipped.push(err.into_skipped(file_path)),
+            }
+            continue;
+        }
+
+        // hash new files, so that moved files are relinked rather than imported again
+        let fingerprint: Fingerprint = match Fingerprint::read(path) {
+            Ok(fingerprint) => fingerprint,
+            Err(err) => {
+                report.skipped.push(SkippedFile {
+                    path: file_path,
+                    reason: format!("Unreadable file: {}", err),
+                });
+                continue;
+            }
+        };
+        match db_relinkfile(&file_path, &fingerprint, &media_root, conn) {
+            Ok(Some(recording_id)) => {
+                report.relinked.insert(recording_id);
+                continue;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                report
+                    .skipped
+                    .push(ImportError::Database(err).into_skipped(file_path));
+                continue;
+            }
+        }
+
+        // read the tags of the file
+        let tags: TrackTags = match read_tags(path) {
+            Ok(tags) => tags,
+            Err(err) => {
+                report.skipped.push(SkippedFile {
+                    path: file_path,
+                    reason: format!("Unreadable tags: {}", err),
+                });
+                continue;
+            }
+        };
+
+        // import each file in its own transaction, so failures leave nothing behind
+        let mut import = FileImport::default();
+        let result = conn.transaction::<(), ImportError, _>(|conn| {
+            db_importfile(&file_path, &fingerprint, &tags, &mut import, conn)
+        });
+        match result {
+            Ok(()) => {
+                report.created.extend(import.created);
+                report.matched.extend(import.matched);
+            }
+            Err(err) => report.skipped.push(err.into_skipped(file_path)),
+        }
+    }
+
+    // recordings whose files were not found anywhere in the directory are no longer available
+    let scope: String = relative_file_path(&media_root, &directory).unwrap_or_default();
+    match db_markunavailable(&media_root, &scope, &seen, conn) {
+        Ok(unavailable) => report.unavailable = unavailable,
+        Err(err) => report.skipped.push(SkippedFile {
+            path: directory.display().to_string(),
+            reason: format!("Database error: {}", err),
+        }),
+    }
+
+    // rows created earlier in the scan are not also reported as matched
+    let created = &report.created;
+    report
+        .matched
+        .composers
+        .retain(|id| !created.composers.contains(id));
+    report
+        .matched
+        .performers
+        .retain(|id| !created.performers.contains(id));
+    report
+        .matched
+        .pieces
+        .retain(|id| !created.pieces.contains(id));
+    report
+        .matched
+        .releases
+        .retain(|id| !created.releases.contains(id));
+    report
+        .matched
+        .recordings
+        .retain(|id| !created.recordings.contains(id));
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+    use std::fs;
+    use std::path::PathBuf;
+    use symphonia::core::meta::Value;
+    use uuid::Uuid;
+
+    /// Build a tag as a container would report it
+    fn tag(std_key: Option<StandardTagKey>, key: &str, value: &str) -> Tag {
+        Tag::new(std_key, key, Value::String(value.to_string()))
+    }
+
+    #[test]
+    fn splits_and_deduplicates_names() {
+        let mut names: Vec<String> = vec!["Berliner Philharmoniker".to_string()];
+        push_names(
+            &mut names,
+            "Herbert von Karajan; berliner philharmoniker\0Anne-Sophie Mutter;;",
+        );
+        assert_eq!(
+            names,
+            vec![
+                "Berliner Philharmoniker",
+                "Herbert von Karajan",
+                "Anne-Sophie Mutter"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_track_numbers() {
+        assert_eq!(parse_track_number("3"), Some(3));
+        assert_eq!(parse_track_number(" 7 /12"), Some(7));
+        assert_eq!(parse_track_number("/12"), None);
+        assert_eq!(parse_track_number("A1"), None);
+        assert_eq!(parse_track_number(""), None);
+    }
+
+    #[test]
+    fn places_tracks_after_the_discs_before_them() {
+        let mut tags = TrackTags::default();
+        assert_eq!(tags.position(), None);
+        tags.apply(&[
+            tag(Some(StandardTagKey::TrackNumber), "TRCK", "3/9"),
+            tag(Some(StandardTagKey::DiscNumber), "TPOS", "2/2"),
+        ]);
+        assert_eq!(tags.disc_number, Some(2));
+        assert_eq!(tags.position(), Some(1003));
+
+        tags.disc_number = None;
+        assert_eq!(tags.position(), Some(3));
+        tags.disc_number = Some(i32::MAX);
+        assert_eq!(tags.position(), None);
+    }
+
+    #[test]
+    fn merges_tags() {
+        let mut tags = TrackTags::default();
+        tags.apply(&[
+            tag(
+                Some(StandardTagKey::TrackTitle),
+                "TITLE",
+                "I. Allegro con brio",
+            ),
+            tag(Some(StandardTagKey::Album), "ALBUM", "  "),
+            tag(Some(StandardTagKey::Artist), "ARTIST", "Carlos Kleiber"),
+            tag(
+                Some(StandardTagKey::Conductor),
+                "CONDUCTOR",
+                "Carlos Kleiber",
+            ),
+            tag(
+                Some(StandardTagKey::Ensemble),
+                "ENSEMBLE",
+                "Wiener Philharmoniker",
+            ),
+            tag(
+                Some(StandardTagKey::Composer),
+                "COMPOSER",
+                "Ludwig van Beethoven",
+            ),
+            tag(Some(StandardTagKey::TrackNumber), "TRACKNUMBER", "1/4"),
+            tag(None, "WORK", "Symphony No. 5 in C minor, Op. 67"),
+        ]);
+        // tags found later, such as those inside the container, never override earlier ones
+        tags.apply(&[
+            tag(Some(StandardTagKey::TrackTitle), "TITLE", "Allegro"),
+            tag(Some(StandardTagKey::Album), "ALBUM", "Symphonies 5 & 7"),
+            tag(Some(StandardTagKey::TrackNumber), "TRACKNUMBER", "2"),
+            tag(None, "TXXX:MOVEMENTNAME", "Allegro con brio"),
+            tag(None, "TIT1", "Symphony No. 7"),
+        ]);
+
+        assert_eq!(tags.title.as_deref(), Some("I. Allegro con brio"));
+        assert_eq!(tags.album.as_deref(), Some("Symphonies 5 & 7"));
+        assert_eq!(tags.track_number, Some(1));
+        assert_eq!(tags.movement_name.as_deref(), Some("Allegro con brio"));
+        assert_eq!(tags.composers, vec!["Ludwig van Beethoven"]);
+        assert_eq!(
+            tags.performers,
+            vec!["Carlos Kleiber", "Wiener Philharmoniker"]
+        );
+        assert_eq!(
+            tags.piece_name().map(String::as_str),
+            Some("Symphony No. 5 in C minor, Op. 67")
+        );
+        assert_eq!(tags.recording_performers(), &tags.performers);
+        assert_eq!(tags.release_performers(), &tags.artists);
+    }
+
+    #[test]
+    fn falls_back_to_the_title_and_artists() {
+        let mut tags = TrackTags::default();
+        tags.apply(&[
+            tag(Some(StandardTagKey::TrackTitle), "TITLE", "Clair de lune"),
+            tag(Some(StandardTagKey::Artist), "ARTIST", "Claudio Arrau"),
+            tag(Some(StandardTagKey::AlbumArtist), "ALBUMARTIST", "Various"),
+        ]);
+        assert_eq!(tags.piece_name().map(String::as_str), Some("Clair de lune"));
+        assert_eq!(
+            tags.recording_performers(),
+            &vec!["Claudio Arrau".to_string()]
+        );
+        assert_eq!(tags.release_performers(), &vec!["Various".to_string()]);
+        assert_eq!(tags.track_number, None);
+    }
+
+    #[test]
+    fn stores_paths_relative_to_the_media_root() {
+        let media_root: &Path = Path::new("/srv/music");
+        assert_eq!(
+            relative_file_path(media_root, Path::new("/srv/music/Kleiber/05 Allegro.flac")),
+            Some("Kleiber/05 Allegro.flac".to_string())
+        );
+        assert_eq!(
+            relative_file_path(media_root, Path::new("/srv/music")),
+            Some(String::new())
+        );
+        assert_eq!(
+            relative_file_path(media_root, Path::new("/srv/other/track.flac")),
+            None
+        );
+        assert_eq!(
+            relative_file_path(media_root, Path::new("/srv/music/../other/track.flac")),
+            None
+        );
+    }
+
+    /// A media root of its own for a test, removed once the test is done with it
+    struct MediaRoot(PathBuf);
+
+    impl MediaRoot {
+        /// Create an empty media root in the temporary directory
+        fn new() -> Self {
+            let path: PathBuf = std::env::temp_dir().join(format!("allegro-{}", Uuid::new_v4()));
+            fs::create_dir(&path).unwrap();
+            MediaRoot(path.canonicalize().unwrap())
+        }
+
+        /// Write a file under the media root, creating its directories
+        fn write(&self, file_path: &str, contents: &str) -> PathBuf {
+            let path: PathBuf = self.0.join(file_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for MediaRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Add a recording linked to a file with the given fingerprint
+    fn recording(
+        file_path: &str,
+        fingerprint: &Fingerprint,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> i32 {
+        use crate::schema::{pieces, recordings, releases};
+
+        let piece_id: i32 = diesel::insert_into(pieces::table)
+            .values(pieces::name.eq("Das wohltemperierte Klavier"))
+            .returning(pieces::id)
+            .get_result(conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("The Well-Tempered Clavier"))
+            .returning(releases::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(recordings::table)
+            .values((
+                recordings::piece_name.eq("Prelude in C major"),
+                recordings::piece_id.eq(piece_id),
+                recordings::release_id.eq(release_id),
+                recordings::track_number.eq(1),
+                recordings::file_path.eq(file_path),
+                recordings::file_size.eq(fingerprint.stat.size),
+                recordings::file_modified.eq(fingerprint.stat.modified),
+                recordings::file_hash.eq(&fingerprint.hash),
+            ))
+            .returning(recordings::id)
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// The stored path, hash and availability of a recording
+    fn linked(
+        recording_id: i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> (Option<String>, Option<String>, bool) {
+        use crate::schema::recordings;
+
+        recordings::dsl::recordings
+            .find(recording_id)
+            .select((
+                recordings::dsl::file_path,
+                recordings::dsl::file_hash,
+                recordings::dsl::available,
+            ))
+            .first(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn rescans_only_files_that_changed() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let root = MediaRoot::new();
+        let path: PathBuf = root.write("bach/01.flac", "prelude");
+        let fingerprint: Fingerprint = Fingerprint::read(&path).unwrap();
+        let recording_id: i32 = recording(
+            "bach/01.flac",
+            &Fingerprint {
+                hash: "stale".to_string(),
+                ..fingerprint.clone()
+            },
+            &mut conn,
+        );
+
+        // a rescan trusts an unchanged size and modification time
+        let report: ScanReport = scan_library(&root.0, &root.0, true, &mut conn);
+        assert!(report.matched.recordings.contains(&recording_id));
+        assert!(report.updated.is_empty());
+        assert_eq!(linked(recording_id, &mut conn).1.as_deref(), Some("stale"));
+
+        // a full scan hashes every file
+        let report: ScanReport = scan_library(&root.0, &root.0, false, &mut conn);
+        assert!(report.updated.contains(&recording_id));
+        assert_eq!(linked(recording_id, &mut conn).1, Some(fingerprint.hash));
+
+        // a rescan hashes a file once it is rewritten
+        root.write("bach/01.flac", "prelude and fugue");
+        let rewritten: Fingerprint = Fingerprint::read(&path).unwrap();
+        let report: ScanReport = scan_library(&root.0, &root.0, true, &mut conn);
+        assert!(report.updated.contains(&recording_id));
+        assert_eq!(linked(recording_id, &mut conn).1, Some(rewritten.hash));
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn relinks_moved_files_by_their_hash() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let root = MediaRoot::new();
+        let path: PathBuf = root.write("bach/book 1/01.flac", "prelude");
+        let fingerprint: Fingerprint = Fingerprint::read(&path).unwrap();
+        let recording_id: i32 = recording("bach/01.flac", &fingerprint, &mut conn);
+
+        let report: ScanReport = scan_library(&root.0, &root.0, true, &mut conn);
+        assert_eq!(report.relinked, BTreeSet::from([recording_id]));
+        assert!(report.created.recordings.is_empty());
+        assert!(report.unavailable.is_empty());
+        assert_eq!(
+            linked(recording_id, &mut conn),
+            (
+                Some("bach/book 1/01.flac".to_string()),
+                Some(fingerprint.hash),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn marks_missing_files_unavailable_only_under_the_scanned_directory() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let root = MediaRoot::new();
+        let path: PathBuf = root.write("disc_1/01.flac", "prelude");
+        let fingerprint: Fingerprint = Fingerprint::read(&path).unwrap();
+        let present: i32 = recording("disc_1/01.flac", &fingerprint, &mut conn);
+        let gone: i32 = recording("disc_1/02.flac", &fingerprint, &mut conn);
+        // an underscore in the directory only matches itself
+        let elsewhere: i32 = recording("discA1/02.flac", &fingerprint, &mut conn);
+
+        let report: ScanReport = scan_library(&root.0, &root.0.join("disc_1"), true, &mut conn);
+        assert_eq!(report.unavailable, BTreeSet::from([gone]));
+        assert!(linked(present, &mut conn).2);
+        assert!(linked(elsewhere, &mut conn).2);
+
+        // the path is kept, so the file is linked again once it comes back
+        let (file_path, _, available) = linked(gone, &mut conn);
+        assert_eq!(file_path.as_deref(), Some("disc_1/02.flac"));
+        assert!(!available);
+        root.write("disc_1/02.flac", "prelude");
+        let report: ScanReport = scan_library(&root.0, &root.0.join("disc_1"), true, &mut conn);
+        assert!(report.updated.contains(&gone));
+        assert!(linked(gone, &mut conn).2);
+    }
+
+    #[test]
+    fn imports_the_same_track_of_each_disc() {
+        use crate::schema::recordings;
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let root = MediaRoot::new();
+        let mut recording_ids: Vec<i32> = Vec::new();
+        for disc in ["1", "2"] {
+            let file_path: String = format!("disc_{}/01.flac", disc);
+            let fingerprint: Fingerprint =
+                Fingerprint::read(&root.write(&file_path, disc)).unwrap();
+            let mut tags = TrackTags::default();
+            tags.apply(&[
+                tag(Some(StandardTagKey::TrackTitle), "TITLE", "Kyrie"),
+                tag(Some(StandardTagKey::Album), "ALBUM", "Mass in B minor"),
+                tag(Some(StandardTagKey::TrackNumber), "TRACKNUMBER", "1"),
+                tag(Some(StandardTagKey::DiscNumber), "DISCNUMBER", disc),
+            ]);
+            let mut import = FileImport::default();
+            assert!(db_importfile(&file_path, &fingerprint, &tags, &mut import, &mut conn).is_ok());
+            recording_ids.extend(import.created.recordings);
+        }
+
+        // both tracks are recordings of their own on the one release
+        let positions: Vec<(i32, i32, Option<String>)> = recordings::table
+            .filter(recordings::id.eq_any(&recording_ids))
+            .select((
+                recordings::release_id,
+                recordings::track_number,
+                recordings::file_path,
+            ))
+            .order(recordings::track_number)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].0, positions[1].0);
+        assert_eq!(
+            positions
+                .into_iter()
+                .map(|(_, track_number, file_path)| (track_number, file_path.unwrap()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, "disc_1/01.flac".to_string()),
+                (1001, "disc_2/01.flac".to_string())
+            ]
+        );
+    }
+}
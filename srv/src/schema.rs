@@ -1,10 +1,20 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
-    admin (id) {
+    api_keys (id) {
         id -> Int4,
-        #[max_length = 50]
-        username -> Varchar,
+        user_id -> Int4,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 64]
+        key_hash -> Bpchar,
+        #[max_length = 16]
+        prefix -> Varchar,
+        #[max_length = 16]
+        scope -> Varchar,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        expires_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -14,6 +24,51 @@ diesel::table! {
         name -> Varchar,
         description -> Nullable<Text>,
         image_path -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    library_entries (id) {
+        id -> Int4,
+        user_id -> Int4,
+        recording_id -> Nullable<Int4>,
+        release_id -> Nullable<Int4>,
+        piece_id -> Nullable<Int4>,
+        performer_id -> Nullable<Int4>,
+        composer_id -> Nullable<Int4>,
+        songwriter_id -> Nullable<Int4>,
+        favorited_at -> Nullable<Timestamptz>,
+        rating -> Nullable<Int2>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    login_failures (id) {
+        id -> Int4,
+        #[max_length = 255]
+        username -> Varchar,
+        #[max_length = 64]
+        ip_address -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        #[max_length = 32]
+        reason -> Varchar,
+        attempted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    login_throttles (scope, subject) {
+        #[max_length = 16]
+        scope -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        failures -> Int4,
+        last_failure_at -> Timestamptz,
+        blocked_until -> Timestamptz,
+        refused -> Int4,
     }
 }
 
@@ -23,6 +78,7 @@ diesel::table! {
         name -> Varchar,
         description -> Nullable<Text>,
         image_path -> Nullable<Varchar>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -46,6 +102,61 @@ diesel::table! {
         name -> Varchar,
         movements -> Nullable<Int4>,
         description -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    play_events (id) {
+        id -> Int4,
+        play_id -> Int4,
+        #[max_length = 16]
+        event -> Varchar,
+        position_seconds -> Int4,
+        occurred_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    playlist_entries (id) {
+        id -> Int4,
+        playlist_id -> Int4,
+        recording_id -> Int4,
+        position -> Int4,
+        added_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    playlist_shares (playlist_id, user_id) {
+        playlist_id -> Int4,
+        user_id -> Int4,
+    }
+}
+
+diesel::table! {
+    playlists (id) {
+        id -> Int4,
+        owner_id -> Int4,
+        #[max_length = 255]
+        name -> Varchar,
+        description -> Nullable<Text>,
+        public -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    plays (id) {
+        id -> Int4,
+        user_id -> Int4,
+        recording_id -> Int4,
+        #[max_length = 16]
+        state -> Varchar,
+        listened_seconds -> Int4,
+        started_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -64,6 +175,11 @@ diesel::table! {
         release_id -> Int4,
         track_number -> Int4,
         file_path -> Nullable<Varchar>,
+        file_size -> Nullable<Int8>,
+        file_modified -> Nullable<Timestamptz>,
+        #[max_length = 64]
+        file_hash -> Nullable<Varchar>,
+        available -> Bool,
     }
 }
 
@@ -80,6 +196,65 @@ diesel::table! {
         name -> Varchar,
         description -> Nullable<Text>,
         image_path -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    role_permissions (role, permission) {
+        #[max_length = 50]
+        role -> Varchar,
+        #[max_length = 50]
+        permission -> Varchar,
+    }
+}
+
+diesel::table! {
+    roles (name) {
+        #[max_length = 50]
+        name -> Varchar,
+        description -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    scrobble_outbox (id) {
+        id -> Int4,
+        user_id -> Int4,
+        play_id -> Int4,
+        attempts -> Int4,
+        next_attempt_at -> Timestamptz,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    scrobble_targets (user_id) {
+        user_id -> Int4,
+        #[max_length = 2048]
+        submit_url -> Varchar,
+        encrypted_token -> Text,
+        enabled -> Bool,
+        last_submitted_at -> Nullable<Timestamptz>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Bpchar,
+        created_at -> Timestamptz,
+        last_used_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        user_agent -> Nullable<Text>,
+        #[max_length = 64]
+        ip_address -> Nullable<Varchar>,
     }
 }
 
@@ -89,6 +264,15 @@ diesel::table! {
         name -> Varchar,
         description -> Nullable<Text>,
         image_path -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    subsonic_passwords (user_id) {
+        user_id -> Int4,
+        encrypted_password -> Text,
+        created_at -> Timestamptz,
     }
 }
 
@@ -100,35 +284,81 @@ diesel::table! {
         #[max_length = 255]
         password_hash -> Varchar,
         #[max_length = 255]
-        salt -> Varchar,
-        #[max_length = 255]
-        session -> Nullable<Varchar>,
-        created_at -> Nullable<Timestamptz>,
+        salt -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        disabled_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    user_roles (user_id, role) {
+        user_id -> Int4,
+        #[max_length = 50]
+        role -> Varchar,
     }
 }
 
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(library_entries -> composers (composer_id));
+diesel::joinable!(library_entries -> performers (performer_id));
+diesel::joinable!(library_entries -> pieces (piece_id));
+diesel::joinable!(library_entries -> recordings (recording_id));
+diesel::joinable!(library_entries -> releases (release_id));
+diesel::joinable!(library_entries -> songwriters (songwriter_id));
+diesel::joinable!(library_entries -> users (user_id));
 diesel::joinable!(piece_composers -> composers (composer_id));
 diesel::joinable!(piece_composers -> pieces (piece_id));
 diesel::joinable!(piece_songwriters -> pieces (piece_id));
 diesel::joinable!(piece_songwriters -> songwriters (songwriter_id));
+diesel::joinable!(play_events -> plays (play_id));
+diesel::joinable!(playlist_entries -> playlists (playlist_id));
+diesel::joinable!(playlist_entries -> recordings (recording_id));
+diesel::joinable!(playlist_shares -> playlists (playlist_id));
+diesel::joinable!(playlist_shares -> users (user_id));
+diesel::joinable!(playlists -> users (owner_id));
+diesel::joinable!(plays -> recordings (recording_id));
+diesel::joinable!(plays -> users (user_id));
 diesel::joinable!(recording_performers -> performers (performer_id));
 diesel::joinable!(recording_performers -> recordings (recording_id));
 diesel::joinable!(recordings -> pieces (piece_id));
 diesel::joinable!(recordings -> releases (release_id));
 diesel::joinable!(release_performers -> performers (performer_id));
 diesel::joinable!(release_performers -> releases (release_id));
+diesel::joinable!(role_permissions -> roles (role));
+diesel::joinable!(scrobble_outbox -> plays (play_id));
+diesel::joinable!(scrobble_outbox -> scrobble_targets (user_id));
+diesel::joinable!(scrobble_targets -> users (user_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(subsonic_passwords -> users (user_id));
+diesel::joinable!(user_roles -> roles (role));
+diesel::joinable!(user_roles -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
-    admin,
+    api_keys,
     composers,
+    library_entries,
+    login_failures,
+    login_throttles,
     performers,
     piece_composers,
     piece_songwriters,
     pieces,
+    play_events,
+    playlist_entries,
+    playlist_shares,
+    playlists,
+    plays,
     recording_performers,
     recordings,
     release_performers,
     releases,
+    role_permissions,
+    roles,
+    scrobble_outbox,
+    scrobble_targets,
+    sessions,
     songwriters,
+    subsonic_passwords,
+    user_roles,
     users,
 );
@@ -0,0 +1,779 @@
+use crate::error::ApiError;
+use crate::models::{Composer, DbPiece, DbRecording, DbScrobbleTarget, Performer};
+use crate::password;
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::thread;
+
+/// Submission URL of ListenBrainz itself, used when a user does not give one
+pub const LISTENBRAINZ_URL: &str = "https://api.listenbrainz.org/1/submit-listens";
+
+// most listens sent to a target in one submission
+const BATCH_SIZE: i64 = 100;
+
+// seconds a worker holds the listens it is submitting before another may try them
+const CLAIM_SECONDS: i64 = 5 * 60;
+
+// longest wait between attempts at submitting a listen, in seconds
+const MAX_BACKOFF: i64 = 6 * 60 * 60;
+
+// attempts after which a listen is given up on, a little over a week at the longest backoff
+const MAX_ATTEMPTS: i32 = 40;
+
+// seconds to wait for a target to answer
+const TIMEOUT: u64 = 30;
+
+// longest error message kept from a target
+const MAX_ERROR_LEN: usize = 500;
+
+/// A submission of listens to a ListenBrainz-compatible server
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Submission {
+    pub listen_type: String,
+    pub payload: Vec<Listen>,
+}
+
+/// One play of a recording as ListenBrainz describes it
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Listen {
+    pub listened_at: i64,
+    pub track_metadata: TrackMetadata,
+}
+
+/// What was played, with performers as the artist
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    pub release_name: String,
+    pub additional_info: AdditionalInfo,
+}
+
+/// Classical metadata that ListenBrainz has no fields of its own for
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AdditionalInfo {
+    pub submission_client: String,
+    pub submission_client_version: String,
+    pub tracknumber: i32,
+    pub work_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub work_movements: Option<i32>,
+    pub composers: Vec<String>,
+    pub performers: Vec<String>,
+    pub artist_names: Vec<String>,
+}
+
+/// Whether an address is on the public internet rather than a network the server can reach
+/// but its users should not
+fn public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => public_v4(mapped),
+            None => public_v6(ip),
+        },
+    }
+}
+
+fn public_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // shared address space of carrier-grade NAT, and addresses reserved for the future
+        || (first == 100 && (64..128).contains(&second))
+        || first >= 240
+        || first == 0)
+}
+
+fn public_v6(ip: Ipv6Addr) -> bool {
+    let first: u16 = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local and link-local addresses
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Resolve the address of a target, keeping only public addresses so users cannot make the
+/// server send requests into its own network
+pub fn resolve_public(netloc: &str) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = netloc
+        .to_socket_addrs()?
+        .filter(|addr| public(addr.ip()))
+        .collect();
+    match addrs.is_empty() {
+        true => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Target has no public address",
+        )),
+        false => Ok(addrs),
+    }
+}
+
+/// Resolve the address of a target, keeping only public addresses unless private ones are
+/// allowed
+fn resolve(netloc: &str, allow_private: bool) -> io::Result<Vec<SocketAddr>> {
+    match allow_private {
+        true => Ok(netloc.to_socket_addrs()?.collect()),
+        false => resolve_public(netloc),
+    }
+}
+
+/// Check that a submission URL is well formed and points to a public address, or to any
+/// address when private ones are allowed
+pub fn check_url(url: &str, allow_private: bool) -> Result<(), ApiError> {
+    let invalid = || ApiError::Validation("Submission URL is not valid".to_string());
+    let request_url: ureq::RequestUrl = ureq::post(url).request_url().map_err(|_| invalid())?;
+    let port: u16 = match (request_url.port(), request_url.scheme()) {
+        (Some(port), _) => port,
+        (None, "https") => 443,
+        (None, _) => 80,
+    };
+    let netloc: String = format!("{}:{}", request_url.host(), port);
+    resolve(&netloc, allow_private).map_err(|err| match err.kind() {
+        io::ErrorKind::PermissionDenied => {
+            ApiError::Validation("Submission URL must point to a public address".to_string())
+        }
+        _ => ApiError::Validation("Submission URL host could not be resolved".to_string()),
+    })?;
+    Ok(())
+}
+
+/// Build the client submissions are sent with, which only connects to public addresses unless
+/// private ones are allowed, and does not follow redirects elsewhere
+fn agent(allow_private: bool) -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_secs(TIMEOUT))
+        .user_agent(&format!("allegro/{}", env!("CARGO_PKG_VERSION")))
+        .resolver(move |netloc: &str| resolve(netloc, allow_private))
+        .redirects(0)
+        .build()
+}
+
+/// Queue a play to be forwarded if its user forwards their plays. Plays are only queued once.
+pub fn enqueue(
+    user_id: i32,
+    play_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{scrobble_outbox, scrobble_targets};
+
+    let forwarding: bool = scrobble_targets::dsl::scrobble_targets
+        .filter(scrobble_targets::dsl::user_id.eq(user_id))
+        .filter(scrobble_targets::dsl::enabled.eq(true))
+        .select(scrobble_targets::dsl::user_id)
+        .first::<i32>(conn)
+        .optional()?
+        .is_some();
+    if forwarding {
+        diesel::insert_into(scrobble_outbox::dsl::scrobble_outbox)
+            .values((
+                scrobble_outbox::dsl::user_id.eq(user_id),
+                scrobble_outbox::dsl::play_id.eq(play_id),
+            ))
+            .on_conflict(scrobble_outbox::dsl::play_id)
+            .do_nothing()
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Submit the queued plays of every user that are due, forever, pausing between rounds
+pub fn run(
+    pool: Pool<ConnectionManager<PgConnection>>,
+    key: [u8; 32],
+    interval: u64,
+    allow_private: bool,
+) {
+    let agent: ureq::Agent = agent(allow_private);
+    loop {
+        match pool.get() {
+            Ok(mut conn) => {
+                if let Err(err) = submit_due(&agent, &key, &mut conn) {
+                    log::error!("Failed to forward plays: {}", err);
+                }
+            }
+            Err(err) => log::error!("Failed to forward plays: {}", err),
+        }
+        thread::sleep(std::time::Duration::from_secs(interval));
+    }
+}
+
+/// Submit one batch of due plays for every user with some waiting
+fn submit_due(
+    agent: &ureq::Agent,
+    key: &[u8; 32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{scrobble_outbox, scrobble_targets};
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    let targets: Vec<DbScrobbleTarget> = scrobble_targets::dsl::scrobble_targets
+        .filter(scrobble_targets::dsl::enabled.eq(true))
+        .filter(diesel::dsl::exists(
+            scrobble_outbox::dsl::scrobble_outbox
+                .filter(scrobble_outbox::dsl::user_id.eq(scrobble_targets::dsl::user_id))
+                .filter(scrobble_outbox::dsl::next_attempt_at.le(now)),
+        ))
+        .select(DbScrobbleTarget::as_select())
+        .load::<DbScrobbleTarget>(conn)?;
+
+    for target in targets {
+        let claimed: Vec<i32> = claim(target.user_id, conn)?;
+        if claimed.is_empty() {
+            continue;
+        }
+
+        // a token that no longer decrypts cannot succeed, so it is reported like a failed try
+        let result: Result<(), String> = match password::decrypt(key, &target.encrypted_token) {
+            Some(token) => {
+                let submission: Submission = db_submission(&claimed, conn)?;
+                submit(agent, &target.submit_url, &token, &submission)
+            }
+            None => Err("Token could not be decrypted, set it again".to_string()),
+        };
+        match result {
+            Ok(()) => db_submitted(target.user_id, &claimed, conn)?,
+            Err(err) => {
+                log::warn!(
+                    "Failed to forward plays of user {}: {}",
+                    target.user_id,
+                    err
+                );
+                db_failed(target.user_id, &claimed, &err, conn)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Claim the next batch of due plays of a user, so other workers leave them alone while they
+/// are submitted
+fn claim(
+    user_id: i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<i32>, ApiError> {
+    use crate::schema::scrobble_outbox;
+
+    conn.transaction::<Vec<i32>, ApiError, _>(|conn| {
+        let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+        let ids: Vec<i32> = scrobble_outbox::dsl::scrobble_outbox
+            .filter(scrobble_outbox::dsl::user_id.eq(user_id))
+            .filter(scrobble_outbox::dsl::next_attempt_at.le(now))
+            .order(scrobble_outbox::dsl::id)
+            .limit(BATCH_SIZE)
+            .select(scrobble_outbox::dsl::id)
+            .for_update()
+            .skip_locked()
+            .load::<i32>(conn)?;
+        diesel::update(scrobble_outbox::dsl::scrobble_outbox)
+            .filter(scrobble_outbox::dsl::id.eq_any(&ids))
+            .set(scrobble_outbox::dsl::next_attempt_at.eq(now + Duration::seconds(CLAIM_SECONDS)))
+            .execute(conn)?;
+        Ok(ids)
+    })
+}
+
+/// Describe queued plays as listens, with the piece, composers and performers of each
+fn db_submission(
+    ids: &[i32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Submission, ApiError> {
+    use crate::schema::{
+        composers, performers, piece_composers, pieces, plays, recording_performers, recordings,
+        releases, scrobble_outbox,
+    };
+
+    let rows: Vec<(NaiveDateTime, DbRecording, DbPiece, String)> =
+        scrobble_outbox::dsl::scrobble_outbox
+            .inner_join(
+                plays::dsl::plays.inner_join(
+                    recordings::dsl::recordings
+                        .inner_join(pieces::dsl::pieces)
+                        .inner_join(releases::dsl::releases),
+                ),
+            )
+            .filter(scrobble_outbox::dsl::id.eq_any(ids))
+            .order(plays::dsl::started_at)
+            .select((
+                plays::dsl::started_at,
+                DbRecording::as_select(),
+                DbPiece::as_select(),
+                releases::dsl::name,
+            ))
+            .load::<(NaiveDateTime, DbRecording, DbPiece, String)>(conn)?;
+
+    let piece_ids: Vec<i32> = rows.iter().map(|(_, _, piece, _)| piece.id).collect();
+    let mut composers_by_piece: HashMap<i32, Vec<String>> = HashMap::new();
+    for (piece_id, composer) in piece_composers::dsl::piece_composers
+        .inner_join(composers::dsl::composers)
+        .filter(piece_composers::dsl::piece_id.eq_any(&piece_ids))
+        .order(composers::dsl::name)
+        .select((piece_composers::dsl::piece_id, Composer::as_select()))
+        .load::<(i32, Composer)>(conn)?
+    {
+        composers_by_piece
+            .entry(piece_id)
+            .or_default()
+            .push(composer.name);
+    }
+    let recording_ids: Vec<i32> = rows
+        .iter()
+        .map(|(_, recording, _, _)| recording.id)
+        .collect();
+    let mut performers_by_recording: HashMap<i32, Vec<String>> = HashMap::new();
+    for (recording_id, performer) in recording_performers::dsl::recording_performers
+        .inner_join(performers::dsl::performers)
+        .filter(recording_performers::dsl::recording_id.eq_any(&recording_ids))
+        .order(performers::dsl::name)
+        .select((
+            recording_performers::dsl::recording_id,
+            Performer::as_select(),
+        ))
+        .load::<(i32, Performer)>(conn)?
+    {
+        performers_by_recording
+            .entry(recording_id)
+            .or_default()
+            .push(performer.name);
+    }
+
+    let payload: Vec<Listen> = rows
+        .into_iter()
+        .map(|(started_at, recording, piece, release_name)| {
+            let composers: Vec<String> = composers_by_piece
+                .get(&piece.id)
+                .cloned()
+                .unwrap_or_default();
+            let performers: Vec<String> = performers_by_recording
+                .get(&recording.id)
+                .cloned()
+                .unwrap_or_default();
+
+            // recordings without performers are credited to their composers instead
+            let artist_names: Vec<String> = match performers.is_empty() {
+                true => composers.clone(),
+                false => performers.clone(),
+            };
+            Listen {
+                listened_at: started_at.and_utc().timestamp(),
+                track_metadata: TrackMetadata {
+                    artist_name: artist_names.join(", "),
+                    track_name: recording.piece_name,
+                    release_name,
+                    additional_info: AdditionalInfo {
+                        submission_client: "allegro".to_string(),
+                        submission_client_version: env!("CARGO_PKG_VERSION").to_string(),
+                        tracknumber: recording.track_number,
+                        work_name: piece.name,
+                        work_movements: piece.movements,
+                        composers,
+                        performers,
+                        artist_names,
+                    },
+                },
+            }
+        })
+        .collect();
+
+    // a lone listen is a single submission, several are an import
+    Ok(Submission {
+        listen_type: match payload.len() {
+            1 => "single".to_string(),
+            _ => "import".to_string(),
+        },
+        payload,
+    })
+}
+
+/// Send listens to a target, describing why if it does not accept them
+fn submit(
+    agent: &ureq::Agent,
+    url: &str,
+    token: &str,
+    submission: &Submission,
+) -> Result<(), String> {
+    // plays that vanished since they were queued leave nothing to send
+    if submission.payload.is_empty() {
+        return Ok(());
+    }
+    let body: String = serde_json::to_string(submission).map_err(|err| err.to_string())?;
+    let answer = agent
+        .post(url)
+        .set("Authorization", &format!("Token {}", token))
+        .set("Content-Type", "application/json")
+        .send_string(&body);
+    match answer {
+        Ok(response) if (200..300).contains(&response.status()) => Ok(()),
+        // redirects are not followed, so the listens did not arrive anywhere
+        Ok(response) => Err(format!("Target answered {}", response.status())),
+        // the body is left out, as users may point the target at a server that is not theirs
+        Err(ureq::Error::Status(status, _)) => Err(format!("Target answered {}", status)),
+        Err(err) => Err(err.to_string().chars().take(MAX_ERROR_LEN).collect()),
+    }
+}
+
+/// Remove listens the target accepted and note the success
+fn db_submitted(
+    user_id: i32,
+    ids: &[i32],
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{scrobble_outbox, scrobble_targets};
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    diesel::delete(scrobble_outbox::dsl::scrobble_outbox)
+        .filter(scrobble_outbox::dsl::id.eq_any(ids))
+        .execute(conn)?;
+    diesel::update(scrobble_targets::dsl::scrobble_targets.find(user_id))
+        .set((
+            scrobble_targets::dsl::last_submitted_at.eq(now),
+            scrobble_targets::dsl::last_error.eq(None::<String>),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Put off listens the target did not accept, waiting twice as long after every failed
+/// attempt, and give up on those tried too often
+fn db_failed(
+    user_id: i32,
+    ids: &[i32],
+    err: &str,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), ApiError> {
+    use crate::schema::{scrobble_outbox, scrobble_targets};
+
+    let now: NaiveDateTime = chrono::Utc::now().naive_utc();
+    let attempts: Vec<(i32, i32)> = scrobble_outbox::dsl::scrobble_outbox
+        .filter(scrobble_outbox::dsl::id.eq_any(ids))
+        .select((scrobble_outbox::dsl::id, scrobble_outbox::dsl::attempts))
+        .load::<(i32, i32)>(conn)?;
+    for (id, attempts) in attempts {
+        let attempts: i32 = attempts + 1;
+        let entry = scrobble_outbox::dsl::scrobble_outbox.find(id);
+        if attempts >= MAX_ATTEMPTS {
+            log::warn!(
+                "Giving up forwarding play of user {} after {} attempts",
+                user_id,
+                attempts
+            );
+            diesel::delete(entry).execute(conn)?;
+            continue;
+        }
+        let backoff: i64 = (60_i64 << attempts.min(20)).min(MAX_BACKOFF);
+        diesel::update(entry)
+            .set((
+                scrobble_outbox::dsl::attempts.eq(attempts),
+                scrobble_outbox::dsl::next_attempt_at.eq(now + Duration::seconds(backoff)),
+                scrobble_outbox::dsl::last_error.eq(err),
+            ))
+            .execute(conn)?;
+    }
+    diesel::update(scrobble_targets::dsl::scrobble_targets.find(user_id))
+        .set(scrobble_targets::dsl::last_error.eq(err))
+        .execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::connect;
+    use chrono::SubsecRound;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Answer one request on a local port with a status and body, handing over the headers and
+    /// body of the request
+    fn listen(
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: String = format!("http://{}/1/submit-listens", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers: String = String::new();
+            let mut length: usize = 0;
+            loop {
+                let mut line: String = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                headers.push_str(&line);
+            }
+            let mut request: Vec<u8> = vec![0; length];
+            reader.read_exact(&mut request).unwrap();
+            let answer: String = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            reader.get_mut().write_all(answer.as_bytes()).unwrap();
+            sender
+                .send((headers, String::from_utf8(request).unwrap()))
+                .unwrap();
+        });
+        (url, receiver)
+    }
+
+    /// The current time, to the microsecond as the database keeps it
+    fn now() -> NaiveDateTime {
+        chrono::Utc::now().naive_utc().round_subsecs(6)
+    }
+
+    /// Queue a play of a movement of a symphony, returning its user and outbox entry
+    fn queue_play(conn: &mut PooledConnection<ConnectionManager<PgConnection>>) -> (i32, i32) {
+        use crate::schema::{
+            composers, performers, piece_composers, pieces, plays, recording_performers,
+            recordings, releases, scrobble_outbox, scrobble_targets, users,
+        };
+
+        let user_id: i32 = diesel::insert_into(users::table)
+            .values((
+                users::username.eq("scrobbler-test"),
+                users::password_hash.eq("unused"),
+            ))
+            .returning(users::id)
+            .get_result(conn)
+            .unwrap();
+        let composer_id: i32 = diesel::insert_into(composers::table)
+            .values(composers::name.eq("Antonín Dvořák"))
+            .returning(composers::id)
+            .get_result(conn)
+            .unwrap();
+        let performer_id: i32 = diesel::insert_into(performers::table)
+            .values(performers::name.eq("Berliner Philharmoniker"))
+            .returning(performers::id)
+            .get_result(conn)
+            .unwrap();
+        let piece_id: i32 = diesel::insert_into(pieces::table)
+            .values((
+                pieces::name.eq("Symphony No. 9 in E minor"),
+                pieces::movements.eq(4),
+            ))
+            .returning(pieces::id)
+            .get_result(conn)
+            .unwrap();
+        let release_id: i32 = diesel::insert_into(releases::table)
+            .values(releases::name.eq("New World Symphony"))
+            .returning(releases::id)
+            .get_result(conn)
+            .unwrap();
+        let recording_id: i32 = diesel::insert_into(recordings::table)
+            .values((
+                recordings::piece_name.eq("Symphony No. 9: II. Largo"),
+                recordings::piece_id.eq(piece_id),
+                recordings::release_id.eq(release_id),
+                recordings::track_number.eq(2),
+            ))
+            .returning(recordings::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(piece_composers::table)
+            .values((
+                piece_composers::piece_id.eq(piece_id),
+                piece_composers::composer_id.eq(composer_id),
+            ))
+            .execute(conn)
+            .unwrap();
+        diesel::insert_into(recording_performers::table)
+            .values((
+                recording_performers::recording_id.eq(recording_id),
+                recording_performers::performer_id.eq(performer_id),
+            ))
+            .execute(conn)
+            .unwrap();
+        let play_id: i32 = diesel::insert_into(plays::table)
+            .values((
+                plays::user_id.eq(user_id),
+                plays::recording_id.eq(recording_id),
+                plays::state.eq("finished"),
+            ))
+            .returning(plays::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(scrobble_targets::table)
+            .values((
+                scrobble_targets::user_id.eq(user_id),
+                scrobble_targets::submit_url.eq(LISTENBRAINZ_URL),
+                scrobble_targets::encrypted_token.eq("unused"),
+            ))
+            .execute(conn)
+            .unwrap();
+        let outbox_id: i32 = diesel::insert_into(scrobble_outbox::table)
+            .values((
+                scrobble_outbox::user_id.eq(user_id),
+                scrobble_outbox::play_id.eq(play_id),
+            ))
+            .returning(scrobble_outbox::id)
+            .get_result(conn)
+            .unwrap();
+        (user_id, outbox_id)
+    }
+
+    #[test]
+    fn submits_classical_metadata() {
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (_, outbox_id) = queue_play(&mut conn);
+        let submission: Submission = db_submission(&[outbox_id], &mut conn).unwrap();
+
+        let (url, received) = listen("200 OK", "{\"status\":\"ok\"}");
+        let agent: ureq::Agent = agent(true);
+        submit(&agent, &url, "secret-token", &submission).unwrap();
+        let (headers, body) = received.recv().unwrap();
+        assert!(headers.contains("Authorization: Token secret-token"));
+
+        let sent: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(sent["listen_type"], "single");
+        let metadata: &serde_json::Value = &sent["payload"][0]["track_metadata"];
+        assert_eq!(metadata["artist_name"], "Berliner Philharmoniker");
+        assert_eq!(metadata["track_name"], "Symphony No. 9: II. Largo");
+        assert_eq!(metadata["release_name"], "New World Symphony");
+        let info: &serde_json::Value = &metadata["additional_info"];
+        assert_eq!(info["work_name"], "Symphony No. 9 in E minor");
+        assert_eq!(info["work_movements"], 4);
+        assert_eq!(info["tracknumber"], 2);
+        assert_eq!(info["composers"], serde_json::json!(["Antonín Dvořák"]));
+        assert_eq!(
+            info["performers"],
+            serde_json::json!(["Berliner Philharmoniker"])
+        );
+    }
+
+    #[test]
+    fn backs_off_after_failures() {
+        use crate::schema::{scrobble_outbox, scrobble_targets};
+
+        let Some(mut conn) = connect() else {
+            return;
+        };
+        let (user_id, outbox_id) = queue_play(&mut conn);
+        let submission: Submission = db_submission(&[outbox_id], &mut conn).unwrap();
+
+        // the answer of the target is not kept, only its status
+        let (url, _received) = listen("503 Service Unavailable", "internal details");
+        let agent: ureq::Agent = agent(true);
+        let err: String = submit(&agent, &url, "secret-token", &submission).unwrap_err();
+        assert_eq!(err, "Target answered 503");
+
+        let entry = scrobble_outbox::table.find(outbox_id);
+        let before: NaiveDateTime = now();
+        db_failed(user_id, &[outbox_id], &err, &mut conn).unwrap();
+        let after: NaiveDateTime = now();
+        let (attempts, next_attempt_at, last_error) = entry
+            .select((
+                scrobble_outbox::attempts,
+                scrobble_outbox::next_attempt_at,
+                scrobble_outbox::last_error,
+            ))
+            .first::<(i32, NaiveDateTime, Option<String>)>(&mut conn)
+            .unwrap();
+        assert_eq!(attempts, 1);
+        assert!(next_attempt_at >= before + Duration::seconds(120));
+        assert!(next_attempt_at <= after + Duration::seconds(120));
+        assert_eq!(last_error.as_deref(), Some("Target answered 503"));
+        let target_error: Option<String> = scrobble_targets::table
+            .find(user_id)
+            .select(scrobble_targets::last_error)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(target_error.as_deref(), Some("Target answered 503"));
+
+        // the wait stops growing at the longest backoff
+        diesel::update(entry)
+            .set(scrobble_outbox::attempts.eq(20))
+            .execute(&mut conn)
+            .unwrap();
+        let before: NaiveDateTime = now();
+        db_failed(user_id, &[outbox_id], &err, &mut conn).unwrap();
+        let next_attempt_at: NaiveDateTime = entry
+            .select(scrobble_outbox::next_attempt_at)
+            .first(&mut conn)
+            .unwrap();
+        assert!(next_attempt_at >= before + Duration::seconds(MAX_BACKOFF));
+        assert!(next_attempt_at < before + Duration::seconds(MAX_BACKOFF + 60));
+
+        // a target sending the listens elsewhere has not accepted them
+        let (url, _received) = listen("301 Moved Permanently", "");
+        let err: String = submit(&agent, &url, "secret-token", &submission).unwrap_err();
+        assert_eq!(err, "Target answered 301");
+
+        // and the play is given up on after too many attempts
+        diesel::update(entry)
+            .set(scrobble_outbox::attempts.eq(MAX_ATTEMPTS - 1))
+            .execute(&mut conn)
+            .unwrap();
+        db_failed(user_id, &[outbox_id], &err, &mut conn).unwrap();
+        let left: i64 = entry.count().get_result(&mut conn).unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn refuses_private_targets() {
+        assert!(resolve_public("127.0.0.1:80").is_err());
+        assert!(resolve_public("10.0.0.1:80").is_err());
+        assert!(resolve_public("169.254.169.254:80").is_err());
+        assert!(resolve_public("[::1]:80").is_err());
+        assert!(resolve_public("[fd00::1]:80").is_err());
+        assert!(resolve_public("[::ffff:192.168.1.1]:80").is_err());
+        assert!(resolve_public("1.1.1.1:443").is_ok());
+    }
+
+    #[test]
+    fn allows_private_targets_when_configured() {
+        let (url, received) = listen("200 OK", "{\"status\":\"ok\"}");
+        assert!(check_url(&url, false).is_err());
+        assert!(check_url(&url, true).is_ok());
+
+        let submission = Submission {
+            listen_type: "single".to_string(),
+            payload: vec![Listen {
+                listened_at: 0,
+                track_metadata: TrackMetadata {
+                    artist_name: "Berliner Philharmoniker".to_string(),
+                    track_name: "Symphony No. 9: II. Largo".to_string(),
+                    release_name: "New World Symphony".to_string(),
+                    additional_info: AdditionalInfo {
+                        submission_client: "allegro".to_string(),
+                        submission_client_version: env!("CARGO_PKG_VERSION").to_string(),
+                        tracknumber: 2,
+                        work_name: "Symphony No. 9 in E minor".to_string(),
+                        work_movements: Some(4),
+                        composers: vec!["Antonín Dvořák".to_string()],
+                        performers: vec!["Berliner Philharmoniker".to_string()],
+                        artist_names: vec!["Berliner Philharmoniker".to_string()],
+                    },
+                },
+            }],
+        };
+        assert!(submit(&agent(false), &url, "secret-token", &submission).is_err());
+        submit(&agent(true), &url, "secret-token", &submission).unwrap();
+        received.recv().unwrap();
+    }
+}
@@ -0,0 +1,19 @@
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+
+/// Connect to the database named by DATABASE_URL in a transaction that is never committed, or
+/// give nothing when no database is available so tests that need one can be skipped
+pub fn connect() -> Option<PooledConnection<ConnectionManager<PgConnection>>> {
+    let Ok(url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set, skipping");
+        return None;
+    };
+    let pool: Pool<ConnectionManager<PgConnection>> = Pool::builder()
+        .max_size(1)
+        .build(ConnectionManager::<PgConnection>::new(url))
+        .expect("failed to connect to the database");
+    let mut conn = pool.get().expect("failed to get a connection");
+    conn.begin_test_transaction()
+        .expect("failed to begin a transaction");
+    Some(conn)
+}
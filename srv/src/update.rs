@@ -0,0 +1,127 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+// fields that are None are left unchanged, and nullable fields set to Some(None) are cleared
+
+/// Represents changes to a composer in the composers table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::composers)]
+pub struct UpdateComposer {
+    pub name: Option<String>,
+    pub description: Option<Option<String>>,
+}
+
+impl UpdateComposer {
+    /// Whether there are no changes to apply
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.description.is_none()
+    }
+}
+
+/// Represents changes to a performer in the performers table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::performers)]
+pub struct UpdatePerformer {
+    pub name: Option<String>,
+    pub description: Option<Option<String>>,
+}
+
+impl UpdatePerformer {
+    /// Whether there are no changes to apply
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.description.is_none()
+    }
+}
+
+/// Represents changes to a songwriter in the songwriters table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::songwriters)]
+pub struct UpdateSongwriter {
+    pub name: Option<String>,
+    pub description: Option<Option<String>>,
+}
+
+impl UpdateSongwriter {
+    /// Whether there are no changes to apply
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.description.is_none()
+    }
+}
+
+/// Represents changes to a release in the releases table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::releases)]
+pub struct UpdateRelease {
+    pub name: Option<String>,
+    pub description: Option<Option<String>>,
+}
+
+impl UpdateRelease {
+    /// Whether there are no changes to apply
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.description.is_none()
+    }
+}
+
+/// Represents changes to a piece in the pieces table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::pieces)]
+pub struct UpdatePiece {
+    pub name: Option<String>,
+    pub movements: Option<Option<i32>>,
+    pub description: Option<Option<String>>,
+}
+
+impl UpdatePiece {
+    /// Whether there are no changes to apply
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.movements.is_none() && self.description.is_none()
+    }
+}
+
+/// Represents changes to a recording in the recordings table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::recordings)]
+pub struct UpdateRecording {
+    pub piece_name: Option<String>,
+    pub piece_id: Option<i32>,
+    pub release_id: Option<i32>,
+    pub track_number: Option<i32>,
+}
+
+impl UpdateRecording {
+    /// Whether there are no changes to apply
+    pub fn is_empty(&self) -> bool {
+        self.piece_name.is_none()
+            && self.piece_id.is_none()
+            && self.release_id.is_none()
+            && self.track_number.is_none()
+    }
+}
+
+/// Represents changes to a playlist in the playlists table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::playlists)]
+pub struct UpdatePlaylist {
+    pub name: Option<String>,
+    pub description: Option<Option<String>>,
+    pub public: Option<bool>,
+}
+
+impl UpdatePlaylist {
+    /// Whether there are no changes to apply
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.description.is_none() && self.public.is_none()
+    }
+}
+
+/// Represents changes to where a user forwards their plays in the scrobble_targets table
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::scrobble_targets)]
+pub struct UpdateScrobbleTarget {
+    pub submit_url: Option<String>,
+    pub encrypted_token: Option<String>,
+    pub enabled: Option<bool>,
+    pub last_error: Option<Option<String>>,
+    pub updated_at: NaiveDateTime,
+}
@@ -17,7 +17,7 @@ export async function buildArtist(
   id: number,
   type: "performer" | "composer" | "songwriter",
 ): Promise<Artist> {
-  const response = await api.post(`/music/get/${type}`, { id });
+  const response = await api.get(`/music/get/${type}`, { params: { id } });
   const data = response.data.message;
 
   return {
@@ -71,14 +71,14 @@ export async function buildRecording(
   dbRecording: DbRecording,
 ): Promise<Recording> {
   // Get the piece
-  const pieceResponse = await api.post("/music/get/piece", {
-    id: dbRecording.piece_id,
+  const pieceResponse = await api.get("/music/get/piece", {
+    params: { id: dbRecording.piece_id },
   });
   const piece = await buildPiece(pieceResponse.data.message);
 
   // Get the release
-  const releaseResponse = await api.post("/music/get/release", {
-    id: dbRecording.release_id,
+  const releaseResponse = await api.get("/music/get/release", {
+    params: { id: dbRecording.release_id },
   });
   const release = await buildRelease(releaseResponse.data.message);
 
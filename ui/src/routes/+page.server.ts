@@ -42,7 +42,15 @@ export const actions: Actions = {
       });
 
       return { success: true };
-    } catch {
+    } catch (err) {
+      // wrong credentials are reported with an unauthorized status
+      if (axios.isAxiosError(err) && err.response?.status === 401) {
+        return fail(400, { error: "Login failed" });
+      }
+      // repeated failures are throttled, and the server says how long to wait
+      if (axios.isAxiosError(err) && err.response?.status === 429) {
+        return fail(429, { error: err.response.data.message });
+      }
       return fail(400, { error: "Server error" });
     }
   },
@@ -52,6 +60,7 @@ export const actions: Actions = {
     const username = data.get("username");
     const password = data.get("password");
     const verifyPassword = data.get("verifyPassword");
+    const setupToken = data.get("setupToken");
 
     if (username === "") {
       return fail(400, { error: "Username cannot be empty" });
@@ -69,15 +78,18 @@ export const actions: Actions = {
       const response = await api.post("/auth/adduser", {
         username,
         password,
-        token: "",
+        setup_token: setupToken || undefined,
       });
-      const token = response.data.token;
 
       // ensure the response is successful
       if (!response.data.success) {
         return fail(400, { error: "Add user failed" });
       }
 
+      // sign the new admin in
+      const login = await api.post("/auth/login", { username, password });
+      const token = login.data.token;
+
       // Set the token as a cookie
       cookies.set("token", token, {
         path: "/",
@@ -88,7 +100,11 @@ export const actions: Actions = {
       });
 
       return { success: true };
-    } catch {
+    } catch (err) {
+      // a missing or wrong setup token is refused
+      if (axios.isAxiosError(err) && err.response?.status === 403) {
+        return fail(403, { error: err.response.data.message });
+      }
       return fail(400, { error: "Failed to create admin user" });
     }
   },
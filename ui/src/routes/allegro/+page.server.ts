@@ -1,12 +1,23 @@
 import type { Actions, PageServerLoad } from "./$types";
 import { fail } from "@sveltejs/kit";
-import { writeFile } from "fs/promises";
 import axios from "axios";
 
 const api = axios.create({
   baseURL: "http://localhost:9000",
 });
 
+// authenticate a request with the session token of the user
+function authorization(token: string | undefined) {
+  return { headers: token ? { Authorization: `Bearer ${token}` } : {} };
+}
+
+// upload a file to the server under the name generated for it
+async function upload(fileName: string, file: File, token: string | undefined) {
+  const form = new FormData();
+  form.append("file", file);
+  return api.post(`/music/upload/${fileName}`, form, authorization(token));
+}
+
 export const load: PageServerLoad = ({ cookies }) => {
   return {
     token: cookies.get("token") || null,
@@ -41,27 +52,25 @@ export const actions: Actions = {
     }
 
     try {
-      const response = await api.post("/music/add/recording", {
-        piece_id: pieceId,
-        release_id: releaseId,
-        performer_ids: performerIds,
-        track_number: trackNumber,
-        token,
-      });
+      const response = await api.post(
+        "/music/add/recording",
+        {
+          piece_id: pieceId,
+          release_id: releaseId,
+          performer_ids: performerIds,
+          track_number: trackNumber,
+        },
+        authorization(token),
+      );
 
       if (!response.data.success) {
         return fail(400, response.data.message);
       }
 
       // upload recording
-      let recordingPath = "";
       if (recording.size > 0) {
         const fileName = response.data.message;
-        recordingPath = `static/recordings/${fileName}`;
-        await writeFile(
-          recordingPath,
-          Buffer.from(await recording.arrayBuffer()),
-        );
+        await upload(fileName, recording, token);
       }
 
       return { success: true };
@@ -98,14 +107,17 @@ export const actions: Actions = {
     }
 
     try {
-      const response = await api.post("/music/add/piece", {
-        name,
-        movements,
-        composer_ids: composerIds,
-        songwriter_ids: songwriterIds,
-        description,
-        token,
-      });
+      const response = await api.post(
+        "/music/add/piece",
+        {
+          name,
+          movements,
+          composer_ids: composerIds,
+          songwriter_ids: songwriterIds,
+          description,
+        },
+        authorization(token),
+      );
 
       if (!response.data.success) {
         return fail(400, response.data.message);
@@ -139,24 +151,25 @@ export const actions: Actions = {
 
     try {
       const hasImage = image.size > 0;
-      const response = await api.post("/music/add/release", {
-        name,
-        performer_ids: performerIds,
-        description,
-        has_image: hasImage,
-        token,
-      });
+      const response = await api.post(
+        "/music/add/release",
+        {
+          name,
+          performer_ids: performerIds,
+          description,
+          has_image: hasImage,
+        },
+        authorization(token),
+      );
 
       if (!response.data.success) {
         return fail(400, response.data.message);
       }
 
       // upload image
-      let imagePath = "";
       if (image.size > 0) {
         const fileName = response.data.message;
-        imagePath = `static/uploads/${fileName}`;
-        await writeFile(imagePath, Buffer.from(await image.arrayBuffer()));
+        await upload(fileName, image, token);
       }
 
       return { success: true };
@@ -186,24 +199,25 @@ export const actions: Actions = {
 
     try {
       const hasImage = image.size > 0;
-      const response = await api.post("/music/add/artist", {
-        name,
-        description,
-        has_image: hasImage,
-        artist_type,
-        token,
-      });
+      const response = await api.post(
+        "/music/add/artist",
+        {
+          name,
+          description,
+          has_image: hasImage,
+          artist_type,
+        },
+        authorization(token),
+      );
 
       if (!response.data.success) {
         return fail(400, response.data.message);
       }
 
       // upload image
-      let imagePath = "";
       if (image.size > 0) {
         const fileName = response.data.message;
-        imagePath = `static/uploads/${fileName}`;
-        await writeFile(imagePath, Buffer.from(await image.arrayBuffer()));
+        await upload(fileName, image, token);
       }
 
       return { success: true };